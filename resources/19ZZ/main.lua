@@ -1,5 +1,9 @@
 ---@diagnostic disable: lowercase-global
 SONGMAP = {}
+table.insert(SONGMAP, {
+    format_version = 2
+})
+
 table.insert(SONGMAP, {
     music = "19ZZ.mp3"
 })
@@ -1,5 +1,6 @@
 ---@diagnostic disable: lowercase-global
 SONGMAP = {}
+table.insert(SONGMAP, {format_version = 2})
 table.insert(SONGMAP, {music = "supersquare.mp3"})
 
 
@@ -0,0 +1,209 @@
+/// This module handles estimating the BPM of a song directly from its audio.
+/// This is meant as a starting point for chart authors who don't know the BPM
+/// of a song, and isn't expected to be perfectly accurate.
+/// The estimate is done by computing an onset envelope of the audio (how much
+/// the loudness increases from one frame to the next), then finding the beat
+/// length which best autocorrelates with the envelope.
+/// This module also measures how loud songs are, so that every level's music
+/// can be played at roughly the same loudness. The loudness is measured in
+/// the style of EBU R128 (the mean power of 400ms blocks, ignoring silent and
+/// quiet blocks), but without its frequency weighting, so it's only a rough
+/// match for how loud the song sounds.
+use std::io::Cursor;
+use std::path::Path;
+
+use rodio::{Decoder, Source};
+
+use crate::time::{self, Seconds};
+
+// The number of samples per frame of the onset envelope.
+const HOP_SIZE: usize = 512;
+// The range of BPMs considered when estimating the BPM.
+const MIN_BPM: f64 = 60.0;
+const MAX_BPM: f64 = 200.0;
+
+/// The loudness songs are normalized to, in dB relative to full scale.
+const TARGET_LOUDNESS: f64 = -14.0;
+/// The most a song may be made louder or quieter by normalization, in dB.
+/// Boosting is limited more than cutting since boosting quiet songs too much
+/// would make them clip.
+const MAX_BOOST: f64 = 6.0;
+const MAX_CUT: f64 = 12.0;
+/// The length of each block loudness is measured over, in seconds.
+const LOUDNESS_BLOCK: f64 = 0.4;
+/// Blocks quieter than this (in dB relative to full scale) are silence and
+/// are ignored.
+const SILENCE_GATE: f64 = -70.0;
+/// Blocks quieter than the average loudness by more than this (in dB) are
+/// ignored, so that quiet intros and breakdowns don't make a song seem quieter
+/// than it is.
+const RELATIVE_GATE: f64 = 10.0;
+
+/// The result of analyzing a song.
+#[derive(Debug, Clone, Copy)]
+pub struct TempoEstimate {
+    pub bpm: f64,
+    /// The time of the first downbeat in the song.
+    pub offset: Seconds,
+}
+
+impl TempoEstimate {
+    /// Print suggested chart values for this estimate.
+    pub fn print_suggestion(&self) {
+        println!("Estimated BPM: {:.2}", self.bpm);
+        println!(
+            "First beat at: {:.3} seconds ({:.2} beats)",
+            self.offset.0,
+            time::to_beats(self.offset, self.bpm).0
+        );
+        println!("Suggested chart entries:");
+        println!("table.insert(SONGMAP, {{ bpm = {:.2} }})", self.bpm);
+        println!(
+            "table.insert(SONGMAP, {{ skip = {:.2} }})",
+            time::to_beats(self.offset, self.bpm).0
+        );
+    }
+}
+
+/// Estimate the BPM and downbeat offset of the audio file at `path`.
+pub fn estimate_tempo(path: impl AsRef<Path>) -> anyhow::Result<TempoEstimate> {
+    estimate_tempo_of(std::fs::read(path)?)
+}
+
+/// Like `estimate_tempo`, but for an audio file which has already been read.
+pub fn estimate_tempo_of(audio: Vec<u8>) -> anyhow::Result<TempoEstimate> {
+    let (mono, sample_rate) = decode_mono(audio)?;
+
+    let envelope = onset_envelope(&mono);
+    let envelope_rate = sample_rate / HOP_SIZE as f64;
+    let lag = best_lag(&envelope, envelope_rate)
+        .ok_or_else(|| anyhow::anyhow!("Song is too short to estimate a BPM"))?;
+    let phase = best_phase(&envelope, lag);
+
+    Ok(TempoEstimate {
+        bpm: 60.0 * envelope_rate / lag as f64,
+        offset: Seconds(phase as f64 / envelope_rate),
+    })
+}
+
+/// Return the duration of the audio file at `path`.
+pub fn song_duration(path: impl AsRef<Path>) -> anyhow::Result<Seconds> {
+    song_duration_of(std::fs::read(path)?)
+}
+
+/// Like `song_duration`, but for an audio file which has already been read.
+pub fn song_duration_of(audio: Vec<u8>) -> anyhow::Result<Seconds> {
+    let (mono, sample_rate) = decode_mono(audio)?;
+    Ok(Seconds(mono.len() as f64 / sample_rate))
+}
+
+/// Return the gain, in dB, which brings the audio file to the target loudness.
+pub fn normalization_gain_of(audio: Vec<u8>) -> anyhow::Result<f64> {
+    let (mono, sample_rate) = decode_mono(audio)?;
+    let loudness = gated_loudness(&mono, sample_rate)
+        .ok_or_else(|| anyhow::anyhow!("Song is silent, so its loudness can't be measured"))?;
+    Ok((TARGET_LOUDNESS - loudness).clamp(-MAX_CUT, MAX_BOOST))
+}
+
+/// Convert a gain in dB to the amount to multiply the amplitude by.
+pub fn db_to_amplitude(db: f64) -> f64 {
+    10.0f64.powf(db / 20.0)
+}
+
+/// Return the loudness of the samples in dB relative to full scale, or None if
+/// the samples are silent.
+fn gated_loudness(samples: &[f32], sample_rate: f64) -> Option<f64> {
+    fn to_db(mean_square: f64) -> f64 {
+        10.0 * mean_square.max(1.0e-12).log10()
+    }
+    fn mean(values: &[f64]) -> f64 {
+        values.iter().sum::<f64>() / values.len() as f64
+    }
+
+    let block_len = ((sample_rate * LOUDNESS_BLOCK) as usize).max(1);
+    let blocks: Vec<f64> = samples
+        .chunks(block_len)
+        .map(|block| block.iter().map(|&x| (x * x) as f64).sum::<f64>() / block.len() as f64)
+        .filter(|&mean_square| to_db(mean_square) > SILENCE_GATE)
+        .collect();
+    if blocks.is_empty() {
+        return None;
+    }
+
+    let gate = to_db(mean(&blocks)) - RELATIVE_GATE;
+    let loud: Vec<f64> = blocks
+        .into_iter()
+        .filter(|&mean_square| to_db(mean_square) > gate)
+        .collect();
+    Some(to_db(mean(&loud)))
+}
+
+/// Decode the audio file, mixing it down to mono. Also returns the sample rate
+/// of the file.
+fn decode_mono(audio: Vec<u8>) -> anyhow::Result<(Vec<f32>, f64)> {
+    let decoder = Decoder::new(Cursor::new(audio))?;
+    let channels = decoder.channels().max(1) as usize;
+    let sample_rate = decoder.sample_rate() as f64;
+
+    let samples: Vec<i16> = decoder.collect();
+    let mono = samples
+        .chunks(channels)
+        .map(|frame| {
+            frame
+                .iter()
+                .map(|&x| x as f32 / i16::MAX as f32)
+                .sum::<f32>()
+                / channels as f32
+        })
+        .collect();
+    Ok((mono, sample_rate))
+}
+
+/// Return the onset envelope of the given samples. Each entry is the increase
+/// in log-energy from the previous frame, or zero if the energy decreased.
+fn onset_envelope(samples: &[f32]) -> Vec<f32> {
+    let energies: Vec<f32> = samples
+        .chunks(HOP_SIZE)
+        .map(|frame| (frame.iter().map(|x| x * x).sum::<f32>() + 1.0e-6).ln())
+        .collect();
+
+    let mut envelope = Vec::with_capacity(energies.len());
+    envelope.push(0.0);
+    for window in energies.windows(2) {
+        envelope.push((window[1] - window[0]).max(0.0));
+    }
+    envelope
+}
+
+/// Return the lag (in envelope frames) within the allowed BPM range that best
+/// autocorrelates with the envelope.
+fn best_lag(envelope: &[f32], envelope_rate: f64) -> Option<usize> {
+    let min_lag = (60.0 * envelope_rate / MAX_BPM).floor().max(1.0) as usize;
+    let max_lag = (60.0 * envelope_rate / MIN_BPM).ceil() as usize;
+
+    (min_lag..=max_lag)
+        .filter(|&lag| lag < envelope.len())
+        .map(|lag| {
+            let correlation: f32 = envelope
+                .iter()
+                .zip(envelope[lag..].iter())
+                .map(|(a, b)| a * b)
+                .sum();
+            // Normalize by the number of terms so that short lags aren't favored.
+            (lag, correlation / (envelope.len() - lag) as f32)
+        })
+        .max_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal))
+        .map(|(lag, _)| lag)
+}
+
+/// Return the phase (in envelope frames, less than `lag`) such that the onsets
+/// at `phase + k * lag` are the strongest.
+fn best_phase(envelope: &[f32], lag: usize) -> usize {
+    (0..lag)
+        .map(|phase| {
+            let strength: f32 = envelope.iter().skip(phase).step_by(lag).sum();
+            (phase, strength)
+        })
+        .max_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal))
+        .map_or(0, |(phase, _)| phase)
+}
@@ -0,0 +1,600 @@
+/// This module implements the game's audio output. Audio is optional: if there
+/// is no audio device (or it can't be opened), the game runs silently and
+/// tries to open the device again every REINIT_INTERVAL, so that plugging in
+/// headphones partway through a session restores sound. The song's file is
+/// kept around so that it can be added to the new device once it opens.
+///
+/// The player may pick which output device to use (see `set_device`). kira 0.5
+/// always opens the system's default output device, so music on any other
+/// device is played with rodio instead, which can open any device cpal lists.
+///
+/// Besides the song, charts may play sounds (see `SpawnCmd::PlaySound`) and
+/// the debug metronome clicks on every beat. These are scheduled a little
+/// ahead of their beat with `schedule_sound` and `schedule_click`, and then
+/// played by the audio device's own clock (a kira sequence, or a delayed rodio
+/// source), rather than started from the frame loop, which would quantize them
+/// to frames.
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use kira::instance::handle::InstanceHandle;
+use kira::instance::{
+    InstanceSettings, InstanceState, PauseInstanceSettings, ResumeInstanceSettings,
+    StopInstanceSettings,
+};
+use kira::manager::{AudioManager, AudioManagerSettings};
+use kira::sequence::handle::SequenceInstanceHandle;
+use kira::sequence::{Sequence, SequenceInstanceSettings, SequenceSettings};
+use kira::sound::handle::SoundHandle;
+use kira::sound::{Sound, SoundSettings};
+use kira::Frame;
+use once_cell::sync::Lazy;
+use rodio::buffer::SamplesBuffer;
+use rodio::cpal::traits::{DeviceTrait, HostTrait};
+use rodio::{Decoder, OutputStream, OutputStreamHandle, Sample, Sink, Source};
+
+use crate::time::Seconds;
+
+/// How long to wait between attempts to open the audio device.
+const REINIT_INTERVAL: Duration = Duration::from_secs(3);
+/// The volume the music is played at, before its gain is applied.
+const MUSIC_VOLUME: f64 = 0.5;
+/// The volume the chart's sounds are played at, before their own volume is
+/// applied.
+const SOUND_VOLUME: f64 = 0.5;
+/// The volume of the metronome's clicks.
+const CLICK_VOLUME: f64 = 0.4;
+/// The metronome's clicks are generated rather than loaded, at this sample
+/// rate.
+const CLICK_SAMPLE_RATE: u32 = 44100;
+
+/// The name of the output device picked by the player, or None to use the
+/// system's default.
+static DEVICE: Lazy<Mutex<Option<String>>> = Lazy::new(|| Mutex::new(None));
+
+/// Set the output device audio is played on, by name (see `output_devices`),
+/// or None to use the system's default. This applies to every AudioOutput
+/// opened from now on. See `AudioOutput::reopen` for switching an open one.
+pub fn set_device(device: Option<String>) {
+    *DEVICE.lock().unwrap() = device;
+}
+
+pub fn device() -> Option<String> {
+    DEVICE.lock().unwrap().clone()
+}
+
+/// Return the names of every output device, or an empty list if they can't be
+/// listed.
+pub fn output_devices() -> Vec<String> {
+    match rodio::cpal::default_host().output_devices() {
+        Ok(devices) => devices.filter_map(|device| device.name().ok()).collect(),
+        Err(err) => {
+            log::warn!("Couldn't list the audio devices: {}", err);
+            vec![]
+        }
+    }
+}
+
+/// Whatever is playing the audio.
+enum Backend {
+    /// kira, on the system's default device.
+    Kira(KiraBackend),
+    /// rodio, on a device picked by the player.
+    Rodio(OutputStream, OutputStreamHandle),
+}
+
+/// The kira audio device, along with every sound which has been added to it.
+struct KiraBackend {
+    manager: AudioManager,
+    music: Option<SoundHandle>,
+    sounds: HashMap<PathBuf, SoundHandle>,
+    /// The metronome's click, and its accented click for the first beat of a
+    /// measure.
+    clicks: Option<[SoundHandle; 2]>,
+}
+
+/// Something played at a set time. See `AudioOutput::schedule_sound`.
+enum Cue<'a> {
+    Sound(&'a Path),
+    Click { accent: bool },
+}
+
+/// A sound which has been scheduled, but which may not have started yet.
+struct Scheduled {
+    /// When the sound should start.
+    due: Instant,
+    handle: ScheduledHandle,
+}
+
+enum ScheduledHandle {
+    Kira(SequenceInstanceHandle<()>),
+    Rodio(Sink),
+}
+
+/// The song, while it is being played. See `AudioOutput::play`.
+pub enum MusicHandle {
+    Kira(InstanceHandle),
+    Rodio(RodioMusic),
+}
+
+impl MusicHandle {
+    pub fn is_playing(&self) -> bool {
+        match self {
+            MusicHandle::Kira(handle) => matches!(handle.state(), InstanceState::Playing),
+            MusicHandle::Rodio(music) => !music.sink.is_paused() && !music.sink.empty(),
+        }
+    }
+
+    /// Returns true if the song has finished, or was stopped.
+    pub fn is_stopped(&self) -> bool {
+        match self {
+            MusicHandle::Kira(handle) => matches!(handle.state(), InstanceState::Stopped),
+            MusicHandle::Rodio(music) => music.sink.empty(),
+        }
+    }
+
+    /// How far into the song the music is, in seconds of song time.
+    pub fn position(&self) -> Seconds {
+        match self {
+            MusicHandle::Kira(handle) => Seconds(handle.position()),
+            MusicHandle::Rodio(music) => music.position(),
+        }
+    }
+
+    pub fn stop(&mut self) -> anyhow::Result<()> {
+        match self {
+            MusicHandle::Kira(handle) => handle.stop(StopInstanceSettings::new())?,
+            MusicHandle::Rodio(music) => music.sink.stop(),
+        }
+        Ok(())
+    }
+
+    pub fn pause(&mut self) -> anyhow::Result<()> {
+        match self {
+            MusicHandle::Kira(handle) => handle.pause(PauseInstanceSettings::new())?,
+            MusicHandle::Rodio(music) => music.sink.pause(),
+        }
+        Ok(())
+    }
+
+    pub fn resume(&mut self) -> anyhow::Result<()> {
+        match self {
+            MusicHandle::Kira(handle) => handle.resume(ResumeInstanceSettings::new())?,
+            MusicHandle::Rodio(music) => music.sink.play(),
+        }
+        Ok(())
+    }
+}
+
+/// The song, while it is played by rodio. rodio can't say how far into a
+/// sound it is, so the position is worked out from how many of the song's
+/// samples the device has read. Like kira's position, this stops advancing if
+/// the device stalls, which lets `Time::resync` notice.
+pub struct RodioMusic {
+    sink: Sink,
+    /// Where in the song it was started from.
+    start: Seconds,
+    /// How many samples the device has read since the song started.
+    samples: Arc<AtomicU64>,
+    /// How many samples make up a second of the song, over every channel.
+    samples_per_second: f64,
+}
+
+impl RodioMusic {
+    fn position(&self) -> Seconds {
+        let samples = self.samples.load(Ordering::Relaxed);
+        Seconds(self.start.0 + samples as f64 / self.samples_per_second)
+    }
+}
+
+/// A source which counts how many samples have been read from it. See
+/// `RodioMusic`.
+struct Counted<S> {
+    source: S,
+    samples: Arc<AtomicU64>,
+}
+
+impl<S> Iterator for Counted<S>
+where
+    S: Source,
+    S::Item: Sample,
+{
+    type Item = S::Item;
+
+    fn next(&mut self) -> Option<S::Item> {
+        let sample = self.source.next()?;
+        self.samples.fetch_add(1, Ordering::Relaxed);
+        Some(sample)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.source.size_hint()
+    }
+}
+
+impl<S> Source for Counted<S>
+where
+    S: Source,
+    S::Item: Sample,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        self.source.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.source.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.source.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.source.total_duration()
+    }
+}
+
+pub struct AudioOutput {
+    /// The audio device, if it could be opened.
+    backend: Option<Backend>,
+    /// The song, as an mp3 file.
+    music_file: Option<Vec<u8>>,
+    /// The chart's sounds, keyed by their path in the level's folder. These
+    /// are kept for the same reason as the song's file.
+    sound_files: HashMap<PathBuf, Vec<u8>>,
+    /// Every sound scheduled on the device which may still be waiting to
+    /// start, so that they can be cancelled.
+    scheduled: Vec<Scheduled>,
+    /// How much to multiply the music's amplitude by. See `analyze`.
+    gain: f64,
+    last_attempt: Instant,
+}
+
+impl AudioOutput {
+    /// Open the audio device and add the song to it. If the device can't be
+    /// opened, the output is silent until `try_reinit` succeeds. `gain` is the
+    /// amount to multiply the music's amplitude by.
+    pub fn new(music_file: Option<Vec<u8>>, gain: f64) -> AudioOutput {
+        let mut output = AudioOutput {
+            backend: None,
+            music_file,
+            sound_files: HashMap::new(),
+            scheduled: vec![],
+            gain,
+            last_attempt: Instant::now(),
+        };
+        output.init();
+        output
+    }
+
+    /// Returns true if the audio device is open.
+    pub fn available(&self) -> bool {
+        self.backend.is_some()
+    }
+
+    /// Returns true if there is a song to play.
+    pub fn has_music(&self) -> bool {
+        self.music_file.is_some()
+    }
+
+    /// Try to open the audio device again, if it isn't open and enough time
+    /// has passed since the last attempt. Returns true if the device was just
+    /// opened, in which case any music which should be playing needs to be
+    /// started again.
+    pub fn try_reinit(&mut self) -> bool {
+        if self.available() || self.last_attempt.elapsed() < REINIT_INTERVAL {
+            return false;
+        }
+        self.last_attempt = Instant::now();
+        self.init();
+        self.available()
+    }
+
+    /// Close the audio device and open the one set by `set_device`, adding the
+    /// song to it again. Any music playing on the old device is stopped, and
+    /// any sounds scheduled on it are cancelled.
+    pub fn reopen(&mut self) {
+        self.cancel_scheduled();
+        self.backend = None;
+        self.last_attempt = Instant::now();
+        self.init();
+    }
+
+    /// Start playing the song from `start`, `rate` times as fast as normal (see
+    /// `SongSpeed`). Returns None if there is no song or the audio device
+    /// isn't open.
+    pub fn play(&mut self, start: Seconds, rate: f64) -> Option<MusicHandle> {
+        let volume = MUSIC_VOLUME * self.gain;
+        let handle: anyhow::Result<MusicHandle> = match (self.backend.as_mut()?, &self.music_file) {
+            (
+                Backend::Kira(KiraBackend {
+                    music: Some(music), ..
+                }),
+                _,
+            ) => {
+                try {
+                    let settings = InstanceSettings::new()
+                        .volume(volume)
+                        .playback_rate(rate)
+                        .start_position(start.0);
+                    MusicHandle::Kira(music.play(settings)?)
+                }
+            }
+            (Backend::Rodio(_, stream), Some(file)) => {
+                try {
+                    let source = Decoder::new(Cursor::new(file.clone()))?
+                        .skip_duration(Duration::from_secs_f64(start.0.max(0.0)));
+                    let samples_per_second =
+                        f64::from(source.channels()) * f64::from(source.sample_rate());
+                    // The samples are counted before the speed is changed, so
+                    // that they count seconds of the song rather than of real
+                    // time.
+                    let samples = Arc::new(AtomicU64::new(0));
+                    let source = Counted {
+                        source,
+                        samples: samples.clone(),
+                    };
+                    let sink = Sink::try_new(stream)?;
+                    sink.set_volume(volume as f32);
+                    sink.append(source.speed(rate as f32));
+                    MusicHandle::Rodio(RodioMusic {
+                        sink,
+                        start,
+                        samples,
+                        samples_per_second,
+                    })
+                }
+            }
+            _ => return None,
+        };
+        match handle {
+            Ok(handle) => Some(handle),
+            Err(err) => {
+                log::error!("Error starting music: {}", err);
+                None
+            }
+        }
+    }
+
+    /// Add a sound from the chart, so that it can be scheduled with
+    /// `schedule_sound`. `path` is the sound's path in the level's folder.
+    pub fn add_sound(&mut self, path: PathBuf, file: Vec<u8>) {
+        if let Some(Backend::Kira(kira)) = &mut self.backend {
+            kira.add_sound(&path, &file);
+        }
+        self.sound_files.insert(path, file);
+    }
+
+    /// Play the sound added as `path` after `delay`, `volume` times as loud as
+    /// normal.
+    pub fn schedule_sound(&mut self, path: &Path, delay: Seconds, volume: f64) {
+        self.schedule(Cue::Sound(path), delay, SOUND_VOLUME * volume);
+    }
+
+    /// Play a metronome click after `delay`. Accented clicks are higher, for
+    /// the first beat of each measure.
+    pub fn schedule_click(&mut self, delay: Seconds, accent: bool) {
+        self.schedule(Cue::Click { accent }, delay, CLICK_VOLUME);
+    }
+
+    /// Cancel every scheduled sound which hasn't started yet.
+    pub fn cancel_scheduled(&mut self) {
+        for scheduled in self.scheduled.drain(..) {
+            match scheduled.handle {
+                ScheduledHandle::Kira(mut handle) => {
+                    if let Err(err) = handle.stop() {
+                        log::error!("Error cancelling sound: {}", err);
+                    }
+                }
+                ScheduledHandle::Rodio(sink) => sink.stop(),
+            }
+        }
+    }
+
+    fn schedule(&mut self, cue: Cue, delay: Seconds, volume: f64) {
+        // Sounds which have already started can't be cancelled any more, so
+        // there is no need to keep track of them. Dropping a rodio Sink stops
+        // it, so those are detached instead.
+        let now = Instant::now();
+        for scheduled in self
+            .scheduled
+            .drain_filter(|scheduled| scheduled.due <= now)
+        {
+            if let ScheduledHandle::Rodio(sink) = scheduled.handle {
+                sink.detach();
+            }
+        }
+
+        let wait = Duration::from_secs_f64(delay.0.max(0.0));
+        let handle: anyhow::Result<ScheduledHandle> = match self.backend.as_mut() {
+            None => return,
+            Some(Backend::Kira(kira)) => {
+                try {
+                    let sound = match cue {
+                        Cue::Sound(path) => kira.sounds.get(path),
+                        Cue::Click { accent } => {
+                            kira.clicks.as_ref().map(|clicks| &clicks[accent as usize])
+                        }
+                    };
+                    let sound = match sound {
+                        Some(sound) => sound.id(),
+                        None => return,
+                    };
+                    let mut sequence = Sequence::<()>::new(SequenceSettings::default());
+                    sequence.wait(kira::Duration::Seconds(wait.as_secs_f64()));
+                    sequence.play(sound, InstanceSettings::new().volume(volume));
+                    let handle = kira
+                        .manager
+                        .start_sequence(sequence, SequenceInstanceSettings::default())?;
+                    ScheduledHandle::Kira(handle)
+                }
+            }
+            Some(Backend::Rodio(_, stream)) => {
+                try {
+                    let sink = Sink::try_new(stream)?;
+                    sink.set_volume(volume as f32);
+                    match cue {
+                        Cue::Sound(path) => match self.sound_files.get(path) {
+                            Some(file) => {
+                                sink.append(Decoder::new(Cursor::new(file.clone()))?.delay(wait))
+                            }
+                            None => return,
+                        },
+                        Cue::Click { accent } => sink.append(
+                            SamplesBuffer::new(1, CLICK_SAMPLE_RATE, click_samples(accent))
+                                .delay(wait),
+                        ),
+                    }
+                    ScheduledHandle::Rodio(sink)
+                }
+            }
+        };
+        match handle {
+            Ok(handle) => self.scheduled.push(Scheduled {
+                due: now + wait,
+                handle,
+            }),
+            Err(err) => log::error!("Error scheduling sound: {}", err),
+        }
+    }
+
+    fn init(&mut self) {
+        self.backend = match device() {
+            Some(name) => match open_rodio(&name) {
+                Ok(backend) => Some(backend),
+                Err(err) => {
+                    log::warn!(
+                        "Couldn't open audio device {:?}, using the default: {}",
+                        name,
+                        err
+                    );
+                    self.open_kira()
+                }
+            },
+            None => self.open_kira(),
+        };
+    }
+
+    fn open_kira(&self) -> Option<Backend> {
+        let manager = match AudioManager::new(AudioManagerSettings::default()) {
+            Ok(manager) => manager,
+            Err(err) => {
+                log::warn!("Couldn't open the audio device, running silently: {}", err);
+                return None;
+            }
+        };
+        let mut kira = KiraBackend {
+            manager,
+            music: None,
+            sounds: HashMap::new(),
+            clicks: None,
+        };
+        if let Some(file) = &self.music_file {
+            let music: anyhow::Result<SoundHandle> = try {
+                let sound = Sound::from_mp3_reader(file.as_slice(), SoundSettings::default())?;
+                kira.manager.add_sound(sound)?
+            };
+            match music {
+                Ok(music) => kira.music = Some(music),
+                Err(err) => log::warn!("Couldn't load music: {}", err),
+            }
+        }
+        for (path, file) in &self.sound_files {
+            kira.add_sound(path, file);
+        }
+        let clicks: anyhow::Result<[SoundHandle; 2]> = try {
+            let mut click = |accent| {
+                let frames = click_samples(accent)
+                    .into_iter()
+                    .map(Frame::from_mono)
+                    .collect();
+                let sound = Sound::from_frames(CLICK_SAMPLE_RATE, frames, SoundSettings::default());
+                kira.manager.add_sound(sound)
+            };
+            [click(false)?, click(true)?]
+        };
+        match clicks {
+            Ok(clicks) => kira.clicks = Some(clicks),
+            Err(err) => log::warn!("Couldn't add the metronome's clicks: {}", err),
+        }
+        Some(Backend::Kira(kira))
+    }
+}
+
+impl KiraBackend {
+    fn add_sound(&mut self, path: &Path, file: &[u8]) {
+        let sound: anyhow::Result<SoundHandle> = try {
+            let file = Cursor::new(file);
+            let settings = SoundSettings::default();
+            let sound = match path.extension().and_then(OsStr::to_str) {
+                Some("ogg") => Sound::from_ogg_reader(file, settings)?,
+                Some("flac") => Sound::from_flac_reader(file, settings)?,
+                Some("wav") => Sound::from_wav_reader(file, settings)?,
+                _ => Sound::from_mp3_reader(file, settings)?,
+            };
+            self.manager.add_sound(sound)?
+        };
+        match sound {
+            Ok(sound) => {
+                self.sounds.insert(path.to_path_buf(), sound);
+            }
+            Err(err) => log::warn!("Couldn't load sound {:?}: {}", path, err),
+        }
+    }
+}
+
+/// Generate a metronome click: a short, quickly fading beep.
+fn click_samples(accent: bool) -> Vec<f32> {
+    let pitch = if accent { 1500.0 } else { 1000.0 };
+    let length = CLICK_SAMPLE_RATE as usize / 20;
+    (0..length)
+        .map(|i| {
+            let t = i as f32 / CLICK_SAMPLE_RATE as f32;
+            (t * pitch * std::f32::consts::TAU).sin() * (-t * 80.0).exp()
+        })
+        .collect()
+}
+
+/// Open the output device with the given name with rodio.
+fn open_rodio(name: &str) -> anyhow::Result<Backend> {
+    let device = rodio::cpal::default_host()
+        .output_devices()?
+        .find(|device| {
+            device
+                .name()
+                .map_or(false, |device_name| device_name == name)
+        })
+        .ok_or_else(|| anyhow::anyhow!("No output device is named {:?}", name))?;
+    let (stream, handle) = OutputStream::try_from_device(&device)?;
+    Ok(Backend::Rodio(stream, handle))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_clicks_fade_out() {
+        for &accent in &[false, true] {
+            let samples = click_samples(accent);
+            assert!(samples.iter().all(|sample| sample.abs() <= 1.0));
+            assert!(samples.last().unwrap().abs() < 0.05);
+        }
+    }
+
+    #[test]
+    pub fn test_counted_counts_samples_read() {
+        let samples = Arc::new(AtomicU64::new(0));
+        let source = Counted {
+            source: SamplesBuffer::new(2, CLICK_SAMPLE_RATE, vec![0.0f32; 100]),
+            samples: samples.clone(),
+        };
+        assert_eq!(source.take(30).count(), 30);
+        assert_eq!(samples.load(Ordering::Relaxed), 30);
+    }
+}
@@ -0,0 +1,92 @@
+/// This module implements the autoplay bot, which plays a chart on its own. It
+/// is used for demos of levels the player has no replay of. The bot only looks
+/// a moment ahead: it steers away from every hitbox which is (or is about to
+/// be) close to the player, along the hitbox's sdf gradient, and otherwise
+/// drifts back towards the middle of the arena. It never focuses or uses
+/// bombs, so it will be hit by dense or fast patterns, which is fine for a
+/// demo.
+use crate::keyboard::InputState;
+use crate::time::Beats;
+use crate::world::WorldLen;
+use crate::{GroupEnemy, InnerWorldState};
+
+/// Hitboxes further than this from the edge of the player are ignored.
+const DANGER_DISTANCE: WorldLen = WorldLen(8.0);
+/// How far ahead the bot looks for hitboxes, so that it starts dodging enemies
+/// before they reach (or become Active next to) the player.
+const LOOKAHEAD: Beats = Beats(0.5);
+/// How strongly the bot is pulled back towards the middle of the arena, per
+/// WorldLen unit away from it.
+const CENTER_PULL: f64 = 0.01;
+/// The bot stands still unless it is pushed at least this hard, so that it
+/// doesn't jitter around the middle of the arena.
+const MIN_PUSH: f64 = 0.05;
+/// The bot only moves along an axis if the direction it wants to go in leans
+/// at least this far along it. This is sin(22.5 degrees), which splits the
+/// directions evenly between the eight the keyboard can hold.
+const AXIS_THRESHOLD: f64 = 0.383;
+
+/// Return the input the bot holds for the current update.
+pub fn input(world: &InnerWorldState, curr_time: Beats) -> InputState {
+    let player = &world.player;
+    let reach = WorldLen(player.size.0 + DANGER_DISTANCE.0);
+    let mut push = (-player.pos.x * CENTER_PULL, -player.pos.y * CENTER_PULL);
+    for &time in &[curr_time, curr_time + LOOKAHEAD] {
+        for group in world.groups.iter().filter(|group| group.use_hitbox) {
+            let rotated_about = group.rotation_ease(time);
+            for GroupEnemy { enemy, .. } in &group.enemies {
+                if !enemy.could_touch(player.pos, reach, time, rotated_about) {
+                    continue;
+                }
+                let sdf = enemy.sdf(player.pos, time, rotated_about);
+                let gradient = enemy.sdf_gradient(player.pos, time, rotated_about);
+                let (sdf, gradient) = match (sdf, gradient) {
+                    (Some(sdf), Some(gradient)) => (sdf, gradient),
+                    _ => continue,
+                };
+                // Closer hitboxes push harder.
+                let clearance = (sdf.0 - player.size.0).max(0.0);
+                let weight = 1.0 - clearance / DANGER_DISTANCE.0;
+                if weight > 0.0 {
+                    push.0 += gradient.0 * weight;
+                    push.1 += gradient.1 * weight;
+                }
+            }
+        }
+    }
+
+    let length = push.0.hypot(push.1);
+    if length < MIN_PUSH {
+        return InputState::default();
+    }
+    // Input is given relative to the screen. See `Player::update`.
+    let (x, y) = world
+        .view_transform()
+        .apply(push.0 / length, push.1 / length);
+    InputState {
+        left: x < -AXIS_THRESHOLD,
+        right: x > AXIS_THRESHOLD,
+        up: y > AXIS_THRESHOLD,
+        down: y < -AXIS_THRESHOLD,
+        focus: false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::player::Player;
+    use crate::world::WorldPos;
+
+    #[test]
+    pub fn test_bot_heads_back_to_the_middle() {
+        let mut world = InnerWorldState::new(Player::default());
+        world.player.pos = WorldPos { x: 20.0, y: 0.0 };
+        let held = input(&world, Beats(0.0));
+        assert!(held.left && !held.right && !held.up && !held.down);
+
+        world.player.pos = WorldPos::origin();
+        assert_eq!(input(&world, Beats(0.0)), InputState::default());
+    }
+}
@@ -0,0 +1,161 @@
+/// This module handles downloading community levels from a level index, which
+/// is a JSON file listing levels that can be downloaded. The index looks like
+/// `{ "levels": [ { "name": ..., "author": ..., "bpm": ..., "description": ...,
+/// "url": ... } ] }`, where `url` points at a level archive (see `vfs`).
+/// Downloaded archives are saved straight into the levels folder, since
+/// archives can be played without unpacking them. Each listing is saved under a
+/// file name made from its name, which is the same every time the index is
+/// read, so downloading a level again updates it.
+/// Downloading requires the `level_browser` cargo feature. Without it, every
+/// download fails with an error explaining why.
+use std::collections::HashSet;
+use std::path::PathBuf;
+
+use serde::Deserialize;
+
+use crate::task::Task;
+use crate::vfs::{self, ARCHIVE_EXTENSION};
+
+/// The largest file that will be downloaded, in bytes. This keeps a broken
+/// index from filling up the player's disk. Archives can't be bigger than what
+/// they decompress to, so this is the same as the limit on that.
+#[cfg(feature = "level_browser")]
+const MAX_DOWNLOAD_SIZE: u64 = vfs::MAX_ARCHIVE_SIZE;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct LevelIndex {
+    pub levels: Vec<LevelListing>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct LevelListing {
+    pub name: String,
+    #[serde(default)]
+    pub author: Option<String>,
+    #[serde(default)]
+    pub bpm: Option<f64>,
+    #[serde(default)]
+    pub description: Option<String>,
+    /// Where to download the level archive from.
+    pub url: String,
+}
+
+impl LevelListing {
+    /// The file name (minus the extension) the level is saved as, unless an
+    /// earlier listing has the same one. See `file_names`. Names come from the
+    /// internet, so anything which could be a path separator (or otherwise odd
+    /// in a file name) is replaced.
+    fn file_stem(&self) -> String {
+        let name: String = self
+            .name
+            .chars()
+            .map(|c| {
+                if c.is_alphanumeric() || c == ' ' || c == '-' || c == '_' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        let name = name.trim();
+        let name = if name.is_empty() { "level" } else { name };
+        name.to_string()
+    }
+}
+
+/// Return the file name each of the listings is saved as. Different names may
+/// be the same once odd characters are replaced (ex: "Song?" and "Song!"), so
+/// later listings with the same file name as an earlier one are numbered to
+/// keep them from overwriting each other. Names are compared ignoring case,
+/// since some filesystems do.
+pub fn file_names(listings: &[LevelListing]) -> Vec<String> {
+    let mut used = HashSet::new();
+    listings
+        .iter()
+        .map(|listing| {
+            let name = listing.file_stem();
+            let mut file_name = format!("{}.{}", name, ARCHIVE_EXTENSION);
+            let mut number = 2;
+            while !used.insert(file_name.to_lowercase()) {
+                file_name = format!("{} ({}).{}", name, number, ARCHIVE_EXTENSION);
+                number += 1;
+            }
+            file_name
+        })
+        .collect()
+}
+
+/// Fetch the level index at `url`.
+pub fn fetch_index(url: String) -> Task<LevelIndex> {
+    Task::spawn(move || Ok(serde_json::from_slice(&http_get(&url)?)?))
+}
+
+/// Download the level into `levels_folder` as `file_name` (see `file_names`),
+/// returning the path of the saved archive. Files which aren't valid level
+/// archives are not saved.
+pub fn download(
+    listing: &LevelListing,
+    file_name: String,
+    levels_folder: PathBuf,
+) -> Task<PathBuf> {
+    let url = listing.url.clone();
+    Task::spawn(move || {
+        let bytes = http_get(&url)?;
+        vfs::check_archive(&bytes)?;
+        let path = levels_folder.join(file_name);
+        std::fs::write(&path, bytes)?;
+        Ok(path)
+    })
+}
+
+#[cfg(feature = "level_browser")]
+fn http_get(url: &str) -> anyhow::Result<Vec<u8>> {
+    use std::io::Read;
+
+    let mut bytes = vec![];
+    ureq::get(url)
+        .call()?
+        .into_reader()
+        .take(MAX_DOWNLOAD_SIZE + 1)
+        .read_to_end(&mut bytes)?;
+    if bytes.len() as u64 > MAX_DOWNLOAD_SIZE {
+        anyhow::bail!("{} is larger than {} bytes", url, MAX_DOWNLOAD_SIZE);
+    }
+    Ok(bytes)
+}
+
+#[cfg(not(feature = "level_browser"))]
+fn http_get(_url: &str) -> anyhow::Result<Vec<u8>> {
+    Err(anyhow::anyhow!(
+        "this build can't download levels (it was built without the level_browser feature)"
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn listing(name: &str) -> LevelListing {
+        LevelListing {
+            name: name.to_string(),
+            author: None,
+            bpm: None,
+            description: None,
+            url: String::new(),
+        }
+    }
+
+    #[test]
+    pub fn test_colliding_file_names_are_numbered() {
+        let listings = [
+            listing("Song?"),
+            listing("Song!"),
+            listing("song_"),
+            listing("Other"),
+        ];
+        assert_eq!(
+            file_names(&listings),
+            ["Song_.zip", "Song_ (2).zip", "song_ (3).zip", "Other.zip"]
+        );
+    }
+}
@@ -0,0 +1,277 @@
+/// This module implements the frame-time budget guard. Every frame's time is
+/// recorded, and if frames are consistently taking longer than FRAME_BUDGET,
+/// visuals are degraded one step at a time (first glow layers are skipped,
+/// then curves are tessellated more coarsely) so that the game slows down
+/// visually before gameplay starts to stutter. Once frames are comfortably
+/// under budget again, the degradations are undone in reverse order.
+/// Degrading goes from skipping glow layers, to capping particles lower, to
+/// tessellating curves more coarsely.
+/// The current degradation is global, since it needs to be checked deep inside
+/// mesh building code, which has no access to the game's state. The player's
+/// graphics preset sets how degraded visuals are to begin with, along with the
+/// amount of multisampling, for machines which can never keep up.
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::time::Duration;
+
+use ggez::conf::NumSamples;
+use serde::{Deserialize, Serialize};
+
+use crate::ease::Lerp;
+
+/// How long a frame may take before visuals start being degraded.
+pub const FRAME_BUDGET: Duration = Duration::from_millis(16);
+/// How much each frame affects the average frame time. Lower values make the
+/// average steadier, so that a single slow frame (ex: from loading a chart)
+/// doesn't degrade visuals.
+const SMOOTHING: f64 = 0.1;
+/// The fraction of the budget the average frame time must drop under before a
+/// degradation is undone. This is lower than the budget so that the quality
+/// doesn't flicker between two levels.
+const RECOVER_FRACTION: f64 = 0.6;
+/// How many frames to wait after changing the degradation before changing it
+/// again, which gives the average frame time a chance to settle.
+const FRAMES_BETWEEN_CHANGES: u32 = 30;
+/// How much coarser curves are tessellated when meshes are degraded.
+const COARSE_TOLERANCE_FACTOR: f32 = 4.0;
+/// The most particles there may be at once. See `particle`.
+const MAX_PARTICLES: usize = 2000;
+/// The most particles there may be at once, once particles are degraded.
+const FEWER_PARTICLES: usize = 300;
+
+static DEGRADATION: AtomicU8 = AtomicU8::new(0);
+
+/// How far visuals have been degraded. Each level includes the degradations of
+/// the levels before it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Degradation {
+    None,
+    /// Glowing lasers are drawn flat.
+    NoGlow,
+    /// Fewer particles may exist at once.
+    FewerParticles,
+    /// Curves are tessellated with fewer segments.
+    CoarseMeshes,
+}
+
+impl Degradation {
+    /// Returns true if glow layers should not be drawn.
+    pub fn skip_glow(self) -> bool {
+        self >= Degradation::NoGlow
+    }
+
+    /// The most particles there may be at once.
+    pub fn max_particles(self) -> usize {
+        if self >= Degradation::FewerParticles {
+            FEWER_PARTICLES
+        } else {
+            MAX_PARTICLES
+        }
+    }
+
+    /// How much to multiply tessellation tolerances by.
+    pub fn tolerance_factor(self) -> f32 {
+        if self >= Degradation::CoarseMeshes {
+            COARSE_TOLERANCE_FACTOR
+        } else {
+            1.0
+        }
+    }
+
+    /// A short description of the degradation, for the debug overlay.
+    pub fn description(self) -> &'static str {
+        match self {
+            Degradation::None => "none",
+            Degradation::NoGlow => "no glow",
+            Degradation::FewerParticles => "no glow, fewer particles",
+            Degradation::CoarseMeshes => "no glow, fewer particles, coarse meshes",
+        }
+    }
+
+    fn degrade(self) -> Degradation {
+        match self {
+            Degradation::None => Degradation::NoGlow,
+            Degradation::NoGlow => Degradation::FewerParticles,
+            Degradation::FewerParticles | Degradation::CoarseMeshes => Degradation::CoarseMeshes,
+        }
+    }
+
+    fn restore(self) -> Degradation {
+        match self {
+            Degradation::None | Degradation::NoGlow => Degradation::None,
+            Degradation::FewerParticles => Degradation::NoGlow,
+            Degradation::CoarseMeshes => Degradation::FewerParticles,
+        }
+    }
+
+    fn from_u8(value: u8) -> Degradation {
+        match value {
+            0 => Degradation::None,
+            1 => Degradation::NoGlow,
+            2 => Degradation::FewerParticles,
+            _ => Degradation::CoarseMeshes,
+        }
+    }
+}
+
+/// How good the game looks, picked by the player in the options.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GraphicsPreset {
+    /// No multisampling, no glow, fewer particles and coarse meshes.
+    Low,
+    /// Some multisampling, no glow and fewer particles.
+    Medium,
+    High,
+}
+
+impl GraphicsPreset {
+    pub const ALL: [GraphicsPreset; 3] = [
+        GraphicsPreset::Low,
+        GraphicsPreset::Medium,
+        GraphicsPreset::High,
+    ];
+
+    /// The key of the preset's name in the lang files.
+    pub fn key(self) -> &'static str {
+        match self {
+            GraphicsPreset::Low => "graphics.low",
+            GraphicsPreset::Medium => "graphics.medium",
+            GraphicsPreset::High => "graphics.high",
+        }
+    }
+
+    /// How many samples to multisample with. This can only be set when the
+    /// window is created, so changing it takes effect once the game restarts.
+    pub fn samples(self) -> NumSamples {
+        match self {
+            GraphicsPreset::Low => NumSamples::One,
+            GraphicsPreset::Medium => NumSamples::Four,
+            GraphicsPreset::High => NumSamples::Eight,
+        }
+    }
+
+    /// The least degraded visuals may be. The frame budget may still degrade
+    /// visuals further than this.
+    pub fn min_degradation(self) -> Degradation {
+        match self {
+            GraphicsPreset::Low => Degradation::CoarseMeshes,
+            GraphicsPreset::Medium => Degradation::FewerParticles,
+            GraphicsPreset::High => Degradation::None,
+        }
+    }
+
+    /// Return the next better preset, wrapping around to the worst.
+    pub fn next(self) -> GraphicsPreset {
+        let i = GraphicsPreset::ALL
+            .iter()
+            .position(|&preset| preset == self)
+            .unwrap();
+        GraphicsPreset::ALL[(i + 1) % GraphicsPreset::ALL.len()]
+    }
+}
+
+impl Default for GraphicsPreset {
+    fn default() -> Self {
+        GraphicsPreset::High
+    }
+}
+
+/// Return the degradation currently applied to visuals.
+pub fn degradation() -> Degradation {
+    Degradation::from_u8(DEGRADATION.load(Ordering::Relaxed))
+}
+
+/// Set the degradation applied to visuals.
+pub fn set_degradation(degradation: Degradation) {
+    DEGRADATION.store(degradation as u8, Ordering::Relaxed);
+}
+
+/// Tracks how long frames are taking and decides how far to degrade visuals.
+#[derive(Debug, Clone)]
+pub struct FrameBudget {
+    /// The smoothed frame time, in seconds.
+    average: f64,
+    degradation: Degradation,
+    frames_since_change: u32,
+}
+
+impl Default for FrameBudget {
+    fn default() -> Self {
+        FrameBudget {
+            average: 0.0,
+            degradation: Degradation::None,
+            frames_since_change: 0,
+        }
+    }
+}
+
+impl FrameBudget {
+    /// Record how long the most recent frame took, degrading or restoring
+    /// visuals if needed. This doesn't change the global degradation, use
+    /// `set_degradation` with `FrameBudget::degradation` to apply it.
+    pub fn record(&mut self, frame_time: Duration) {
+        self.average = f64::lerp_unclamped(self.average, frame_time.as_secs_f64(), SMOOTHING);
+        self.frames_since_change += 1;
+        if self.frames_since_change < FRAMES_BETWEEN_CHANGES {
+            return;
+        }
+
+        let budget = FRAME_BUDGET.as_secs_f64();
+        let next = if self.average > budget {
+            self.degradation.degrade()
+        } else if self.average < budget * RECOVER_FRACTION {
+            self.degradation.restore()
+        } else {
+            self.degradation
+        };
+        if next != self.degradation {
+            log::info!(
+                "Average frame time {:.2?}, degradation is now {:?}",
+                self.average_frame_time(),
+                next
+            );
+            self.degradation = next;
+            self.frames_since_change = 0;
+        }
+    }
+
+    pub fn degradation(&self) -> Degradation {
+        self.degradation
+    }
+
+    pub fn average_frame_time(&self) -> Duration {
+        Duration::from_secs_f64(self.average)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_degrades_and_recovers() {
+        let mut budget = FrameBudget::default();
+        for _ in 0..FRAMES_BETWEEN_CHANGES * 4 {
+            budget.record(Duration::from_millis(30));
+        }
+        assert_eq!(budget.degradation(), Degradation::CoarseMeshes);
+
+        // Frames which are only just under budget aren't enough to recover.
+        for _ in 0..FRAMES_BETWEEN_CHANGES * 4 {
+            budget.record(Duration::from_millis(15));
+        }
+        assert_eq!(budget.degradation(), Degradation::CoarseMeshes);
+
+        for _ in 0..FRAMES_BETWEEN_CHANGES * 4 {
+            budget.record(Duration::from_millis(2));
+        }
+        assert_eq!(budget.degradation(), Degradation::None);
+    }
+
+    #[test]
+    pub fn test_only_high_preset_allows_every_particle() {
+        for &preset in &GraphicsPreset::ALL {
+            let all_particles = preset.min_degradation().max_particles() == MAX_PARTICLES;
+            assert_eq!(all_particles, preset == GraphicsPreset::High);
+        }
+    }
+}
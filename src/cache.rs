@@ -0,0 +1,399 @@
+/// This module handles caching of compiled charts. Running a chart's Lua (and
+/// parsing all of the MIDI files it reads) can be slow for big charts, so the
+/// resulting SongMap is saved to disk alongside the chart. The cache records
+/// the hash of `main.lua` and of every file read while running it, and is
+/// automatically thrown out if any of those files change.
+/// Note that this means charts which use randomness (ex: `math.random`) will
+/// produce the same chart every time until the chart is modified.
+/// Level archives can't hold their own cache, so their cache is saved next to
+/// the archive instead.
+///
+/// The gain which normalizes the loudness of a level's music is also cached,
+/// since measuring it means decoding the whole song. It is measured in the
+/// background when the level select reads the levels (see
+/// `measure_music_gain`), so loading a level only reads the cached gain.
+///
+/// Things recorded on a level (such as replays and scores) are tied to the
+/// level's `chart_hash`, which covers what the chart actually produces along
+/// with its music, rather than the source of the chart. The hash is taken of a
+/// canonical encoding of the chart (see `song_map_hash`) rather than of the
+/// cache, so that changing the cache format doesn't orphan every replay.
+///
+/// Charts may be compiled on several threads at once (ex: the level select
+/// reads titles and the leaderboard hashes charts in the background), so
+/// compiling a chart and writing its cache is done under `COMPILE_LOCK`.
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
+
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::analyze;
+use crate::parse::{self, SongMap};
+use crate::util;
+use crate::vfs::{self, Vfs};
+
+pub const CHART_FILE: &str = "main.lua";
+const CACHE_FILE: &str = "main.lua.cache";
+/// The extension added to an archive's file name for its cache.
+const ARCHIVE_CACHE_EXTENSION: &str = "cache";
+const GAIN_CACHE_FILE: &str = "music.gain";
+const ARCHIVE_GAIN_EXTENSION: &str = "gain";
+
+/// The version of the cache format. This should be bumped whenever SongMap or
+/// any of the types it contains change, so that old caches are not misread.
+const CACHE_VERSION: u32 = 41;
+
+/// The version of the encoding hashed by `song_map_hash`. Unlike
+/// CACHE_VERSION, this should only be bumped when the encoding itself changes,
+/// since doing so changes the hash of every chart. Replays recorded under an
+/// older hash are moved over to the current one if they are still for the same
+/// chart (see `legacy_chart_hashes`).
+pub const CHART_HASH_VERSION: u32 = 1;
+
+/// Held while a chart is compiled and its cache is written, so that a chart
+/// being loaded on several threads at once is only compiled by one of them,
+/// and the others use the cache it writes.
+static COMPILE_LOCK: Lazy<Mutex<()>> = Lazy::new(|| Mutex::new(()));
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CompiledChart {
+    version: u32,
+    // The hash of the chart's main.lua
+    chart_hash: u64,
+    // The files read by the chart while it ran, along with their hashes.
+    dependencies: Vec<(PathBuf, u64)>,
+    song_map: SongMap,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CachedGain {
+    // The hash of the music file the gain was measured for.
+    music_hash: u64,
+    gain_db: f64,
+}
+
+impl CompiledChart {
+    /// Returns true if the cached chart has the same hashes as the files on
+    /// disk currently do.
+    fn is_fresh(&self, vfs: &Vfs, chart_hash: u64) -> bool {
+        self.version == CACHE_VERSION
+            && self.chart_hash == chart_hash
+            && self
+                .dependencies
+                .iter()
+                .all(|(path, hash)| hash_file(vfs, path).ok() == Some(*hash))
+    }
+}
+
+/// Load the chart in `base_folder`, using the cached compiled chart if it is
+/// still fresh. Otherwise, the chart's Lua is run and the result is cached.
+/// `base_folder` may be either a level folder or a level archive.
+pub fn load_song_map(base_folder: impl AsRef<Path>) -> anyhow::Result<SongMap> {
+    let vfs = Vfs::open(base_folder)?;
+    let base_folder = vfs.root();
+    let source = vfs.read(base_folder.join(CHART_FILE))?;
+    let chart_hash = util::hash_bytes(&source);
+
+    // The lock only orders compiles, so a thread which panicked while holding
+    // it leaves nothing to clean up.
+    let _compiling = COMPILE_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+    let cache_path = cache_path(base_folder, CACHE_FILE, ARCHIVE_CACHE_EXTENSION);
+    match read_cache(&cache_path) {
+        Ok(compiled) if compiled.is_fresh(&vfs, chart_hash) => {
+            log::info!("Using cached chart {:?}", cache_path);
+            return Ok(compiled.song_map);
+        }
+        Ok(_) => log::info!("Cached chart {:?} is stale, recompiling", cache_path),
+        Err(err) => log::info!("No usable cached chart at {:?}: {}", cache_path, err),
+    }
+
+    let (song_map, read_files) =
+        SongMap::run_lua_tracked(&vfs, &source, parse::instruction_budget())?;
+
+    let dependencies: anyhow::Result<Vec<(PathBuf, u64)>> = read_files
+        .into_iter()
+        .map(|path| {
+            let hash = hash_file(&vfs, &path)?;
+            Ok((path, hash))
+        })
+        .collect();
+
+    let compiled = CompiledChart {
+        version: CACHE_VERSION,
+        chart_hash,
+        dependencies: dependencies?,
+        song_map,
+    };
+
+    if let Err(err) = write_cache(&cache_path, &compiled) {
+        log::warn!("Couldn't write chart cache {:?}: {}", cache_path, err);
+    }
+
+    Ok(compiled.song_map)
+}
+
+/// Return the hash of the chart of the level in `level_folder`. This is used
+/// to tell whether things recorded on a level (such as replays) are for the
+/// same version of the chart. See `song_map_hash`.
+pub fn chart_hash(level_folder: impl AsRef<Path>) -> anyhow::Result<u64> {
+    let (song_map, music_file) = load_chart(&level_folder)?;
+    song_map_hash(&song_map, music_file.as_deref())
+}
+
+/// Return the hashes which older versions of the game used for the chart of
+/// the level in `level_folder`, instead of `chart_hash`. Something recorded
+/// under one of these is for the chart as it currently is. The oldest is the
+/// hash of `main.lua`, which can't tell whether the files it reads changed, so
+/// it is only as trustworthy as it was back when it was used.
+pub fn legacy_chart_hashes(level_folder: impl AsRef<Path>) -> anyhow::Result<Vec<u64>> {
+    let (song_map, music_file) = load_chart(&level_folder)?;
+    let vfs = Vfs::open(&level_folder)?;
+    let source = vfs.read(vfs.root().join(CHART_FILE))?;
+    Ok(vec![
+        util::hash_bytes(&source),
+        bincode_song_map_hash(&song_map, music_file.as_deref())?,
+    ])
+}
+
+/// Load the chart of the level in `level_folder`, along with its music file.
+fn load_chart(level_folder: impl AsRef<Path>) -> anyhow::Result<(SongMap, Option<Vec<u8>>)> {
+    let song_map = load_song_map(&level_folder)?;
+    let vfs = Vfs::open(&level_folder)?;
+    let music_file = match &song_map.music_path {
+        Some(path) => Some(vfs.read(vfs.root().join(path))?),
+        None => None,
+    };
+    Ok((song_map, music_file))
+}
+
+/// Return the hash of everything in `song_map` which affects how a run plays
+/// out, along with the hash of its music file. Unlike the hash of `main.lua`,
+/// this stays the same if a chart is edited without changing the chart it
+/// produces (ex: reformatting the Lua), and changes if a MIDI file or the
+/// music the chart uses changes.
+/// The chart is hashed in a canonical encoding (see `encode_canonical`) tagged
+/// with CHART_HASH_VERSION, so the hash doesn't depend on the layout of the
+/// types the chart is made of. Adding a field which is None for existing
+/// charts doesn't change their hash either.
+pub fn song_map_hash(song_map: &SongMap, music_file: Option<&[u8]>) -> anyhow::Result<u64> {
+    let value = serde_json::to_value(&(
+        &song_map.actions,
+        song_map.bpm,
+        song_map.skip_amount,
+        &song_map.player,
+    ))?;
+    let mut bytes = CHART_HASH_VERSION.to_le_bytes().to_vec();
+    encode_canonical(&value, &mut bytes);
+    if let Some(music_file) = music_file {
+        bytes.extend_from_slice(&util::hash_bytes(music_file).to_le_bytes());
+    }
+    Ok(util::hash_bytes(&bytes))
+}
+
+/// The hash `song_map_hash` returned before CHART_HASH_VERSION existed, which
+/// hashed the chart's bincode layout. This only matches charts whose types
+/// haven't changed since, which is all `legacy_chart_hashes` needs.
+fn bincode_song_map_hash(song_map: &SongMap, music_file: Option<&[u8]>) -> anyhow::Result<u64> {
+    let mut bytes = bincode::serialize(&(
+        &song_map.actions,
+        song_map.bpm,
+        song_map.skip_amount,
+        &song_map.player,
+    ))?;
+    if let Some(music_file) = music_file {
+        bytes.extend_from_slice(&util::hash_bytes(music_file).to_le_bytes());
+    }
+    Ok(util::hash_bytes(&bytes))
+}
+
+/// Append an encoding of `value` to `bytes` which only depends on what the
+/// value is. Every value is tagged with its kind, strings and lists are
+/// prefixed with their length, and objects are encoded with their keys in
+/// sorted order and without their null fields.
+fn encode_canonical(value: &Value, bytes: &mut Vec<u8>) {
+    match value {
+        Value::Null => bytes.push(b'n'),
+        Value::Bool(false) => bytes.push(b'f'),
+        Value::Bool(true) => bytes.push(b't'),
+        Value::Number(number) => {
+            bytes.push(b'd');
+            encode_str(&number.to_string(), bytes);
+        }
+        Value::String(string) => {
+            bytes.push(b's');
+            encode_str(string, bytes);
+        }
+        Value::Array(values) => {
+            bytes.push(b'a');
+            bytes.extend_from_slice(&(values.len() as u64).to_le_bytes());
+            for value in values {
+                encode_canonical(value, bytes);
+            }
+        }
+        Value::Object(fields) => {
+            let mut fields: Vec<(&String, &Value)> = fields
+                .iter()
+                .filter(|(_, value)| !value.is_null())
+                .collect();
+            fields.sort_by_key(|(key, _)| *key);
+            bytes.push(b'o');
+            bytes.extend_from_slice(&(fields.len() as u64).to_le_bytes());
+            for (key, value) in fields {
+                encode_str(key, bytes);
+                encode_canonical(value, bytes);
+            }
+        }
+    }
+}
+
+fn encode_str(string: &str, bytes: &mut Vec<u8>) {
+    bytes.extend_from_slice(&(string.len() as u64).to_le_bytes());
+    bytes.extend_from_slice(string.as_bytes());
+}
+
+/// Return the cached gain, in dB, which normalizes the loudness of
+/// `music_file`, the music of the level in `base_folder`. This returns None if
+/// the gain hasn't been measured since the music file last changed. Unlike
+/// `music_gain`, this never decodes the music, so it is fast enough to call
+/// while loading a level.
+pub fn cached_music_gain(base_folder: impl AsRef<Path>, music_file: &[u8]) -> Option<f64> {
+    let cache_path = cache_path(
+        base_folder.as_ref(),
+        GAIN_CACHE_FILE,
+        ARCHIVE_GAIN_EXTENSION,
+    );
+    let cached: anyhow::Result<CachedGain> =
+        try { bincode::deserialize(&std::fs::read(&cache_path)?)? };
+    match cached {
+        Ok(cached) if cached.music_hash == util::hash_bytes(music_file) => Some(cached.gain_db),
+        Ok(_) => {
+            log::info!("Cached gain {:?} is stale", cache_path);
+            None
+        }
+        Err(err) => {
+            log::info!("No usable cached gain at {:?}: {}", cache_path, err);
+            None
+        }
+    }
+}
+
+/// Return the gain, in dB, which normalizes the loudness of `music_file`, the
+/// music of the level in `base_folder`. The gain is measured if it isn't
+/// already cached, and then cached until the music file changes. Measuring
+/// decodes the whole song, so this should be done in the background.
+pub fn music_gain(base_folder: impl AsRef<Path>, music_file: &[u8]) -> anyhow::Result<f64> {
+    if let Some(gain_db) = cached_music_gain(&base_folder, music_file) {
+        return Ok(gain_db);
+    }
+
+    let cache_path = cache_path(
+        base_folder.as_ref(),
+        GAIN_CACHE_FILE,
+        ARCHIVE_GAIN_EXTENSION,
+    );
+    let music_hash = util::hash_bytes(music_file);
+    let gain_db = analyze::normalization_gain_of(music_file.to_vec())?;
+    let cached = CachedGain {
+        music_hash,
+        gain_db,
+    };
+    let written: anyhow::Result<()> =
+        try { write_atomic(&cache_path, &bincode::serialize(&cached)?)? };
+    if let Err(err) = written {
+        log::warn!("Couldn't write gain cache {:?}: {}", cache_path, err);
+    }
+    Ok(gain_db)
+}
+
+/// Measure and cache the gain for the music of the level in `base_folder`, if
+/// the chart doesn't set its own. See `music_gain`.
+pub fn measure_music_gain(base_folder: &Path, song_map: &SongMap) -> anyhow::Result<()> {
+    if let (None, Some(path)) = (song_map.music_gain_db, &song_map.music_path) {
+        let music_file = Vfs::open(base_folder)?.read(&base_folder.join(path))?;
+        music_gain(base_folder, &music_file)?;
+    }
+    Ok(())
+}
+
+/// Return where the cache file named `file_name` is saved for the level. For
+/// level archives, the cache is saved next to the archive, named after the
+/// archive with `archive_extension` added.
+fn cache_path(base_folder: &Path, file_name: &str, archive_extension: &str) -> PathBuf {
+    if vfs::is_archive(base_folder) {
+        let mut file_name = base_folder.file_name().unwrap_or_default().to_owned();
+        file_name.push(".");
+        file_name.push(archive_extension);
+        base_folder.with_file_name(file_name)
+    } else {
+        base_folder.join(file_name)
+    }
+}
+
+fn read_cache(path: &Path) -> anyhow::Result<CompiledChart> {
+    let bytes = std::fs::read(path)?;
+    Ok(bincode::deserialize(&bytes)?)
+}
+
+fn write_cache(path: &Path, compiled: &CompiledChart) -> anyhow::Result<()> {
+    let bytes = bincode::serialize(compiled)?;
+    write_atomic(path, &bytes)?;
+    Ok(())
+}
+
+/// Write `bytes` to `path`, such that anything reading `path` sees either the
+/// old file or all of the new one. Charts may be compiled on several threads at
+/// once (ex: while the level select reads titles in the background), so a
+/// cache may be written while another thread is reading it.
+fn write_atomic(path: &Path, bytes: &[u8]) -> std::io::Result<()> {
+    let mut temp_name = path.file_name().unwrap_or_default().to_owned();
+    temp_name.push(format!(
+        ".{}.{:?}.tmp",
+        std::process::id(),
+        std::thread::current().id()
+    ));
+    let temp_path = path.with_file_name(temp_name);
+    std::fs::write(&temp_path, bytes)?;
+    std::fs::rename(&temp_path, path)
+}
+
+fn hash_file(vfs: &Vfs, path: &Path) -> std::io::Result<u64> {
+    Ok(util::hash_bytes(&vfs.read(path)?))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_song_map_hash_ignores_presentation() {
+        let song_map = SongMap::default();
+        let hash = song_map_hash(&song_map, Some(b"music")).unwrap();
+
+        let retitled = SongMap {
+            title: Some("New title".to_string()),
+            ..SongMap::default()
+        };
+        assert_eq!(song_map_hash(&retitled, Some(b"music")).unwrap(), hash);
+        assert_ne!(
+            song_map_hash(&song_map, Some(b"other music")).unwrap(),
+            hash
+        );
+        assert_ne!(song_map_hash(&song_map, None).unwrap(), hash);
+    }
+
+    #[test]
+    pub fn test_canonical_encoding_ignores_field_order_and_nulls() {
+        let encode = |json: &str| {
+            let mut bytes = vec![];
+            encode_canonical(&serde_json::from_str(json).unwrap(), &mut bytes);
+            bytes
+        };
+        let expected = encode(r#"{"a": 1, "b": [true, "x"]}"#);
+        assert_eq!(encode(r#"{"b": [true, "x"], "a": 1}"#), expected);
+        assert_eq!(encode(r#"{"a": 1, "b": [true, "x"], "c": null}"#), expected);
+        assert_ne!(encode(r#"{"a": 1.5, "b": [true, "x"]}"#), expected);
+        assert_ne!(encode(r#"{"a": 1, "b": ["x", true]}"#), expected);
+    }
+}
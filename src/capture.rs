@@ -0,0 +1,82 @@
+/// This module implements pattern capture, an authoring tool which renders a
+/// range of beats of a chart to a looping GIF, for sharing patterns in chart
+/// review discussions. The range is simulated at a fixed timestep with the
+/// player standing still and drawn to an offscreen canvas, so the capture
+/// comes out the same no matter how fast the machine is. The music isn't
+/// played, since a GIF has nowhere to put it.
+/// Only GIFs are written, since nothing in the dependency tree can encode
+/// APNGs (png 0.16 only decodes them).
+use std::fs::File;
+use std::path::Path;
+
+use ggez::graphics::{self, Canvas};
+use ggez::Context;
+
+use crate::keyboard::KeyboardState;
+use crate::parse::SongMap;
+use crate::time::{self, Beats, Seconds, Time};
+use crate::WorldState;
+
+/// How long each frame of the capture is shown for, in hundredths of a second,
+/// which is the unit GIF frame delays are measured in. This is 25 frames per
+/// second.
+const FRAME_DELAY: u16 = 4;
+/// How hard the color quantizer works on each frame, from 1 (slowest, best
+/// colors) to 30 (fastest).
+const QUANTIZE_SPEED: i32 = 10;
+
+/// Render the SongMap from `start` to `end` into a looping GIF at `out_path`.
+/// `base_folder` is the folder (or level archive) the SongMap was loaded from.
+/// Like the pattern preview, the chart is simulated from the beginning up to
+/// `start` first. Returns the number of frames written.
+pub fn capture_gif<P: AsRef<Path>, Q: AsRef<Path>>(
+    ctx: &mut Context,
+    base_folder: P,
+    map: &SongMap,
+    start: Beats,
+    end: Beats,
+    out_path: Q,
+) -> anyhow::Result<usize> {
+    anyhow::ensure!(
+        start < end,
+        "The capture must end after it starts (got {} to {})",
+        start.0,
+        end.0
+    );
+
+    // Without a music path, the world doesn't read or play the music.
+    let mut map = map.clone();
+    map.music_path = None;
+    let mut world = WorldState::new(base_folder, &map);
+    let mut time = Time::new(map.bpm, Seconds(0.0));
+    world.reset_to(&map, &mut time, start);
+
+    let canvas = Canvas::with_window_size(ctx)?;
+    let (width, height) = (canvas.image().width(), canvas.image().height());
+    let mut encoder = gif::Encoder::new(File::create(out_path)?, width, height, &[])?;
+    encoder.set_repeat(gif::Repeat::Infinite)?;
+
+    let keyboard = KeyboardState::default();
+    let frame_time = Seconds(FRAME_DELAY as f64 / 100.0);
+    let frame_beats = time::to_beats(frame_time, map.bpm);
+    let mut frames = 0;
+    let mut curr_time = start;
+    while curr_time < end {
+        world.update(ctx, &keyboard, frame_time.0, curr_time)?;
+
+        graphics::set_canvas(ctx, Some(&canvas));
+        let drawn = world.draw(ctx, curr_time);
+        // Always switch back to the screen, even if drawing failed.
+        graphics::set_canvas(ctx, None);
+        drawn?;
+
+        let mut pixels = canvas.image().to_rgba8(ctx)?;
+        let mut frame = gif::Frame::from_rgba_speed(width, height, &mut pixels, QUANTIZE_SPEED);
+        frame.delay = FRAME_DELAY;
+        encoder.write_frame(&frame)?;
+
+        frames += 1;
+        curr_time = curr_time + frame_beats;
+    }
+    Ok(frames)
+}
@@ -3,15 +3,31 @@
 use std::cmp::{Ordering, Reverse};
 use std::collections::binary_heap::PeekMut;
 use std::collections::BinaryHeap;
+use std::path::{Path, PathBuf};
 
 use ggez::graphics::Color;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
-use crate::ease::{BeatEasing, Easing};
-use crate::enemy::{Bullet, CircleBomb, EnemyDurations, Laser, BOMB_WARMUP};
+use crate::color::{self, GroupBlendMode};
+use crate::ease::{serde_color_easings, BeatEasing, Easing, FlashOnBeat};
+use crate::enemy::{
+    Bullet, BulletStyle, Chaser, CircleBomb, Emitter, EmitterBullet, EmitterPattern,
+    EnemyDurations, ExactTiming, Laser, LaserRender, LaserSweep, Platform, RectBomb, RingPulse,
+    SweepingLaser, BOMB_WARMUP,
+};
+use crate::event::GameEvent;
+use crate::force::{ForceField, ForceFieldKind};
+use crate::objective::{Objective, ObjectiveKind};
+use crate::obstacle::{Obstacle, ObstacleShape};
 use crate::parse::{MarkedBeat, SongMap};
+use crate::pickup::{Pickup, PickupKind};
+use crate::telegraph::TelegraphStyle;
+use crate::teleporter::Teleporter;
+use crate::theme::Theme;
 use crate::time::Beats;
-use crate::world::{WorldLen, WorldPos};
-use crate::{EnemyGroup, InnerWorldState};
+use crate::vision::Vision;
+use crate::world::{ScreenTransform, WorldLen, WorldPos};
+use crate::{EnemyGroup, GroupEnemy, InnerWorldState};
 
 /// This struct contains all the events that occur during a song. It will perform
 /// a set of events every time update is called.
@@ -27,6 +43,16 @@ impl Scheduler {
         }
     }
 
+    /// Returns true if every scheduled action has been performed.
+    pub fn is_empty(&self) -> bool {
+        self.work_queue.is_empty()
+    }
+
+    /// Return how many actions have yet to be performed.
+    pub fn len(&self) -> usize {
+        self.work_queue.len()
+    }
+
     /// Preform the scheduled actions up to the new beat_time
     /// Note that this will execute every action since the last beat_time and
     /// current beat_time.
@@ -38,11 +64,11 @@ impl Scheduler {
                     if (*peaked).start_time > rev_beat {
                         let beat_action = PeekMut::pop(peaked);
 
-                        beat_action.action.preform(
-                            beat_action.group_number,
-                            beat_action.start_time.0,
-                            world,
-                        )
+                        beat_action.perform(world);
+                        world.events.push(GameEvent::ActionPerformed {
+                            group: beat_action.group_number,
+                            time: beat_action.start_time.0,
+                        });
                     } else {
                         return;
                     }
@@ -97,6 +123,7 @@ impl BeatSplitter {
                 percent: (this_beat + self.offset - self.start) / self.duration,
                 pitch: None,
                 midigroup: None,
+                chord: None,
             });
             this_beat += self.frequency;
         }
@@ -113,7 +140,7 @@ impl BeatSplitter {
 /// command. This also means that some actions may be scheduled earlier than
 /// needed, and that some actions have a maximum latest time at which they can
 /// get scheduled at all.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BeatAction {
     // Stored in reverse ordering so that we can get the _earliest_ beat when in
     // the scheduler, rather than the latest.
@@ -127,24 +154,31 @@ impl BeatAction {
     /// SpawnCmd has no start time of its own, otherwise the action is scheduled
     /// (probably slightly earlier than the SpawnCmd's start time).
     pub fn new(start_time: Beats, group_number: usize, action: SpawnCmd) -> BeatAction {
-        let beat = match action {
-            // Schedule the lasers slightly earlier than their actual time
-            // so that the laser pre-delays occurs at the right time.
-            // Since the laser predelay is 4 beats, but the laser constructors
-            // all assume the passed time is for the active phase, if we want
-            // a laser to _fire_ on beat 20, it needs to be spawned in, at latest
-            // beat 16, so that it works correctly.
-            SpawnCmd::Laser { durations, .. } => start_time - durations.warmup,
-            SpawnCmd::LaserThruPoints { durations, .. } => start_time - durations.warmup,
-            SpawnCmd::CircleBomb { .. } => start_time - BOMB_WARMUP,
-            _ => start_time,
-        };
         BeatAction {
-            start_time: Reverse(beat),
+            start_time: Reverse(action.scheduled_time(start_time)),
             group_number,
             action,
         }
     }
+
+    /// The time at which this action is performed by the Scheduler.
+    pub fn start_time(&self) -> Beats {
+        self.start_time.0
+    }
+
+    pub fn group_number(&self) -> usize {
+        self.group_number
+    }
+
+    pub fn action(&self) -> &SpawnCmd {
+        &self.action
+    }
+
+    /// Perform the action on `world`, the same way the Scheduler does.
+    pub fn perform(&self, world: &mut InnerWorldState) {
+        self.action
+            .preform(self.group_number, self.start_time.0, world);
+    }
 }
 
 impl PartialEq for BeatAction {
@@ -173,7 +207,7 @@ impl Ord for BeatAction {
 
 /// A WorldPosition which depends on some dynamic value (ex: the player's
 /// position). This is computed at run time.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum LiveWorldPos {
     Constant(WorldPos),
     PlayerPos,
@@ -181,7 +215,7 @@ pub enum LiveWorldPos {
 }
 
 impl LiveWorldPos {
-    fn world_pos(&self, player_pos: WorldPos) -> WorldPos {
+    pub fn world_pos(&self, player_pos: WorldPos) -> WorldPos {
         match self {
             &LiveWorldPos::Constant(pos) => pos,
             LiveWorldPos::PlayerPos => player_pos,
@@ -190,6 +224,28 @@ impl LiveWorldPos {
     }
 }
 
+/// An angle which may depend on the player's position, in radians. This is
+/// computed at run time.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum LiveAngle {
+    Constant(f64),
+    /// The angle pointing from the enemy's position towards the player, plus
+    /// `offset`.
+    AimAtPlayer {
+        offset: f64,
+    },
+}
+
+impl LiveAngle {
+    /// Return the angle for an enemy at `pos`.
+    pub fn angle(&self, pos: WorldPos, player_pos: WorldPos) -> f64 {
+        match *self {
+            LiveAngle::Constant(angle) => angle,
+            LiveAngle::AimAtPlayer { offset } => WorldPos::angle_to(pos, player_pos) + offset,
+        }
+    }
+}
+
 impl From<WorldPos> for LiveWorldPos {
     fn from(x: WorldPos) -> Self {
         LiveWorldPos::Constant(x)
@@ -202,51 +258,269 @@ impl From<(f64, f64)> for LiveWorldPos {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum SpawnCmd {
     Bullet {
         start: LiveWorldPos,
         end: LiveWorldPos,
+        /// If set, the bullet curves towards this point on its way from
+        /// `start` to `end`. See `Path::Bezier`.
+        control: Option<LiveWorldPos>,
         size: WorldLen,
+        style: BulletStyle,
+        warmup: Beats,
+        telegraph: TelegraphStyle,
     },
     BulletAngleStart {
-        angle: f64,
+        angle: LiveAngle,
         length: f64,
         start: LiveWorldPos,
         size: WorldLen,
+        style: BulletStyle,
+        warmup: Beats,
+        telegraph: TelegraphStyle,
     },
     BulletAngleEnd {
-        angle: f64,
+        angle: LiveAngle,
         length: f64,
         end: LiveWorldPos,
         size: WorldLen,
+        style: BulletStyle,
+        warmup: Beats,
+        telegraph: TelegraphStyle,
     },
     Laser {
         position: LiveWorldPos,
-        angle: f64,
+        angle: LiveAngle,
         durations: EnemyDurations,
+        #[serde(with = "serde_color_easings")]
         outline_colors: [Easing<Color>; 4],
         outline_keyframes: [Easing<f64>; 3],
+        telegraph: TelegraphStyle,
+        render: LaserRender,
     },
     LaserThruPoints {
         a: LiveWorldPos,
         b: LiveWorldPos,
         durations: EnemyDurations,
+        #[serde(with = "serde_color_easings")]
         outline_colors: [Easing<Color>; 4],
         outline_keyframes: [Easing<f64>; 3],
+        telegraph: TelegraphStyle,
+        render: LaserRender,
+    },
+    /// A laser which sweeps across the arena while it fires. See
+    /// `SweepingLaser`.
+    LaserSweep {
+        position: LiveWorldPos,
+        angle: LiveAngle,
+        durations: EnemyDurations,
+        #[serde(with = "serde_color_easings")]
+        outline_colors: [Easing<Color>; 4],
+        outline_keyframes: [Easing<f64>; 3],
+        telegraph: TelegraphStyle,
+        render: LaserRender,
+        sweep: LaserSweep,
     },
     CircleBomb {
         pos: LiveWorldPos,
+        telegraph: TelegraphStyle,
+    },
+    /// A bomb with a rectangular hitbox, centered on `pos` and rotated by
+    /// `angle`.
+    RectBomb {
+        pos: LiveWorldPos,
+        width: WorldLen,
+        height: WorldLen,
+        angle: LiveAngle,
+        telegraph: TelegraphStyle,
+    },
+    /// A ring which expands out to `radius` from `pos` over its active phase.
+    RingPulse {
+        pos: LiveWorldPos,
+        radius: WorldLen,
+        thickness: WorldLen,
+        durations: EnemyDurations,
+        telegraph: TelegraphStyle,
     },
-    SetFadeOut(Option<(Color, Beats)>),
+    /// A turret which fires bursts of bullets every `interval` beats while it
+    /// is active.
+    Emitter {
+        pos: LiveWorldPos,
+        durations: EnemyDurations,
+        interval: Beats,
+        pattern: EmitterPattern,
+        bullet: EmitterBullet,
+    },
+    /// A ball which chases the player while it is active. `max_speed` is in
+    /// world units per beat, and `acceleration` in world units per beat per
+    /// beat.
+    Chaser {
+        pos: LiveWorldPos,
+        durations: EnemyDurations,
+        radius: WorldLen,
+        max_speed: f64,
+        acceleration: f64,
+    },
+    Platform {
+        path: Vec<LiveWorldPos>,
+        radius: WorldLen,
+        durations: EnemyDurations,
+    },
+    Obstacle {
+        pos: LiveWorldPos,
+        shape: ObstacleShape,
+        duration: Beats,
+    },
+    ForceField {
+        kind: ForceFieldKind,
+        duration: Beats,
+    },
+    Teleporter {
+        entrance: LiveWorldPos,
+        exit: LiveWorldPos,
+        radius: WorldLen,
+        duration: Beats,
+    },
+    Pickup {
+        kind: PickupKind,
+        pos: LiveWorldPos,
+        radius: WorldLen,
+        duration: Beats,
+    },
+    SetFadeOut(#[serde(with = "serde_fadeout")] Option<(Color, Beats)>),
     SetGroupRotation(Option<(f64, f64, Beats, LiveWorldPos)>),
+    SetVision(Option<(WorldLen, Beats)>),
+    SetScreenTransform(ScreenTransform),
+    SetGroupTint(#[serde(with = "color::serde_color")] Color),
+    /// Crossfade to a new theme over the given duration. See `theme`.
+    SetTheme {
+        palette: Theme,
+        duration: Beats,
+    },
+    SetBlendMode(GroupBlendMode),
+    SetFlashOnBeat(Option<FlashOnBeat>),
+    SetBackgroundFlash(Option<FlashOnBeat>),
     SetHitbox(bool),
     SetRenderWarmup(bool),
+    /// Set how long the group's enemies take to grow and fade in after they
+    /// spawn. If None, they appear instantly.
+    SetSpawnAnimation(Option<Beats>),
+    /// Set how long the group's enemies take to shrink and fade out after
+    /// their cooldown ends. If None, they vanish instantly.
+    SetDespawnAnimation(Option<Beats>),
+    /// Set the opacity of the group's enemies over the course of each enemy's
+    /// Active phase. If None, they stay fully opaque. See
+    /// `EnemyAnimation::active_fade`.
+    SetActiveFade(Option<Easing<f64>>),
+    /// Make the group's enemies hurt the player once every given number of
+    /// beats for as long as the player touches them, instead of once each. If
+    /// None, they go back to hurting once each. See `EnemyGroup::contact_damage`.
+    SetContactDamage(Option<Beats>),
+    /// Perform the SpawnCmd, but without a despawn animation for any enemies
+    /// it spawns. See `ExactTiming`.
+    WithoutDespawn(Box<SpawnCmd>),
     SetRender(bool),
     ClearEnemies,
+    /// Mark the start of a named section of the chart, such as a verse or a
+    /// chorus. This is used to break down the player's results by section.
+    MarkSection(String),
+    /// Play a sound file from the level's folder, `volume` times as loud as
+    /// normal. Sounds aren't performed by the Scheduler, which only runs once
+    /// a frame. Instead, the world schedules them on the audio device a little
+    /// ahead of time, so that they play exactly on their beat. See
+    /// `WorldState::schedule_sounds`.
+    PlaySound {
+        path: PathBuf,
+        volume: f64,
+    },
+    /// Add some text to the end of the window's title, or remove it if None.
+    /// The text is only shown until the level ends.
+    SetTitleSuffix(Option<String>),
+    /// Start an objective. See `objective`.
+    SetObjective {
+        name: Option<String>,
+        kind: ObjectiveKind,
+    },
+    /// Perform `then` if the player has been hit at least `at_least` times
+    /// since the most recent section marker, and `otherwise` if not. If
+    /// `hit_by` is set, only hits from that group count. This lets a chart
+    /// spawn a gentler pattern for players who are struggling. Both branches
+    /// must be scheduled at the same time (ex: a laser can't be swapped for a
+    /// bullet, since lasers are spawned early to warm up), since the condition
+    /// is checked when the branch would be performed.
+    IfHits {
+        at_least: usize,
+        hit_by: Option<usize>,
+        then: Box<SpawnCmd>,
+        otherwise: Option<Box<SpawnCmd>>,
+    },
+}
+
+/// Serde helpers for SpawnCmd::SetFadeOut, since ggez's `Color` doesn't
+/// implement Serialize or Deserialize.
+mod serde_fadeout {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(
+        fadeout: &Option<(Color, Beats)>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        fadeout
+            .map(|(color, duration)| (color::to_array(color), duration))
+            .serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<(Color, Beats)>, D::Error> {
+        let fadeout = Option::<([f32; 4], Beats)>::deserialize(deserializer)?;
+        Ok(fadeout.map(|(color, duration)| (color::from_array(color), duration)))
+    }
 }
 
 impl SpawnCmd {
+    /// Return the time at which the Scheduler should perform this SpawnCmd,
+    /// for a SpawnCmd which happens at `start_time`.
+    pub fn scheduled_time(&self, start_time: Beats) -> Beats {
+        match self {
+            // Schedule the lasers slightly earlier than their actual time
+            // so that the laser pre-delays occurs at the right time.
+            // Since the laser predelay is 4 beats, but the laser constructors
+            // all assume the passed time is for the active phase, if we want
+            // a laser to _fire_ on beat 20, it needs to be spawned in, at latest
+            // beat 16, so that it works correctly.
+            SpawnCmd::Laser { durations, .. } => start_time - durations.warmup,
+            SpawnCmd::LaserThruPoints { durations, .. } => start_time - durations.warmup,
+            SpawnCmd::LaserSweep { durations, .. } => start_time - durations.warmup,
+            SpawnCmd::CircleBomb { .. } => start_time - BOMB_WARMUP,
+            SpawnCmd::RectBomb { .. } => start_time - BOMB_WARMUP,
+            SpawnCmd::RingPulse { durations, .. } => start_time - durations.warmup,
+            SpawnCmd::Emitter { durations, .. } => start_time - durations.warmup,
+            SpawnCmd::Chaser { durations, .. } => start_time - durations.warmup,
+            // Bullets wait out their warmup before moving, so they still start
+            // moving at the charted time.
+            SpawnCmd::Bullet { warmup, .. }
+            | SpawnCmd::BulletAngleStart { warmup, .. }
+            | SpawnCmd::BulletAngleEnd { warmup, .. } => start_time - *warmup,
+            SpawnCmd::Platform { durations, .. } => start_time - durations.warmup,
+            // Both branches are scheduled at the same time. See `parse`.
+            SpawnCmd::IfHits { then, .. } => then.scheduled_time(start_time),
+            SpawnCmd::WithoutDespawn(spawn_cmd) => spawn_cmd.scheduled_time(start_time),
+            _ => start_time,
+        }
+    }
+
+    /// If this plays a sound, return the sound's path and volume. See
+    /// `SpawnCmd::PlaySound`.
+    pub fn sound(&self) -> Option<(&Path, f64)> {
+        match self {
+            SpawnCmd::PlaySound { path, volume } => Some((path, *volume)),
+            SpawnCmd::WithoutDespawn(spawn_cmd) => spawn_cmd.sound(),
+            _ => None,
+        }
+    }
+
     fn preform(&self, group_number: usize, start_time: Beats, world: &mut InnerWorldState) {
         let player_pos = world.player.pos;
 
@@ -255,46 +529,86 @@ impl SpawnCmd {
         }
         let group = &mut world.groups[group_number];
         match self {
-            SpawnCmd::Bullet { start, end, size } => {
-                let bullet = Bullet::new(
+            SpawnCmd::Bullet {
+                start,
+                end,
+                control,
+                size,
+                style,
+                warmup,
+                telegraph,
+            } => {
+                let mut bullet = Bullet::new(
                     start.world_pos(player_pos),
                     end.world_pos(player_pos),
                     start_time,
+                    *warmup,
                     Beats(4.0),
                     *size,
+                    *style,
+                    *telegraph,
                 );
-                group.enemies.push(Box::new(bullet));
+                if let Some(control) = control {
+                    bullet.curve_towards(control.world_pos(player_pos));
+                }
+                group.spawn(Box::new(bullet));
             }
             SpawnCmd::BulletAngleStart {
                 angle,
                 length,
                 start,
                 size,
+                style,
+                warmup,
+                telegraph,
             } => {
-                let (unit_x, unit_y) = (angle.cos(), angle.sin());
                 let start_pos = start.world_pos(player_pos);
+                let angle = angle.angle(start_pos, player_pos);
+                let (unit_x, unit_y) = (angle.cos(), angle.sin());
                 let end_pos = WorldPos {
                     x: start_pos.x + unit_x * length,
                     y: start_pos.y + unit_y * length,
                 };
-                let bullet = Bullet::new(start_pos, end_pos, start_time, Beats(4.0), *size);
-                group.enemies.push(Box::new(bullet));
+                let bullet = Bullet::new(
+                    start_pos,
+                    end_pos,
+                    start_time,
+                    *warmup,
+                    Beats(4.0),
+                    *size,
+                    *style,
+                    *telegraph,
+                );
+                group.spawn(Box::new(bullet));
             }
             SpawnCmd::BulletAngleEnd {
                 angle,
                 length,
                 end,
                 size,
+                style,
+                warmup,
+                telegraph,
             } => {
-                let (unit_x, unit_y) = (angle.cos(), angle.sin());
                 let end_pos = end.world_pos(player_pos);
+                let angle = angle.angle(end_pos, player_pos);
+                let (unit_x, unit_y) = (angle.cos(), angle.sin());
                 let start_pos = WorldPos {
                     x: end_pos.x - unit_x * length,
                     y: end_pos.y - unit_y * length,
                 };
 
-                let bullet = Bullet::new(start_pos, end_pos, start_time, Beats(4.0), *size);
-                group.enemies.push(Box::new(bullet));
+                let bullet = Bullet::new(
+                    start_pos,
+                    end_pos,
+                    start_time,
+                    *warmup,
+                    Beats(4.0),
+                    *size,
+                    *style,
+                    *telegraph,
+                );
+                group.spawn(Box::new(bullet));
             }
             SpawnCmd::Laser {
                 position,
@@ -302,16 +616,21 @@ impl SpawnCmd {
                 durations,
                 outline_colors,
                 outline_keyframes,
+                telegraph,
+                render,
             } => {
+                let position = position.world_pos(player_pos);
                 let laser = Laser::new_through_point(
-                    position.world_pos(player_pos),
-                    *angle,
+                    position,
+                    angle.angle(position, player_pos),
                     start_time,
                     *durations,
                     outline_colors,
                     outline_keyframes,
+                    *telegraph,
+                    *render,
                 );
-                group.enemies.push(Box::new(laser));
+                group.spawn(Box::new(laser));
             }
             SpawnCmd::LaserThruPoints {
                 a,
@@ -319,6 +638,8 @@ impl SpawnCmd {
                 durations,
                 outline_colors,
                 outline_keyframes,
+                telegraph,
+                render,
             } => {
                 let laser = Laser::new_through_points(
                     a.world_pos(player_pos),
@@ -327,12 +648,143 @@ impl SpawnCmd {
                     *durations,
                     outline_colors,
                     outline_keyframes,
+                    *telegraph,
+                    *render,
+                );
+                group.spawn(Box::new(laser));
+            }
+            SpawnCmd::LaserSweep {
+                position,
+                angle,
+                durations,
+                outline_colors,
+                outline_keyframes,
+                telegraph,
+                render,
+                sweep,
+            } => {
+                let position = position.world_pos(player_pos);
+                let laser = Laser::new_through_point(
+                    position,
+                    angle.angle(position, player_pos),
+                    start_time,
+                    *durations,
+                    outline_colors,
+                    outline_keyframes,
+                    *telegraph,
+                    *render,
+                );
+                group.spawn(Box::new(SweepingLaser::new(laser, sweep.clone())));
+            }
+            SpawnCmd::CircleBomb { pos, telegraph } => {
+                let bomb = CircleBomb::new(start_time, pos.world_pos(player_pos), *telegraph);
+                group.spawn(Box::new(bomb))
+            }
+            SpawnCmd::RectBomb {
+                pos,
+                width,
+                height,
+                angle,
+                telegraph,
+            } => {
+                let pos = pos.world_pos(player_pos);
+                let angle = angle.angle(pos, player_pos);
+                let bomb = RectBomb::new(start_time, pos, *width, *height, angle, *telegraph);
+                group.spawn(Box::new(bomb))
+            }
+            SpawnCmd::RingPulse {
+                pos,
+                radius,
+                thickness,
+                durations,
+                telegraph,
+            } => {
+                let pos = pos.world_pos(player_pos);
+                let ring =
+                    RingPulse::new(start_time, *durations, pos, *radius, *thickness, *telegraph);
+                group.spawn(Box::new(ring))
+            }
+            SpawnCmd::Emitter {
+                pos,
+                durations,
+                interval,
+                pattern,
+                bullet,
+            } => {
+                let pos = pos.world_pos(player_pos);
+                let emitter =
+                    Emitter::new(start_time, *durations, pos, *interval, *pattern, *bullet);
+                group.spawn(Box::new(emitter))
+            }
+            SpawnCmd::Chaser {
+                pos,
+                durations,
+                radius,
+                max_speed,
+                acceleration,
+            } => {
+                let pos = pos.world_pos(player_pos);
+                let chaser = Chaser::new(
+                    start_time,
+                    *durations,
+                    pos,
+                    *radius,
+                    *max_speed,
+                    *acceleration,
+                );
+                group.spawn(Box::new(chaser))
+            }
+            SpawnCmd::Platform {
+                path,
+                radius,
+                durations,
+            } => {
+                let path = path.iter().map(|pos| pos.world_pos(player_pos)).collect();
+                let platform = Platform::new(start_time, *durations, path, *radius);
+                group.spawn(Box::new(platform))
+            }
+            SpawnCmd::Obstacle {
+                pos,
+                shape,
+                duration,
+            } => {
+                let obstacle =
+                    Obstacle::new(*shape, pos.world_pos(player_pos), start_time, *duration);
+                world.obstacles.push(obstacle);
+            }
+            SpawnCmd::ForceField { kind, duration } => {
+                let force_field = ForceField::new(*kind, start_time, *duration);
+                world.force_fields.push(force_field);
+            }
+            SpawnCmd::Teleporter {
+                entrance,
+                exit,
+                radius,
+                duration,
+            } => {
+                let teleporter = Teleporter::new(
+                    entrance.world_pos(player_pos),
+                    exit.world_pos(player_pos),
+                    *radius,
+                    start_time,
+                    *duration,
                 );
-                group.enemies.push(Box::new(laser));
+                world.teleporters.push(teleporter);
             }
-            SpawnCmd::CircleBomb { pos } => {
-                let bomb = CircleBomb::new(start_time, pos.world_pos(player_pos));
-                group.enemies.push(Box::new(bomb))
+            SpawnCmd::Pickup {
+                kind,
+                pos,
+                radius,
+                duration,
+            } => {
+                let pickup = Pickup::new(
+                    *kind,
+                    pos.world_pos(player_pos),
+                    *radius,
+                    start_time,
+                    *duration,
+                );
+                world.pickups.push(pickup);
             }
             &SpawnCmd::SetFadeOut(fadeout) => {
                 if let Some((color, duration)) = fadeout {
@@ -345,8 +797,24 @@ impl SpawnCmd {
                     group.fadeout = None;
                 }
             }
+            &SpawnCmd::SetVision(vision) => {
+                world.vision =
+                    vision.map(|(radius, duration)| Vision::new(radius, start_time, duration));
+            }
+            &SpawnCmd::SetScreenTransform(transform) => world.screen_transform = transform,
+            &SpawnCmd::SetGroupTint(tint) => group.tint = tint,
+            &SpawnCmd::SetBlendMode(blend_mode) => group.blend_mode = blend_mode,
+            &SpawnCmd::SetFlashOnBeat(flash) => group.flash = flash,
+            &SpawnCmd::SetBackgroundFlash(flash) => world.background_flash = flash,
+            &SpawnCmd::SetTheme { palette, duration } => {
+                world.theme = world.theme.fade_to(palette, start_time, duration)
+            }
             &SpawnCmd::SetHitbox(use_hitbox) => group.use_hitbox = use_hitbox,
             &SpawnCmd::SetRenderWarmup(show) => group.render_warmup = show,
+            &SpawnCmd::SetSpawnAnimation(spawn_in) => group.animation.spawn_in = spawn_in,
+            &SpawnCmd::SetDespawnAnimation(despawn) => group.animation.despawn = despawn,
+            SpawnCmd::SetActiveFade(fade) => group.animation.active_fade = fade.clone(),
+            &SpawnCmd::SetContactDamage(interval) => group.contact_damage = interval,
             &SpawnCmd::SetRender(show) => group.do_render = show,
             SpawnCmd::SetGroupRotation(rotation) => {
                 if let Some((start_angle, end_angle, duration, rot_point)) = rotation {
@@ -363,6 +831,48 @@ impl SpawnCmd {
                 }
             }
             SpawnCmd::ClearEnemies => group.enemies.clear(),
+            SpawnCmd::MarkSection(name) => {
+                world.section_hits.clear();
+                world.section = Some(name.clone());
+                world.events.push(GameEvent::SectionStarted {
+                    name: name.clone(),
+                    time: start_time,
+                })
+            }
+            SpawnCmd::SetTitleSuffix(suffix) => world.title_suffix = suffix.clone(),
+            SpawnCmd::PlaySound { .. } => (),
+            SpawnCmd::SetObjective { name, kind } => {
+                world
+                    .objectives
+                    .push(Objective::new(name.clone(), *kind, start_time))
+            }
+            SpawnCmd::IfHits {
+                at_least,
+                hit_by,
+                then,
+                otherwise,
+            } => {
+                let hits = match hit_by {
+                    Some(group) => world.section_hits.get(*group).copied().unwrap_or(0),
+                    None => world.section_hits.iter().sum(),
+                };
+                if hits >= *at_least {
+                    then.preform(group_number, start_time, world);
+                } else if let Some(otherwise) = otherwise {
+                    otherwise.preform(group_number, start_time, world);
+                }
+            }
+            SpawnCmd::WithoutDespawn(spawn_cmd) => {
+                let spawned_before = world.groups[group_number].enemies.len();
+                spawn_cmd.preform(group_number, start_time, world);
+                let enemies = &mut world.groups[group_number].enemies;
+                let spawned: Vec<GroupEnemy> = enemies.drain(spawned_before..).collect();
+                enemies.extend(
+                    spawned
+                        .into_iter()
+                        .map(|spawned| GroupEnemy::new(Box::new(ExactTiming(spawned.enemy)))),
+                );
+            }
         }
     }
 }
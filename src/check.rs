@@ -0,0 +1,286 @@
+/// This module implements the chart testing harness, which is run by the
+/// `test` subcommand. Each level's Lua is run, every action is validated, and
+/// then the level is simulated headlessly to the end, so that chart authors
+/// can catch mistakes without needing to play through the entire song. The
+/// simulation also doubles as a stress test, since it fails levels which use
+/// more resources (ex: live enemies) than the given ResourceCeilings allow.
+/// Levels which simulate cleanly are then checked for sections the player
+/// can't dodge, which are reported as warnings (see `reach`).
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+
+use crate::cache::CHART_FILE;
+use crate::chart::{LiveWorldPos, SpawnCmd};
+use crate::keyboard::KeyboardState;
+use crate::memory::{ResourceCeilings, ResourceCounts};
+use crate::parse::{self, SongMap};
+use crate::path;
+use crate::time::{self, Beats};
+use crate::vfs::{self, Vfs};
+use crate::world::WorldPos;
+use crate::{analyze, reach, InnerWorldState, Scheduler};
+
+/// How far past the last action the simulation should run if the song's length
+/// is not known.
+const SIMULATION_PADDING: Beats = Beats(16.0);
+
+/// The results of testing a single level.
+#[derive(Debug, Default)]
+pub struct LevelReport {
+    pub name: String,
+    pub errors: Vec<String>,
+    pub warnings: Vec<String>,
+}
+
+impl LevelReport {
+    pub fn passed(&self) -> bool {
+        self.errors.is_empty()
+    }
+
+    pub fn print(&self) {
+        let status = if self.passed() { "OK" } else { "FAIL" };
+        println!(
+            "[{}] {} ({} errors, {} warnings)",
+            status,
+            self.name,
+            self.errors.len(),
+            self.warnings.len()
+        );
+        for error in &self.errors {
+            println!("    error: {}", error);
+        }
+        for warning in &self.warnings {
+            println!("    warning: {}", warning);
+        }
+    }
+}
+
+/// Test every level in `levels_folder`. Folders without a main.lua are skipped,
+/// but level archives are tested.
+pub fn check_levels(
+    levels_folder: impl AsRef<Path>,
+    ceilings: &ResourceCeilings,
+) -> anyhow::Result<Vec<LevelReport>> {
+    let mut folders: Vec<PathBuf> = std::fs::read_dir(levels_folder)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| vfs::is_level(path))
+        .collect();
+    folders.sort();
+    Ok(folders
+        .iter()
+        .map(|folder| check_level(folder, ceilings))
+        .collect())
+}
+
+/// Test a single level.
+pub fn check_level(base_folder: impl AsRef<Path>, ceilings: &ResourceCeilings) -> LevelReport {
+    let base_folder = base_folder.as_ref();
+    let mut report = LevelReport {
+        name: base_folder.to_string_lossy().to_string(),
+        ..Default::default()
+    };
+
+    let vfs = match Vfs::open(base_folder) {
+        Ok(vfs) => vfs,
+        Err(err) => {
+            report.errors.push(format!("Couldn't open level: {}", err));
+            return report;
+        }
+    };
+    let song_map: anyhow::Result<SongMap> = try {
+        let source = vfs.read(base_folder.join(CHART_FILE))?;
+        SongMap::run_lua_tracked(&vfs, &source, parse::instruction_budget())?.0
+    };
+    let song_map = match song_map {
+        Ok(song_map) => song_map,
+        Err(err) => {
+            report.errors.push(format!("Couldn't run chart: {}", err));
+            return report;
+        }
+    };
+
+    let song_end = match &song_map.music_path {
+        Some(path) => match vfs
+            .read(base_folder.join(path))
+            .map_err(anyhow::Error::from)
+            .and_then(analyze::song_duration_of)
+        {
+            Ok(duration) => Some(time::to_beats(duration, song_map.bpm)),
+            Err(err) => {
+                report
+                    .errors
+                    .push(format!("Couldn't read music file {:?}: {}", path, err));
+                None
+            }
+        },
+        None => {
+            report.warnings.push("Chart has no music".to_string());
+            None
+        }
+    };
+
+    report
+        .warnings
+        .extend(parse::format_warnings(song_map.format_version));
+    check_actions(&song_map, song_end, &mut report);
+    let end = simulation_end(&song_map, song_end);
+    if simulate(&song_map, end, ceilings, &mut report) {
+        check_reachability(&song_map, end, &mut report);
+    }
+    report
+}
+
+fn check_actions(song_map: &SongMap, song_end: Option<Beats>, report: &mut LevelReport) {
+    for action in &song_map.actions {
+        let start_time = action.start_time();
+        if start_time < Beats(0.0) {
+            report.warnings.push(format!(
+                "{:?} on group {} is scheduled before the song starts (beat {:?})",
+                action.action(),
+                action.group_number(),
+                start_time
+            ));
+        }
+
+        if let Some(song_end) = song_end {
+            if start_time > song_end {
+                report.warnings.push(format!(
+                    "{:?} on group {} is scheduled after the song ends (beat {:?}, song ends at {:?})",
+                    action.action(),
+                    action.group_number(),
+                    start_time,
+                    song_end
+                ));
+            }
+        }
+
+        if let Some(message) = off_arena(action.action()) {
+            report.warnings.push(format!(
+                "{} (beat {:?}, group {})",
+                message,
+                start_time,
+                action.group_number()
+            ));
+        }
+    }
+}
+
+/// Returns a message if the SpawnCmd spawns an enemy which will never be inside
+/// the arena. Only constant positions are checked, since positions relative to
+/// the player are always near the arena.
+fn off_arena(spawn_cmd: &SpawnCmd) -> Option<String> {
+    fn constant(pos: &LiveWorldPos) -> Option<WorldPos> {
+        match pos {
+            &LiveWorldPos::Constant(pos) => Some(pos),
+            _ => None,
+        }
+    }
+
+    match spawn_cmd {
+        SpawnCmd::WithoutDespawn(spawn_cmd) => off_arena(spawn_cmd),
+        SpawnCmd::CircleBomb { pos, .. } | SpawnCmd::RectBomb { pos, .. } => match constant(pos) {
+            Some(pos) if !pos.in_arena() => Some(format!("Bomb spawned off-arena at {:?}", pos)),
+            _ => None,
+        },
+        SpawnCmd::Bullet {
+            start,
+            end,
+            control,
+            ..
+        } => match (constant(start), constant(end)) {
+            (Some(start), Some(end)) => {
+                let bullet_path = match control {
+                    None => path::Path::Line { start, end },
+                    Some(control) => match constant(control) {
+                        Some(control) => path::Path::Bezier {
+                            start,
+                            control,
+                            end,
+                        },
+                        None => return None,
+                    },
+                };
+                // Sample along the path of the bullet to see if it ever passes
+                // through the arena.
+                const SAMPLES: usize = 32;
+                let enters_arena =
+                    (0..=SAMPLES).any(|i| bullet_path.pos(i as f64 / SAMPLES as f64).in_arena());
+                if enters_arena {
+                    None
+                } else {
+                    Some(format!(
+                        "Bullet from {:?} to {:?} never enters the arena",
+                        start, end
+                    ))
+                }
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Return the beat to simulate the SongMap until, which is the end of the song
+/// if its length is known.
+fn simulation_end(song_map: &SongMap, song_end: Option<Beats>) -> Beats {
+    let last_action = song_map
+        .actions
+        .iter()
+        .map(|action| action.start_time())
+        .fold(Beats(0.0), |a, b| if a > b { a } else { b });
+    song_end.unwrap_or(last_action + SIMULATION_PADDING)
+}
+
+/// Play the SongMap from start to `end` with no player input, checking that
+/// nothing goes wrong along the way and that the resources used stay under
+/// `ceilings`. Returns false if the simulation panicked.
+fn simulate(
+    song_map: &SongMap,
+    end: Beats,
+    ceilings: &ResourceCeilings,
+    report: &mut LevelReport,
+) -> bool {
+    const PHYSICS_DELTA_TIME: f64 = 1.0 / 60.0;
+
+    let delta_beats = time::to_beats(time::Seconds(PHYSICS_DELTA_TIME), song_map.bpm);
+
+    let mut peak = ResourceCounts::default();
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        let keyboard = KeyboardState::default();
+        let mut world = InnerWorldState::new(song_map.player);
+        let mut scheduler = Scheduler::new(song_map);
+        let mut curr_time = song_map.skip_amount;
+        while curr_time < end {
+            world.update(&keyboard, PHYSICS_DELTA_TIME, curr_time);
+            scheduler.update(curr_time, &mut world);
+            peak = peak.max(ResourceCounts::of(&world, &scheduler));
+            curr_time = curr_time + delta_beats;
+        }
+    }));
+    report.errors.extend(ceilings.exceeded(&peak));
+
+    if let Err(err) = &result {
+        let message = if let Some(message) = err.downcast_ref::<&str>() {
+            message.to_string()
+        } else if let Some(message) = err.downcast_ref::<String>() {
+            message.clone()
+        } else {
+            "unknown panic".to_string()
+        };
+        report
+            .errors
+            .push(format!("Simulation panicked: {}", message));
+    }
+    result.is_ok()
+}
+
+/// Warn about every section of the SongMap in which the player can't avoid
+/// being hit.
+fn check_reachability(song_map: &SongMap, end: Beats, report: &mut LevelReport) {
+    for (start, end) in reach::unavoidable_ranges(song_map, song_map.skip_amount, end) {
+        report.warnings.push(format!(
+            "No safe spot can be reached from beat {:.2} to beat {:.2}",
+            start.0, end.0
+        ));
+    }
+}
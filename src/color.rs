@@ -1,4 +1,5 @@
-use ggez::graphics::Color;
+use ggez::graphics::{BlendMode, Color};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 pub const WHITE: Color = Color {
     r: 1.0,
@@ -62,3 +63,71 @@ pub const WARNING_RED: Color = Color {
     b: 0.1,
     a: 1.0,
 };
+
+pub const TELEPORT_BLUE: Color = Color {
+    r: 0.2,
+    g: 0.8,
+    b: 1.0,
+    a: 1.0,
+};
+
+pub const GEM_YELLOW: Color = Color {
+    r: 1.0,
+    g: 0.85,
+    b: 0.2,
+    a: 1.0,
+};
+
+pub const BOMB_PURPLE: Color = Color {
+    r: 0.7,
+    g: 0.4,
+    b: 1.0,
+    a: 1.0,
+};
+
+/// Multiply two colors together componentwise. This is how tints are applied.
+pub fn multiply(a: Color, b: Color) -> Color {
+    Color::new(a.r * b.r, a.g * b.g, a.b * b.b, a.a * b.a)
+}
+
+/// How an enemy group is blended onto the screen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GroupBlendMode {
+    /// Normal alpha blending.
+    Alpha,
+    /// Additive blending, which makes overlapping enemies brighter. This is
+    /// good for glows.
+    Additive,
+}
+
+impl From<GroupBlendMode> for BlendMode {
+    fn from(mode: GroupBlendMode) -> BlendMode {
+        match mode {
+            GroupBlendMode::Alpha => BlendMode::Alpha,
+            GroupBlendMode::Additive => BlendMode::Add,
+        }
+    }
+}
+
+pub fn to_array(color: Color) -> [f32; 4] {
+    [color.r, color.g, color.b, color.a]
+}
+
+pub fn from_array([r, g, b, a]: [f32; 4]) -> Color {
+    Color::new(r, g, b, a)
+}
+
+/// Serde helpers for ggez's `Color`, which doesn't implement Serialize or
+/// Deserialize itself. Colors are stored as `[r, g, b, a]` arrays. Use via
+/// `#[serde(with = "crate::color::serde_color")]`
+pub mod serde_color {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(color: &Color, serializer: S) -> Result<S::Ok, S::Error> {
+        to_array(*color).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Color, D::Error> {
+        <[f32; 4]>::deserialize(deserializer).map(from_array)
+    }
+}
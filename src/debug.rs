@@ -0,0 +1,163 @@
+/// This module collects the numbers shown in the debug stats panel. The world's
+/// stats are collected at the end of every update, so the panel shows the state
+/// the last update simulated rather than recomputing it while drawing.
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use crate::memory::ResourceCounts;
+use crate::time::Beats;
+use crate::world::WorldPos;
+use crate::WorldState;
+
+/// How many recent frames the frame time percentiles are taken over.
+const FRAME_HISTORY: usize = 240;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GroupStats {
+    pub enemies: usize,
+    /// The group's current rotation, in degrees, and the point it rotates
+    /// about, if the group is rotated.
+    pub rotation: Option<(f64, WorldPos)>,
+    pub use_hitbox: bool,
+    pub do_render: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct DebugStats {
+    pub beat: Beats,
+    pub player_pos: WorldPos,
+    pub hits: usize,
+    pub resources: ResourceCounts,
+    /// The stats of each group, in order of group number.
+    pub groups: Vec<GroupStats>,
+}
+
+impl DebugStats {
+    pub fn collect(world: &WorldState, curr_time: Beats) -> DebugStats {
+        let groups = world
+            .inner
+            .groups
+            .iter()
+            .map(|group| GroupStats {
+                enemies: group.enemies.len(),
+                rotation: group
+                    .rotation_ease(curr_time)
+                    .map(|(point, angle)| (angle.to_degrees(), point)),
+                use_hitbox: group.use_hitbox,
+                do_render: group.do_render,
+            })
+            .collect();
+        DebugStats {
+            beat: curr_time,
+            player_pos: world.inner.player.pos,
+            hits: world.stats.total_hits(),
+            resources: world.resource_counts(),
+            groups,
+        }
+    }
+
+    /// Return one line of text for each stat, for the debug panel.
+    pub fn lines(&self) -> Vec<String> {
+        let resources = &self.resources;
+        let mut lines = vec![
+            format!(
+                "Measure: {}, Beat: {:.2?}",
+                (self.beat.0 / 4.0) as i32,
+                self.beat.0
+            ),
+            format!(
+                "Player position: {:.2?} ({:.2?}, {:.2?})",
+                self.player_pos,
+                self.player_pos.as_screen_coords().x,
+                self.player_pos.as_screen_coords().y
+            ),
+            format!("Hits: {}", self.hits),
+            format!(
+                "Live enemies: {}, objects: {}, particles: {}, queued actions: {}",
+                resources.live_enemies,
+                resources.world_objects,
+                resources.particles,
+                resources.queued_actions
+            ),
+            format!(
+                "Cached MIDI files: {}, cached meshes: {}",
+                resources.cached_midi_files, resources.cached_meshes
+            ),
+        ];
+        // Only groups which have something going on are listed, since most
+        // charts leave most of their groups empty.
+        for (i, group) in self.groups.iter().enumerate() {
+            if group.enemies == 0 && group.rotation.is_none() {
+                continue;
+            }
+            let mut line = format!("Group {}: {} enemies", i, group.enemies);
+            if let Some((angle, point)) = group.rotation {
+                line.push_str(&format!(
+                    ", rotated {:.1} deg about ({:.1}, {:.1})",
+                    angle, point.x, point.y
+                ));
+            }
+            if !group.use_hitbox {
+                line.push_str(", no hitbox");
+            }
+            if !group.do_render {
+                line.push_str(", hidden");
+            }
+            lines.push(line);
+        }
+        lines
+    }
+}
+
+/// The durations of the most recent frames.
+#[derive(Debug, Clone, Default)]
+pub struct FrameTimes {
+    frames: VecDeque<Duration>,
+}
+
+impl FrameTimes {
+    pub fn record(&mut self, frame_time: Duration) {
+        if self.frames.len() >= FRAME_HISTORY {
+            self.frames.pop_front();
+        }
+        self.frames.push_back(frame_time);
+    }
+
+    /// The average number of frames per second over the recent frames.
+    pub fn fps(&self) -> f64 {
+        let total: Duration = self.frames.iter().sum();
+        if total.as_secs_f64() > 0.0 {
+            self.frames.len() as f64 / total.as_secs_f64()
+        } else {
+            0.0
+        }
+    }
+
+    /// Return the frame time which `percentile` percent of recent frames were
+    /// at least as fast as (ex: 99.0 for the slowest 1% of frames).
+    pub fn percentile(&self, percentile: f64) -> Duration {
+        let mut frames: Vec<Duration> = self.frames.iter().copied().collect();
+        if frames.is_empty() {
+            return Duration::default();
+        }
+        frames.sort();
+        let i = ((percentile / 100.0) * (frames.len() - 1) as f64).round() as usize;
+        frames[i.min(frames.len() - 1)]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_frame_percentiles() {
+        let mut frames = FrameTimes::default();
+        for ms in 1..=100 {
+            frames.record(Duration::from_millis(ms));
+        }
+        assert_eq!(frames.percentile(0.0), Duration::from_millis(1));
+        assert_eq!(frames.percentile(100.0), Duration::from_millis(100));
+        assert_eq!(frames.percentile(50.0), Duration::from_millis(51));
+    }
+}
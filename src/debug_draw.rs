@@ -0,0 +1,155 @@
+/// This module implements debug gizmos, which are lines, circles, arrows and
+/// labels positioned in world space. Any module can queue gizmos while
+/// updating or drawing through the shared functions here (ex: `line`), without
+/// needing a Context. Everything queued during a frame is drawn in one batch by
+/// `flush` once the playfield has been drawn, and then cleared, so gizmos only
+/// last for the frame they were queued in. Nothing is queued while gizmos are
+/// turned off, so queueing them is cheap outside of debugging.
+use std::sync::Mutex;
+
+use ggez::graphics::mint::Point2;
+use ggez::graphics::{
+    self, Color, DrawMode, DrawParam, Drawable, FilterMode, Font, MeshBuilder, PxScale, Text,
+    TextFragment,
+};
+use ggez::{Context, GameResult};
+use once_cell::sync::Lazy;
+
+use crate::meshutil;
+use crate::world::{ScreenTransform, WorldLen, WorldPos};
+
+/// How thick gizmo lines are, in pixels.
+const LINE_THICKNESS: f32 = 1.0;
+/// How long the sides of an arrow's head are, in pixels.
+const ARROW_HEAD_LENGTH: f32 = 8.0;
+/// The size of the text of labels.
+const LABEL_SCALE: f32 = 14.0;
+
+static DEBUG_DRAW: Lazy<Mutex<DebugDraw>> = Lazy::new(|| Mutex::new(DebugDraw::default()));
+
+#[derive(Debug, Clone)]
+enum Gizmo {
+    Line(WorldPos, WorldPos),
+    Circle(WorldPos, WorldLen),
+    /// An arrow from the first point to the second.
+    Arrow(WorldPos, WorldPos),
+    Label(WorldPos, String),
+}
+
+/// The gizmos queued for the current frame.
+#[derive(Debug, Default)]
+struct DebugDraw {
+    enabled: bool,
+    gizmos: Vec<(Gizmo, Color)>,
+}
+
+/// Turn gizmos on or off. Turning them off throws away anything queued.
+pub fn set_enabled(enabled: bool) {
+    let mut debug_draw = DEBUG_DRAW.lock().unwrap();
+    debug_draw.enabled = enabled;
+    if !enabled {
+        debug_draw.gizmos.clear();
+    }
+}
+
+pub fn enabled() -> bool {
+    DEBUG_DRAW.lock().unwrap().enabled
+}
+
+/// Throw away every queued gizmo without drawing it.
+pub fn clear() {
+    DEBUG_DRAW.lock().unwrap().gizmos.clear();
+}
+
+fn queue(gizmo: Gizmo, color: Color) {
+    let mut debug_draw = DEBUG_DRAW.lock().unwrap();
+    if debug_draw.enabled {
+        debug_draw.gizmos.push((gizmo, color));
+    }
+}
+
+pub fn line(a: WorldPos, b: WorldPos, color: Color) {
+    queue(Gizmo::Line(a, b), color)
+}
+
+/// Queue the outline of a circle.
+pub fn circle(center: WorldPos, radius: WorldLen, color: Color) {
+    queue(Gizmo::Circle(center, radius), color)
+}
+
+pub fn arrow(from: WorldPos, to: WorldPos, color: Color) {
+    queue(Gizmo::Arrow(from, to), color)
+}
+
+/// Queue some text, centered on `pos`.
+pub fn label(pos: WorldPos, text: impl Into<String>, color: Color) {
+    queue(Gizmo::Label(pos, text.into()), color)
+}
+
+/// Draw every gizmo queued since the last flush, then clear them. This is
+/// drawn in screen space, with `transform` (the playfield's transform) applied
+/// to each gizmo's position, so that gizmos line up with the playfield while
+/// labels stay readable.
+pub fn flush(ctx: &mut Context, font: Font, transform: ScreenTransform) -> GameResult<()> {
+    let gizmos = std::mem::take(&mut DEBUG_DRAW.lock().unwrap().gizmos);
+    if gizmos.is_empty() {
+        return Ok(());
+    }
+    let to_screen = |pos: WorldPos| {
+        let (x, y) = transform.apply(pos.x, pos.y);
+        WorldPos { x, y }.as_screen_coords()
+    };
+    let mut mesh = MeshBuilder::new();
+    let mut is_empty = true;
+    for (gizmo, color) in gizmos {
+        match gizmo {
+            Gizmo::Line(a, b) => {
+                meshutil::thick_line(
+                    &mut mesh,
+                    &[to_screen(a), to_screen(b)],
+                    LINE_THICKNESS,
+                    color,
+                )?;
+            }
+            Gizmo::Circle(center, radius) => {
+                let radius = radius.as_screen_length();
+                let mode = DrawMode::stroke(2.0 * LINE_THICKNESS);
+                meshutil::circle(&mut mesh, mode, to_screen(center), radius, 1.0, color)?;
+            }
+            Gizmo::Arrow(from, to) => {
+                let (from, to) = (to_screen(from), to_screen(to));
+                meshutil::thick_line(&mut mesh, &[from, to], LINE_THICKNESS, color)?;
+                let angle = (from.y - to.y).atan2(from.x - to.x);
+                for &side in &[-0.5, 0.5] {
+                    let head = Point2 {
+                        x: to.x + ARROW_HEAD_LENGTH * (angle + side).cos(),
+                        y: to.y + ARROW_HEAD_LENGTH * (angle + side).sin(),
+                    };
+                    meshutil::thick_line(&mut mesh, &[to, head], LINE_THICKNESS, color)?;
+                }
+            }
+            Gizmo::Label(pos, text) => {
+                let text = Text::new(TextFragment {
+                    text,
+                    color: Some(color),
+                    font: Some(font),
+                    scale: Some(PxScale::from(LABEL_SCALE)),
+                });
+                let pos = to_screen(pos);
+                let (width, height) = (text.width(ctx) as f32, text.height(ctx) as f32);
+                let dest = Point2 {
+                    x: pos.x - width / 2.0,
+                    y: pos.y - height / 2.0,
+                };
+                graphics::queue_text(ctx, &text, dest, None);
+                continue;
+            }
+        }
+        is_empty = false;
+    }
+    // Building an empty mesh is an error.
+    if !is_empty {
+        mesh.build(ctx)?.draw(ctx, DrawParam::default())?;
+    }
+    graphics::draw_queued_text(ctx, DrawParam::default(), None, FilterMode::Linear)
+}
@@ -1,5 +1,7 @@
 use ggez::graphics::Color;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
+use crate::color;
 use crate::time::Beats;
 
 pub trait Lerp: Sized + Copy {
@@ -60,6 +62,34 @@ impl Lerp for Color {
     }
 }
 
+/// A brightness pulse which happens on every subdivision of the beat. Since the
+/// pulse is computed directly from the song's time, it always lines up with the
+/// music, regardless of when the thing it is applied to was spawned.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct FlashOnBeat {
+    /// The number of pulses per beat. For example, 1.0 pulses on quarter notes,
+    /// 2.0 on eighth notes, and 3.0 on eighth note triplets.
+    pub subdivision: f64,
+    /// How strong the pulse is, from 0.0 (no pulse) to 1.0.
+    pub intensity: f64,
+}
+
+impl FlashOnBeat {
+    /// Return the strength of the pulse at the given time, from 0.0 to
+    /// `intensity`. The pulse is strongest exactly on the subdivision and
+    /// quickly decays afterwards.
+    pub fn pulse(&self, curr_time: Beats) -> f64 {
+        let phase = (curr_time.0 * self.subdivision).rem_euclid(1.0);
+        self.intensity * (1.0 - phase).powi(4)
+    }
+
+    /// Return the brightness multiplier at the given time. This is 1.0 on the
+    /// pulse and dims down to `1.0 - intensity` between pulses.
+    pub fn brightness(&self, curr_time: Beats) -> f32 {
+        (1.0 - self.intensity + self.pulse(curr_time)) as f32
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct BeatEasing<T> {
     pub easing: Easing<T>,
@@ -75,7 +105,7 @@ impl<T: Lerp> BeatEasing<T> {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Easing<T> {
     pub start: T,
     pub end: T,
@@ -116,7 +146,7 @@ impl<T: InvLerp> Easing<T> {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 /// An enum representing an ease.
 pub enum EasingKind {
     /// Returns `start` always.
@@ -167,3 +197,32 @@ pub fn ease_in_expo(x: f64) -> f64 {
         (2.0f64.powf(10.0 * x) - 1.0) / (2.0f64.powf(10.0) - 1.0)
     }
 }
+
+/// Serde helpers for a laser's outline color keyframes. ggez's `Color` doesn't
+/// implement Serialize or Deserialize, so the colors are stored as arrays.
+pub mod serde_color_easings {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(
+        easings: &[Easing<Color>; 4],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let easings = easings.clone().map(|easing| Easing {
+            start: color::to_array(easing.start),
+            end: color::to_array(easing.end),
+            kind: easing.kind,
+        });
+        easings.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<[Easing<Color>; 4], D::Error> {
+        let easings = <[Easing<[f32; 4]>; 4]>::deserialize(deserializer)?;
+        Ok(easings.map(|easing| Easing {
+            start: color::from_array(easing.start),
+            end: color::from_array(easing.end),
+            kind: easing.kind,
+        }))
+    }
+}
@@ -1,33 +1,123 @@
-use ggez::graphics::{Color, DrawMode, DrawParam, Mesh, MeshBuilder};
+use ggez::graphics::{Color, DrawMode, DrawParam, Mesh, MeshBuilder, Rect, Vertex};
 use ggez::{Context, GameResult};
 
 use cg::prelude::*;
 use cgmath as cg;
+use serde::{Deserialize, Serialize};
 
+use crate::budget;
 use crate::color::{self, LASER_RED, RED, TRANSPARENT, WHITE};
 use crate::ease::{Easing, EasingKind, Lerp};
+use crate::instanced::Circle;
+use crate::meshcache::{MeshCache, MeshKey, FRAMES_PER_PHASE};
+use crate::meshutil;
+use crate::path::{self, Path, PathPreview};
+use crate::telegraph::{self, TelegraphShape, TelegraphStyle};
 use crate::time::Beats;
 use crate::util;
-use crate::world::{WorldLen, WorldPos, WORLD_SCALE_FACTOR};
+use crate::world::{self, WorldLen, WorldPos, WORLD_SCALE_FACTOR};
 
 pub const LASER_WARMUP: Beats = Beats(4.0);
 
 pub const BOMB_WARMUP: Beats = Beats(4.0);
 
-const LASER_COOLDOWN: Beats = Beats(0.25);
+pub const PLATFORM_WARMUP: Beats = Beats(4.0);
+
+/// How long a ring pulse warns the player before expanding, unless the chart
+/// sets otherwise.
+pub const RING_WARMUP: Beats = Beats(2.0);
+
+/// How long an emitter warns the player before firing, unless the chart sets
+/// otherwise.
+pub const EMITTER_WARMUP: Beats = Beats(2.0);
+/// The shortest time allowed between an emitter's bursts.
+const MIN_EMITTER_INTERVAL: Beats = Beats(1.0 / 16.0);
+/// The radius of an emitter's body.
+const EMITTER_RADIUS: f32 = 2.0;
+/// How long each step of a chaser's movement is. Chasers move in fixed steps
+/// rather than once per update, so that they move the same way at every tick
+/// rate.
+const CHASER_STEP: Beats = Beats(1.0 / 128.0);
+
+/// How long a chaser warns the player before it starts moving, unless the
+/// chart sets otherwise.
+pub const CHASER_WARMUP: Beats = Beats(2.0);
+
+/// How long a bullet's path is previewed before the bullet starts moving,
+/// unless the chart sets otherwise.
+pub const DEFAULT_BULLET_WARMUP: Beats = Beats(1.0);
+/// How much larger than the enemy its CountdownRing telegraph starts out. See
+/// `telegraph::draw_telegraph`.
+const COUNTDOWN_TELEGRAPH_SCALE: f64 = 3.0;
 
-const TOLERANCE: f32 = 0.1;
-const OUTLINE_THICKNESS: f32 = 0.25;
+const LASER_COOLDOWN: Beats = Beats(0.25);
+/// How long a laser flares for after hitting the player.
+const LASER_FLARE: Beats = Beats(0.5);
+/// How many times thicker a laser's outline is at the start of a flare.
+const LASER_FLARE_SCALE: f64 = 2.0;
+
+/// How many segments a glowing laser is split into along its length. More
+/// segments make the noise along the beam smoother.
+const LASER_GLOW_SEGMENTS: usize = 64;
+/// How much the noise along a glowing laser changes the width of its glow.
+const LASER_GLOW_NOISE: f32 = 0.35;
+/// The thickness of enemy outlines and warmup telegraphs.
+pub const OUTLINE_THICKNESS: f32 = 0.25;
+/// How long enemies take to shrink and fade out after their cooldown ends,
+/// unless their group sets otherwise.
+const DEFAULT_DESPAWN: Beats = Beats(0.25);
+/// How long before the end of an enemy's cooldown its despawn animation is
+/// drawn from. The enemy's mesh can't be built once the enemy is Dead.
+const LAST_FRAME: Beats = Beats(1.0 / 1024.0);
 
 /// The public facing enemy trait that specifies how an enemy behaves over its
 /// lifetime of existence.
 pub trait Enemy {
-    fn update(&mut self, curr_time: Beats);
+    /// Update the enemy. `player_pos` is where the player is, for enemies
+    /// which react to the player (ex: by chasing them).
+    fn update(
+        &mut self,
+        curr_time: Beats,
+        player_pos: WorldPos,
+        rotated_about: Option<(WorldPos, f64)>,
+    );
+    /// Return the enemy's mesh and how to draw it. The DrawParam's color is
+    /// the enemy's opacity from `animation`, which should be multiplied into
+    /// any other color the enemy is drawn with. Enemies with a shape (see
+    /// `EnemyImpl::shape`) take their mesh from `mesh_cache`.
     fn draw(
         &self,
         ctx: &mut Context,
         curr_time: Beats,
         rotated_about: Option<(WorldPos, f64)>,
+        animation: &EnemyAnimation,
+        mesh_cache: &mut MeshCache,
+    ) -> GameResult<Option<(Mesh, DrawParam)>>;
+    /// Build every mesh the enemy will be drawn with over its lifetime and add
+    /// them to `mesh_cache`. This does nothing if the enemy has no shape.
+    fn cache_meshes(&self, ctx: &mut Context, mesh_cache: &mut MeshCache) -> GameResult<()>;
+    /// Return the filled circles the enemy is drawn with, in screen
+    /// coordinates, if they can be drawn apart from the rest of its mesh right
+    /// now. These are drawn in a `CircleBatch` instead of with the enemy's
+    /// mesh, and whatever else the enemy draws comes from `draw_extra`.
+    /// Returns None if the enemy should be drawn with `draw` instead. The
+    /// circles' colors include the enemy's opacity from `animation`, like
+    /// `draw`'s DrawParam.
+    fn draw_circles(
+        &self,
+        curr_time: Beats,
+        rotated_about: Option<(WorldPos, f64)>,
+        animation: &EnemyAnimation,
+    ) -> Option<Vec<Circle>>;
+    /// Return the mesh of everything the enemy draws besides the circles from
+    /// `draw_circles` (ex: a bullet's guide), if there is anything. This is
+    /// only drawn when the circles are.
+    fn draw_extra(
+        &self,
+        ctx: &mut Context,
+        curr_time: Beats,
+        rotated_about: Option<(WorldPos, f64)>,
+        animation: &EnemyAnimation,
     ) -> GameResult<Option<(Mesh, DrawParam)>>;
     // fn position_info(&self, curr_time: Beats) -> (WorldPos, f64);
     /// If None, the enemy has no hitbox, otherwise, positive values give the
@@ -38,10 +128,139 @@ pub trait Enemy {
         curr_time: Beats,
         rotated_about: Option<(WorldPos, f64)>,
     ) -> Option<WorldLen>;
+    /// Return the gradient of the sdf at `pos`, which points in the direction
+    /// the sdf increases fastest (that is, usually away from the enemy). This
+    /// is None whenever the sdf is, and (0, 0) where the gradient is undefined
+    /// (ex: at the center of a bullet).
+    fn sdf_gradient(
+        &self,
+        pos: WorldPos,
+        curr_time: Beats,
+        rotated_about: Option<(WorldPos, f64)>,
+    ) -> Option<(f64, f64)>;
     fn lifetime_state(&self, curr_time: Beats) -> EnemyLifetime;
+    /// Return when the enemy's cooldown ends and it becomes Dead.
+    fn end_time(&self) -> Beats;
+    /// Return when the enemy becomes Active and when its Active phase ends.
+    fn active_range(&self) -> (Beats, Beats);
+    /// Return where the enemy is, after rotating it along with its group. For
+    /// lasers, this is the point the laser passes through.
+    fn position(&self, curr_time: Beats, rotated_about: Option<(WorldPos, f64)>) -> WorldPos;
+    /// Returns false if the enemy should vanish as soon as it is Dead, even
+    /// if its group has a despawn animation. See `ExactTiming`.
+    fn despawns(&self) -> bool {
+        true
+    }
+    /// Return a conservative bounding circle, as a center and a radius, which
+    /// contains the enemy's hitbox and everything it draws. This ignores group
+    /// rotation. Enemies which can't be bounded (ex: because their hitbox
+    /// covers everything outside of them) return an infinite radius.
+    fn bounds(&self, curr_time: Beats) -> (WorldPos, WorldLen);
+    /// Returns false if the enemy is definitely entirely off screen.
+    fn on_screen(&self, curr_time: Beats, rotated_about: Option<(WorldPos, f64)>) -> bool;
+    /// Returns false if the enemy's hitbox is definitely further than
+    /// `radius` away from `pos`. This is much cheaper than checking the sdf.
+    fn could_touch(
+        &self,
+        pos: WorldPos,
+        radius: WorldLen,
+        curr_time: Beats,
+        rotated_about: Option<(WorldPos, f64)>,
+    ) -> bool;
+    /// Called when the enemy hits the player, so that it can react to the
+    /// hit. The default does nothing.
+    fn on_player_hit(&mut self, _curr_time: Beats) {}
+    /// Return the enemies this enemy has spawned since this was last called,
+    /// which are added to the enemy's group. This is called after every
+    /// update. `player_pos` is where the player is, so that spawned enemies
+    /// can be aimed at them.
+    fn spawn_children(
+        &mut self,
+        curr_time: Beats,
+        player_pos: WorldPos,
+        rotated_about: Option<(WorldPos, f64)>,
+    ) -> Vec<Box<dyn Enemy>>;
+}
+
+/// Animations drawn on top of every enemy's own warmup and cooldown
+/// animations. These only change how enemies look, never their hitboxes, and
+/// are set per group (see `SpawnCmd::SetSpawnAnimation`,
+/// `SpawnCmd::SetDespawnAnimation` and `SpawnCmd::SetActiveFade`).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EnemyAnimation {
+    /// How long enemies take to grow from nothing and fade in after they
+    /// spawn. If None, enemies appear at full size.
+    pub spawn_in: Option<Beats>,
+    /// How long enemies take to shrink and fade out after their cooldown ends.
+    /// Enemies are kept in their group until this is over. If None, enemies
+    /// vanish as soon as they are Dead.
+    pub despawn: Option<Beats>,
+    /// The opacity of enemies over the course of their Active phase, where
+    /// the easing's `t` is how far through the phase the enemy is. Unlike a
+    /// group's fadeout, this is relative to each enemy rather than to a fixed
+    /// beat. Enemies are fully opaque during their warmup and keep the end
+    /// opacity during their cooldown. If None, enemies are fully opaque.
+    pub active_fade: Option<Easing<f64>>,
+}
+
+impl Default for EnemyAnimation {
+    fn default() -> Self {
+        EnemyAnimation {
+            spawn_in: None,
+            despawn: Some(DEFAULT_DESPAWN),
+            active_fade: None,
+        }
+    }
+}
+
+impl EnemyAnimation {
+    /// Returns true if an enemy which became Dead at `end_time` is still
+    /// being animated at `curr_time`.
+    pub fn despawning(&self, end_time: Beats, curr_time: Beats) -> bool {
+        match self.despawn {
+            Some(duration) => curr_time >= end_time && curr_time < end_time + duration,
+            None => false,
+        }
+    }
+
+    /// Return the opacity from the active fade of an enemy with the given
+    /// durations, `delta_time` after it spawned.
+    fn active_opacity(&self, delta_time: Beats, durations: EnemyDurations) -> f32 {
+        let fade = match &self.active_fade {
+            Some(fade) => fade,
+            None => return 1.0,
+        };
+        if delta_time < durations.warmup {
+            return 1.0;
+        }
+        let t = if durations.active.0 > 0.0 {
+            durations.percent_over_active(delta_time).min(1.0)
+        } else {
+            1.0
+        };
+        fade.ease(t).clamp(0.0, 1.0) as f32
+    }
+
+    /// Return how much to scale an enemy by and its opacity, `delta_time`
+    /// after it spawned. `dead_time` is how long the enemy has been Dead, if
+    /// it is.
+    fn scale_and_opacity(&self, delta_time: Beats, dead_time: Option<Beats>) -> (f32, f32) {
+        if let (Some(dead_time), Some(duration)) = (dead_time, self.despawn) {
+            let t = (dead_time.0 / duration.0).clamp(0.0, 1.0) as f32;
+            return (1.0 - t * t, 1.0 - t);
+        }
+        match self.spawn_in {
+            Some(duration) if delta_time < duration => {
+                let t = (delta_time.0 / duration.0).clamp(0.0, 1.0) as f32;
+                // Ease out, so the enemy is easy to see early on.
+                (1.0 - (1.0 - t).powi(2), t)
+            }
+            _ => (1.0, 1.0),
+        }
+    }
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub enum EnemyLifetime {
     Unspawned, // The enemy has not spawned yet
     Warmup,    // The enemy's hitbox is not active and a warmup animation is shown
@@ -50,6 +269,39 @@ pub enum EnemyLifetime {
     Dead,      // The enemy is now dead.
 }
 
+/// What an enemy looks like, apart from where it is and how far through each
+/// phase of its lifetime it is. Enemies with the same shape are drawn with the
+/// same meshes, which are cached (see `meshcache`). Lengths are the bits of
+/// their f32s, so that shapes can be hashed.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum EnemyShape {
+    CircleBomb {
+        max_radius: u32,
+        telegraph: TelegraphStyle,
+    },
+    RectBomb {
+        half_width: u32,
+        half_height: u32,
+        telegraph: TelegraphStyle,
+    },
+    RingPulse {
+        max_radius: u32,
+        thickness: u32,
+        telegraph: TelegraphStyle,
+    },
+    Chaser {
+        radius: u32,
+    },
+    Platform {
+        radius: u32,
+    },
+}
+
+/// Return the bits of `len` as an f32, for use in an EnemyShape.
+fn shape_len(len: WorldLen) -> u32 {
+    (len.0 as f32).to_bits()
+}
+
 /// The internal enemy implementation trait. This is done so that a blanket impl
 /// can be done that specifies most of the desired default behaviors of enemies.
 pub trait EnemyImpl {
@@ -68,22 +320,71 @@ pub trait EnemyImpl {
     /// Return the sdf of the enemy. Called only if this enemy's lifetime is
     /// in Warmup/Active/Cooldown
     fn sdf(&self, pos: WorldPos, curr_time: Beats) -> WorldLen;
+    /// Return the gradient of the enemy's sdf. Called only if this enemy's
+    /// lifetime is Active. This defaults to estimating the gradient from the
+    /// sdf, so enemies whose gradient is easy to find should override it.
+    fn sdf_gradient(&self, pos: WorldPos, curr_time: Beats) -> (f64, f64) {
+        estimate_gradient(|pos| EnemyImpl::sdf(self, pos, curr_time), pos)
+    }
     /// Update the enemy. Called only if this enemy's lifetime is
-    /// in Warmup/Active/Cooldown
-    fn update(&mut self, curr_time: Beats);
+    /// in Warmup/Active/Cooldown. `player_pos` is relative to the enemy's
+    /// group rotation, the same way the enemy's own position is.
+    fn update(&mut self, curr_time: Beats, player_pos: WorldPos);
     /// Draw the enemy. Called only if this enemy's lifetime is
     /// in Warmup/Active/Cooldown
     fn get_mesh(&self, ctx: &mut Context, curr_time: Beats) -> GameResult<Mesh>;
+    /// Return the filled circles in the enemy's mesh, in the mesh's
+    /// coordinates, if the rest of the mesh can be drawn underneath them with
+    /// `get_extra_mesh`. Enemies drawn this way skip building their full mesh
+    /// when circles can be batched. This defaults to None, so that the enemy
+    /// is always drawn with its mesh.
+    fn circles(&self, _curr_time: Beats) -> Option<Vec<Circle>> {
+        None
+    }
+    /// Return the rest of the enemy's mesh besides `circles`, if there is any.
+    /// This defaults to None.
+    fn get_extra_mesh(&self, _ctx: &mut Context, _curr_time: Beats) -> GameResult<Option<Mesh>> {
+        Ok(None)
+    }
+    /// Return the enemy's shape, if its mesh depends only on its shape, its
+    /// lifetime state, and how far through that state it is. The meshes of
+    /// enemies with shapes are cached. This defaults to None, so that the
+    /// enemy's mesh is built every frame.
+    fn shape(&self) -> Option<EnemyShape> {
+        None
+    }
 
     fn position_info(&self, curr_time: Beats) -> (WorldPos, f64);
+
+    /// See `Enemy::bounds`.
+    fn bounds(&self, curr_time: Beats) -> (WorldPos, WorldLen);
+
+    /// See `Enemy::on_player_hit`.
+    fn on_player_hit(&mut self, _curr_time: Beats) {}
+
+    /// See `Enemy::spawn_children`. Called only if this enemy's lifetime is
+    /// in Warmup/Active/Cooldown. `player_pos` is relative to the enemy's
+    /// group rotation, the same way the enemy's own position is. This
+    /// defaults to spawning nothing.
+    fn spawn_children(&mut self, _curr_time: Beats, _player_pos: WorldPos) -> Vec<Box<dyn Enemy>> {
+        Vec::new()
+    }
 }
 
 impl<T: EnemyImpl> Enemy for T {
-    fn update(&mut self, curr_time: Beats) {
+    fn update(
+        &mut self,
+        curr_time: Beats,
+        player_pos: WorldPos,
+        rotated_about: Option<(WorldPos, f64)>,
+    ) {
         match self.lifetime_state(curr_time) {
             EnemyLifetime::Unspawned => (),
             EnemyLifetime::Dead => (),
-            _ => self.update(curr_time),
+            _ => {
+                let player_pos = unrotated(player_pos, rotated_about);
+                EnemyImpl::update(self, curr_time, player_pos)
+            }
         }
     }
 
@@ -92,33 +393,72 @@ impl<T: EnemyImpl> Enemy for T {
         ctx: &mut Context,
         curr_time: Beats,
         rotated_about: Option<(WorldPos, f64)>,
+        animation: &EnemyAnimation,
+        mesh_cache: &mut MeshCache,
     ) -> GameResult<Option<(Mesh, DrawParam)>> {
-        match self.lifetime_state(curr_time) {
-            EnemyLifetime::Unspawned => Ok(None),
-            EnemyLifetime::Dead => Ok(None),
-            _ => {
-                let mesh = self.get_mesh(ctx, curr_time)?;
-                let (pos, angle) = self.position_info(curr_time);
-
-                // apply the rotation if need be
-                let (pos, angle) = if let Some((rot_point, rot_angle)) = rotated_about {
-                    (rotate_point(pos, rot_point, rot_angle), angle + rot_angle)
-                } else {
-                    (pos, angle)
-                };
+        let placement = match placement(self, curr_time, rotated_about, animation) {
+            Some(placement) => placement,
+            None => return Ok(None),
+        };
+        let mesh = match mesh_frame(self, placement.mesh_time) {
+            Some((key, frame_time)) => {
+                mesh_cache.get_or_build(key, || self.get_mesh(ctx, frame_time))?
+            }
+            None => self.get_mesh(ctx, placement.mesh_time)?,
+        };
+        Ok(Some((mesh, placement.param())))
+    }
 
-                // Note that the negative angle is required here as `rotation`
-                // rotates objects clockwise, but we need counterclockwise
-                // rotation. Also note the -4.0 on `scale`. This is needed to
-                // flip the y-axis since screen space has the y-axis increasing
-                // downwards but worldspace is increasing upwards.
-                let param = DrawParam::default()
-                    .dest(pos.as_screen_coords())
-                    .rotation(-angle as f32)
-                    .scale([WORLD_SCALE_FACTOR, -WORLD_SCALE_FACTOR]);
-                Ok(Some((mesh, param)))
+    fn cache_meshes(&self, ctx: &mut Context, mesh_cache: &mut MeshCache) -> GameResult<()> {
+        let shape = match self.shape() {
+            Some(shape) => shape,
+            None => return Ok(()),
+        };
+        use EnemyLifetime::*;
+        for &lifetime in &[Warmup, Active, Cooldown] {
+            for frame in 0..FRAMES_PER_PHASE {
+                if let Some(frame_time) = frame_time(self, lifetime, frame) {
+                    let key = MeshKey {
+                        shape,
+                        lifetime,
+                        frame,
+                    };
+                    mesh_cache.get_or_build(key, || self.get_mesh(ctx, frame_time))?;
+                }
             }
         }
+        Ok(())
+    }
+
+    fn draw_circles(
+        &self,
+        curr_time: Beats,
+        rotated_about: Option<(WorldPos, f64)>,
+        animation: &EnemyAnimation,
+    ) -> Option<Vec<Circle>> {
+        let placement = placement(self, curr_time, rotated_about, animation)?;
+        let circles = self.circles(placement.mesh_time)?;
+        Some(
+            circles
+                .into_iter()
+                .map(|circle| placement.circle(circle))
+                .collect(),
+        )
+    }
+
+    fn draw_extra(
+        &self,
+        ctx: &mut Context,
+        curr_time: Beats,
+        rotated_about: Option<(WorldPos, f64)>,
+        animation: &EnemyAnimation,
+    ) -> GameResult<Option<(Mesh, DrawParam)>> {
+        let placement = match placement(self, curr_time, rotated_about, animation) {
+            Some(placement) => placement,
+            None => return Ok(None),
+        };
+        let mesh = self.get_extra_mesh(ctx, placement.mesh_time)?;
+        Ok(mesh.map(|mesh| (mesh, placement.param())))
     }
 
     fn sdf(
@@ -142,6 +482,87 @@ impl<T: EnemyImpl> Enemy for T {
         }
     }
 
+    fn sdf_gradient(
+        &self,
+        pos: WorldPos,
+        curr_time: Beats,
+        rotated_about: Option<(WorldPos, f64)>,
+    ) -> Option<(f64, f64)> {
+        if self.lifetime_state(curr_time) != EnemyLifetime::Active {
+            return None;
+        }
+        match rotated_about {
+            Some((rot_point, rot_angle)) => {
+                // As in `sdf`, the point is rotated into the enemy's frame,
+                // so the gradient needs to be rotated back out of it.
+                let pos = rotate_point(pos, rot_point, -rot_angle);
+                let gradient = EnemyImpl::sdf_gradient(self, pos, curr_time);
+                let gradient = rotate_point(gradient.into(), WorldPos::origin(), rot_angle);
+                Some((gradient.x, gradient.y))
+            }
+            None => Some(EnemyImpl::sdf_gradient(self, pos, curr_time)),
+        }
+    }
+
+    fn position(&self, curr_time: Beats, rotated_about: Option<(WorldPos, f64)>) -> WorldPos {
+        let (pos, _) = self.position_info(curr_time);
+        match rotated_about {
+            Some((rot_point, rot_angle)) => rotate_point(pos, rot_point, rot_angle),
+            None => pos,
+        }
+    }
+
+    fn end_time(&self) -> Beats {
+        let durations = self.durations();
+        self.start_time() + durations.warmup + durations.active + durations.cooldown
+    }
+
+    fn active_range(&self) -> (Beats, Beats) {
+        let durations = self.durations();
+        let active_start = self.start_time() + durations.warmup;
+        (active_start, active_start + durations.active)
+    }
+
+    fn bounds(&self, curr_time: Beats) -> (WorldPos, WorldLen) {
+        self.bounds(curr_time)
+    }
+
+    fn on_screen(&self, curr_time: Beats, rotated_about: Option<(WorldPos, f64)>) -> bool {
+        let (center, radius) = rotated_bounds(self, curr_time, rotated_about);
+        WorldPos::distance(center, WorldPos::origin()) - radius <= world::view_radius()
+    }
+
+    fn could_touch(
+        &self,
+        pos: WorldPos,
+        radius: WorldLen,
+        curr_time: Beats,
+        rotated_about: Option<(WorldPos, f64)>,
+    ) -> bool {
+        let (center, bound) = rotated_bounds(self, curr_time, rotated_about);
+        WorldPos::distance(center, pos) - bound < radius
+    }
+
+    fn on_player_hit(&mut self, curr_time: Beats) {
+        EnemyImpl::on_player_hit(self, curr_time)
+    }
+
+    fn spawn_children(
+        &mut self,
+        curr_time: Beats,
+        player_pos: WorldPos,
+        rotated_about: Option<(WorldPos, f64)>,
+    ) -> Vec<Box<dyn Enemy>> {
+        match self.lifetime_state(curr_time) {
+            EnemyLifetime::Unspawned => Vec::new(),
+            EnemyLifetime::Dead => Vec::new(),
+            _ => {
+                let player_pos = unrotated(player_pos, rotated_about);
+                EnemyImpl::spawn_children(self, curr_time, player_pos)
+            }
+        }
+    }
+
     fn lifetime_state(&self, curr_time: Beats) -> EnemyLifetime {
         let delta_time = self.delta_time(curr_time);
         let warmup = self.durations().warmup;
@@ -161,7 +582,291 @@ impl<T: EnemyImpl> Enemy for T {
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+/// Where an enemy is drawn on screen, and how.
+struct Placement {
+    /// The time to draw the enemy as of. This differs from the current time
+    /// while the enemy is despawning.
+    mesh_time: Beats,
+    pos: WorldPos,
+    angle: f64,
+    scale: f32,
+    opacity: f32,
+}
+
+impl Placement {
+    fn param(&self) -> DrawParam {
+        // Note that the negative angle is required here as `rotation`
+        // rotates objects clockwise, but we need counterclockwise
+        // rotation. Also note the -4.0 on `scale`. This is needed to
+        // flip the y-axis since screen space has the y-axis increasing
+        // downwards but worldspace is increasing upwards.
+        DrawParam::default()
+            .dest(self.pos.as_screen_coords())
+            .rotation(-self.angle as f32)
+            .scale([
+                WORLD_SCALE_FACTOR * self.scale,
+                -WORLD_SCALE_FACTOR * self.scale,
+            ])
+            .color(Color::new(1.0, 1.0, 1.0, self.opacity))
+    }
+
+    /// Move a circle from the enemy's mesh coordinates to screen coordinates,
+    /// the same way `param` moves the mesh.
+    fn circle(&self, circle: Circle) -> Circle {
+        let (x, y) = (circle.center.x as f64, circle.center.y as f64);
+        let (sin, cos) = self.angle.sin_cos();
+        let scale = self.scale as f64;
+        let center = WorldPos {
+            x: self.pos.x + scale * (x * cos - y * sin),
+            y: self.pos.y + scale * (x * sin + y * cos),
+        };
+        Circle {
+            center: center.as_screen_coords(),
+            radius: circle.radius * WORLD_SCALE_FACTOR * self.scale,
+            color: Color {
+                a: circle.color.a * self.opacity,
+                ..circle.color
+            },
+        }
+    }
+}
+
+// Return where to draw the enemy, or None if it shouldn't be drawn.
+fn placement<T: EnemyImpl>(
+    enemy: &T,
+    curr_time: Beats,
+    rotated_about: Option<(WorldPos, f64)>,
+    animation: &EnemyAnimation,
+) -> Option<Placement> {
+    // Dead enemies which are despawning are drawn as they were at the end
+    // of their cooldown.
+    let end_time = Enemy::end_time(enemy);
+    let (mesh_time, dead_time) = match enemy.lifetime_state(curr_time) {
+        EnemyLifetime::Dead if animation.despawning(end_time, curr_time) => {
+            (end_time - LAST_FRAME, Some(curr_time - end_time))
+        }
+        _ => (curr_time, None),
+    };
+    match enemy.lifetime_state(mesh_time) {
+        EnemyLifetime::Unspawned => return None,
+        EnemyLifetime::Dead => return None,
+        _ if !enemy.on_screen(mesh_time, rotated_about) => return None,
+        _ => (),
+    }
+    let (pos, angle) = enemy.position_info(mesh_time);
+
+    // apply the rotation if need be
+    let (pos, angle) = if let Some((rot_point, rot_angle)) = rotated_about {
+        (rotate_point(pos, rot_point, rot_angle), angle + rot_angle)
+    } else {
+        (pos, angle)
+    };
+
+    let delta_time = enemy.delta_time(mesh_time);
+    let (scale, opacity) = animation.scale_and_opacity(delta_time, dead_time);
+    let opacity = opacity * animation.active_opacity(delta_time, enemy.durations());
+    Some(Placement {
+        mesh_time,
+        pos,
+        angle,
+        scale,
+        opacity,
+    })
+}
+
+// Return the key of the enemy's cached mesh at `curr_time`, along with the time
+// to build that mesh as of. Returns None if the enemy's mesh isn't cached.
+fn mesh_frame<T: EnemyImpl>(enemy: &T, curr_time: Beats) -> Option<(MeshKey, Beats)> {
+    let shape = enemy.shape()?;
+    let lifetime = enemy.lifetime_state(curr_time);
+    let t = enemy.percent_over_curr_state(curr_time);
+    let frame = ((t * FRAMES_PER_PHASE as f64) as u32).min(FRAMES_PER_PHASE - 1);
+    let frame_time = frame_time(enemy, lifetime, frame)?;
+    let key = MeshKey {
+        shape,
+        lifetime,
+        frame,
+    };
+    Some((key, frame_time))
+}
+
+// Return the time in the middle of the `frame`th of the FRAMES_PER_PHASE frames
+// of the enemy's `lifetime` phase. Returns None if that time isn't in the phase
+// (ex: because the phase is too short to draw).
+fn frame_time<T: EnemyImpl>(enemy: &T, lifetime: EnemyLifetime, frame: u32) -> Option<Beats> {
+    let durations = enemy.durations();
+    let (phase_start, phase_length) = match lifetime {
+        EnemyLifetime::Warmup => (Beats(0.0), durations.warmup),
+        EnemyLifetime::Active => (durations.warmup, durations.active),
+        EnemyLifetime::Cooldown => (durations.warmup + durations.active, durations.cooldown),
+        _ => return None,
+    };
+    let t = (frame as f64 + 0.5) / FRAMES_PER_PHASE as f64;
+    let time = enemy.start_time() + phase_start + phase_length * t;
+    if enemy.lifetime_state(time) == lifetime {
+        Some(time)
+    } else {
+        None
+    }
+}
+
+// Return `pos` as it is relative to a group rotated about `rotated_about`.
+// Enemies (and the children they spawn) are rotated along with their group,
+// so they see the player where the player is before the rotation.
+fn unrotated(pos: WorldPos, rotated_about: Option<(WorldPos, f64)>) -> WorldPos {
+    match rotated_about {
+        Some((rot_point, rot_angle)) => rotate_point(pos, rot_point, -rot_angle),
+        None => pos,
+    }
+}
+
+// Return the enemy's bounds, rotated along with the enemy's group.
+fn rotated_bounds<T: EnemyImpl>(
+    enemy: &T,
+    curr_time: Beats,
+    rotated_about: Option<(WorldPos, f64)>,
+) -> (WorldPos, WorldLen) {
+    let (center, radius) = enemy.bounds(curr_time);
+    let center = match rotated_about {
+        Some((rot_point, rot_angle)) => rotate_point(center, rot_point, rot_angle),
+        None => center,
+    };
+    (center, radius)
+}
+
+/// An enemy which vanishes as soon as it is Dead, even if its group has a
+/// despawn animation. This is for charts which need an enemy to disappear
+/// exactly when its cooldown ends (ex: to line up with the music).
+pub struct ExactTiming(pub Box<dyn Enemy>);
+
+impl Enemy for ExactTiming {
+    fn update(
+        &mut self,
+        curr_time: Beats,
+        player_pos: WorldPos,
+        rotated_about: Option<(WorldPos, f64)>,
+    ) {
+        self.0.update(curr_time, player_pos, rotated_about)
+    }
+
+    fn draw(
+        &self,
+        ctx: &mut Context,
+        curr_time: Beats,
+        rotated_about: Option<(WorldPos, f64)>,
+        animation: &EnemyAnimation,
+        mesh_cache: &mut MeshCache,
+    ) -> GameResult<Option<(Mesh, DrawParam)>> {
+        let animation = EnemyAnimation {
+            despawn: None,
+            ..animation.clone()
+        };
+        self.0
+            .draw(ctx, curr_time, rotated_about, &animation, mesh_cache)
+    }
+
+    fn cache_meshes(&self, ctx: &mut Context, mesh_cache: &mut MeshCache) -> GameResult<()> {
+        self.0.cache_meshes(ctx, mesh_cache)
+    }
+
+    fn draw_circles(
+        &self,
+        curr_time: Beats,
+        rotated_about: Option<(WorldPos, f64)>,
+        animation: &EnemyAnimation,
+    ) -> Option<Vec<Circle>> {
+        let animation = EnemyAnimation {
+            despawn: None,
+            ..animation.clone()
+        };
+        self.0.draw_circles(curr_time, rotated_about, &animation)
+    }
+
+    fn draw_extra(
+        &self,
+        ctx: &mut Context,
+        curr_time: Beats,
+        rotated_about: Option<(WorldPos, f64)>,
+        animation: &EnemyAnimation,
+    ) -> GameResult<Option<(Mesh, DrawParam)>> {
+        let animation = EnemyAnimation {
+            despawn: None,
+            ..animation.clone()
+        };
+        self.0.draw_extra(ctx, curr_time, rotated_about, &animation)
+    }
+
+    fn sdf(
+        &self,
+        pos: WorldPos,
+        curr_time: Beats,
+        rotated_about: Option<(WorldPos, f64)>,
+    ) -> Option<WorldLen> {
+        self.0.sdf(pos, curr_time, rotated_about)
+    }
+
+    fn sdf_gradient(
+        &self,
+        pos: WorldPos,
+        curr_time: Beats,
+        rotated_about: Option<(WorldPos, f64)>,
+    ) -> Option<(f64, f64)> {
+        self.0.sdf_gradient(pos, curr_time, rotated_about)
+    }
+
+    fn lifetime_state(&self, curr_time: Beats) -> EnemyLifetime {
+        self.0.lifetime_state(curr_time)
+    }
+
+    fn end_time(&self) -> Beats {
+        self.0.end_time()
+    }
+
+    fn active_range(&self) -> (Beats, Beats) {
+        self.0.active_range()
+    }
+
+    fn position(&self, curr_time: Beats, rotated_about: Option<(WorldPos, f64)>) -> WorldPos {
+        self.0.position(curr_time, rotated_about)
+    }
+
+    fn despawns(&self) -> bool {
+        false
+    }
+
+    fn bounds(&self, curr_time: Beats) -> (WorldPos, WorldLen) {
+        self.0.bounds(curr_time)
+    }
+
+    fn on_screen(&self, curr_time: Beats, rotated_about: Option<(WorldPos, f64)>) -> bool {
+        self.0.on_screen(curr_time, rotated_about)
+    }
+
+    fn could_touch(
+        &self,
+        pos: WorldPos,
+        radius: WorldLen,
+        curr_time: Beats,
+        rotated_about: Option<(WorldPos, f64)>,
+    ) -> bool {
+        self.0.could_touch(pos, radius, curr_time, rotated_about)
+    }
+
+    fn on_player_hit(&mut self, curr_time: Beats) {
+        self.0.on_player_hit(curr_time)
+    }
+
+    fn spawn_children(
+        &mut self,
+        curr_time: Beats,
+        player_pos: WorldPos,
+        rotated_about: Option<(WorldPos, f64)>,
+    ) -> Vec<Box<dyn Enemy>> {
+        self.0.spawn_children(curr_time, player_pos, rotated_about)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub struct EnemyDurations {
     pub warmup: Beats,   // The amount of time to show a warmup warning
     pub active: Beats,   // The amount of time to actually do hit detection
@@ -204,21 +909,57 @@ impl EnemyDurations {
     }
 }
 
+/// The visual style of a bullet. This has no effect on the bullet's hitbox.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct BulletStyle {
+    /// How far the glow extends past the bullet at the start of each beat. If
+    /// None, the glow is not drawn.
+    pub glow_size: Option<WorldLen>,
+    /// If true, a guide line and circle show where the bullet is headed.
+    pub show_guide: bool,
+    /// The thickness of the guide line and circle.
+    pub outline_thickness: f32,
+    #[serde(with = "color::serde_color")]
+    pub fill_color: Color,
+    #[serde(with = "color::serde_color")]
+    pub guide_color: Color,
+    #[serde(with = "color::serde_color")]
+    pub glow_color: Color,
+}
+
+impl Default for BulletStyle {
+    fn default() -> Self {
+        BulletStyle {
+            glow_size: Some(WorldLen(5.0)),
+            show_guide: true,
+            outline_thickness: OUTLINE_THICKNESS,
+            fill_color: RED,
+            guide_color: color::GREEN,
+            glow_color: RED,
+        }
+    }
+}
+
 /// A bullet is a simple enemy that moves from point A to point B in some amount
 /// of time. It also has a cool glowy decoration thing for cool glowiness.
-// TODO: Add a predelay for fairness
+/// Bullets are consumed by hitting the player, which ends them early. Before
+/// setting off, bullets spend their warmup at their start position with their
+/// path previewed and their telegraph shown, so that they never come out of
+/// nowhere.
 #[derive(Debug)]
 pub struct Bullet {
-    // Position bullet started from
-    start_pos: WorldPos,
-    // Position bullet must end up at
-    end_pos: WorldPos,
-    // The start of bullet existance.
+    path: Path,
+    // The start of bullet existance. The bullet starts moving after its warmup.
     start_time: Beats,
-    // Time over which this bullet lives, in beats.
+    warmup: Beats,
+    // Time over which this bullet moves along its path, in beats.
     duration: Beats,
     // The radius of this bullet, in World space
     size: WorldLen,
+    style: BulletStyle,
+    telegraph: TelegraphStyle,
+    // When the bullet hit the player, if it has.
+    consumed_at: Option<Beats>,
 }
 
 impl Bullet {
@@ -226,83 +967,119 @@ impl Bullet {
         start_pos: WorldPos,
         end_pos: WorldPos,
         start_time: Beats,
+        warmup: Beats,
         duration: Beats,
         size: WorldLen,
+        style: BulletStyle,
+        telegraph: TelegraphStyle,
     ) -> Bullet {
         Bullet {
-            start_pos,
-            end_pos,
+            path: Path::Line {
+                start: start_pos,
+                end: end_pos,
+            },
             start_time,
+            warmup,
             duration,
             size,
+            style,
+            telegraph,
+            consumed_at: None,
         }
     }
 
+    /// Make the bullet curve towards `control` on its way to its end, rather
+    /// than moving in a straight line.
+    pub fn curve_towards(&mut self, control: WorldPos) {
+        let (start, end) = (self.path.pos(0.0), self.path.end());
+        self.path = Path::Bezier {
+            start,
+            control,
+            end,
+        };
+    }
+
+    /// Return how far along its path the bullet is, from 0 to 1.
+    fn percent_along(&self, curr_time: Beats) -> f64 {
+        let moving_time = self.delta_time(curr_time) - self.warmup;
+        (moving_time.0 / self.duration.0).clamp(0.0, 1.0)
+    }
+
     fn pos(&self, curr_time: Beats) -> WorldPos {
-        let delta_time = self.delta_time(curr_time);
-        let total_percent = delta_time.0 / self.duration.0;
-        WorldPos::lerp(self.start_pos, self.end_pos, total_percent)
+        self.path.pos(self.percent_along(curr_time))
     }
 }
 
 impl EnemyImpl for Bullet {
-    fn update(&mut self, _curr_time: Beats) {}
+    fn update(&mut self, _curr_time: Beats, _player_pos: WorldPos) {}
 
     fn sdf(&self, pos: WorldPos, curr_time: Beats) -> WorldLen {
         WorldPos::distance(pos, self.pos(curr_time)) - self.size
     }
 
-    fn get_mesh(&self, ctx: &mut Context, curr_time: Beats) -> GameResult<Mesh> {
-        let origin = WorldPos::origin().as_mint();
-        let pos = self.pos(curr_time);
-        let end_pos = WorldPos::from((self.end_pos.x - pos.x, self.end_pos.y - pos.y)).as_mint();
+    fn sdf_gradient(&self, pos: WorldPos, curr_time: Beats) -> (f64, f64) {
+        direction_from(self.pos(curr_time), pos)
+    }
 
-        let guide_radius = self.size.0 as f32;
+    fn get_mesh(&self, ctx: &mut Context, curr_time: Beats) -> GameResult<Mesh> {
+        let warming_up = self.lifetime_state(curr_time) == EnemyLifetime::Warmup;
 
-        // Draw the guide circle
         let mut mesh = MeshBuilder::new();
-        mesh.circle(
-            DrawMode::stroke(OUTLINE_THICKNESS),
-            end_pos,
-            guide_radius,
-            TOLERANCE,
-            crate::color::GREEN,
-        )?;
-        // Draw the green guide line
-        let cg_origin = util::into_cg(origin);
-        let cg_end_pos = util::into_cg(end_pos);
-        let distance = cg_origin.distance(cg_end_pos);
-        if distance > guide_radius {
-            let scale_factor = (distance - guide_radius) / distance;
-            let cg_delta = (cg_end_pos - cg_origin) * scale_factor;
-            mesh.line(
-                &[origin, util::into_mint(cg_origin + cg_delta)],
-                OUTLINE_THICKNESS,
-                crate::color::GREEN,
+        if self.style.show_guide {
+            self.add_guide(&mut mesh, curr_time)?;
+        }
+
+        if warming_up && self.telegraph != TelegraphStyle::Default {
+            telegraph::draw_telegraph(
+                &mut mesh,
+                self.telegraph,
+                TelegraphShape::Circle {
+                    radius: self.size.0 as f32,
+                },
+                self.percent_over_curr_state(curr_time),
             )?;
+            return mesh.build(ctx);
         }
 
-        // Draw the bullet itself.
-        mesh.circle(DrawMode::fill(), origin, self.size.0 as f32, TOLERANCE, RED)?;
+        for circle in self.body(curr_time) {
+            meshutil::circle(
+                &mut mesh,
+                DrawMode::fill(),
+                circle.center,
+                circle.radius,
+                WORLD_SCALE_FACTOR,
+                circle.color,
+            )?;
+        }
+        mesh.build(ctx)
+    }
 
-        // transparent glow
-        let (glow_size, glow_trans) = self.glow(curr_time);
-        let glow_color = Color::new(1.0, 0.0, 0.0, glow_trans);
-        mesh.circle(
-            DrawMode::fill(),
-            origin,
-            glow_size.0 as f32,
-            TOLERANCE,
-            glow_color,
-        )?;
+    fn circles(&self, curr_time: Beats) -> Option<Vec<Circle>> {
+        // Telegraphs are drawn instead of the body, so they need the mesh.
+        let warming_up = self.lifetime_state(curr_time) == EnemyLifetime::Warmup;
+        if warming_up && self.telegraph != TelegraphStyle::Default {
+            return None;
+        }
+        Some(self.body(curr_time))
+    }
 
-        mesh.build(ctx)
+    fn get_extra_mesh(&self, ctx: &mut Context, curr_time: Beats) -> GameResult<Option<Mesh>> {
+        if !self.style.show_guide {
+            return Ok(None);
+        }
+        let mut mesh = MeshBuilder::new();
+        self.add_guide(&mut mesh, curr_time)?;
+        Ok(Some(mesh.build(ctx)?))
     }
 
     fn durations(&self) -> EnemyDurations {
+        let active = match self.consumed_at {
+            Some(consumed_at) => consumed_at - self.start_time - self.warmup,
+            None => self.duration,
+        };
         EnemyDurations {
-            warmup: Beats(0.0),
-            active: self.duration,
+            warmup: self.warmup,
+            active,
             cooldown: Beats(0.0),
         }
     }
@@ -314,14 +1091,99 @@ impl EnemyImpl for Bullet {
     fn position_info(&self, curr_time: Beats) -> (WorldPos, f64) {
         (self.pos(curr_time), 0.0)
     }
-}
+
+    fn bounds(&self, curr_time: Beats) -> (WorldPos, WorldLen) {
+        let glow = self.style.glow_size.unwrap_or(WorldLen(0.0));
+        let radius = self.size + glow + WorldLen(self.style.outline_thickness as f64);
+        let radius = if self.telegraph == TelegraphStyle::Default {
+            radius
+        } else {
+            WorldLen(radius.0.max(self.size.0 * COUNTDOWN_TELEGRAPH_SCALE))
+        };
+        if !self.style.show_guide {
+            return (self.pos(curr_time), radius);
+        }
+        // The preview goes all the way to the end of the path, so the circle
+        // needs to cover the rest of the path, along with the preview's tick.
+        let (center, path_radius) = self.path.bounds(self.percent_along(curr_time));
+        (
+            center,
+            path_radius + radius + WorldLen(path::TICK_HALF_LENGTH),
+        )
+    }
+
+    fn on_player_hit(&mut self, curr_time: Beats) {
+        self.consumed_at.get_or_insert(curr_time);
+    }
+}
 
 impl Bullet {
-    fn glow(&self, curr_time: Beats) -> (WorldLen, f32) {
+    /// Add the preview of the bullet's path to `mesh`. During the warmup, the
+    /// tick runs along the path to show which way the bullet is about to go.
+    fn add_guide(&self, mesh: &mut MeshBuilder, curr_time: Beats) -> GameResult<()> {
+        let warming_up = self.lifetime_state(curr_time) == EnemyLifetime::Warmup;
+        let preview = PathPreview {
+            from: self.percent_along(curr_time),
+            tick: if warming_up {
+                Some(self.percent_over_curr_state(curr_time))
+            } else {
+                None
+            },
+            end_radius: self.size.0 as f32,
+            thickness: self.style.outline_thickness,
+            color: self.style.guide_color,
+        };
+        preview.draw(mesh, &self.path, self.pos(curr_time))
+    }
+
+    /// Return the circles the bullet itself is drawn with, without its guide
+    /// or telegraph. The bullet fades in over its warmup, and its glow is only
+    /// drawn once it is moving.
+    fn body(&self, curr_time: Beats) -> Vec<Circle> {
+        let center = WorldPos::origin().as_mint();
+        let warming_up = self.lifetime_state(curr_time) == EnemyLifetime::Warmup;
+        let fill_color = if warming_up {
+            let t = self.percent_over_curr_state(curr_time) as f32;
+            Color {
+                a: self.style.fill_color.a * t,
+                ..self.style.fill_color
+            }
+        } else {
+            self.style.fill_color
+        };
+        let mut circles = vec![Circle {
+            center,
+            radius: self.size.0 as f32,
+            color: fill_color,
+        }];
+
+        // transparent glow
+        let glow = if warming_up {
+            None
+        } else {
+            self.glow(curr_time)
+        };
+        if let Some((glow_size, glow_trans)) = glow {
+            circles.push(Circle {
+                center,
+                radius: glow_size.0 as f32,
+                color: Color {
+                    a: self.style.glow_color.a * glow_trans,
+                    ..self.style.glow_color
+                },
+            });
+        }
+        circles
+    }
+
+    /// Returns the size and transparency of the glow, or None if the glow is
+    /// disabled.
+    fn glow(&self, curr_time: Beats) -> Option<(WorldLen, f32)> {
+        let max_glow = self.style.glow_size?;
         let percent = curr_time.0 % 1.0;
-        let glow_size = self.size + WorldLen(5.0 * crate::util::rev_quartic(percent));
+        let glow_size = self.size + WorldLen(max_glow.0 * crate::util::rev_quartic(percent));
         let glow_trans = 0.5 * (1.0 - percent as f32).powi(4);
-        (glow_size, glow_trans)
+        Some((glow_size, glow_trans))
     }
 }
 
@@ -329,6 +1191,7 @@ impl Bullet {
 /// Predelay - The warning for the player before the laser activates.
 /// Active - The laser is actively firing and can hurt the player.
 /// Cooldown - The laser is over and the last bits of the laser are fading out.
+/// Lasers briefly flare whenever they hit the player.
 pub struct Laser {
     // The start time of this laser. Note that this is when the laser starts to
     // appear on screen (ie: when the Predelay phase occurs)
@@ -345,7 +1208,28 @@ pub struct Laser {
     hitbox_thickness: WorldLen,  // In World space
     position: WorldPos,
     angle: f64,
+    telegraph: TelegraphStyle,
+    render: LaserRender,
+    // When the laser last hit the player, if it has.
+    flared_at: Option<Beats>,
 }
+
+/// How a laser is drawn. This has no effect on the laser's hitbox.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LaserRender {
+    /// A solid line for the hitbox, surrounded by a solid outline.
+    Flat,
+    /// A bright core exactly as thick as the hitbox, with a glow which fades
+    /// out towards the edge of the outline and flickers along the beam.
+    Glow,
+}
+
+impl Default for LaserRender {
+    fn default() -> Self {
+        LaserRender::Flat
+    }
+}
+
 impl Laser {
     /// Create a new laser going through the given points.
     /// start_time marks when the predelay phase of the laser occurs. Note that
@@ -357,6 +1241,8 @@ impl Laser {
         durations: EnemyDurations,
         outline_colors: &[Easing<Color>; 4],
         outline_keyframes: &[Easing<f64>; 3],
+        telegraph: TelegraphStyle,
+        render: LaserRender,
     ) -> Laser {
         let dx = a.x - b.x;
         let dy = a.y - b.y;
@@ -369,6 +1255,8 @@ impl Laser {
             durations,
             outline_colors,
             outline_keyframes,
+            telegraph,
+            render,
         )
     }
 
@@ -379,6 +1267,8 @@ impl Laser {
         durations: EnemyDurations,
         outline_colors: &[Easing<Color>; 4],
         outline_keyframes: &[Easing<f64>; 3],
+        telegraph: TelegraphStyle,
+        render: LaserRender,
     ) -> Laser {
         Laser {
             start_time,
@@ -401,6 +1291,18 @@ impl Laser {
             width: WorldLen(300.0),
             outline_thickness: WorldLen(0.0),
             hitbox_thickness: WorldLen(0.0),
+            telegraph,
+            render,
+            flared_at: None,
+        }
+    }
+
+    /// Return how strongly the laser is flaring, from 1 right after it hits
+    /// the player to 0 once the flare is over.
+    fn flare(&self, curr_time: Beats) -> f64 {
+        match self.flared_at {
+            Some(flared_at) => (1.0 - (curr_time - flared_at).0 / LASER_FLARE.0).clamp(0.0, 1.0),
+            None => 0.0,
         }
     }
 
@@ -419,7 +1321,8 @@ impl Laser {
             EnemyLifetime::Cooldown => (3, self.durations.percent_over_cooldown(delta_time)),
             _ => unreachable!(),
         };
-        self.outline_colors[index].ease(percent)
+        let color = self.outline_colors[index].ease(percent);
+        Color::lerp(color, WHITE, self.flare(curr_time))
     }
 
     pub fn default_outline_color() -> [Easing<Color>; 4] {
@@ -443,7 +1346,7 @@ impl Laser {
 }
 
 impl EnemyImpl for Laser {
-    fn update(&mut self, curr_time: Beats) {
+    fn update(&mut self, curr_time: Beats, _player_pos: WorldPos) {
         let delta_time = self.delta_time(curr_time);
 
         let state = self.lifetime_state(curr_time);
@@ -461,7 +1364,23 @@ impl EnemyImpl for Laser {
     fn get_mesh(&self, ctx: &mut Context, curr_time: Beats) -> GameResult<Mesh> {
         let length = self.width.0 as f32;
         let hitbox_thickness = self.hitbox_thickness.0 as f32;
-        let outline_thickness = self.outline_thickness.0 as f32;
+        let flare_scale = f64::lerp(1.0, LASER_FLARE_SCALE, self.flare(curr_time));
+        let outline_thickness = (self.outline_thickness.0 * flare_scale) as f32;
+
+        if self.telegraph != TelegraphStyle::Default
+            && self.lifetime_state(curr_time) == EnemyLifetime::Warmup
+        {
+            let mut mesh = MeshBuilder::new();
+            // The thickest the hitbox will get, which is right when it fires.
+            let thickness = self.hitbox_keyframes[1].start as f32;
+            telegraph::draw_telegraph(
+                &mut mesh,
+                self.telegraph,
+                TelegraphShape::Line { length, thickness },
+                self.percent_over_curr_state(curr_time),
+            )?;
+            return mesh.build(ctx);
+        }
 
         fn draw_laser_rect(
             mesh: &mut MeshBuilder,
@@ -469,12 +1388,23 @@ impl EnemyImpl for Laser {
             thickness: f32,
             color: Color,
         ) -> GameResult<()> {
-            let points = [util::mint(-length, 0.0), util::mint(length, 0.0)];
-            // Multiply by two here so that the laser is of appropriate thickness.
-            mesh.line(&points, thickness * 2.0, color)?;
-            Ok(())
+            let (a, b) = (util::mint(-length, 0.0), util::mint(length, 0.0));
+            meshutil::capsule(mesh, a, b, thickness, WORLD_SCALE_FACTOR, color)
         }
         let mut mesh = MeshBuilder::new();
+        // Glows are skipped if the frame budget is being exceeded, in which
+        // case the laser is drawn flat.
+        if self.render == LaserRender::Glow && !budget::degradation().skip_glow() {
+            draw_laser_glow(
+                &mut mesh,
+                length,
+                hitbox_thickness,
+                outline_thickness,
+                self.outline_color(curr_time),
+                curr_time,
+            )?;
+            return mesh.build(ctx);
+        }
         // outline
         draw_laser_rect(
             &mut mesh,
@@ -498,6 +1428,18 @@ impl EnemyImpl for Laser {
         WorldLen(dist_to_laser) - width
     }
 
+    fn sdf_gradient(&self, pos: WorldPos, _curr_time: Beats) -> (f64, f64) {
+        // Away from the closest point on the line.
+        let (unit_x, unit_y) = (self.angle.cos(), self.angle.sin());
+        let (x, y) = (pos.x - self.position.x, pos.y - self.position.y);
+        let along = x * unit_x + y * unit_y;
+        let closest = WorldPos {
+            x: self.position.x + along * unit_x,
+            y: self.position.y + along * unit_y,
+        };
+        direction_from(closest, pos)
+    }
+
     fn durations(&self) -> EnemyDurations {
         self.durations
     }
@@ -509,22 +1451,185 @@ impl EnemyImpl for Laser {
     fn position_info(&self, _curr_time: Beats) -> (WorldPos, f64) {
         (self.position, self.angle)
     }
+
+    fn bounds(&self, _curr_time: Beats) -> (WorldPos, WorldLen) {
+        // The laser extends `width` in both directions. The thickest it can be
+        // drawn is either its glow while flaring, its hitbox, or its telegraph.
+        let glow = 1.0 + LASER_GLOW_NOISE as f64;
+        let thickness = (self.outline_thickness.0 * glow * LASER_FLARE_SCALE)
+            .max(self.hitbox_thickness.0)
+            .max(self.hitbox_keyframes[1].start);
+        (self.position, self.width + WorldLen(thickness))
+    }
+
+    fn on_player_hit(&mut self, curr_time: Beats) {
+        self.flared_at = Some(curr_time);
+    }
+}
+
+/// How a SweepingLaser moves over its active phase. Each easing is an offset
+/// from where the laser was spawned, with angles in radians. Anything left as
+/// None doesn't move.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LaserSweep {
+    pub x: Option<Easing<f64>>,
+    pub y: Option<Easing<f64>>,
+    pub angle: Option<Easing<f64>>,
+}
+
+/// A laser which sweeps across the arena while it fires. The laser stays at
+/// the start of its sweep during its warmup, so that the telegraph shows where
+/// it will fire from, and stays at the end of its sweep during its cooldown.
+pub struct SweepingLaser {
+    laser: Laser,
+    sweep: LaserSweep,
+    // Where the laser was spawned, which the sweep is relative to.
+    position: WorldPos,
+    angle: f64,
+}
+
+impl SweepingLaser {
+    pub fn new(laser: Laser, sweep: LaserSweep) -> SweepingLaser {
+        let (position, angle) = (laser.position, laser.angle);
+        let mut sweeping_laser = SweepingLaser {
+            laser,
+            sweep,
+            position,
+            angle,
+        };
+        sweeping_laser.move_to(0.0);
+        sweeping_laser
+    }
+
+    /// Move the laser to the point `t` of the way through its sweep.
+    fn move_to(&mut self, t: f64) {
+        let offset = |easing: &Option<Easing<f64>>| easing.as_ref().map_or(0.0, |e| e.ease(t));
+        self.laser.position = WorldPos {
+            x: self.position.x + offset(&self.sweep.x),
+            y: self.position.y + offset(&self.sweep.y),
+        };
+        self.laser.angle = self.angle + offset(&self.sweep.angle);
+    }
+}
+
+impl EnemyImpl for SweepingLaser {
+    fn update(&mut self, curr_time: Beats, player_pos: WorldPos) {
+        let t = match self.lifetime_state(curr_time) {
+            EnemyLifetime::Warmup => 0.0,
+            EnemyLifetime::Active => self.percent_over_curr_state(curr_time),
+            _ => 1.0,
+        };
+        self.move_to(t);
+        EnemyImpl::update(&mut self.laser, curr_time, player_pos);
+    }
+
+    fn get_mesh(&self, ctx: &mut Context, curr_time: Beats) -> GameResult<Mesh> {
+        self.laser.get_mesh(ctx, curr_time)
+    }
+
+    fn sdf(&self, pos: WorldPos, curr_time: Beats) -> WorldLen {
+        EnemyImpl::sdf(&self.laser, pos, curr_time)
+    }
+
+    fn sdf_gradient(&self, pos: WorldPos, curr_time: Beats) -> (f64, f64) {
+        EnemyImpl::sdf_gradient(&self.laser, pos, curr_time)
+    }
+
+    fn durations(&self) -> EnemyDurations {
+        self.laser.durations
+    }
+
+    fn start_time(&self) -> Beats {
+        self.laser.start_time
+    }
+
+    fn position_info(&self, curr_time: Beats) -> (WorldPos, f64) {
+        self.laser.position_info(curr_time)
+    }
+
+    fn bounds(&self, curr_time: Beats) -> (WorldPos, WorldLen) {
+        EnemyImpl::bounds(&self.laser, curr_time)
+    }
+
+    fn on_player_hit(&mut self, curr_time: Beats) {
+        EnemyImpl::on_player_hit(&mut self.laser, curr_time)
+    }
+}
+
+/// Draw a glowing laser along the x axis. The core is drawn `core_thickness`
+/// out from the center in white, and the glow fades from `glow_color` at the
+/// edge of the core to transparent at roughly `glow_thickness` out. The glow's
+/// width is varied along the beam by a noise which moves over time.
+fn draw_laser_glow(
+    mesh: &mut MeshBuilder,
+    length: f32,
+    core_thickness: f32,
+    glow_thickness: f32,
+    glow_color: Color,
+    curr_time: Beats,
+) -> GameResult<()> {
+    let vertex = |x: f32, y: f32, color: Color| Vertex {
+        pos: [x, y],
+        uv: [0.0, 0.0],
+        color: [color.r, color.g, color.b, color.a],
+    };
+    let faded = Color {
+        a: 0.0,
+        ..glow_color
+    };
+    let t = curr_time.0 as f32;
+
+    // Each column of the beam has six vertices, going from the bottom edge of
+    // the glow to the top edge. The core's vertices are doubled up so that the
+    // core has a sharp edge rather than blending into the glow.
+    let mut verts = Vec::with_capacity((LASER_GLOW_SEGMENTS + 1) * 6);
+    for i in 0..=LASER_GLOW_SEGMENTS {
+        let x = -length + 2.0 * length * (i as f32 / LASER_GLOW_SEGMENTS as f32);
+        let noise = ((x * 0.15 + t * 3.0).sin() + 0.5 * (x * 0.37 - t * 5.0).sin()) / 1.5;
+        let glow = (glow_thickness * (1.0 + LASER_GLOW_NOISE * noise)).max(core_thickness);
+        verts.push(vertex(x, -glow, faded));
+        verts.push(vertex(x, -core_thickness, glow_color));
+        verts.push(vertex(x, -core_thickness, WHITE));
+        verts.push(vertex(x, core_thickness, WHITE));
+        verts.push(vertex(x, core_thickness, glow_color));
+        verts.push(vertex(x, glow, faded));
+    }
+
+    let mut indices = Vec::with_capacity(LASER_GLOW_SEGMENTS * 18);
+    for i in 0..LASER_GLOW_SEGMENTS as u32 {
+        let (left, right) = (i * 6, (i + 1) * 6);
+        // The bottom glow, the core, and the top glow.
+        for &row in [0, 2, 4].iter() {
+            let (a, b, c, d) = (left + row, left + row + 1, right + row, right + row + 1);
+            indices.extend_from_slice(&[a, b, c, b, d, c]);
+        }
+    }
+    mesh.raw(&verts, &indices, None)?;
+    Ok(())
 }
 
+/// A circle which grows to its full size over its first quarter beat of being
+/// active. Bombs detonate as soon as they hit the player, which skips the rest
+/// of their active phase.
 pub struct CircleBomb {
     // The start time of this laser. Note that this is when the laser starts to
     // appear on screen (ie: when the Predelay phase occurs)
     start_time: Beats,
     position: WorldPos,
     max_radius: WorldLen,
+    telegraph: TelegraphStyle,
+    // When the bomb hit the player, if it has.
+    detonated_at: Option<Beats>,
 }
 
 impl CircleBomb {
-    pub fn new(start_time: Beats, position: WorldPos) -> CircleBomb {
+    pub fn new(start_time: Beats, position: WorldPos, telegraph: TelegraphStyle) -> CircleBomb {
         CircleBomb {
             start_time,
             position,
             max_radius: WorldLen(10.0),
+            telegraph,
+            detonated_at: None,
         }
     }
 
@@ -544,9 +1649,13 @@ impl CircleBomb {
 
 impl EnemyImpl for CircleBomb {
     fn durations(&self) -> EnemyDurations {
+        let active = match self.detonated_at {
+            Some(detonated_at) => detonated_at - self.start_time - BOMB_WARMUP,
+            None => Beats(1.0),
+        };
         EnemyDurations {
             warmup: BOMB_WARMUP,
-            active: Beats(1.0),
+            active,
             cooldown: Beats(0.25),
         }
     }
@@ -559,7 +1668,11 @@ impl EnemyImpl for CircleBomb {
         WorldPos::distance(pos, self.position) - self.radius(curr_time)
     }
 
-    fn update(&mut self, _curr_time: Beats) {
+    fn sdf_gradient(&self, pos: WorldPos, _curr_time: Beats) -> (f64, f64) {
+        direction_from(self.position, pos)
+    }
+
+    fn update(&mut self, _curr_time: Beats, _player_pos: WorldPos) {
         // Nothing lmao
     }
 
@@ -568,6 +1681,20 @@ impl EnemyImpl for CircleBomb {
         let origin = WorldPos::origin().as_mint();
         let t = self.percent_over_curr_state(curr_time);
 
+        if self.telegraph != TelegraphStyle::Default
+            && self.lifetime_state(curr_time) == EnemyLifetime::Warmup
+        {
+            telegraph::draw_telegraph(
+                &mut mesh,
+                self.telegraph,
+                TelegraphShape::Circle {
+                    radius: self.max_radius.0 as f32,
+                },
+                t,
+            )?;
+            return mesh.build(ctx);
+        }
+
         // outline
         let outline_radius = self.max_radius.0 as f32;
         let outline_color = match self.lifetime_state(curr_time) {
@@ -577,11 +1704,12 @@ impl EnemyImpl for CircleBomb {
             _ => unreachable!(),
         };
 
-        mesh.circle(
+        meshutil::circle(
+            &mut mesh,
             DrawMode::stroke(OUTLINE_THICKNESS),
             origin,
             outline_radius,
-            TOLERANCE,
+            WORLD_SCALE_FACTOR,
             outline_color,
         )?;
 
@@ -600,119 +1728,1299 @@ impl EnemyImpl for CircleBomb {
             _ => unreachable!(),
         };
 
-        mesh.circle(
+        meshutil::circle(
+            &mut mesh,
             DrawMode::fill(),
             origin,
             inner_radius,
-            TOLERANCE,
+            WORLD_SCALE_FACTOR,
             inner_color,
         )?;
 
         mesh.build(ctx)
     }
 
+    fn shape(&self) -> Option<EnemyShape> {
+        Some(EnemyShape::CircleBomb {
+            max_radius: shape_len(self.max_radius),
+            telegraph: self.telegraph,
+        })
+    }
+
     fn position_info(&self, _curr_time: Beats) -> (WorldPos, f64) {
         (self.position, 0.0)
     }
+
+    fn bounds(&self, curr_time: Beats) -> (WorldPos, WorldLen) {
+        // Telegraphs are drawn during the warmup instead of the bomb, and a
+        // CountdownRing starts out larger than the bomb.
+        let radius = if self.lifetime_state(curr_time) == EnemyLifetime::Warmup
+            && self.telegraph != TelegraphStyle::Default
+        {
+            self.max_radius.0 * COUNTDOWN_TELEGRAPH_SCALE
+        } else {
+            self.max_radius.0
+        };
+        (self.position, WorldLen(radius + OUTLINE_THICKNESS as f64))
+    }
+
+    fn on_player_hit(&mut self, curr_time: Beats) {
+        self.detonated_at.get_or_insert(curr_time);
+    }
 }
 
-/// Return the shortest distance from `pos` to the line defined by `line_pos`
-/// and `angle`. `angle` is in radians and measure the angle between a horizontal
-/// line and the line in question.
-pub fn shortest_distance_to_line(
-    pos: impl Into<cg::Point2<f64>>,
-    line_pos: impl Into<cg::Point2<f64>>,
+/// A rectangle which grows to its full size over its first quarter beat of
+/// being active, the same way a CircleBomb does. The rectangle is centered on
+/// `position` and rotated counterclockwise by `angle` radians.
+pub struct RectBomb {
+    start_time: Beats,
+    position: WorldPos,
+    // Half of the full width and height of the rectangle, before rotation.
+    half_width: WorldLen,
+    half_height: WorldLen,
     angle: f64,
-) -> f64 {
-    let pos = pos.into();
-    let line_pos = line_pos.into();
-    // We have the vector LP,
-    #[allow(non_snake_case)]
-    let LP_vec = pos - line_pos;
-    // The unit vector along the laser
-    let laser_unit_vec = cg::Vector2::new(angle.cos(), angle.sin());
+    telegraph: TelegraphStyle,
+    // When the bomb hit the player, if it has.
+    detonated_at: Option<Beats>,
+}
 
-    // We now find the angle between the two vectors
-    let dot_prod = LP_vec.dot(laser_unit_vec);
+impl RectBomb {
+    pub fn new(
+        start_time: Beats,
+        position: WorldPos,
+        width: WorldLen,
+        height: WorldLen,
+        angle: f64,
+        telegraph: TelegraphStyle,
+    ) -> RectBomb {
+        RectBomb {
+            start_time,
+            position,
+            half_width: WorldLen(width.0 / 2.0),
+            half_height: WorldLen(height.0 / 2.0),
+            angle,
+            telegraph,
+            detonated_at: None,
+        }
+    }
 
-    // Project LP_vec along laser_unit_vec
-    let proj = dot_prod * laser_unit_vec;
+    /// Return how much of its full size the hitbox is, from 0 to 1.
+    fn growth(&self, curr_time: Beats) -> f64 {
+        match self.lifetime_state(curr_time) {
+            EnemyLifetime::Active => {
+                let t = self
+                    .durations()
+                    .percent_over_active(self.delta_time(curr_time));
+                (t * 4.0).clamp(0.0, 1.0)
+            }
+            _ => 0.0,
+        }
+    }
 
-    // Now get the perpendicular, this is the distance to the laser.
-    let perp = LP_vec - proj;
-    perp.magnitude()
+    /// Return `pos` relative to the center of the rectangle, in the
+    /// rectangle's unrotated coordinates.
+    fn to_local(&self, pos: WorldPos) -> (f64, f64) {
+        let (x, y) = (pos.x - self.position.x, pos.y - self.position.y);
+        let (sin, cos) = self.angle.sin_cos();
+        (x * cos + y * sin, -x * sin + y * cos)
+    }
 }
 
-/// Rotate `point` about `rot_point` by `rot_angle` radians.
-pub fn rotate_point(point: WorldPos, rot_point: WorldPos, rot_angle: f64) -> WorldPos {
-    // first translate the point so that the rotation point is at the origin
-    let (pos_x, pos_y) = (point.x - rot_point.x, point.y - rot_point.y);
-    // now rotate the point by rot_angle
-    let (pos_x, pos_y) = (
-        pos_x * rot_angle.cos() - pos_y * rot_angle.sin(),
-        pos_x * rot_angle.sin() + pos_y * rot_angle.cos(),
-    );
-    // finally translate the point back
-    let (pos_x, pos_y) = (pos_x + rot_point.x, pos_y + rot_point.y);
-
-    WorldPos::from((pos_x, pos_y))
+/// Return the signed distance from `pos` to an axis-aligned box centered on
+/// the origin, which extends `half_size` in each direction. This is negative
+/// inside of the box.
+pub fn box_sdf(pos: (f64, f64), half_size: (f64, f64)) -> f64 {
+    let (dx, dy) = (pos.0.abs() - half_size.0, pos.1.abs() - half_size.1);
+    let outside = dx.max(0.0).hypot(dy.max(0.0));
+    let inside = dx.max(dy).min(0.0);
+    outside + inside
 }
 
-#[cfg(test)]
-mod test {
-    use crate::enemy::shortest_distance_to_line;
-    use cg::EuclideanSpace;
-    use cgmath as cg;
-
-    macro_rules! assert_eq_delta {
-        ($x:expr, $y:expr) => {
-            if ($x - $y).abs() > 0.0001 {
-                panic!("{:?} does not approx. equal {:?}", $x, $y);
-            }
+impl EnemyImpl for RectBomb {
+    fn durations(&self) -> EnemyDurations {
+        let active = match self.detonated_at {
+            Some(detonated_at) => detonated_at - self.start_time - BOMB_WARMUP,
+            None => Beats(1.0),
         };
+        EnemyDurations {
+            warmup: BOMB_WARMUP,
+            active,
+            cooldown: Beats(0.25),
+        }
     }
 
-    #[test]
-    pub fn test_shortest_distance_to_line_horiz() {
-        let pi = std::f64::consts::PI;
-        let sqrt_3 = 3.0_f64.sqrt();
+    fn start_time(&self) -> Beats {
+        self.start_time
+    }
 
-        let origin = cg::Point2::<f64>::origin();
-        let pos = cg::Point2::new(1.0, sqrt_3);
-        assert_eq_delta!(shortest_distance_to_line(pos, origin, 0.0), pos.y.abs());
+    fn sdf(&self, pos: WorldPos, curr_time: Beats) -> WorldLen {
+        let growth = self.growth(curr_time);
+        let half_size = (self.half_width.0 * growth, self.half_height.0 * growth);
+        WorldLen(box_sdf(self.to_local(pos), half_size))
+    }
 
-        let pos = cg::Point2::new(1.0, -sqrt_3);
-        assert_eq_delta!(shortest_distance_to_line(pos, origin, pi), pos.y.abs());
+    fn update(&mut self, _curr_time: Beats, _player_pos: WorldPos) {}
 
-        let pos = cg::Point2::new(1.0, -sqrt_3);
-        assert_eq_delta!(
-            shortest_distance_to_line(pos, origin, 2.0 * pi),
-            pos.y.abs()
-        );
+    fn get_mesh(&self, ctx: &mut Context, curr_time: Beats) -> GameResult<Mesh> {
+        let mut mesh = MeshBuilder::new();
+        let t = self.percent_over_curr_state(curr_time);
+        let (half_width, half_height) = (self.half_width.0 as f32, self.half_height.0 as f32);
+
+        if self.telegraph != TelegraphStyle::Default
+            && self.lifetime_state(curr_time) == EnemyLifetime::Warmup
+        {
+            telegraph::draw_telegraph(
+                &mut mesh,
+                self.telegraph,
+                TelegraphShape::Rect {
+                    half_width,
+                    half_height,
+                },
+                t,
+            )?;
+            return mesh.build(ctx);
+        }
+
+        let rect = |scale: f32| {
+            Rect::new(
+                -half_width * scale,
+                -half_height * scale,
+                2.0 * half_width * scale,
+                2.0 * half_height * scale,
+            )
+        };
+
+        // outline
+        let outline_color = match self.lifetime_state(curr_time) {
+            EnemyLifetime::Warmup => color::WARNING_RED,
+            EnemyLifetime::Active => color::RED,
+            EnemyLifetime::Cooldown => color::TRANSPARENT,
+            _ => unreachable!(),
+        };
+        mesh.rectangle(
+            DrawMode::stroke(OUTLINE_THICKNESS),
+            rect(1.0),
+            outline_color,
+        )?;
+
+        // inner solid rectangle
+        let inner_scale = match self.lifetime_state(curr_time) {
+            EnemyLifetime::Warmup => t,
+            EnemyLifetime::Active => self.growth(curr_time),
+            EnemyLifetime::Cooldown => 1.0 - t,
+            _ => unreachable!(),
+        } as f32;
+        let inner_color = match self.lifetime_state(curr_time) {
+            EnemyLifetime::Warmup => Color::lerp(color::DARK_WARNING_RED, color::WARNING_RED, t),
+            EnemyLifetime::Active => color::RED,
+            EnemyLifetime::Cooldown => Color::lerp(color::RED, color::TRANSPARENT, t),
+            _ => unreachable!(),
+        };
+        if inner_scale > 0.0 {
+            mesh.rectangle(DrawMode::fill(), rect(inner_scale), inner_color)?;
+        }
+
+        mesh.build(ctx)
     }
 
-    #[test]
-    pub fn test_shortest_distance_to_line_vert() {
-        let pi = std::f64::consts::PI;
-        let sqrt_3 = 3.0_f64.sqrt();
+    fn shape(&self) -> Option<EnemyShape> {
+        Some(EnemyShape::RectBomb {
+            half_width: shape_len(self.half_width),
+            half_height: shape_len(self.half_height),
+            telegraph: self.telegraph,
+        })
+    }
 
-        let origin = cg::Point2::<f64>::origin();
-        let pos = cg::Point2::new(1.0, sqrt_3);
-        assert_eq_delta!(
-            shortest_distance_to_line(pos, origin, pi / 2.0),
-            pos.x.abs()
-        );
+    fn position_info(&self, _curr_time: Beats) -> (WorldPos, f64) {
+        (self.position, self.angle)
+    }
 
-        let pos = cg::Point2::new(1.0, sqrt_3);
-        assert_eq_delta!(
-            shortest_distance_to_line(pos, origin, 3.0 * pi / 2.0),
-            pos.x.abs()
-        );
+    fn bounds(&self, curr_time: Beats) -> (WorldPos, WorldLen) {
+        // As with CircleBomb, a CountdownRing starts out larger than the bomb.
+        let radius = self.half_width.0.hypot(self.half_height.0);
+        let radius = if self.lifetime_state(curr_time) == EnemyLifetime::Warmup
+            && self.telegraph != TelegraphStyle::Default
+        {
+            radius * COUNTDOWN_TELEGRAPH_SCALE
+        } else {
+            radius
+        };
+        (self.position, WorldLen(radius + OUTLINE_THICKNESS as f64))
+    }
 
-        let pos = cg::Point2::new(-1.0, -sqrt_3);
-        assert_eq_delta!(
-            shortest_distance_to_line(pos, origin, -pi / 2.0),
-            pos.x.abs()
-        );
+    fn on_player_hit(&mut self, curr_time: Beats) {
+        self.detonated_at.get_or_insert(curr_time);
+    }
+}
+
+/// A ring which expands from `position` to `max_radius` over its active phase,
+/// like a shockwave. Only the band of the ring hurts, so the player has to dodge
+/// through the ring rather than away from it.
+pub struct RingPulse {
+    start_time: Beats,
+    durations: EnemyDurations,
+    position: WorldPos,
+    max_radius: WorldLen,
+    // How far the band extends to either side of the ring's radius.
+    thickness: WorldLen,
+    telegraph: TelegraphStyle,
+}
+
+impl RingPulse {
+    pub fn new(
+        start_time: Beats,
+        durations: EnemyDurations,
+        position: WorldPos,
+        max_radius: WorldLen,
+        thickness: WorldLen,
+        telegraph: TelegraphStyle,
+    ) -> RingPulse {
+        RingPulse {
+            start_time,
+            durations,
+            position,
+            max_radius,
+            thickness,
+            telegraph,
+        }
+    }
+
+    /// Return the radius of the middle of the ring's band.
+    fn radius(&self, curr_time: Beats) -> WorldLen {
+        match self.lifetime_state(curr_time) {
+            EnemyLifetime::Active => {
+                let t = self.percent_over_curr_state(curr_time);
+                WorldLen::lerp(WorldLen(0.0), self.max_radius, t)
+            }
+            EnemyLifetime::Cooldown => self.max_radius,
+            _ => WorldLen(0.0),
+        }
+    }
+}
+
+impl EnemyImpl for RingPulse {
+    fn durations(&self) -> EnemyDurations {
+        self.durations
+    }
+
+    fn start_time(&self) -> Beats {
+        self.start_time
+    }
+
+    fn sdf(&self, pos: WorldPos, curr_time: Beats) -> WorldLen {
+        let distance = WorldPos::distance(pos, self.position);
+        WorldLen((distance - self.radius(curr_time)).0.abs()) - self.thickness
+    }
+
+    fn sdf_gradient(&self, pos: WorldPos, curr_time: Beats) -> (f64, f64) {
+        // Inside of the ring, the band is further away from the center.
+        let (x, y) = direction_from(self.position, pos);
+        if WorldPos::distance(pos, self.position) < self.radius(curr_time) {
+            (-x, -y)
+        } else {
+            (x, y)
+        }
+    }
+
+    fn update(&mut self, _curr_time: Beats, _player_pos: WorldPos) {}
+
+    fn get_mesh(&self, ctx: &mut Context, curr_time: Beats) -> GameResult<Mesh> {
+        let mut mesh = MeshBuilder::new();
+        let origin = WorldPos::origin().as_mint();
+        let t = self.percent_over_curr_state(curr_time);
+        let max_radius = self.max_radius.0 as f32;
+        let thickness = self.thickness.0 as f32;
+
+        if self.telegraph != TelegraphStyle::Default
+            && self.lifetime_state(curr_time) == EnemyLifetime::Warmup
+        {
+            telegraph::draw_telegraph(
+                &mut mesh,
+                self.telegraph,
+                TelegraphShape::Circle { radius: max_radius },
+                t,
+            )?;
+            return mesh.build(ctx);
+        }
+
+        // The outline shows how far the ring will go.
+        let outline_color = match self.lifetime_state(curr_time) {
+            EnemyLifetime::Warmup => Color::lerp(color::TRANSPARENT, color::WARNING_RED, t),
+            EnemyLifetime::Active => color::WARNING_RED,
+            EnemyLifetime::Cooldown => Color::lerp(color::WARNING_RED, color::TRANSPARENT, t),
+            _ => unreachable!(),
+        };
+        meshutil::circle(
+            &mut mesh,
+            DrawMode::stroke(OUTLINE_THICKNESS),
+            origin,
+            max_radius,
+            WORLD_SCALE_FACTOR,
+            outline_color,
+        )?;
+
+        // The band itself, which starts as a dot in the center during the
+        // warmup.
+        let (radius, band_color) = match self.lifetime_state(curr_time) {
+            EnemyLifetime::Warmup => (
+                0.0,
+                Color::lerp(color::DARK_WARNING_RED, color::WARNING_RED, t),
+            ),
+            EnemyLifetime::Active => (self.radius(curr_time).0 as f32, color::RED),
+            EnemyLifetime::Cooldown => {
+                let band_color = Color::lerp(color::RED, color::TRANSPARENT, t);
+                (max_radius, band_color)
+            }
+            _ => unreachable!(),
+        };
+        if radius <= thickness {
+            // The band covers the center, so it is a disk rather than a ring.
+            meshutil::circle(
+                &mut mesh,
+                DrawMode::fill(),
+                origin,
+                radius + thickness,
+                WORLD_SCALE_FACTOR,
+                band_color,
+            )?;
+        } else {
+            meshutil::circle(
+                &mut mesh,
+                DrawMode::stroke(2.0 * thickness),
+                origin,
+                radius,
+                WORLD_SCALE_FACTOR,
+                band_color,
+            )?;
+        }
+
+        mesh.build(ctx)
+    }
+
+    fn shape(&self) -> Option<EnemyShape> {
+        Some(EnemyShape::RingPulse {
+            max_radius: shape_len(self.max_radius),
+            thickness: shape_len(self.thickness),
+            telegraph: self.telegraph,
+        })
+    }
+
+    fn position_info(&self, _curr_time: Beats) -> (WorldPos, f64) {
+        (self.position, 0.0)
+    }
+
+    fn bounds(&self, _curr_time: Beats) -> (WorldPos, WorldLen) {
+        let radius = self.max_radius + self.thickness + WorldLen(OUTLINE_THICKNESS as f64);
+        (self.position, radius)
+    }
+}
+
+/// How an Emitter aims each burst of bullets. Angles are in radians.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum EmitterPattern {
+    /// `count` bullets spread evenly around the emitter. The first bullet of
+    /// the first burst is fired at `angle`, and each burst after that is
+    /// turned `spin` further.
+    Radial { count: usize, angle: f64, spin: f64 },
+    /// `count` bullets fanned out over `spread`, centered on the player.
+    Aimed { count: usize, spread: f64 },
+}
+
+/// The bullets fired by an Emitter.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct EmitterBullet {
+    pub size: WorldLen,
+    /// How far each bullet travels from the emitter.
+    pub length: f64,
+    /// How long each bullet takes to travel `length`.
+    pub duration: Beats,
+    pub style: BulletStyle,
+}
+
+/// A turret which fires a burst of bullets into its group every `interval`
+/// beats while it is active, starting as soon as it becomes active. The
+/// bullets are separate enemies, so they keep going after the emitter is gone.
+/// The emitter's body hurts the player like a bullet does.
+pub struct Emitter {
+    start_time: Beats,
+    durations: EnemyDurations,
+    position: WorldPos,
+    interval: Beats,
+    pattern: EmitterPattern,
+    bullet: EmitterBullet,
+    // How many bursts have been fired so far.
+    bursts: usize,
+}
+
+impl Emitter {
+    pub fn new(
+        start_time: Beats,
+        durations: EnemyDurations,
+        position: WorldPos,
+        interval: Beats,
+        pattern: EmitterPattern,
+        bullet: EmitterBullet,
+    ) -> Emitter {
+        Emitter {
+            start_time,
+            durations,
+            position,
+            interval: Beats(interval.0.max(MIN_EMITTER_INTERVAL.0)),
+            pattern,
+            bullet,
+            bursts: 0,
+        }
+    }
+
+    /// Return when the `burst`th burst is fired.
+    fn burst_time(&self, burst: usize) -> Beats {
+        self.start_time + self.durations.warmup + Beats(self.interval.0 * burst as f64)
+    }
+
+    /// Return the angles of the bullets of the `burst`th burst.
+    fn burst_angles(&self, burst: usize, player_pos: WorldPos) -> Vec<f64> {
+        match self.pattern {
+            EmitterPattern::Radial { count, angle, spin } => {
+                let angle = angle + spin * burst as f64;
+                (0..count)
+                    .map(|i| angle + std::f64::consts::TAU * i as f64 / count as f64)
+                    .collect()
+            }
+            EmitterPattern::Aimed { count, spread } => {
+                let aim = WorldPos::angle_to(self.position, player_pos);
+                if count <= 1 {
+                    return vec![aim; count];
+                }
+                (0..count)
+                    .map(|i| aim - spread / 2.0 + spread * i as f64 / (count - 1) as f64)
+                    .collect()
+            }
+        }
+    }
+}
+
+impl EnemyImpl for Emitter {
+    fn durations(&self) -> EnemyDurations {
+        self.durations
+    }
+
+    fn start_time(&self) -> Beats {
+        self.start_time
+    }
+
+    fn sdf(&self, pos: WorldPos, _curr_time: Beats) -> WorldLen {
+        WorldPos::distance(pos, self.position) - WorldLen(EMITTER_RADIUS as f64)
+    }
+
+    fn sdf_gradient(&self, pos: WorldPos, _curr_time: Beats) -> (f64, f64) {
+        direction_from(self.position, pos)
+    }
+
+    fn update(&mut self, _curr_time: Beats, _player_pos: WorldPos) {}
+
+    fn spawn_children(&mut self, curr_time: Beats, player_pos: WorldPos) -> Vec<Box<dyn Enemy>> {
+        let (_, active_end) = Enemy::active_range(self);
+        let mut bullets: Vec<Box<dyn Enemy>> = Vec::new();
+        // Every burst which should have been fired by now is fired, at the
+        // time it should have been fired, so that bursts stay on the beat
+        // however long frames take.
+        loop {
+            let burst_time = self.burst_time(self.bursts);
+            if burst_time > curr_time || burst_time >= active_end {
+                break;
+            }
+            for angle in self.burst_angles(self.bursts, player_pos) {
+                let end = WorldPos {
+                    x: self.position.x + angle.cos() * self.bullet.length,
+                    y: self.position.y + angle.sin() * self.bullet.length,
+                };
+                bullets.push(Box::new(Bullet::new(
+                    self.position,
+                    end,
+                    burst_time,
+                    Beats(0.0),
+                    self.bullet.duration,
+                    self.bullet.size,
+                    self.bullet.style,
+                    TelegraphStyle::Default,
+                )));
+            }
+            self.bursts += 1;
+        }
+        bullets
+    }
+
+    fn get_mesh(&self, ctx: &mut Context, curr_time: Beats) -> GameResult<Mesh> {
+        let mut mesh = MeshBuilder::new();
+        let origin = WorldPos::origin().as_mint();
+        let t = self.percent_over_curr_state(curr_time);
+
+        let (fill_radius, fill_color) = match self.lifetime_state(curr_time) {
+            EnemyLifetime::Warmup => (
+                EMITTER_RADIUS * t as f32,
+                Color::lerp(color::DARK_WARNING_RED, color::WARNING_RED, t),
+            ),
+            EnemyLifetime::Active => (EMITTER_RADIUS, color::RED),
+            EnemyLifetime::Cooldown => (
+                EMITTER_RADIUS,
+                Color::lerp(color::RED, color::TRANSPARENT, t),
+            ),
+            _ => unreachable!(),
+        };
+        meshutil::circle(
+            &mut mesh,
+            DrawMode::stroke(OUTLINE_THICKNESS),
+            origin,
+            EMITTER_RADIUS,
+            WORLD_SCALE_FACTOR,
+            fill_color,
+        )?;
+        if fill_radius > 0.0 {
+            meshutil::circle(
+                &mut mesh,
+                DrawMode::fill(),
+                origin,
+                fill_radius,
+                WORLD_SCALE_FACTOR,
+                fill_color,
+            )?;
+        }
+
+        // A ring shrinks onto the body before each burst, so that the player
+        // can see when the next one is coming. The first burst is warned by
+        // the warmup instead.
+        if self.lifetime_state(curr_time) == EnemyLifetime::Active {
+            let until_burst = (self.burst_time(self.bursts) - curr_time).0 / self.interval.0;
+            let ring_radius = EMITTER_RADIUS * (1.0 + until_burst.clamp(0.0, 1.0) as f32);
+            meshutil::circle(
+                &mut mesh,
+                DrawMode::stroke(OUTLINE_THICKNESS),
+                origin,
+                ring_radius,
+                WORLD_SCALE_FACTOR,
+                color::WARNING_RED,
+            )?;
+        }
+
+        mesh.build(ctx)
+    }
+
+    fn position_info(&self, _curr_time: Beats) -> (WorldPos, f64) {
+        (self.position, 0.0)
+    }
+
+    fn bounds(&self, _curr_time: Beats) -> (WorldPos, WorldLen) {
+        let radius = 2.0 * EMITTER_RADIUS + OUTLINE_THICKNESS;
+        (self.position, WorldLen(radius as f64))
+    }
+}
+
+/// A ball which chases the player during its active phase, accelerating
+/// towards wherever the player currently is up to a maximum speed. Since it
+/// has to turn around like anything else with momentum, the player can dodge
+/// it by changing direction.
+pub struct Chaser {
+    start_time: Beats,
+    durations: EnemyDurations,
+    position: WorldPos,
+    // In world units per beat.
+    velocity: (f64, f64),
+    radius: WorldLen,
+    // In world units per beat.
+    max_speed: f64,
+    // In world units per beat per beat.
+    acceleration: f64,
+    // How many steps the chaser has moved since its active phase started. See
+    // CHASER_STEP.
+    steps: usize,
+}
+
+impl Chaser {
+    pub fn new(
+        start_time: Beats,
+        durations: EnemyDurations,
+        position: WorldPos,
+        radius: WorldLen,
+        max_speed: f64,
+        acceleration: f64,
+    ) -> Chaser {
+        Chaser {
+            start_time,
+            durations,
+            position,
+            velocity: (0.0, 0.0),
+            radius,
+            max_speed,
+            acceleration,
+            steps: 0,
+        }
+    }
+
+    /// Move the chaser one step towards `player_pos`.
+    fn step(&mut self, player_pos: WorldPos) {
+        let delta_time = CHASER_STEP.0;
+        let (x, y) = direction_from(self.position, player_pos);
+        let (vel_x, vel_y) = (
+            self.velocity.0 + x * self.acceleration * delta_time,
+            self.velocity.1 + y * self.acceleration * delta_time,
+        );
+        let speed = vel_x.hypot(vel_y);
+        self.velocity = if speed > self.max_speed {
+            let scale = self.max_speed / speed;
+            (vel_x * scale, vel_y * scale)
+        } else {
+            (vel_x, vel_y)
+        };
+        self.position.x += self.velocity.0 * delta_time;
+        self.position.y += self.velocity.1 * delta_time;
+    }
+}
+
+impl EnemyImpl for Chaser {
+    fn durations(&self) -> EnemyDurations {
+        self.durations
+    }
+
+    fn start_time(&self) -> Beats {
+        self.start_time
+    }
+
+    fn sdf(&self, pos: WorldPos, _curr_time: Beats) -> WorldLen {
+        WorldPos::distance(pos, self.position) - self.radius
+    }
+
+    fn sdf_gradient(&self, pos: WorldPos, _curr_time: Beats) -> (f64, f64) {
+        direction_from(self.position, pos)
+    }
+
+    fn update(&mut self, curr_time: Beats, player_pos: WorldPos) {
+        // Every step up to now is taken, even if the active phase ended since
+        // the last update. Where the player was during each step isn't known,
+        // so every step chases where the player is now.
+        let (active_start, active_end) = Enemy::active_range(self);
+        loop {
+            let step_time = active_start + CHASER_STEP * (self.steps + 1) as f64;
+            if step_time > curr_time || step_time > active_end {
+                break;
+            }
+            self.step(player_pos);
+            self.steps += 1;
+        }
+    }
+
+    fn get_mesh(&self, ctx: &mut Context, curr_time: Beats) -> GameResult<Mesh> {
+        let mut mesh = MeshBuilder::new();
+        let origin = WorldPos::origin().as_mint();
+        let t = self.percent_over_curr_state(curr_time);
+        let radius = self.radius.0 as f32;
+
+        let (fill_radius, fill_color) = match self.lifetime_state(curr_time) {
+            EnemyLifetime::Warmup => (
+                radius * t as f32,
+                Color::lerp(color::DARK_WARNING_RED, color::WARNING_RED, t),
+            ),
+            EnemyLifetime::Active => (radius, color::RED),
+            EnemyLifetime::Cooldown => (radius, Color::lerp(color::RED, color::TRANSPARENT, t)),
+            _ => unreachable!(),
+        };
+        meshutil::circle(
+            &mut mesh,
+            DrawMode::stroke(OUTLINE_THICKNESS),
+            origin,
+            radius,
+            WORLD_SCALE_FACTOR,
+            fill_color,
+        )?;
+        if fill_radius > 0.0 {
+            meshutil::circle(
+                &mut mesh,
+                DrawMode::fill(),
+                origin,
+                fill_radius,
+                WORLD_SCALE_FACTOR,
+                fill_color,
+            )?;
+        }
+
+        mesh.build(ctx)
+    }
+
+    fn shape(&self) -> Option<EnemyShape> {
+        Some(EnemyShape::Chaser {
+            radius: shape_len(self.radius),
+        })
+    }
+
+    fn position_info(&self, _curr_time: Beats) -> (WorldPos, f64) {
+        (self.position, 0.0)
+    }
+
+    fn bounds(&self, _curr_time: Beats) -> (WorldPos, WorldLen) {
+        (
+            self.position,
+            self.radius + WorldLen(OUTLINE_THICKNESS as f64),
+        )
+    }
+}
+
+/// A moving safe zone. This enemy is the inverse of a CircleBomb: while active,
+/// the inside of the platform is safe and everything outside of it is lethal.
+/// The platform travels along its path over the course of its active phase,
+/// spending an equal amount of time on each segment of the path.
+pub struct Platform {
+    start_time: Beats,
+    durations: EnemyDurations,
+    path: Vec<WorldPos>,
+    radius: WorldLen,
+}
+
+impl Platform {
+    /// Create a new Platform. `path` must contain at least one point.
+    pub fn new(
+        start_time: Beats,
+        durations: EnemyDurations,
+        path: Vec<WorldPos>,
+        radius: WorldLen,
+    ) -> Platform {
+        assert!(!path.is_empty(), "Platform path must not be empty");
+        Platform {
+            start_time,
+            durations,
+            path,
+            radius,
+        }
+    }
+
+    fn pos(&self, curr_time: Beats) -> WorldPos {
+        let t = match self.lifetime_state(curr_time) {
+            EnemyLifetime::Unspawned | EnemyLifetime::Warmup => 0.0,
+            EnemyLifetime::Active => self
+                .durations
+                .percent_over_active(self.delta_time(curr_time))
+                .clamp(0.0, 1.0),
+            EnemyLifetime::Cooldown | EnemyLifetime::Dead => 1.0,
+        };
+
+        let segments = self.path.len() - 1;
+        if segments == 0 {
+            return self.path[0];
+        }
+        let segment_t = t * segments as f64;
+        let i = (segment_t.floor() as usize).min(segments - 1);
+        WorldPos::lerp(self.path[i], self.path[i + 1], segment_t - i as f64)
+    }
+}
+
+impl EnemyImpl for Platform {
+    fn durations(&self) -> EnemyDurations {
+        self.durations
+    }
+
+    fn start_time(&self) -> Beats {
+        self.start_time
+    }
+
+    fn sdf(&self, pos: WorldPos, curr_time: Beats) -> WorldLen {
+        // This is inverted from a normal circle's sdf, so that the player is
+        // hit whenever they are not entirely inside of the platform.
+        self.radius - WorldPos::distance(pos, self.pos(curr_time))
+    }
+
+    fn sdf_gradient(&self, pos: WorldPos, curr_time: Beats) -> (f64, f64) {
+        direction_from(pos, self.pos(curr_time))
+    }
+
+    fn update(&mut self, _curr_time: Beats, _player_pos: WorldPos) {}
+
+    fn get_mesh(&self, ctx: &mut Context, curr_time: Beats) -> GameResult<Mesh> {
+        // Wide enough to cover the whole arena no matter where the platform is.
+        const DANGER_WIDTH: f32 = 300.0;
+
+        let mut mesh = MeshBuilder::new();
+        let origin = WorldPos::origin().as_mint();
+        let t = self.percent_over_curr_state(curr_time) as f32;
+        let radius = self.radius.0 as f32;
+
+        let (outline_color, danger_color) = match self.lifetime_state(curr_time) {
+            EnemyLifetime::Warmup => (
+                Color::lerp(TRANSPARENT, color::GREEN, t as f64),
+                Color::new(1.0, 0.0, 0.0, 0.1 * t),
+            ),
+            EnemyLifetime::Active => (color::GREEN, Color::new(1.0, 0.0, 0.0, 0.25)),
+            EnemyLifetime::Cooldown => (
+                Color::lerp(color::GREEN, TRANSPARENT, t as f64),
+                Color::new(1.0, 0.0, 0.0, 0.25 * (1.0 - t)),
+            ),
+            _ => unreachable!(),
+        };
+
+        // The lethal area outside of the platform
+        meshutil::circle(
+            &mut mesh,
+            DrawMode::stroke(DANGER_WIDTH),
+            origin,
+            radius + DANGER_WIDTH / 2.0,
+            WORLD_SCALE_FACTOR,
+            danger_color,
+        )?;
+        meshutil::circle(
+            &mut mesh,
+            DrawMode::stroke(OUTLINE_THICKNESS),
+            origin,
+            radius,
+            WORLD_SCALE_FACTOR,
+            outline_color,
+        )?;
+
+        mesh.build(ctx)
+    }
+
+    fn shape(&self) -> Option<EnemyShape> {
+        Some(EnemyShape::Platform {
+            radius: shape_len(self.radius),
+        })
+    }
+
+    fn position_info(&self, curr_time: Beats) -> (WorldPos, f64) {
+        (self.pos(curr_time), 0.0)
+    }
+
+    fn bounds(&self, curr_time: Beats) -> (WorldPos, WorldLen) {
+        // Everything outside of the platform is lethal, so the hitbox has no
+        // bound.
+        (self.pos(curr_time), WorldLen(f64::INFINITY))
+    }
+}
+
+/// Return the shortest distance from `pos` to the line defined by `line_pos`
+/// and `angle`. `angle` is in radians and measure the angle between a horizontal
+/// line and the line in question.
+pub fn shortest_distance_to_line(
+    pos: impl Into<cg::Point2<f64>>,
+    line_pos: impl Into<cg::Point2<f64>>,
+    angle: f64,
+) -> f64 {
+    let pos = pos.into();
+    let line_pos = line_pos.into();
+    // We have the vector LP,
+    #[allow(non_snake_case)]
+    let LP_vec = pos - line_pos;
+    // The unit vector along the laser
+    let laser_unit_vec = cg::Vector2::new(angle.cos(), angle.sin());
+
+    // We now find the angle between the two vectors
+    let dot_prod = LP_vec.dot(laser_unit_vec);
+
+    // Project LP_vec along laser_unit_vec
+    let proj = dot_prod * laser_unit_vec;
+
+    // Now get the perpendicular, this is the distance to the laser.
+    let perp = LP_vec - proj;
+    perp.magnitude()
+}
+
+/// How far apart the points `estimate_gradient` samples are.
+const GRADIENT_EPSILON: f64 = 0.01;
+
+/// Estimate the gradient of `sdf` at `pos` from the sdf at nearby points.
+pub fn estimate_gradient(sdf: impl Fn(WorldPos) -> WorldLen, pos: WorldPos) -> (f64, f64) {
+    let sdf = |x: f64, y: f64| {
+        sdf(WorldPos {
+            x: pos.x + x,
+            y: pos.y + y,
+        })
+        .0
+    };
+    let dx = sdf(GRADIENT_EPSILON, 0.0) - sdf(-GRADIENT_EPSILON, 0.0);
+    let dy = sdf(0.0, GRADIENT_EPSILON) - sdf(0.0, -GRADIENT_EPSILON);
+    (dx / (2.0 * GRADIENT_EPSILON), dy / (2.0 * GRADIENT_EPSILON))
+}
+
+/// Return the unit vector pointing from `from` to `to`, or (0, 0) if they
+/// are the same point.
+fn direction_from(from: WorldPos, to: WorldPos) -> (f64, f64) {
+    let distance = WorldPos::distance(from, to).0;
+    if distance <= 0.0 {
+        return (0.0, 0.0);
+    }
+    ((to.x - from.x) / distance, (to.y - from.y) / distance)
+}
+
+/// Rotate `point` about `rot_point` by `rot_angle` radians.
+pub fn rotate_point(point: WorldPos, rot_point: WorldPos, rot_angle: f64) -> WorldPos {
+    // first translate the point so that the rotation point is at the origin
+    let (pos_x, pos_y) = (point.x - rot_point.x, point.y - rot_point.y);
+    // now rotate the point by rot_angle
+    let (pos_x, pos_y) = (
+        pos_x * rot_angle.cos() - pos_y * rot_angle.sin(),
+        pos_x * rot_angle.sin() + pos_y * rot_angle.cos(),
+    );
+    // finally translate the point back
+    let (pos_x, pos_y) = (pos_x + rot_point.x, pos_y + rot_point.y);
+
+    WorldPos::from((pos_x, pos_y))
+}
+
+#[cfg(test)]
+mod test {
+    use cg::EuclideanSpace;
+    use cgmath as cg;
+
+    use crate::ease::Easing;
+    use crate::enemy::{
+        box_sdf, estimate_gradient, frame_time, mesh_frame, shortest_distance_to_line, Bullet,
+        BulletStyle, Chaser, CircleBomb, Emitter, EmitterBullet, EmitterPattern, Enemy,
+        EnemyAnimation, EnemyDurations, EnemyLifetime, Laser, LaserRender, LaserSweep, RectBomb,
+        RingPulse, SweepingLaser, BOMB_WARMUP, DEFAULT_BULLET_WARMUP,
+    };
+    use crate::meshcache::FRAMES_PER_PHASE;
+    use crate::telegraph::TelegraphStyle;
+    use crate::time::Beats;
+    use crate::world::{WorldLen, WorldPos, WORLD_SCALE_FACTOR};
+
+    macro_rules! assert_eq_delta {
+        ($x:expr, $y:expr) => {
+            if ($x - $y).abs() > 0.0001 {
+                panic!("{:?} does not approx. equal {:?}", $x, $y);
+            }
+        };
+    }
+
+    /// The durations of the enemies which take them, unless a test needs
+    /// something else.
+    fn durations() -> EnemyDurations {
+        EnemyDurations {
+            warmup: Beats(1.0),
+            active: Beats(2.0),
+            cooldown: Beats(0.25),
+        }
+    }
+
+    #[test]
+    pub fn test_shortest_distance_to_line_horiz() {
+        let pi = std::f64::consts::PI;
+        let sqrt_3 = 3.0_f64.sqrt();
+
+        let origin = cg::Point2::<f64>::origin();
+        let pos = cg::Point2::new(1.0, sqrt_3);
+        assert_eq_delta!(shortest_distance_to_line(pos, origin, 0.0), pos.y.abs());
+
+        let pos = cg::Point2::new(1.0, -sqrt_3);
+        assert_eq_delta!(shortest_distance_to_line(pos, origin, pi), pos.y.abs());
+
+        let pos = cg::Point2::new(1.0, -sqrt_3);
+        assert_eq_delta!(
+            shortest_distance_to_line(pos, origin, 2.0 * pi),
+            pos.y.abs()
+        );
+    }
+
+    #[test]
+    pub fn test_shortest_distance_to_line_vert() {
+        let pi = std::f64::consts::PI;
+        let sqrt_3 = 3.0_f64.sqrt();
+
+        let origin = cg::Point2::<f64>::origin();
+        let pos = cg::Point2::new(1.0, sqrt_3);
+        assert_eq_delta!(
+            shortest_distance_to_line(pos, origin, pi / 2.0),
+            pos.x.abs()
+        );
+
+        let pos = cg::Point2::new(1.0, sqrt_3);
+        assert_eq_delta!(
+            shortest_distance_to_line(pos, origin, 3.0 * pi / 2.0),
+            pos.x.abs()
+        );
+
+        let pos = cg::Point2::new(-1.0, -sqrt_3);
+        assert_eq_delta!(
+            shortest_distance_to_line(pos, origin, -pi / 2.0),
+            pos.x.abs()
+        );
+    }
+
+    #[test]
+    pub fn test_culling_matches_sdf() {
+        let pos = WorldPos { x: 10.0, y: 0.0 };
+        let bullet = Bullet::new(
+            pos,
+            pos,
+            Beats(0.0),
+            DEFAULT_BULLET_WARMUP,
+            Beats(4.0),
+            WorldLen(1.0),
+            BulletStyle::default(),
+            TelegraphStyle::Default,
+        );
+        let time = Beats(1.0);
+        let near = WorldPos { x: 11.5, y: 0.0 };
+        let far = WorldPos { x: 40.0, y: 0.0 };
+        assert!(bullet.could_touch(near, WorldLen(1.0), time, None));
+        assert!(!bullet.could_touch(far, WorldLen(1.0), time, None));
+        // Rotating the group moves the bullet onto the far point.
+        let rotated_about = Some((WorldPos { x: 25.0, y: 0.0 }, std::f64::consts::PI));
+        assert!(bullet.could_touch(far, WorldLen(1.0), time, rotated_about));
+        assert!(bullet.on_screen(time, None));
+    }
+
+    #[test]
+    pub fn test_active_fade_follows_active_phase() {
+        let animation = EnemyAnimation {
+            active_fade: Some(Easing::linear(1.0, 0.0)),
+            ..EnemyAnimation::default()
+        };
+        let durations = EnemyDurations {
+            warmup: Beats(2.0),
+            active: Beats(4.0),
+            cooldown: Beats(1.0),
+        };
+        assert_eq_delta!(animation.active_opacity(Beats(1.0), durations), 1.0);
+        assert_eq_delta!(animation.active_opacity(Beats(3.0), durations), 0.75);
+        assert_eq_delta!(animation.active_opacity(Beats(6.5), durations), 0.0);
+    }
+
+    #[test]
+    pub fn test_bullet_consumed_on_hit() {
+        let pos = WorldPos { x: 0.0, y: 0.0 };
+        let mut bullet = Bullet::new(
+            pos,
+            pos,
+            Beats(0.0),
+            DEFAULT_BULLET_WARMUP,
+            Beats(4.0),
+            WorldLen(1.0),
+            BulletStyle::default(),
+            TelegraphStyle::Default,
+        );
+        bullet.on_player_hit(Beats(2.0));
+        assert_eq!(bullet.lifetime_state(Beats(2.0)), EnemyLifetime::Dead);
+        assert!(bullet.sdf(pos, Beats(2.0), None).is_none());
+        assert_eq_delta!(Enemy::end_time(&bullet).0, 2.0);
+    }
+
+    #[test]
+    pub fn test_bullet_circles() {
+        let start = WorldPos { x: -10.0, y: 5.0 };
+        let end = WorldPos { x: 10.0, y: 5.0 };
+        let bullet = |show_guide, telegraph| {
+            let style = BulletStyle {
+                show_guide,
+                ..BulletStyle::default()
+            };
+            Bullet::new(
+                start,
+                end,
+                Beats(0.0),
+                DEFAULT_BULLET_WARMUP,
+                Beats(4.0),
+                WorldLen(2.0),
+                style,
+                telegraph,
+            )
+        };
+        let animation = EnemyAnimation::default();
+        let curr_time = DEFAULT_BULLET_WARMUP + Beats(2.0);
+        // The guide is drawn separately, so the body is batched either way.
+        for &show_guide in &[true, false] {
+            let circles = bullet(show_guide, TelegraphStyle::Default)
+                .draw_circles(curr_time, None, &animation)
+                .unwrap();
+            let center = WorldPos { x: 0.0, y: 5.0 }.as_screen_coords();
+            assert_eq_delta!(circles[0].center.x, center.x);
+            assert_eq_delta!(circles[0].center.y, center.y);
+            assert_eq_delta!(circles[0].radius, 2.0 * WORLD_SCALE_FACTOR);
+        }
+
+        // Telegraphs are drawn instead of the body during the warmup.
+        let warmup = Beats(DEFAULT_BULLET_WARMUP.0 / 2.0);
+        assert!(bullet(false, TelegraphStyle::CountdownRing)
+            .draw_circles(warmup, None, &animation)
+            .is_none());
+    }
+
+    #[test]
+    pub fn test_rect_bomb_sdf_is_rotated() {
+        assert_eq_delta!(box_sdf((5.0, 0.0), (2.0, 1.0)), 3.0);
+        assert_eq_delta!(box_sdf((5.0, 5.0), (2.0, 1.0)), 5.0);
+        assert_eq_delta!(box_sdf((0.5, 0.0), (2.0, 1.0)), -1.0);
+
+        // A wide bomb turned a quarter turn is tall instead.
+        let center = WorldPos { x: 3.0, y: -2.0 };
+        let bomb = RectBomb::new(
+            Beats(0.0),
+            center,
+            WorldLen(10.0),
+            WorldLen(2.0),
+            std::f64::consts::FRAC_PI_2,
+            TelegraphStyle::Default,
+        );
+        let curr_time = BOMB_WARMUP + Beats(0.5);
+        let above = WorldPos { x: 3.0, y: 2.0 };
+        let beside = WorldPos { x: 7.0, y: -2.0 };
+        assert_eq_delta!(bomb.sdf(above, curr_time, None).unwrap().0, -1.0);
+        assert_eq_delta!(bomb.sdf(beside, curr_time, None).unwrap().0, 3.0);
+    }
+
+    #[test]
+    pub fn test_ring_pulse_only_hurts_on_band() {
+        let center = WorldPos { x: 1.0, y: 1.0 };
+        let durations = durations();
+        let ring = RingPulse::new(
+            Beats(0.0),
+            durations,
+            center,
+            WorldLen(20.0),
+            WorldLen(1.0),
+            TelegraphStyle::Default,
+        );
+        // Halfway through the active phase, the ring is 10 units out.
+        let curr_time = Beats(2.0);
+        let at = |x: f64| WorldPos { x: 1.0 + x, y: 1.0 };
+        assert_eq_delta!(ring.sdf(center, curr_time, None).unwrap().0, 9.0);
+        assert_eq_delta!(ring.sdf(at(10.0), curr_time, None).unwrap().0, -1.0);
+        assert_eq_delta!(ring.sdf(at(15.0), curr_time, None).unwrap().0, 4.0);
+        assert!(ring.sdf(at(10.0), Beats(0.5), None).is_none());
+    }
+
+    #[test]
+    pub fn test_emitter_fires_every_interval() {
+        let durations = durations();
+        let bullet = EmitterBullet {
+            size: WorldLen(1.0),
+            length: 10.0,
+            duration: Beats(1.0),
+            style: BulletStyle::default(),
+        };
+        let pattern = EmitterPattern::Radial {
+            count: 4,
+            angle: 0.0,
+            spin: 0.0,
+        };
+        let origin = WorldPos { x: 0.0, y: 0.0 };
+        let mut emitter = Emitter::new(Beats(0.0), durations, origin, Beats(0.5), pattern, bullet);
+        let mut spawn = |t| emitter.spawn_children(Beats(t), origin, None).len();
+        assert_eq!(spawn(0.5), 0);
+        // A long frame fires every burst it skipped over.
+        assert_eq!(spawn(1.6), 2 * 4);
+        assert_eq!(spawn(1.7), 0);
+        // Bursts stop once the emitter is no longer active.
+        assert_eq!(spawn(3.1), 2 * 4);
+        assert_eq!(spawn(5.0), 0);
+    }
+
+    #[test]
+    pub fn test_chaser_accelerates_up_to_max_speed() {
+        let origin = WorldPos { x: 0.0, y: 0.0 };
+        let player = WorldPos { x: 100.0, y: 0.0 };
+        let mut chaser = Chaser::new(Beats(0.0), durations(), origin, WorldLen(1.0), 10.0, 20.0);
+        let mut distance_moved = |t| {
+            chaser.update(Beats(t), player, None);
+            chaser.position(Beats(t), None).x
+        };
+        // The chaser waits for its warmup to end before moving.
+        assert_eq_delta!(distance_moved(0.5), 0.0);
+        assert_eq_delta!(distance_moved(1.0), 0.0);
+        // It takes half a beat to reach 10 units per beat...
+        let accelerating = distance_moved(1.5);
+        assert!(accelerating > 2.5 && accelerating < 2.6);
+        // ...and is then capped at that speed.
+        assert_eq_delta!(distance_moved(2.0), accelerating + 5.0);
+        // It stops once it is no longer active, even if it isn't updated
+        // right when its active phase ends.
+        assert_eq_delta!(distance_moved(3.2), accelerating + 15.0);
+    }
+
+    #[test]
+    pub fn test_chaser_moves_the_same_at_any_tick_rate() {
+        let origin = WorldPos { x: 0.0, y: 0.0 };
+        let player = WorldPos { x: 100.0, y: 20.0 };
+        let chase = |updates_per_beat: usize| {
+            let mut chaser =
+                Chaser::new(Beats(0.0), durations(), origin, WorldLen(1.0), 10.0, 20.0);
+            for i in 0..=4 * updates_per_beat {
+                chaser.update(Beats(i as f64 / updates_per_beat as f64), player, None);
+            }
+            chaser.position(Beats(3.0), None)
+        };
+        let (slow, fast) = (chase(60), chase(240));
+        assert_eq_delta!(slow.x, fast.x);
+        assert_eq_delta!(slow.y, fast.y);
+    }
+
+    #[test]
+    pub fn test_sdf_gradient_matches_estimate() {
+        let mut laser = Laser::new_through_point(
+            WorldPos { x: 3.0, y: -2.0 },
+            0.7,
+            Beats(0.0),
+            EnemyDurations::default_laser(Beats(1.0)),
+            &Laser::default_outline_color(),
+            &Laser::default_outline_keyframes(),
+            TelegraphStyle::Default,
+            LaserRender::Flat,
+        );
+        let time = Beats(4.5);
+        laser.update(time, WorldPos::origin(), None);
+        let rotated_about = Some((WorldPos { x: -5.0, y: 1.0 }, 1.2));
+        let pos = WorldPos { x: 10.0, y: 4.0 };
+        let (x, y) = laser.sdf_gradient(pos, time, rotated_about).unwrap();
+        let (estimate_x, estimate_y) =
+            estimate_gradient(|pos| laser.sdf(pos, time, rotated_about).unwrap(), pos);
+        assert_eq_delta!(x, estimate_x);
+        assert_eq_delta!(y, estimate_y);
+    }
+
+    #[test]
+    pub fn test_sweeping_laser_sweeps_while_active() {
+        let laser = Laser::new_through_point(
+            WorldPos { x: 0.0, y: 0.0 },
+            0.0,
+            Beats(0.0),
+            EnemyDurations::default_laser(Beats(2.0)),
+            &Laser::default_outline_color(),
+            &Laser::default_outline_keyframes(),
+            TelegraphStyle::Default,
+            LaserRender::Flat,
+        );
+        let sweep = LaserSweep {
+            y: Some(Easing::linear(0.0, 10.0)),
+            ..LaserSweep::default()
+        };
+        let mut laser = SweepingLaser::new(laser, sweep);
+        let (active_start, active_end) = laser.active_range();
+
+        // The laser waits at the start of its sweep during the warmup.
+        let warmup = Beats(active_start.0 / 2.0);
+        laser.update(warmup, WorldPos::origin(), None);
+        assert_eq_delta!(laser.position(warmup, None).y, 0.0);
+
+        let halfway = Beats((active_start.0 + active_end.0) / 2.0);
+        laser.update(halfway, WorldPos::origin(), None);
+        assert_eq_delta!(laser.position(halfway, None).y, 5.0);
+    }
+
+    #[test]
+    pub fn test_mesh_frames_stay_in_their_phase() {
+        use EnemyLifetime::*;
+        let bomb = CircleBomb::new(Beats(4.0), WorldPos::origin(), TelegraphStyle::Default);
+        for &lifetime in &[Warmup, Active, Cooldown] {
+            for frame in 0..FRAMES_PER_PHASE {
+                let time = frame_time(&bomb, lifetime, frame).unwrap();
+                assert_eq!(bomb.lifetime_state(time), lifetime);
+                let (key, key_time) = mesh_frame(&bomb, time).unwrap();
+                assert_eq!((key.lifetime, key.frame), (lifetime, frame));
+                assert_eq!(key_time, time);
+            }
+        }
+
+        // Phases with no length are never drawn, so they have no frames.
+        let durations = EnemyDurations {
+            cooldown: Beats(0.0),
+            ..durations()
+        };
+        let origin = WorldPos::origin();
+        let chaser = Chaser::new(Beats(0.0), durations, origin, WorldLen(1.0), 1.0, 1.0);
+        assert_eq!(frame_time(&chaser, Cooldown, 0), None);
     }
 }
@@ -0,0 +1,50 @@
+/// This module defines the events the world emits while it updates. Systems
+/// which only need to react to what happened in the world (ex: scoring, sound
+/// effects, or the HUD) should read these instead of inspecting the world's
+/// state directly.
+use crate::pickup::PickupKind;
+use crate::time::Beats;
+use crate::world::WorldPos;
+
+#[derive(Debug, Clone)]
+pub enum GameEvent {
+    /// The player was hit by an enemy in the given group. This is only sent
+    /// once per hit, rather than every frame the player overlaps the enemy.
+    PlayerHit {
+        group: usize,
+        pos: WorldPos,
+        time: Beats,
+    },
+    /// The player came close to an enemy in the given group without touching
+    /// it (see `GRAZE_DISTANCE`). This is sent at most once per enemy.
+    Grazed {
+        group: usize,
+        pos: WorldPos,
+        time: Beats,
+    },
+    /// The player was warped by a teleporter.
+    Teleported {
+        from: WorldPos,
+        to: WorldPos,
+        time: Beats,
+    },
+    /// The scheduler performed a BeatAction for the given group.
+    ActionPerformed { group: usize, time: Beats },
+    /// The player used a bomb, clearing the enemies around them.
+    BombUsed { pos: WorldPos, time: Beats },
+    /// The player collected a pickup.
+    PickupCollected {
+        kind: PickupKind,
+        pos: WorldPos,
+        time: Beats,
+    },
+    /// An enemy was added to the given group, either by the chart or by
+    /// another enemy in the group (ex: an emitter's bullets). This is sent
+    /// during the first update after the enemy is added.
+    EnemySpawned { group: usize, time: Beats },
+    /// An enemy in the given group became Dead, either because its cooldown
+    /// ended or because it was used up (ex: a bullet which hit the player).
+    EnemyDied { group: usize, time: Beats },
+    /// The chart reached a section marker.
+    SectionStarted { name: String, time: Beats },
+}
@@ -0,0 +1,127 @@
+/// This module runs the game's event loop. It is the same as ggez's
+/// `event::run`, except that it also forwards files dropped onto the window,
+/// which ggez's EventHandler has no callback for. Players install levels by
+/// dropping them onto the window (see `vfs::install_level`).
+/// Only the events the game uses are forwarded, so mouse wheel, text input,
+/// and resize events are dropped.
+use std::path::PathBuf;
+
+use ggez::event::winit_event::{ElementState, Event, KeyboardInput, WindowEvent};
+use ggez::event::{self, ControlFlow, EventHandler, EventLoop};
+use ggez::input::{gamepad, keyboard, mouse};
+use ggez::{Context, GameError};
+
+/// An EventHandler which also handles files being dropped onto the window.
+pub trait DropHandler: EventHandler<GameError> {
+    /// Called once for each file (or folder) dropped onto the window.
+    fn file_dropped_event(&mut self, ctx: &mut Context, path: PathBuf);
+}
+
+/// Run the game until it quits. See `ggez::event::run`.
+pub fn run<S: DropHandler + 'static>(
+    mut ctx: Context,
+    event_loop: EventLoop<()>,
+    mut state: S,
+) -> ! {
+    event_loop.run(move |mut event, _, control_flow| {
+        if !ctx.continuing {
+            *control_flow = ControlFlow::Exit;
+            return;
+        }
+        *control_flow = ControlFlow::Poll;
+
+        let ctx = &mut ctx;
+        event::process_event(ctx, &mut event);
+        match event {
+            Event::WindowEvent { event, .. } => match event {
+                WindowEvent::DroppedFile(path) => state.file_dropped_event(ctx, path),
+                WindowEvent::CloseRequested => {
+                    if !state.quit_event(ctx) {
+                        event::quit(ctx);
+                    }
+                }
+                WindowEvent::Focused(gained) => state.focus_event(ctx, gained),
+                WindowEvent::KeyboardInput {
+                    input:
+                        KeyboardInput {
+                            state: key_state,
+                            virtual_keycode: Some(keycode),
+                            ..
+                        },
+                    ..
+                } => {
+                    let mods = keyboard::active_mods(ctx);
+                    match key_state {
+                        ElementState::Pressed => {
+                            let repeat = keyboard::is_key_repeated(ctx);
+                            state.key_down_event(ctx, keycode, mods, repeat);
+                        }
+                        ElementState::Released => state.key_up_event(ctx, keycode, mods),
+                    }
+                }
+                WindowEvent::MouseInput {
+                    state: button_state,
+                    button,
+                    ..
+                } => {
+                    let position = mouse::position(ctx);
+                    match button_state {
+                        ElementState::Pressed => {
+                            state.mouse_button_down_event(ctx, button, position.x, position.y)
+                        }
+                        ElementState::Released => {
+                            state.mouse_button_up_event(ctx, button, position.x, position.y)
+                        }
+                    }
+                }
+                WindowEvent::CursorMoved { .. } => {
+                    let position = mouse::position(ctx);
+                    let delta = mouse::delta(ctx);
+                    state.mouse_motion_event(ctx, position.x, position.y, delta.x, delta.y);
+                }
+                _ => (),
+            },
+            Event::MainEventsCleared => {
+                ctx.timer_context.tick();
+                dispatch_gamepad_events(ctx, &mut state);
+
+                if let Err(err) = state.update(ctx) {
+                    log::error!("Error on EventHandler::update(): {:?}", err);
+                    *control_flow = ControlFlow::Exit;
+                    return;
+                }
+                if let Err(err) = state.draw(ctx) {
+                    log::error!("Error on EventHandler::draw(): {:?}", err);
+                    *control_flow = ControlFlow::Exit;
+                    return;
+                }
+                // The mouse delta is summed up over each frame.
+                ctx.mouse_context.set_delta([0.0, 0.0].into());
+            }
+            _ => (),
+        }
+    })
+}
+
+fn dispatch_gamepad_events<S: DropHandler>(ctx: &mut Context, state: &mut S) {
+    while let Some(gilrs::Event { id, event, .. }) = ctx.gamepad_context.next_event() {
+        // ggez's GamepadIds can't be made outside of ggez, so look up the one
+        // ggez has for the gamepad.
+        let id = match gamepad::gamepads(ctx).find(|(_, gamepad)| gamepad.id() == id) {
+            Some((id, _)) => id,
+            None => continue,
+        };
+        match event {
+            gilrs::EventType::ButtonPressed(button, _) => {
+                state.gamepad_button_down_event(ctx, button, id)
+            }
+            gilrs::EventType::ButtonReleased(button, _) => {
+                state.gamepad_button_up_event(ctx, button, id)
+            }
+            gilrs::EventType::AxisChanged(axis, value, _) => {
+                state.gamepad_axis_event(ctx, axis, value, id)
+            }
+            _ => (),
+        }
+    }
+}
@@ -0,0 +1,173 @@
+/// This module handles drawing text which may contain characters that the main
+/// font can't display, such as Japanese song titles. Text is split into runs of
+/// characters, and each run is drawn with the first font that covers it.
+use ggez::graphics::{Color, Font, PxScale, Text, TextFragment};
+use ggez::{Context, GameResult};
+
+/// A fallback font, along with the characters it should be used for.
+pub struct FallbackFont {
+    /// The path of the font in the resources folder, if the player put it
+    /// there.
+    pub path: &'static str,
+    /// Where fonts covering the same characters are usually installed on each
+    /// platform, in order of preference. These are used if the font isn't in
+    /// the resources folder.
+    pub system_paths: &'static [&'static str],
+    pub covers: fn(char) -> bool,
+}
+
+/// The fallback fonts, in order of preference. These aren't shipped with the
+/// game, since they are large and most charts don't need them, so they are
+/// loaded from the system instead. A missing fallback font only means that the
+/// characters it covers won't display.
+pub const FALLBACK_FONTS: &[FallbackFont] = &[FallbackFont {
+    path: "/NotoSansCJK-Regular.ttf",
+    system_paths: &[
+        "/usr/share/fonts/opentype/noto/NotoSansCJK-Regular.ttc",
+        "/usr/share/fonts/noto-cjk/NotoSansCJK-Regular.ttc",
+        "/usr/share/fonts/google-noto-cjk/NotoSansCJK-Regular.ttc",
+        "/usr/share/fonts/truetype/wqy/wqy-microhei.ttc",
+        "/System/Library/Fonts/Supplemental/Arial Unicode.ttf",
+        "/Library/Fonts/Arial Unicode.ttf",
+        "C:\\Windows\\Fonts\\msyh.ttc",
+        "C:\\Windows\\Fonts\\msgothic.ttc",
+    ],
+    covers: is_cjk,
+}];
+
+impl FallbackFont {
+    /// Load the font from the resources folder, or from the system if it isn't
+    /// there.
+    fn load(&self, ctx: &mut Context) -> GameResult<Font> {
+        match Font::new(ctx, self.path) {
+            Ok(font) => Ok(font),
+            Err(err) => match self.system_font() {
+                Some((path, bytes)) => {
+                    log::info!("Using system font {:?} in place of {:?}", path, self.path);
+                    Font::new_glyph_font_bytes(ctx, &bytes)
+                }
+                None => Err(err),
+            },
+        }
+    }
+
+    /// Return the path and contents of the first of the `system_paths` which
+    /// exists.
+    pub fn system_font(&self) -> Option<(&'static str, Vec<u8>)> {
+        read_first(self.system_paths)
+    }
+}
+
+/// Return the first of `paths` which can be read, along with its contents.
+fn read_first<'a>(paths: &[&'a str]) -> Option<(&'a str, Vec<u8>)> {
+    paths
+        .iter()
+        .find_map(|&path| std::fs::read(path).ok().map(|bytes| (path, bytes)))
+}
+
+pub struct FontSet {
+    primary: Font,
+    fallbacks: Vec<(Font, fn(char) -> bool)>,
+}
+
+impl FontSet {
+    /// Create a FontSet using `primary` for everything not covered by one of
+    /// the FALLBACK_FONTS that could be loaded.
+    pub fn new(ctx: &mut Context, primary: Font) -> FontSet {
+        let fallbacks = FALLBACK_FONTS
+            .iter()
+            .filter_map(|fallback| match fallback.load(ctx) {
+                Ok(font) => Some((font, fallback.covers)),
+                Err(err) => {
+                    log::warn!("Couldn't load fallback font {:?}: {}", fallback.path, err);
+                    None
+                }
+            })
+            .collect();
+        FontSet { primary, fallbacks }
+    }
+
+    pub fn primary(&self) -> Font {
+        self.primary
+    }
+
+    /// Return the font to draw `c` with.
+    pub fn font_for(&self, c: char) -> Font {
+        self.fallbacks
+            .iter()
+            .find(|(_, covers)| covers(c))
+            .map_or(self.primary, |(font, _)| *font)
+    }
+
+    /// Build a Text for `text`, switching fonts wherever needed.
+    pub fn text(&self, text: &str, color: Color, scale: f32) -> Text {
+        let mut result = Text::default();
+        let mut run = String::new();
+        let mut run_font = None;
+        for c in text.chars() {
+            let font = self.font_for(c);
+            if run_font.is_some() && run_font != Some(font) {
+                result.add(fragment(std::mem::take(&mut run), run_font, color, scale));
+            }
+            run_font = Some(font);
+            run.push(c);
+        }
+        if !run.is_empty() {
+            result.add(fragment(run, run_font, color, scale));
+        }
+        result
+    }
+}
+
+fn fragment(text: String, font: Option<Font>, color: Color, scale: f32) -> TextFragment {
+    TextFragment {
+        text,
+        color: Some(color),
+        font,
+        scale: Some(PxScale::from(scale)),
+    }
+}
+
+/// Returns true for Chinese, Japanese, and Korean characters, as well as the
+/// punctuation and full width forms used alongside them.
+pub fn is_cjk(c: char) -> bool {
+    matches!(c as u32,
+        0x1100..=0x11FF // Hangul Jamo
+        | 0x3000..=0x303F // CJK symbols and punctuation
+        | 0x3040..=0x30FF // Hiragana and Katakana
+        | 0x3130..=0x318F // Hangul compatibility Jamo
+        | 0x3400..=0x4DBF // CJK unified ideographs extension A
+        | 0x4E00..=0x9FFF // CJK unified ideographs
+        | 0xAC00..=0xD7AF // Hangul syllables
+        | 0xF900..=0xFAFF // CJK compatibility ideographs
+        | 0xFF00..=0xFFEF // Half width and full width forms
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_read_first_skips_missing_fonts() {
+        let folder = std::env::temp_dir().join("rthm_test_read_first");
+        std::fs::create_dir_all(&folder).unwrap();
+        let path = folder.join("font.ttc");
+        std::fs::write(&path, b"font").unwrap();
+        let path = path.to_string_lossy();
+        let missing = "/nonexistent/font.ttc";
+
+        assert_eq!(
+            read_first(&[missing, &path]),
+            Some((&*path, b"font".to_vec()))
+        );
+        assert_eq!(read_first(&[missing]), None);
+    }
+
+    #[test]
+    pub fn test_fallback_fonts_cover_cjk() {
+        for c in "日本語 한국어 中文".chars().filter(|c| !c.is_whitespace()) {
+            assert!(FALLBACK_FONTS.iter().any(|fallback| (fallback.covers)(c)));
+        }
+    }
+}
@@ -0,0 +1,87 @@
+/// This module implements force fields, which push the player around for some
+/// amount of time. Force fields never hurt the player on their own, but are
+/// useful for making sections of a song where the movement feels different.
+use ggez::graphics::{DrawMode, DrawParam, Drawable, Mesh};
+use ggez::{Context, GameResult};
+use serde::{Deserialize, Serialize};
+
+use crate::color;
+use crate::meshutil;
+use crate::time::Beats;
+use crate::world::{WorldLen, WorldPos};
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum ForceFieldKind {
+    /// A constant push in one direction, in WorldLen units per second.
+    Wind { x: f64, y: f64 },
+    /// A push towards `center`, in WorldLen units per second. Negative
+    /// strengths push the player away instead. The strength falls off linearly
+    /// to zero at `radius`.
+    Radial {
+        center: WorldPos,
+        strength: f64,
+        radius: WorldLen,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct ForceField {
+    pub kind: ForceFieldKind,
+    start_time: Beats,
+    duration: Beats,
+}
+
+impl ForceField {
+    pub fn new(kind: ForceFieldKind, start_time: Beats, duration: Beats) -> ForceField {
+        ForceField {
+            kind,
+            start_time,
+            duration,
+        }
+    }
+
+    /// Returns true if the force field should be removed from the world.
+    pub fn is_dead(&self, curr_time: Beats) -> bool {
+        curr_time > self.start_time + self.duration
+    }
+
+    /// Return the velocity the force field applies to something at `pos`.
+    pub fn velocity(&self, pos: WorldPos) -> (f64, f64) {
+        match self.kind {
+            ForceFieldKind::Wind { x, y } => (x, y),
+            ForceFieldKind::Radial {
+                center,
+                strength,
+                radius,
+            } => {
+                let (dx, dy) = (center.x - pos.x, center.y - pos.y);
+                let distance = (dx * dx + dy * dy).sqrt();
+                if distance >= radius.0 || distance == 0.0 {
+                    return (0.0, 0.0);
+                }
+                let falloff = 1.0 - distance / radius.0;
+                let speed = strength * falloff;
+                (speed * dx / distance, speed * dy / distance)
+            }
+        }
+    }
+
+    pub fn draw(&self, ctx: &mut Context) -> GameResult<()> {
+        match self.kind {
+            // Wind is everywhere, so there isn't much to draw.
+            ForceFieldKind::Wind { .. } => Ok(()),
+            ForceFieldKind::Radial { center, radius, .. } => {
+                let radius = radius.as_screen_length();
+                let mesh = Mesh::new_circle(
+                    ctx,
+                    DrawMode::stroke(1.0),
+                    center.as_screen_coords(),
+                    radius,
+                    meshutil::tolerance(radius, 1.0),
+                    color::GUIDE_GREY,
+                )?;
+                mesh.draw(ctx, DrawParam::default())
+            }
+        }
+    }
+}
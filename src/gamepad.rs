@@ -0,0 +1,146 @@
+/// This module implements the settings for the gamepad's analog stick. The raw
+/// position of the stick goes through each axis's deadzone and response curve
+/// before it is turned into held direction keys (see
+/// `KeyboardState::update_stick`), so the stick moves the player in the same
+/// eight directions the keyboard does, and replays record it the same way.
+/// The stick display shows the raw and processed positions side by side, so
+/// that players can see what the settings do while changing them.
+use ggez::graphics::mint::Point2;
+use ggez::graphics::{DrawMode, DrawParam, Drawable, MeshBuilder, Rect};
+use ggez::{Context, GameResult};
+use serde::{Deserialize, Serialize};
+
+use crate::color;
+use crate::meshutil;
+
+/// The width and height of the stick display.
+pub const STICK_DISPLAY_SIZE: f32 = 120.0;
+/// The radius of the dots marking the stick's position on the stick display.
+const DOT_RADIUS: f32 = 4.0;
+
+/// How one axis of the stick responds to being pushed.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct AxisSettings {
+    /// How far the stick may be pushed, from 0 to 1, before it counts as
+    /// pushed at all.
+    pub deadzone: f32,
+    /// The exponent of the response curve. 1 is linear, and higher values
+    /// make the stick need to be pushed further before it holds a direction.
+    pub curve: f32,
+}
+
+impl AxisSettings {
+    /// Return the processed position of the axis, from -1 to 1, given its raw
+    /// position. Positions inside the deadzone are 0, and the rest of the
+    /// range is stretched to fill 0 to 1 before the curve is applied.
+    pub fn apply(&self, raw: f32) -> f32 {
+        let magnitude = raw.abs().min(1.0);
+        if magnitude <= self.deadzone {
+            return 0.0;
+        }
+        let t = (magnitude - self.deadzone) / (1.0 - self.deadzone);
+        t.powf(self.curve).copysign(raw)
+    }
+}
+
+impl Default for AxisSettings {
+    fn default() -> Self {
+        AxisSettings {
+            deadzone: 0.2,
+            curve: 1.0,
+        }
+    }
+}
+
+/// The settings of both axes of the stick.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct StickSettings {
+    #[serde(default)]
+    pub x: AxisSettings,
+    #[serde(default)]
+    pub y: AxisSettings,
+}
+
+impl StickSettings {
+    /// Return the processed position of the stick given its raw position. Up
+    /// is positive y, as in world space.
+    pub fn apply(&self, raw: Point2<f32>) -> Point2<f32> {
+        Point2 {
+            x: self.x.apply(raw.x),
+            y: self.y.apply(raw.y),
+        }
+    }
+}
+
+/// Draw the stick display with its top left corner at `position`. The raw
+/// position of the stick is drawn in grey over the deadzone, and the processed
+/// position is drawn in white.
+pub fn draw_stick_display(
+    ctx: &mut Context,
+    raw: Point2<f32>,
+    settings: &StickSettings,
+    position: Point2<f32>,
+) -> GameResult<()> {
+    let half_size = STICK_DISPLAY_SIZE / 2.0;
+    let center = Point2 {
+        x: position.x + half_size,
+        y: position.y + half_size,
+    };
+    // Screen space has the y-axis flipped.
+    let to_screen = |stick: Point2<f32>| Point2 {
+        x: center.x + stick.x.clamp(-1.0, 1.0) * half_size,
+        y: center.y - stick.y.clamp(-1.0, 1.0) * half_size,
+    };
+
+    let mut mesh = MeshBuilder::new();
+    let outline = Rect::new(
+        position.x,
+        position.y,
+        STICK_DISPLAY_SIZE,
+        STICK_DISPLAY_SIZE,
+    );
+    mesh.rectangle(DrawMode::stroke(1.0), outline, color::GUIDE_GREY)?;
+    let (deadzone_x, deadzone_y) = (
+        settings.x.deadzone * half_size,
+        settings.y.deadzone * half_size,
+    );
+    let deadzone = Rect::new(
+        center.x - deadzone_x,
+        center.y - deadzone_y,
+        2.0 * deadzone_x,
+        2.0 * deadzone_y,
+    );
+    mesh.rectangle(DrawMode::stroke(1.0), deadzone, color::GUIDE_GREY)?;
+    // The display is drawn in screen coordinates, so it isn't scaled.
+    let fill = DrawMode::fill();
+    meshutil::circle(
+        &mut mesh,
+        fill,
+        to_screen(raw),
+        DOT_RADIUS,
+        1.0,
+        color::GUIDE_GREY,
+    )?;
+    let processed = to_screen(settings.apply(raw));
+    meshutil::circle(&mut mesh, fill, processed, DOT_RADIUS, 1.0, color::WHITE)?;
+    mesh.build(ctx)?.draw(ctx, DrawParam::default())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_deadzone_and_curve() {
+        let axis = AxisSettings {
+            deadzone: 0.2,
+            curve: 2.0,
+        };
+        assert_eq!(axis.apply(0.1), 0.0);
+        assert_eq!(axis.apply(-0.2), 0.0);
+        assert_eq!(axis.apply(1.0), 1.0);
+        assert_eq!(axis.apply(-1.0), -1.0);
+        // Halfway out of the deadzone, squared.
+        assert!((axis.apply(0.6) - 0.25).abs() < 1e-6);
+    }
+}
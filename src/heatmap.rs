@@ -0,0 +1,90 @@
+/// This module implements the hit heatmap, an overlay which shows where the
+/// player has been hit on a level across every session. Areas where the player
+/// is hit often are drawn brighter, which makes it easy to see which patterns
+/// cause the most trouble.
+use ggez::graphics::{Color, DrawMode, DrawParam, Drawable, MeshBuilder};
+use ggez::{Context, GameResult};
+
+use crate::save::HitRecord;
+use crate::world::{WorldLen, WorldPos, ARENA_HALF_SIZE};
+
+/// The width of each cell of the heatmap, in WorldLen units.
+const CELL_SIZE: f64 = 4.0;
+/// The opacity of the cell with the most hits.
+const MAX_OPACITY: f32 = 0.6;
+
+pub struct HitHeatmap {
+    cells_per_side: usize,
+    // The number of hits in each cell. Stored in row-major order, starting
+    // from the bottom left of the arena.
+    counts: Vec<usize>,
+    max_count: usize,
+}
+
+impl HitHeatmap {
+    pub fn new(hits: &[HitRecord]) -> HitHeatmap {
+        let cells_per_side = (2.0 * ARENA_HALF_SIZE / CELL_SIZE).ceil() as usize;
+        let mut counts = vec![0; cells_per_side * cells_per_side];
+        for hit in hits {
+            if let Some(i) = cell_index(hit.pos, cells_per_side) {
+                counts[i] += 1;
+            }
+        }
+        let max_count = counts.iter().copied().max().unwrap_or(0);
+        HitHeatmap {
+            cells_per_side,
+            counts,
+            max_count,
+        }
+    }
+
+    pub fn draw(&self, ctx: &mut Context) -> GameResult<()> {
+        // MeshBuilder::build errors on an empty mesh.
+        if self.max_count == 0 {
+            return Ok(());
+        }
+
+        let mut mesh = MeshBuilder::new();
+        for (i, &count) in self.counts.iter().enumerate() {
+            if count == 0 {
+                continue;
+            }
+            let heat = count as f32 / self.max_count as f32;
+            let rect = WorldPos::as_screen_rect(
+                cell_center(i, self.cells_per_side),
+                WorldLen(CELL_SIZE),
+                WorldLen(CELL_SIZE),
+            );
+            mesh.rectangle(
+                DrawMode::fill(),
+                rect,
+                Color::new(1.0, 0.5 * (1.0 - heat), 0.0, MAX_OPACITY * heat),
+            )?;
+        }
+        mesh.build(ctx)?.draw(ctx, DrawParam::default())
+    }
+}
+
+fn cell_index(pos: WorldPos, cells_per_side: usize) -> Option<usize> {
+    if !pos.in_arena() {
+        return None;
+    }
+    // Clamp, since a player standing on the far edge of the arena is exactly
+    // on the far side of the last cell.
+    let last = cells_per_side as f64 - 1.0;
+    let col = ((pos.x + ARENA_HALF_SIZE) / CELL_SIZE)
+        .floor()
+        .clamp(0.0, last);
+    let row = ((pos.y + ARENA_HALF_SIZE) / CELL_SIZE)
+        .floor()
+        .clamp(0.0, last);
+    Some(row as usize * cells_per_side + col as usize)
+}
+
+fn cell_center(i: usize, cells_per_side: usize) -> WorldPos {
+    let (col, row) = (i % cells_per_side, i / cells_per_side);
+    WorldPos {
+        x: -ARENA_HALF_SIZE + (col as f64 + 0.5) * CELL_SIZE,
+        y: -ARENA_HALF_SIZE + (row as f64 + 0.5) * CELL_SIZE,
+    }
+}
@@ -0,0 +1,111 @@
+/// This module draws arrows at the edge of the screen pointing towards enemies
+/// which are warming up off screen (ex: a laser passing through a point
+/// outside of the view), so that patterns which start out of view can still
+/// be read. Arrows are only drawn during warmup, since that is when the player
+/// needs to react.
+use ggez::graphics::mint::Point2;
+use ggez::graphics::{Color, DrawMode, DrawParam, Drawable, MeshBuilder};
+use ggez::{Context, GameResult};
+
+use crate::color;
+use crate::enemy::EnemyLifetime;
+use crate::time::Beats;
+use crate::world::{ScreenTransform, WorldPos};
+use crate::{EnemyGroup, GroupEnemy, WINDOW_HEIGHT, WINDOW_WIDTH};
+
+/// How far in from the edge of the screen the tip of each arrow is, in pixels.
+const EDGE_MARGIN: f32 = 12.0;
+/// The length of each arrow, in pixels.
+const ARROW_LENGTH: f32 = 14.0;
+/// How far each side of an arrow's base is from its center, in pixels.
+const ARROW_HALF_WIDTH: f32 = 7.0;
+
+/// Draw an arrow for every enemy in `groups` which is warming up off screen.
+/// Groups which hide their enemies' warmup (see `EnemyGroup::render_warmup`)
+/// get no arrows, since the arrows would give the hidden warmup away.
+/// This is drawn in screen space, with `transform` applied to each enemy's
+/// position rather than to the arrows, so that the arrows stay on the edge of
+/// the screen. `tint` is the theme's enemy color.
+pub fn draw_offscreen_indicators(
+    ctx: &mut Context,
+    groups: &[EnemyGroup],
+    transform: ScreenTransform,
+    curr_time: Beats,
+    tint: Color,
+) -> GameResult<()> {
+    let mut mesh = MeshBuilder::new();
+    let mut is_empty = true;
+    for group in groups
+        .iter()
+        .filter(|group| group.do_render && group.render_warmup)
+    {
+        let color = color::multiply(tint, group.tint);
+        let rotated_about = group.rotation_ease(curr_time);
+        for GroupEnemy { enemy, .. } in group.enemies.iter() {
+            if enemy.lifetime_state(curr_time) != EnemyLifetime::Warmup {
+                continue;
+            }
+            let pos = enemy.position(curr_time, rotated_about);
+            let (x, y) = transform.apply(pos.x, pos.y);
+            if let Some(points) = arrow_towards(WorldPos { x, y }.as_screen_coords()) {
+                mesh.polygon(DrawMode::fill(), &points, color)?;
+                is_empty = false;
+            }
+        }
+    }
+    // Building an empty mesh is an error.
+    if !is_empty {
+        mesh.build(ctx)?.draw(ctx, DrawParam::default())?;
+    }
+    Ok(())
+}
+
+/// Return the points of an arrow on the edge of the screen which points from
+/// the center of the screen towards `target`, or None if `target` is on
+/// screen.
+fn arrow_towards(target: Point2<f32>) -> Option<[Point2<f32>; 3]> {
+    let (center_x, center_y) = (WINDOW_WIDTH / 2.0, WINDOW_HEIGHT / 2.0);
+    let (dx, dy) = (target.x - center_x, target.y - center_y);
+    if dx.abs() <= center_x && dy.abs() <= center_y {
+        return None;
+    }
+    // Shorten the direction until it just reaches the edge of the screen.
+    let scale = ((center_x - EDGE_MARGIN) / dx.abs()).min((center_y - EDGE_MARGIN) / dy.abs());
+    let tip = Point2 {
+        x: center_x + dx * scale,
+        y: center_y + dy * scale,
+    };
+    let length = dx.hypot(dy);
+    let (unit_x, unit_y) = (dx / length, dy / length);
+    let base = Point2 {
+        x: tip.x - unit_x * ARROW_LENGTH,
+        y: tip.y - unit_y * ARROW_LENGTH,
+    };
+    let side = |sign: f32| Point2 {
+        x: base.x - unit_y * ARROW_HALF_WIDTH * sign,
+        y: base.y + unit_x * ARROW_HALF_WIDTH * sign,
+    };
+    Some([tip, side(1.0), side(-1.0)])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_arrows_stay_on_screen() {
+        let center = Point2 {
+            x: WINDOW_WIDTH / 2.0,
+            y: WINDOW_HEIGHT / 2.0,
+        };
+        assert!(arrow_towards(center).is_none());
+
+        let [tip, ..] = arrow_towards(Point2 {
+            x: WINDOW_WIDTH * 3.0,
+            y: center.y,
+        })
+        .unwrap();
+        assert!((tip.x - (WINDOW_WIDTH - EDGE_MARGIN)).abs() < 1e-3);
+        assert!((tip.y - center.y).abs() < 1e-3);
+    }
+}
@@ -0,0 +1,74 @@
+/// This module draws the input display, which shows which movement keys are
+/// held as a direction pad along with a bar for the focus key. This is meant
+/// for tutorials, streams, and for checking that replays play back the inputs
+/// that were recorded.
+use ggez::graphics::mint::Point2;
+use ggez::graphics::{Color, DrawMode, DrawParam, Drawable, MeshBuilder, Rect};
+use ggez::{Context, GameResult};
+
+use crate::color;
+use crate::keyboard::InputState;
+use crate::meshutil;
+
+/// The width and height of each key of the direction pad.
+const KEY_SIZE: f32 = 24.0;
+/// The space between keys.
+const KEY_GAP: f32 = 4.0;
+/// How rounded the corners of each key are.
+const KEY_CORNER_RADIUS: f32 = 4.0;
+
+/// The width and height of the input display.
+pub const INPUT_DISPLAY_SIZE: Point2<f32> = Point2 {
+    x: 3.0 * KEY_SIZE + 2.0 * KEY_GAP,
+    y: 2.0 * (KEY_SIZE + KEY_GAP) + KEY_SIZE / 2.0,
+};
+
+/// Draw the input display with its top left corner at `position`. `color` is
+/// the color of held keys.
+pub fn draw_input_display(
+    ctx: &mut Context,
+    input: InputState,
+    position: Point2<f32>,
+    color: Color,
+) -> GameResult<()> {
+    let step = KEY_SIZE + KEY_GAP;
+    // The direction pad, as (column, row, is held).
+    let keys = [
+        (1.0, 0.0, input.up),
+        (0.0, 1.0, input.left),
+        (1.0, 1.0, input.down),
+        (2.0, 1.0, input.right),
+    ];
+
+    let mut mesh = MeshBuilder::new();
+    for &(column, row, held) in &keys {
+        let rect = Rect::new(
+            position.x + column * step,
+            position.y + row * step,
+            KEY_SIZE,
+            KEY_SIZE,
+        );
+        add_key(&mut mesh, rect, held, color)?;
+    }
+
+    // The focus key sits underneath the direction pad, spanning its width.
+    let focus = Rect::new(
+        position.x,
+        position.y + 2.0 * step,
+        INPUT_DISPLAY_SIZE.x,
+        KEY_SIZE / 2.0,
+    );
+    add_key(&mut mesh, focus, input.focus, color)?;
+
+    mesh.build(ctx)?.draw(ctx, DrawParam::default())
+}
+
+fn add_key(mesh: &mut MeshBuilder, rect: Rect, held: bool, color: Color) -> GameResult<()> {
+    // The display is drawn in screen coordinates, so it isn't scaled.
+    if held {
+        meshutil::rounded_rect(mesh, DrawMode::fill(), rect, KEY_CORNER_RADIUS, 1.0, color)
+    } else {
+        let mode = DrawMode::stroke(1.0);
+        meshutil::rounded_rect(mesh, mode, rect, KEY_CORNER_RADIUS, 1.0, color::GUIDE_GREY)
+    }
+}
@@ -0,0 +1,103 @@
+/// This module draws circles in batches, as instanced quads which a fragment
+/// shader fills in with a circle. Enemies which are drawn with only circles
+/// (see `Enemy::draw_circles`) are added to a batch instead of building a mesh
+/// each, which skips tessellation entirely and draws any number of circles in
+/// a single draw call. If the shader can't be loaded, batches are unavailable
+/// and every enemy is drawn with its mesh instead.
+use ggez::graphics::mint::Point2;
+use ggez::graphics::spritebatch::SpriteBatch;
+use ggez::graphics::{self, BlendMode, Color, DrawParam, Drawable, EmptyConst, Image, Shader};
+use ggez::{Context, GameResult};
+
+const VERTEX_SHADER: &str = "/shaders/circle_150.glslv";
+const FRAGMENT_SHADER: &str = "/shaders/circle_150.glslf";
+/// The blend modes enemy groups may be drawn with. See `GroupBlendMode`.
+const BLEND_MODES: [BlendMode; 2] = [BlendMode::Alpha, BlendMode::Add];
+
+/// A filled circle. Enemies give their circles in the same units as their
+/// meshes (see `EnemyImpl::circles`), which `Enemy::draw_circles` turns into
+/// screen coordinates.
+#[derive(Debug, Clone, Copy)]
+pub struct Circle {
+    pub center: Point2<f32>,
+    pub radius: f32,
+    pub color: Color,
+}
+
+pub struct CircleBatch {
+    /// The shader and batch of quads, or None if the shader couldn't be
+    /// loaded.
+    batch: Option<(Shader<EmptyConst>, SpriteBatch)>,
+    /// How many circles have been added since the last draw.
+    len: usize,
+}
+
+impl CircleBatch {
+    pub fn new(ctx: &mut Context) -> CircleBatch {
+        let batch: GameResult<_> = try {
+            let shader = Shader::new(
+                ctx,
+                VERTEX_SHADER,
+                FRAGMENT_SHADER,
+                EmptyConst,
+                "circle",
+                Some(&BLEND_MODES),
+            )?;
+            // Quads are one pixel wide, so they are scaled by their diameter.
+            let image = Image::solid(ctx, 1, Color::WHITE)?;
+            (shader, SpriteBatch::new(image))
+        };
+        match batch {
+            Ok(batch) => CircleBatch {
+                batch: Some(batch),
+                len: 0,
+            },
+            Err(err) => {
+                log::warn!(
+                    "Couldn't load the circle shader, drawing meshes instead: {}",
+                    err
+                );
+                CircleBatch {
+                    batch: None,
+                    len: 0,
+                }
+            }
+        }
+    }
+
+    /// Returns false if the shader couldn't be loaded, in which case circles
+    /// must be drawn some other way.
+    pub fn available(&self) -> bool {
+        self.batch.is_some()
+    }
+
+    /// Add a circle, in screen coordinates. This does nothing if the batch is
+    /// unavailable.
+    pub fn add(&mut self, circle: Circle) {
+        if let Some((_, batch)) = &mut self.batch {
+            let diameter = 2.0 * circle.radius;
+            batch.add(
+                DrawParam::default()
+                    .dest(circle.center)
+                    .offset([0.5, 0.5])
+                    .scale([diameter, diameter])
+                    .color(circle.color),
+            );
+            self.len += 1;
+        }
+    }
+
+    /// Draw every circle added since the last draw, then clear them.
+    pub fn draw(&mut self, ctx: &mut Context, blend_mode: BlendMode) -> GameResult<()> {
+        let (shader, batch) = match &mut self.batch {
+            Some(batch) if self.len > 0 => batch,
+            _ => return Ok(()),
+        };
+        let _lock = graphics::use_shader(ctx, shader);
+        batch.set_blend_mode(Some(blend_mode));
+        graphics::draw(ctx, batch, DrawParam::default())?;
+        batch.clear();
+        self.len = 0;
+        Ok(())
+    }
+}
@@ -1,9 +1,79 @@
 use std::time::Instant;
 
 use ggez::event::KeyCode;
+use serde::{Deserialize, Serialize};
 
 use crate::util::Direction8;
 
+/// How far the processed stick must be pushed along an axis, from 0 to 1, to
+/// hold that direction.
+const STICK_PRESS_THRESHOLD: f32 = 0.5;
+
+/// The inputs which hold the focus key. Each is a bit of
+/// `KeyboardState::focus_held`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FocusInput {
+    Space = 1,
+    Shift = 2,
+    /// The gamepad's right trigger.
+    Trigger = 4,
+}
+
+/// Which FocusInputs the player focuses with. Inputs which aren't part of the
+/// binding are ignored, so that (ex:) Shift can be left free for other
+/// programs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FocusBinding {
+    Any,
+    Space,
+    Shift,
+    Trigger,
+}
+
+impl Default for FocusBinding {
+    fn default() -> FocusBinding {
+        FocusBinding::Any
+    }
+}
+
+impl FocusBinding {
+    pub const ALL: [FocusBinding; 4] = [
+        FocusBinding::Any,
+        FocusBinding::Space,
+        FocusBinding::Shift,
+        FocusBinding::Trigger,
+    ];
+
+    /// The key of the binding's name in the lang files.
+    pub fn key(self) -> &'static str {
+        match self {
+            FocusBinding::Any => "focus_binding.any",
+            FocusBinding::Space => "focus_binding.space",
+            FocusBinding::Shift => "focus_binding.shift",
+            FocusBinding::Trigger => "focus_binding.trigger",
+        }
+    }
+
+    /// Return true if `input` holds the focus key under this binding.
+    pub fn accepts(self, input: FocusInput) -> bool {
+        match self {
+            FocusBinding::Any => true,
+            FocusBinding::Space => input == FocusInput::Space,
+            FocusBinding::Shift => input == FocusInput::Shift,
+            FocusBinding::Trigger => input == FocusInput::Trigger,
+        }
+    }
+
+    /// Return the next binding, wrapping around to the first.
+    pub fn next(self) -> FocusBinding {
+        let i = FocusBinding::ALL
+            .iter()
+            .position(|&binding| binding == self)
+            .unwrap_or(0);
+        FocusBinding::ALL[(i + 1) % FocusBinding::ALL.len()]
+    }
+}
+
 /// Remembers the press state of the key since the last frame.
 /// Maybe should be hashmap?
 #[derive(Default, Debug)]
@@ -12,7 +82,18 @@ pub struct KeyboardState {
     pub right: Key,
     pub up: Key,
     pub down: Key,
+    /// The focus key, which may be held by any FocusInput in `focus_binding`.
     pub space: Key,
+    /// If true, pressing a focus input turns focus on or off, rather than
+    /// focus only being on while an input is held.
+    pub focus_toggle: bool,
+    /// Which FocusInputs hold the focus key. Set this with
+    /// `set_focus_binding`.
+    focus_binding: FocusBinding,
+    /// The FocusInputs currently held, as bits.
+    focus_held: u8,
+    /// The directions the analog stick is holding. See `update_stick`.
+    stick: InputState,
 }
 
 impl KeyboardState {
@@ -23,10 +104,103 @@ impl KeyboardState {
             Right | D => self.right.update(is_down),
             Up | W => self.up.update(is_down),
             Down | S => self.down.update(is_down),
-            Space => self.space.update(is_down),
+            Space => self.update_focus(FocusInput::Space, is_down),
+            LShift | RShift => self.update_focus(FocusInput::Shift, is_down),
             _ => (),
         }
     }
+
+    /// Press or release one of the focus inputs. Repeated presses of an input
+    /// which is already held are ignored, so holding a key in toggle mode
+    /// doesn't keep flipping focus.
+    pub fn update_focus(&mut self, input: FocusInput, is_down: bool) {
+        if !self.focus_binding.accepts(input) {
+            return;
+        }
+        let bit = input as u8;
+        let was_down = self.focus_held & bit != 0;
+        if is_down {
+            self.focus_held |= bit;
+        } else {
+            self.focus_held &= !bit;
+        }
+
+        let focused = if self.focus_toggle {
+            if is_down && !was_down {
+                !self.space.is_down
+            } else {
+                self.space.is_down
+            }
+        } else {
+            self.focus_held != 0
+        };
+        if focused != self.space.is_down {
+            self.space.update(focused);
+        }
+    }
+    /// Change which FocusInputs hold the focus key, releasing it in case it
+    /// was held by an input which no longer does.
+    pub fn set_focus_binding(&mut self, binding: FocusBinding) {
+        self.focus_binding = binding;
+        self.focus_held = 0;
+        if self.space.is_down {
+            self.space.update(false);
+        }
+    }
+
+    /// Return which of the keys used by the player are currently held.
+    pub fn input_state(&self) -> InputState {
+        InputState {
+            left: self.left.is_down,
+            right: self.right.is_down,
+            up: self.up.is_down,
+            down: self.down.is_down,
+            focus: self.space.is_down,
+        }
+    }
+
+    /// Press and release keys to match `input`. This is used to play back the
+    /// inputs of a replay.
+    pub fn set_input_state(&mut self, input: InputState) {
+        for (key, is_down) in [
+            (&mut self.left, input.left),
+            (&mut self.right, input.right),
+            (&mut self.up, input.up),
+            (&mut self.down, input.down),
+            (&mut self.space, input.focus),
+        ] {
+            if key.is_down != is_down {
+                key.update(is_down);
+            }
+        }
+    }
+
+    /// Press and release the direction keys to follow the analog stick. `x`
+    /// and `y` are the stick's position after its deadzone and response curve
+    /// (see `gamepad::StickSettings`), with up being positive. Keys are only
+    /// changed when the stick starts or stops holding their direction, so
+    /// resting the stick doesn't release keys held on the keyboard.
+    pub fn update_stick(&mut self, x: f32, y: f32) {
+        let held = InputState {
+            left: x <= -STICK_PRESS_THRESHOLD,
+            right: x >= STICK_PRESS_THRESHOLD,
+            up: y >= STICK_PRESS_THRESHOLD,
+            down: y <= -STICK_PRESS_THRESHOLD,
+            focus: false,
+        };
+        for (key, was_held, is_held) in [
+            (&mut self.left, self.stick.left, held.left),
+            (&mut self.right, self.stick.right, held.right),
+            (&mut self.up, self.stick.up, held.up),
+            (&mut self.down, self.stick.down, held.down),
+        ] {
+            if was_held != is_held {
+                key.update(is_held);
+            }
+        }
+        self.stick = held;
+    }
+
     /// Return the direction based on the current state.
     /// Supports diagonal directions.
     pub fn direction(&self) -> Result<Direction8, &'static str> {
@@ -52,6 +226,18 @@ impl KeyboardState {
     }
 }
 
+/// A snapshot of which keys used by the player are held. Unlike
+/// KeyboardState, this is small enough to record every frame (see `replay`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InputState {
+    pub left: bool,
+    pub right: bool,
+    pub up: bool,
+    pub down: bool,
+    /// The key held to move slowly.
+    pub focus: bool,
+}
+
 #[derive(Debug)]
 pub struct Key {
     pub is_down: bool,
@@ -59,6 +245,12 @@ pub struct Key {
 }
 
 impl Key {
+    /// When the key was last pressed. Use `Time::input_beats` to turn this
+    /// into a beat.
+    pub fn last_pressed(&self) -> Instant {
+        self.last_pressed
+    }
+
     fn update(&mut self, is_down: bool) {
         self.is_down = is_down;
         if is_down {
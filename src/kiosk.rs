@@ -0,0 +1,107 @@
+/// This module implements kiosk mode, for running the game at a demo booth.
+/// Kiosk mode is turned on by putting a KIOSK_FILE in the levels folder, which
+/// sets the playlist of levels to offer and how long each player gets. Each
+/// session starts when a player starts a level and ends once the session's
+/// time runs out, after which the results are shown briefly and the game goes
+/// back to attract mode (the demo) with the next level of the playlist
+/// selected. The config can also lock the game, so that visitors can't quit or
+/// open the options.
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+
+/// The file in the levels folder which turns on kiosk mode.
+pub const KIOSK_FILE: &str = "kiosk.toml";
+
+/// How long the results are shown for, unless the config sets otherwise.
+const DEFAULT_RESULTS_LENGTH: f64 = 15.0;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct KioskConfig {
+    /// The levels to offer, relative to the levels folder, in the order they
+    /// are offered. If this is empty, every level is offered.
+    #[serde(default)]
+    pub playlist: Vec<PathBuf>,
+    /// How long each session may last, in seconds. Sessions are unlimited if
+    /// this isn't set.
+    #[serde(default)]
+    pub session_length: Option<f64>,
+    /// How long the results are shown before going back to attract mode, in
+    /// seconds. Any input restarts the countdown.
+    #[serde(default = "default_results_length")]
+    pub results_length: f64,
+    /// If true, the game can't be quit and the options can't be opened.
+    #[serde(default)]
+    pub locked: bool,
+}
+
+fn default_results_length() -> f64 {
+    DEFAULT_RESULTS_LENGTH
+}
+
+pub struct Kiosk {
+    pub config: KioskConfig,
+    /// The index of the playlist level to select next.
+    next: usize,
+    /// When the current session started, if one is going.
+    session_start: Option<Instant>,
+}
+
+impl Kiosk {
+    /// Load the kiosk config from the levels folder. Returns None if there is
+    /// no config, in which case kiosk mode is off.
+    pub fn load(levels_folder: impl AsRef<Path>) -> anyhow::Result<Option<Kiosk>> {
+        let path = levels_folder.as_ref().join(KIOSK_FILE);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let config: KioskConfig = toml::from_str(&std::fs::read_to_string(path)?)?;
+        Ok(Some(Kiosk {
+            config,
+            next: 0,
+            session_start: None,
+        }))
+    }
+
+    /// Returns true if `level_folder`, relative to the levels folder, is in
+    /// the playlist.
+    pub fn offers(&self, level_folder: &Path) -> bool {
+        self.config.playlist.is_empty()
+            || self
+                .config
+                .playlist
+                .iter()
+                .any(|level| level == level_folder)
+    }
+
+    /// Return the next level of the playlist, relative to the levels folder,
+    /// and move on to the one after it.
+    pub fn next_level(&mut self) -> Option<PathBuf> {
+        let level = self.config.playlist.get(self.next)?.clone();
+        self.next = (self.next + 1) % self.config.playlist.len();
+        Some(level)
+    }
+
+    /// Start a session, unless one is already going.
+    pub fn start_session(&mut self) {
+        self.session_start.get_or_insert_with(Instant::now);
+    }
+
+    pub fn end_session(&mut self) {
+        self.session_start = None;
+    }
+
+    /// Returns true if a session is going and its time has run out.
+    pub fn session_over(&self) -> bool {
+        match (self.session_start, self.config.session_length) {
+            (Some(start), Some(length)) => start.elapsed().as_secs_f64() >= length,
+            _ => false,
+        }
+    }
+
+    /// How long the results are shown for.
+    pub fn results_length(&self) -> Duration {
+        Duration::from_secs_f64(self.config.results_length.max(0.0))
+    }
+}
@@ -0,0 +1,123 @@
+/// This module handles localization of the game's UI text. Each language has a
+/// TOML file in `resources/lang` mapping string keys (ex: `menu.options`) to
+/// the text for that language. Strings missing from a language fall back to
+/// English, and strings missing from English fall back to the key itself, so a
+/// partial translation never leaves blank text.
+use std::collections::HashMap;
+use std::path::Path;
+
+pub const LANG_FOLDER: &str = "lang";
+pub const DEFAULT_LANGUAGE: &str = "en";
+
+/// The key holding the name of the language, in that language.
+const LANGUAGE_NAME_KEY: &str = "language.name";
+
+#[derive(Debug, Clone, Default)]
+pub struct Strings {
+    language: String,
+    strings: HashMap<String, String>,
+    fallback: HashMap<String, String>,
+}
+
+impl Strings {
+    /// Load the strings for `language` from `resource_path`, along with the
+    /// English strings to fall back on.
+    pub fn load(resource_path: impl AsRef<Path>, language: &str) -> anyhow::Result<Strings> {
+        let lang_folder = resource_path.as_ref().join(LANG_FOLDER);
+        let fallback = read_strings(&lang_folder, DEFAULT_LANGUAGE)?;
+        let strings = if language == DEFAULT_LANGUAGE {
+            HashMap::new()
+        } else {
+            read_strings(&lang_folder, language)?
+        };
+        Ok(Strings {
+            language: language.to_string(),
+            strings,
+            fallback,
+        })
+    }
+
+    /// Return the language code of the strings (ex: "en").
+    pub fn language(&self) -> &str {
+        &self.language
+    }
+
+    /// Return the name of the language, in that language.
+    pub fn language_name(&self) -> &str {
+        self.get(LANGUAGE_NAME_KEY)
+    }
+
+    /// Return the text for `key`.
+    pub fn get<'a>(&'a self, key: &'a str) -> &'a str {
+        self.strings
+            .get(key)
+            .or_else(|| self.fallback.get(key))
+            .map_or(key, String::as_str)
+    }
+
+    /// Return the text for `key`, with each `{}` replaced by the next argument.
+    pub fn format(&self, key: &str, args: &[&dyn std::fmt::Display]) -> String {
+        let mut args = args.iter();
+        let mut pieces = self.get(key).split("{}");
+        let mut text = pieces.next().unwrap_or_default().to_string();
+        for piece in pieces {
+            if let Some(arg) = args.next() {
+                text += &arg.to_string();
+            }
+            text += piece;
+        }
+        text
+    }
+}
+
+/// Return the language codes of every language in `resource_path`, sorted.
+pub fn available_languages(resource_path: impl AsRef<Path>) -> Vec<String> {
+    let lang_folder = resource_path.as_ref().join(LANG_FOLDER);
+    let mut languages: Vec<String> = match std::fs::read_dir(lang_folder) {
+        Ok(entries) => entries
+            .filter_map(|entry| {
+                let path = entry.ok()?.path();
+                if path.extension()? == "toml" {
+                    Some(path.file_stem()?.to_string_lossy().to_string())
+                } else {
+                    None
+                }
+            })
+            .collect(),
+        Err(err) => {
+            log::warn!("Couldn't read languages: {}", err);
+            vec![]
+        }
+    };
+    languages.sort();
+    languages
+}
+
+fn read_strings(lang_folder: &Path, language: &str) -> anyhow::Result<HashMap<String, String>> {
+    let source = std::fs::read_to_string(lang_folder.join(format!("{}.toml", language)))?;
+    let value: toml::Value = toml::from_str(&source)?;
+    let mut strings = HashMap::new();
+    flatten("", &value, &mut strings);
+    Ok(strings)
+}
+
+// TOML reads dotted keys as nested tables, so flatten them back into dotted
+// keys. Non-string values are ignored.
+fn flatten(prefix: &str, value: &toml::Value, strings: &mut HashMap<String, String>) {
+    match value {
+        toml::Value::Table(table) => {
+            for (key, value) in table {
+                let key = if prefix.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{}.{}", prefix, key)
+                };
+                flatten(&key, value, strings);
+            }
+        }
+        toml::Value::String(string) => {
+            strings.insert(prefix.to_string(), string.clone());
+        }
+        _ => log::warn!("Ignoring non-string localization key {:?}", prefix),
+    }
+}
@@ -0,0 +1,231 @@
+/// This module implements the client for the online leaderboard, which is only
+/// built with the `leaderboard` cargo feature. Nothing is sent unless the
+/// player turns the leaderboard on in the save file (see
+/// `save::LeaderboardSettings`).
+/// The leaderboard server is expected to accept scores as JSON POSTed to
+/// `<endpoint>/scores`, and to return the best scores for a level as a JSON
+/// list from `GET <endpoint>/scores?level=<key>&chart_hash=<hash>&limit=<n>`.
+/// Levels are identified by their save key along with the hash of their chart
+/// (see `cache::chart_hash`), so that edited charts get a fresh leaderboard.
+/// Scores submitted under an older `cache::CHART_HASH_VERSION` stay on the
+/// leaderboard of the old hash, since the server can't tell which charts they
+/// were for.
+/// All requests run on a background thread so that a slow server never
+/// freezes the game.
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use ggez::graphics::mint::Point2;
+use ggez::graphics::{DrawParam, Drawable};
+use ggez::{Context, GameResult};
+use serde::{Deserialize, Serialize};
+
+use crate::cache;
+use crate::color;
+use crate::font::FontSet;
+use crate::lang::Strings;
+use crate::modifiers::Modifiers;
+use crate::save::{self, LeaderboardSettings};
+use crate::stats::RunStats;
+use crate::task::Task;
+use crate::time::SongSpeed;
+use crate::util;
+
+/// How many scores are shown for each level.
+pub const TOP_COUNT: usize = 10;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+const LINE_HEIGHT: f32 = 20.0;
+const TEXT_SIZE: f32 = 16.0;
+
+/// A finished run, as sent to the leaderboard server.
+#[derive(Debug, Clone, Serialize)]
+pub struct Submission {
+    pub level: String,
+    pub chart_hash: u64,
+    pub player: String,
+    pub hits: usize,
+    /// How fast the song was played, since runs at different speeds aren't
+    /// comparable.
+    pub song_speed: SongSpeed,
+    pub modifiers: Modifiers,
+    /// How much the player's speed was multiplied by while focusing. This
+    /// differs between charts, and the player may override it.
+    pub focus_factor: f64,
+    /// A hash of the run's results, so that the server can detect the same
+    /// run being submitted twice.
+    pub run_hash: u64,
+}
+
+impl Submission {
+    pub fn new(level: String, chart_hash: u64, player: String, stats: &RunStats) -> Submission {
+        let mut run = format!("{}:{}:{}", level, chart_hash, player).into_bytes();
+        run.extend_from_slice(&stats.song_speed.rate().to_le_bytes());
+        run.extend_from_slice(format!("{:?}", stats.modifiers).as_bytes());
+        run.extend_from_slice(&stats.focus_factor.to_le_bytes());
+        for section in &stats.sections {
+            run.extend_from_slice(&section.start.0.to_le_bytes());
+            run.extend_from_slice(&(section.hits as u64).to_le_bytes());
+        }
+        Submission {
+            level,
+            chart_hash,
+            player,
+            hits: stats.total_hits(),
+            song_speed: stats.song_speed,
+            modifiers: stats.modifiers.clone(),
+            focus_factor: stats.focus_factor,
+            run_hash: util::hash_bytes(&run),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct LeaderboardEntry {
+    pub player: String,
+    pub hits: usize,
+    #[serde(default)]
+    pub song_speed: SongSpeed,
+    #[serde(default)]
+    pub modifiers: Modifiers,
+}
+
+/// The leaderboard client, along with the top scores fetched so far.
+pub struct Leaderboard {
+    agent: ureq::Agent,
+    endpoint: String,
+    player: String,
+    // The folder holding every level, which levels are keyed relative to. See
+    // `save::level_key`.
+    levels_folder: PathBuf,
+    // The top scores of each level, keyed by level folder. Each level is only
+    // fetched once per session, except after submitting a score to it.
+    top_scores: HashMap<PathBuf, Task<Vec<LeaderboardEntry>>>,
+}
+
+impl Leaderboard {
+    /// Create the client, or return None if the player hasn't turned on the
+    /// leaderboard.
+    pub fn new(settings: &LeaderboardSettings, levels_folder: &Path) -> Option<Leaderboard> {
+        if !settings.enabled || settings.endpoint.is_empty() {
+            return None;
+        }
+        Some(Leaderboard {
+            agent: ureq::AgentBuilder::new().timeout(REQUEST_TIMEOUT).build(),
+            endpoint: settings.endpoint.trim_end_matches('/').to_string(),
+            player: settings.player_name.clone(),
+            levels_folder: levels_folder.to_path_buf(),
+            top_scores: HashMap::new(),
+        })
+    }
+
+    /// Submit a finished run of the level in `level_folder`, played on the
+    /// chart with the given `chart_hash`. The level's top scores are refetched
+    /// afterwards so that the new score shows up.
+    pub fn submit(&mut self, level_folder: &Path, chart_hash: u64, stats: &RunStats) {
+        let submission = Submission::new(
+            save::level_key(&self.levels_folder, level_folder),
+            chart_hash,
+            self.player.clone(),
+            stats,
+        );
+        let (agent, url) = (self.agent.clone(), format!("{}/scores", self.endpoint));
+        let (agent2, endpoint) = (self.agent.clone(), self.endpoint.clone());
+        let (level, hash) = (submission.level.clone(), submission.chart_hash);
+        // Fetching after the submission finishes guarantees the new score is
+        // included, since both run on the same thread.
+        let request = Task::spawn(move || {
+            if let Err(err) = agent
+                .post(&url)
+                .send_json(serde_json::to_value(&submission)?)
+            {
+                log::warn!("Couldn't submit score to {:?}: {}", url, err);
+            }
+            fetch_top(&agent2, &endpoint, &level, hash)
+        });
+        self.top_scores.insert(level_folder.to_path_buf(), request);
+    }
+
+    /// Draw the top scores of the level in `level_folder`, starting a fetch if
+    /// they haven't been fetched yet.
+    pub fn draw(
+        &mut self,
+        ctx: &mut Context,
+        fonts: &FontSet,
+        strings: &Strings,
+        level_folder: &Path,
+        position: Point2<f32>,
+    ) -> GameResult<()> {
+        let (agent, endpoint, levels_folder) = (&self.agent, &self.endpoint, &self.levels_folder);
+        let request = self
+            .top_scores
+            .entry(level_folder.to_path_buf())
+            .or_insert_with(|| {
+                let (agent, endpoint) = (agent.clone(), endpoint.clone());
+                let level = save::level_key(levels_folder, level_folder);
+                let level_folder = level_folder.to_path_buf();
+                Task::spawn(move || {
+                    // This may compile the chart if it isn't cached yet, which
+                    // is safe to do alongside the main thread. See
+                    // `cache::load_song_map`.
+                    let hash = cache::chart_hash(&level_folder)?;
+                    fetch_top(&agent, &endpoint, &level, hash)
+                })
+            });
+
+        let mut lines = vec![strings.format("leaderboard.header", &[&TOP_COUNT])];
+        match request.poll() {
+            None => lines.push(strings.get("leaderboard.loading").to_string()),
+            Some(Err(err)) => lines.push(strings.format("leaderboard.error", &[err])),
+            Some(Ok(entries)) if entries.is_empty() => {
+                lines.push(strings.get("leaderboard.empty").to_string())
+            }
+            Some(Ok(entries)) => lines.extend(entries.iter().enumerate().map(|(i, entry)| {
+                let mut line =
+                    strings.format("leaderboard.entry", &[&(i + 1), &entry.player, &entry.hits]);
+                if entry.song_speed != SongSpeed::Normal {
+                    line.push_str(&strings.format(
+                        "leaderboard.song_speed",
+                        &[&strings.get(entry.song_speed.key())],
+                    ));
+                }
+                if !entry.modifiers.is_empty() {
+                    line.push_str(
+                        &strings
+                            .format("leaderboard.modifiers", &[&entry.modifiers.label(strings)]),
+                    );
+                }
+                line
+            })),
+        }
+
+        for (i, line) in lines.iter().enumerate() {
+            fonts.text(line, color::GUIDE_GREY, TEXT_SIZE).draw(
+                ctx,
+                DrawParam::default().dest(Point2 {
+                    x: position.x,
+                    y: position.y + i as f32 * LINE_HEIGHT,
+                }),
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn fetch_top(
+    agent: &ureq::Agent,
+    endpoint: &str,
+    level: &str,
+    chart_hash: u64,
+) -> anyhow::Result<Vec<LeaderboardEntry>> {
+    let mut entries: Vec<LeaderboardEntry> = agent
+        .get(&format!("{}/scores", endpoint))
+        .query("level", level)
+        .query("chart_hash", &chart_hash.to_string())
+        .query("limit", &TOP_COUNT.to_string())
+        .call()?
+        .into_json()?;
+    entries.truncate(TOP_COUNT);
+    Ok(entries)
+}
@@ -0,0 +1,1325 @@
+//! The rthm engine. This crate contains the simulation (enemies, the player,
+//! the chart scheduler) and the Lua chart parser, independent of the actual
+//! game binary. This lets the simulation be driven by things other than the
+//! game itself, such as an editor, a difficulty analyzer, or headless tests.
+//!
+//! The main entry points are:
+//! - [`SongMap`], which is produced by running a chart's `main.lua` file.
+//! - [`WorldState`], which owns the player, the enemy groups, the music, and
+//!   the [`Scheduler`] that plays back a [`SongMap`].
+//!
+//! The simulation and rendering both run on the main thread. Drawing from a
+//! separate thread isn't possible, since ggez's Context (and the OpenGL
+//! context behind it) must stay on the thread which created the window, so a
+//! split would have to move the simulation off of the main thread instead.
+//! That would need the world to be `Send`, which it isn't, since enemies are
+//! boxed trait objects (`Box<dyn Enemy>`) with no `Send` bound. A snapshot of
+//! what to draw would also mean copying every enemy's drawing state each
+//! tick, since drawing needs the enemies themselves (`Enemy::draw` builds each
+//! enemy's mesh from its own fields). Instead, spikes from building meshes are
+//! kept down on the main thread, by the frame budget (see `budget`) and by
+//! batching the bodies of bullets instead of building a mesh for each one (see
+//! `instanced`). A bullet's guide still needs a mesh (see `Enemy::draw_extra`),
+//! as does a bullet warming up with a telegraph other than the default one.
+#![feature(drain_filter)]
+#![feature(trait_alias)]
+#![feature(float_interpolation)]
+#![feature(try_blocks)]
+
+use std::path::{Path, PathBuf};
+
+use ggez::event::KeyCode;
+use ggez::graphics::{Color, DrawMode, DrawParam, Drawable, Mesh};
+use ggez::{Context, GameError, GameResult};
+
+use audio::{AudioOutput, MusicHandle};
+use color::GroupBlendMode;
+use debug::DebugStats;
+use ease::{BeatEasing, FlashOnBeat, Lerp};
+use enemy::{Enemy, EnemyAnimation, EnemyLifetime};
+use event::GameEvent;
+use force::ForceField;
+use heatmap::HitHeatmap;
+use instanced::{Circle, CircleBatch};
+use keyboard::KeyboardState;
+use memory::ResourceCounts;
+use meshcache::MeshCache;
+use modifiers::{Modifier, Modifiers};
+use objective::{Objective, ObjectiveStatus, BEATS_PER_MEASURE};
+use obstacle::Obstacle;
+use particle::Particles;
+use pickup::Pickup;
+use player::Player;
+use preview::PatternPreview;
+use replay::Replay;
+use stats::RunStats;
+use teleporter::Teleporter;
+use theme::{Theme, ThemeFade};
+use time::{to_beats, to_secs, Beats, Seconds, SongSpeed, Time};
+use vfs::Vfs;
+use vision::Vision;
+use world::{ScreenTransform, WorldLen, WorldPos};
+
+pub use chart::Scheduler;
+pub use parse::SongMap;
+
+pub mod analyze;
+pub mod audio;
+pub mod autoplay;
+pub mod browser;
+pub mod budget;
+pub mod cache;
+pub mod capture;
+pub mod chart;
+pub mod check;
+pub mod color;
+pub mod debug;
+pub mod debug_draw;
+pub mod ease;
+pub mod enemy;
+pub mod event;
+pub mod event_loop;
+pub mod font;
+pub mod force;
+pub mod gamepad;
+pub mod heatmap;
+pub mod indicator;
+pub mod input_display;
+pub mod instanced;
+pub mod keyboard;
+pub mod kiosk;
+pub mod lang;
+#[cfg(feature = "leaderboard")]
+pub mod leaderboard;
+pub mod medal;
+pub mod memory;
+pub mod meshcache;
+pub mod meshutil;
+pub mod modifiers;
+pub mod narration;
+pub mod objective;
+pub mod obstacle;
+pub mod parse;
+pub mod particle;
+pub mod path;
+pub mod pickup;
+pub mod player;
+pub mod preview;
+pub mod reach;
+pub mod replay;
+pub mod save;
+pub mod stats;
+pub mod task;
+pub mod telegraph;
+pub mod teleporter;
+pub mod theme;
+pub mod time;
+pub mod ui;
+pub mod util;
+pub mod vfs;
+pub mod vision;
+pub mod world;
+
+pub const WINDOW_WIDTH: f32 = 1.5 * 640.0;
+pub const WINDOW_HEIGHT: f32 = 1.5 * 480.0;
+
+/// The default for `WorldState::max_darkness`, which leaves darkness as dark
+/// as it is drawn.
+pub const DEFAULT_MAX_DARKNESS: f32 = 1.0;
+
+/// The opacity of the background flash at full intensity. This is kept low so
+/// that flashing backgrounds stay easy on the eyes.
+const BACKGROUND_FLASH_OPACITY: f32 = 0.15;
+/// How close the edge of the player must come to an enemy's hitbox, without
+/// touching it, to graze the enemy.
+pub const GRAZE_DISTANCE: WorldLen = WorldLen(2.0);
+/// How close an enemy's hitbox must be to the player to be cleared by a bomb.
+pub const BOMB_RADIUS: WorldLen = WorldLen(30.0);
+/// How far ahead of the current time sounds are scheduled on the audio device.
+/// This must be longer than a frame, or sounds would start late.
+const SOUND_LOOKAHEAD: Seconds = Seconds(0.2);
+
+/// Measure and cache the gain for `music_file` on another thread, so that
+/// it is ready the next time the level in `base_folder` is loaded. See
+/// `cache::music_gain`.
+fn measure_gain_in_background(base_folder: &Path, music_file: Vec<u8>) {
+    let base_folder = base_folder.to_path_buf();
+    std::thread::spawn(move || {
+        if let Err(err) = cache::music_gain(&base_folder, &music_file) {
+            log::warn!("Couldn't measure the loudness of the music: {}", err);
+        }
+    });
+}
+
+/// A sound the chart plays on a given beat. See `SpawnCmd::PlaySound`.
+#[derive(Debug, Clone)]
+struct SoundCue {
+    time: Beats,
+    path: PathBuf,
+    volume: f64,
+}
+
+impl SoundCue {
+    /// Return every sound the SongMap plays, in the order they are played.
+    fn all(map: &SongMap) -> Vec<SoundCue> {
+        let mut cues: Vec<SoundCue> = map
+            .actions
+            .iter()
+            .filter_map(|action| {
+                let (path, volume) = action.action().sound()?;
+                Some(SoundCue {
+                    time: action.start_time(),
+                    path: path.to_path_buf(),
+                    volume,
+                })
+            })
+            .collect();
+        cues.sort_by(|a, b| a.time.0.total_cmp(&b.time.0));
+        cues
+    }
+}
+
+/// An enemy in an EnemyGroup, along with what the group tracks about it.
+pub struct GroupEnemy {
+    pub enemy: Box<dyn Enemy>,
+    /// When the enemy last hit the player, if it has.
+    pub last_hit: Option<Beats>,
+    /// Whether the player has grazed the enemy. Each enemy can only be grazed
+    /// once, and enemies which hit the player can't be grazed afterwards.
+    pub grazed: bool,
+    /// Whether the group has sent the enemy's EnemySpawned and EnemyDied
+    /// events yet.
+    pub announced_spawn: bool,
+    pub announced_death: bool,
+}
+
+impl GroupEnemy {
+    pub fn new(enemy: Box<dyn Enemy>) -> GroupEnemy {
+        GroupEnemy {
+            enemy,
+            last_hit: None,
+            grazed: false,
+            announced_spawn: false,
+            announced_death: false,
+        }
+    }
+}
+
+/// A collection of enemies which share the same rendering and hitbox settings.
+/// Charts refer to groups by their index in `InnerWorldState::groups`.
+pub struct EnemyGroup {
+    pub enemies: Vec<GroupEnemy>,
+    pub use_hitbox: bool,
+    pub do_render: bool,
+    pub render_warmup: bool,
+    pub animation: EnemyAnimation,
+    pub fadeout: Option<BeatEasing<Color>>,
+    pub rotation: Option<(BeatEasing<f64>, WorldPos)>,
+    /// The color every enemy in the group is multiplied by. This is applied
+    /// on top of the fadeout.
+    pub tint: Color,
+    pub blend_mode: GroupBlendMode,
+    pub flash: Option<FlashOnBeat>,
+    /// If set, the group's enemies hurt the player once every this many beats
+    /// for as long as the player touches them, regardless of the player's
+    /// hit cooldown. Otherwise, each enemy hurts the player at most once.
+    pub contact_damage: Option<Beats>,
+}
+
+impl EnemyGroup {
+    fn new() -> EnemyGroup {
+        EnemyGroup {
+            enemies: Vec::with_capacity(16),
+            use_hitbox: true,
+            do_render: true,
+            render_warmup: true,
+            animation: EnemyAnimation::default(),
+            fadeout: None,
+            rotation: None,
+            tint: Color::WHITE,
+            blend_mode: GroupBlendMode::Alpha,
+            flash: None,
+            contact_damage: None,
+        }
+    }
+
+    pub fn spawn(&mut self, enemy: Box<dyn Enemy>) {
+        self.enemies.push(GroupEnemy::new(enemy));
+    }
+
+    /// Update every enemy, checking for hits and grazes unless
+    /// `hitboxes_only` is set. See `InnerWorldState::hitboxes_only`.
+    fn update(
+        &mut self,
+        group_number: usize,
+        player: &mut Player,
+        curr_time: Beats,
+        hitboxes_only: bool,
+        events: &mut Vec<GameEvent>,
+    ) {
+        let rotated_about = self.rotation_ease(curr_time);
+        let mut children = Vec::new();
+        for GroupEnemy {
+            enemy,
+            last_hit,
+            grazed,
+            announced_spawn,
+            ..
+        } in self.enemies.iter_mut()
+        {
+            if !*announced_spawn {
+                *announced_spawn = true;
+                events.push(GameEvent::EnemySpawned {
+                    group: group_number,
+                    time: curr_time,
+                });
+            }
+            enemy.update(curr_time, player.pos, rotated_about);
+            children.extend(enemy.spawn_children(curr_time, player.pos, rotated_about));
+            if !self.use_hitbox || hitboxes_only {
+                continue;
+            }
+            // Most enemies are nowhere near the player, so skip the sdf for
+            // anything that definitely can't be touching (or grazing) them.
+            let can_graze = !*grazed && last_hit.is_none();
+            let reach = if can_graze {
+                WorldLen(player.size.0 + GRAZE_DISTANCE.0)
+            } else {
+                player.size
+            };
+            if !enemy.could_touch(player.pos, reach, curr_time, rotated_about) {
+                continue;
+            }
+            let sdf = match enemy.sdf(player.pos, curr_time, rotated_about) {
+                Some(sdf) => sdf,
+                None => continue,
+            };
+            if sdf >= player.size {
+                if can_graze && sdf < reach {
+                    *grazed = true;
+                    events.push(GameEvent::Grazed {
+                        group: group_number,
+                        pos: player.pos,
+                        time: curr_time,
+                    });
+                }
+                continue;
+            }
+            let hit = match (self.contact_damage, *last_hit) {
+                (_, None) => player.on_hit(curr_time),
+                (None, Some(_)) => false,
+                (Some(interval), Some(last_hit)) => {
+                    curr_time >= last_hit + interval && player.on_contact_hit(curr_time)
+                }
+            };
+            if hit {
+                *last_hit = Some(curr_time);
+                if let Some(gradient) = enemy.sdf_gradient(player.pos, curr_time, rotated_about) {
+                    player.knock_back(gradient);
+                }
+                enemy.on_player_hit(curr_time);
+                events.push(GameEvent::PlayerHit {
+                    group: group_number,
+                    pos: player.pos,
+                    time: curr_time,
+                });
+            }
+        }
+
+        for GroupEnemy {
+            enemy,
+            announced_death,
+            ..
+        } in self.enemies.iter_mut()
+        {
+            if !*announced_death && enemy.lifetime_state(curr_time) == EnemyLifetime::Dead {
+                *announced_death = true;
+                events.push(GameEvent::EnemyDied {
+                    group: group_number,
+                    time: curr_time,
+                });
+            }
+        }
+
+        // remove dead enemies, once they have finished despawning
+        let animation = &self.animation;
+        self.enemies.retain(|GroupEnemy { enemy, .. }| {
+            enemy.lifetime_state(curr_time) != EnemyLifetime::Dead
+                || (enemy.despawns() && animation.despawning(enemy.end_time(), curr_time))
+        });
+        self.enemies
+            .extend(children.into_iter().map(GroupEnemy::new));
+    }
+
+    /// Draw every enemy in the group. `theme_tint` is the current theme's
+    /// enemy color, which is applied on top of the group's own tint. If
+    /// `circle_batch` is available, the circles of enemies which have them
+    /// (see `Enemy::draw_circles`) are added to it, and the rest of those
+    /// enemies is drawn with their meshes.
+    /// Meshes are drawn in the order the enemies spawned, but the batch is
+    /// drawn once every mesh in the group is. So batched circles are drawn on
+    /// top of the group's meshes, even those of enemies which spawned later
+    /// (ex: a bullet's body covers the guide of a later bullet which crosses
+    /// it). Groups are still drawn in order, each on top of the last.
+    fn draw(
+        &self,
+        ctx: &mut Context,
+        curr_time: Beats,
+        theme_tint: Color,
+        modifiers: &Modifiers,
+        circle_batch: &mut CircleBatch,
+        mesh_cache: &mut MeshCache,
+    ) -> GameResult<()> {
+        if !self.do_render {
+            return Ok(());
+        }
+
+        let tint = color::multiply(theme_tint, self.tint);
+        let color = if let Some(fadeout) = &self.fadeout {
+            color::multiply(tint, fadeout.ease(curr_time))
+        } else {
+            tint
+        };
+        let color = if let Some(flash) = &self.flash {
+            let brightness = flash.brightness(curr_time);
+            Color::new(
+                color.r * brightness,
+                color.g * brightness,
+                color.b * brightness,
+                color.a,
+            )
+        } else {
+            color
+        };
+
+        let rotated_about = self.rotation_ease(curr_time);
+        for GroupEnemy { enemy, .. } in self.enemies.iter() {
+            if !self.render_warmup && enemy.lifetime_state(curr_time) == EnemyLifetime::Warmup {
+                continue;
+            }
+
+            let mut color = color;
+            color.a *= modifiers.enemy_opacity(enemy.as_ref(), curr_time);
+
+            if circle_batch.available() {
+                if let Some(circles) = enemy.draw_circles(curr_time, rotated_about, &self.animation)
+                {
+                    if let Some((mut mesh, param)) =
+                        enemy.draw_extra(ctx, curr_time, rotated_about, &self.animation)?
+                    {
+                        let color = color::multiply(param.color, color);
+                        mesh.set_blend_mode(Some(self.blend_mode.into()));
+                        mesh.draw(ctx, param.color(color))?;
+                    }
+                    for circle in circles {
+                        circle_batch.add(Circle {
+                            color: color::multiply(circle.color, color),
+                            ..circle
+                        });
+                    }
+                    continue;
+                }
+            }
+
+            if let Some((mut mesh, param)) =
+                enemy.draw(ctx, curr_time, rotated_about, &self.animation, mesh_cache)?
+            {
+                let color = color::multiply(param.color, color);
+                mesh.set_blend_mode(Some(self.blend_mode.into()));
+                mesh.draw(ctx, param.color(color))?;
+            }
+        }
+
+        circle_batch.draw(ctx, self.blend_mode.into())
+    }
+
+    fn rotation_ease(&self, curr_time: Beats) -> Option<(WorldPos, f64)> {
+        self.rotation
+            .as_ref()
+            .map(|(easing, rot_point)| (*rot_point, easing.ease(curr_time)))
+    }
+}
+
+/// The part of the world that the Scheduler is allowed to modify.
+pub struct InnerWorldState {
+    pub player: Player,
+    pub groups: Vec<EnemyGroup>,
+    pub obstacles: Vec<Obstacle>,
+    pub force_fields: Vec<ForceField>,
+    pub teleporters: Vec<Teleporter>,
+    pub pickups: Vec<Pickup>,
+    pub particles: Particles,
+    pub vision: Option<Vision>,
+    pub screen_transform: ScreenTransform,
+    /// If set, the background flashes along with the beat.
+    pub background_flash: Option<FlashOnBeat>,
+    /// The colors of the arena, which charts may fade between.
+    pub theme: ThemeFade,
+    /// The objectives the chart has set so far, in the order they were set.
+    pub objectives: Vec<Objective>,
+    /// The events emitted since the start of the most recent update.
+    pub events: Vec<GameEvent>,
+    /// How many times each group has hit the player since the most recent
+    /// section marker, indexed by group number. See `SpawnCmd::IfHits`.
+    pub section_hits: Vec<usize>,
+    /// The name of the most recent section marker, if the chart has reached
+    /// one. See `SpawnCmd::MarkSection`.
+    pub section: Option<String>,
+    /// Text the chart has added to the end of the window's title, if any. See
+    /// `SpawnCmd::SetTitleSuffix`.
+    pub title_suffix: Option<String>,
+    /// The modifiers the run is played with. Unlike everything else here,
+    /// these are picked by the player rather than by the chart.
+    pub modifiers: Modifiers,
+    /// If set, the player uses a bomb during the next update, counting as if
+    /// it was used on this beat. This is the beat the bomb was pressed on,
+    /// compensating for the input offset (see `Time::input_beats`). See
+    /// `Player::use_bomb`.
+    pub bomb_pending: Option<Beats>,
+    /// If true, the world is only simulated to find out where the hitboxes go
+    /// (ex: for `reach` and `preview`). Enemies never hit or graze the player,
+    /// so they don't react to a player who isn't really there, and no
+    /// particles are thrown off.
+    pub hitboxes_only: bool,
+}
+
+impl InnerWorldState {
+    pub fn new(player: Player) -> InnerWorldState {
+        InnerWorldState {
+            player,
+            groups: {
+                let mut vec = Vec::with_capacity(8);
+                vec.resize_with(8, EnemyGroup::new);
+                vec
+            },
+            obstacles: vec![],
+            force_fields: vec![],
+            teleporters: vec![],
+            pickups: vec![],
+            particles: Particles::new(),
+            vision: None,
+            screen_transform: ScreenTransform::default(),
+            background_flash: None,
+            theme: ThemeFade::default(),
+            objectives: vec![],
+            events: vec![],
+            section_hits: vec![],
+            section: None,
+            title_suffix: None,
+            modifiers: Modifiers::default(),
+            bomb_pending: None,
+            hitboxes_only: false,
+        }
+    }
+
+    /// Returns true if a player of the given size standing at `pos` would be
+    /// hit by any group with a hitbox.
+    pub fn covers(&self, pos: WorldPos, player_size: WorldLen, curr_time: Beats) -> bool {
+        self.groups.iter().filter(|g| g.use_hitbox).any(|group| {
+            let rotated_about = group.rotation_ease(curr_time);
+            group.enemies.iter().any(|GroupEnemy { enemy, .. }| {
+                // Check the bounds first, since most enemies are far from most
+                // points.
+                if !enemy.could_touch(pos, player_size, curr_time, rotated_about) {
+                    return false;
+                }
+                match enemy.sdf(pos, curr_time, rotated_about) {
+                    Some(sdf) => sdf < player_size,
+                    None => false,
+                }
+            })
+        })
+    }
+
+    /// Use one of the player's bombs, clearing every Active enemy whose hitbox
+    /// is within BOMB_RADIUS of the player. This does nothing if the player
+    /// has no bombs left.
+    fn use_bomb(&mut self, curr_time: Beats) {
+        if !self.player.use_bomb(curr_time) {
+            return;
+        }
+        let pos = self.player.pos;
+        let events = &mut self.events;
+        for (group_number, group) in self.groups.iter_mut().enumerate() {
+            if !group.use_hitbox {
+                continue;
+            }
+            let rotated_about = group.rotation_ease(curr_time);
+            group.enemies.retain(|group_enemy| {
+                let enemy = &group_enemy.enemy;
+                let cleared = enemy.could_touch(pos, BOMB_RADIUS, curr_time, rotated_about)
+                    && matches!(
+                        enemy.sdf(pos, curr_time, rotated_about),
+                        Some(sdf) if sdf < BOMB_RADIUS
+                    );
+                if cleared && !group_enemy.announced_death {
+                    events.push(GameEvent::EnemyDied {
+                        group: group_number,
+                        time: curr_time,
+                    });
+                }
+                !cleared
+            });
+        }
+        events.push(GameEvent::BombUsed {
+            pos,
+            time: curr_time,
+        });
+    }
+
+    /// Return the transform the playfield is drawn with, which is the chart's
+    /// transform with the modifiers applied on top.
+    pub fn view_transform(&self) -> ScreenTransform {
+        self.modifiers.screen_transform(self.screen_transform)
+    }
+
+    /// Move the player and update every enemy group, checking for hits. This
+    /// clears the events from the previous update.
+    pub fn update(&mut self, keyboard: &KeyboardState, physics_delta_time: f64, curr_time: Beats) {
+        self.events.clear();
+        self.obstacles.retain(|o| !o.is_dead(curr_time));
+        self.force_fields.retain(|f| !f.is_dead(curr_time));
+        self.teleporters.retain(|t| !t.is_dead(curr_time));
+        self.pickups.retain(|p| !p.is_dead(curr_time));
+        if matches!(&self.vision, Some(vision) if vision.is_dead(curr_time)) {
+            self.vision = None;
+        }
+        self.player.update(
+            physics_delta_time,
+            keyboard,
+            self.view_transform(),
+            &self.obstacles,
+            &self.force_fields,
+        );
+
+        for teleporter in self.teleporters.iter_mut() {
+            let from = self.player.pos;
+            if teleporter.update(&mut self.player, curr_time) {
+                self.events.push(GameEvent::Teleported {
+                    from,
+                    to: self.player.pos,
+                    time: curr_time,
+                });
+            }
+        }
+
+        let (player, focused) = (&self.player, keyboard.space.is_down);
+        let collected: Vec<Pickup> = self
+            .pickups
+            .drain_filter(|pickup| pickup.update(player, focused, physics_delta_time))
+            .collect();
+        for pickup in collected {
+            pickup.collect(&mut self.player);
+            self.events.push(GameEvent::PickupCollected {
+                kind: pickup.kind,
+                pos: pickup.pos,
+                time: curr_time,
+            });
+        }
+
+        if let Some(pressed) = self.bomb_pending.take() {
+            // A negative input offset may put the press after the current
+            // beat, but bombs can't be used ahead of time.
+            self.use_bomb(Beats(pressed.0.min(curr_time.0)));
+        }
+
+        for (group_number, group) in self.groups.iter_mut().enumerate() {
+            group.update(
+                group_number,
+                &mut self.player,
+                curr_time,
+                self.hitboxes_only,
+                &mut self.events,
+            );
+        }
+
+        if !self.hitboxes_only {
+            self.particles.update(physics_delta_time);
+            self.particles.deflect(&self.groups, curr_time);
+            self.particles.burst_for_events(&self.events);
+        }
+
+        for event in self.events.iter() {
+            if let GameEvent::PlayerHit { group, .. } = *event {
+                if group >= self.section_hits.len() {
+                    self.section_hits.resize(group + 1, 0);
+                }
+                self.section_hits[group] += 1;
+            }
+        }
+    }
+}
+
+/// The state of a level which is currently being played. This owns the music
+/// as well as the Scheduler which spawns enemies over the course of the song.
+pub struct WorldState {
+    pub inner: InnerWorldState,
+    audio: AudioOutput,
+    scheduler: Scheduler,
+    started: bool,
+    /// An enemy spawned manually for debugging purposes. This enemy is updated
+    /// but has no hitbox and is only drawn by `draw_debug_hitbox`.
+    pub debug: Option<Box<dyn Enemy>>,
+    /// The pattern preview to draw underneath the enemies, if any.
+    pub preview: Option<PatternPreview>,
+    /// The heatmap of past hits to draw underneath the enemies, if any.
+    pub heatmap: Option<HitHeatmap>,
+    /// The most opaque that darkness (from SetVision or the Flashlight
+    /// modifier) may be drawn, from 0 to 1. This is an accessibility setting
+    /// (see `SaveData::max_darkness`), and setting it to 0 disables the
+    /// darkness.
+    pub max_darkness: f32,
+    /// The player's preferred focus factor, which replaces the chart's if set.
+    /// See `Player::focus_factor`.
+    pub focus_factor: Option<f64>,
+    /// How fast the song is played, which applies from the next `reset_to`.
+    pub song_speed: SongSpeed,
+    /// The modifiers to play with, which apply from the next `reset_to`.
+    pub modifiers: Modifiers,
+    /// The statistics for the current run.
+    pub stats: RunStats,
+    /// The recording of the current run.
+    pub replay: Replay,
+    /// A previous run to draw alongside the player, if any.
+    pub ghost: Option<Replay>,
+    /// The stats collected during the most recent update, for the debug
+    /// panel. This is None until the world has been updated once.
+    pub debug_stats: Option<DebugStats>,
+    /// The SongMap the world was most recently started with, kept so that the
+    /// world can be restarted without reading the chart again.
+    song_map: SongMap,
+    instance_handle: Option<MusicHandle>,
+    /// If true, the world has been paused with `pause`.
+    paused: bool,
+    /// If true, the music has been started but the Time hasn't been lined up
+    /// with it yet. See `sync_time`.
+    music_start_pending: bool,
+    /// Every sound the chart plays, and the index of the first one which hasn't
+    /// been scheduled yet. See `schedule_sounds`.
+    sound_cues: Vec<SoundCue>,
+    next_cue: usize,
+    /// If true, the metronome clicks on every beat. `next_click` is the first
+    /// beat whose click hasn't been scheduled yet.
+    metronome: bool,
+    next_click: Beats,
+    /// The batch enemies made of circles are drawn with. This is created the
+    /// first time the world is drawn, since it needs a Context.
+    circle_batch: Option<CircleBatch>,
+    /// The meshes of enemies which have a shape. See `load_meshes`.
+    mesh_cache: MeshCache,
+}
+
+impl WorldState {
+    /// Create a new WorldState for the given SongMap. `base_folder` is the
+    /// folder (or level archive) that the SongMap's music path is relative
+    /// to. The world does not start playing until `reset_to` is called.
+    pub fn new<P: AsRef<Path>>(base_folder: P, map: &SongMap) -> WorldState {
+        let music_file = if let Some(path) = &map.music_path {
+            let path = base_folder.as_ref().join(path);
+            let file: anyhow::Result<Vec<u8>> = try { Vfs::open(&base_folder)?.read(&path)? };
+            match file {
+                Ok(file) => Some(file),
+                Err(err) => {
+                    log::warn!("Couldn't read music file from path {:?}: {}", path, err);
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
+        // Music is normalized to the same loudness, unless the chart sets its
+        // own gain. Measuring the music means decoding all of it, which is too
+        // slow to do here, so the level select measures it in the background.
+        // If that hasn't finished yet, the music is played as is this time.
+        let gain_db = match (map.music_gain_db, &music_file) {
+            (Some(gain_db), _) => gain_db,
+            (None, Some(file)) => match cache::cached_music_gain(&base_folder, file) {
+                Some(gain_db) => gain_db,
+                None => {
+                    log::warn!("The music hasn't been measured yet, playing it as is");
+                    measure_gain_in_background(base_folder.as_ref(), file.clone());
+                    0.0
+                }
+            },
+            (None, None) => 0.0,
+        };
+
+        let chart_hash = match cache::song_map_hash(map, music_file.as_deref()) {
+            Ok(hash) => hash,
+            Err(err) => {
+                log::warn!("Couldn't hash chart in {:?}: {}", base_folder.as_ref(), err);
+                0
+            }
+        };
+
+        let mut audio = AudioOutput::new(music_file, analyze::db_to_amplitude(gain_db));
+        let sound_cues = SoundCue::all(map);
+        let mut sound_paths: Vec<&PathBuf> = sound_cues.iter().map(|cue| &cue.path).collect();
+        sound_paths.sort();
+        sound_paths.dedup();
+        for path in sound_paths {
+            let file: anyhow::Result<Vec<u8>> =
+                try { Vfs::open(&base_folder)?.read(&base_folder.as_ref().join(path))? };
+            match file {
+                Ok(file) => audio.add_sound(path.clone(), file),
+                Err(err) => log::warn!("Couldn't read sound file from path {:?}: {}", path, err),
+            }
+        }
+
+        WorldState {
+            inner: InnerWorldState::new(map.player),
+            audio,
+            started: false,
+            scheduler: Scheduler::new(map),
+            debug: None,
+            preview: None,
+            heatmap: None,
+            max_darkness: DEFAULT_MAX_DARKNESS,
+            focus_factor: None,
+            song_speed: SongSpeed::Normal,
+            modifiers: Modifiers::default(),
+            stats: RunStats::new(stats::FIRST_SECTION_NAME.to_string(), map.skip_amount),
+            replay: Replay::new(chart_hash),
+            ghost: None,
+            debug_stats: None,
+            song_map: map.clone(),
+            instance_handle: None,
+            paused: false,
+            music_start_pending: false,
+            sound_cues,
+            next_cue: 0,
+            metronome: false,
+            next_click: Beats(0.0),
+            circle_batch: None,
+            mesh_cache: MeshCache::new(),
+        }
+    }
+
+    /// Returns true if the audio device is open. If not, the world runs
+    /// silently.
+    pub fn audio_available(&self) -> bool {
+        self.audio.available()
+    }
+
+    /// Returns true if the world has been started via `reset_to` and not yet
+    /// stopped.
+    pub fn started(&self) -> bool {
+        self.started
+    }
+
+    /// Return the SongMap the world was most recently started with.
+    pub fn song_map(&self) -> &SongMap {
+        &self.song_map
+    }
+
+    /// Return how much of each resource the world is currently using.
+    pub fn resource_counts(&self) -> ResourceCounts {
+        ResourceCounts {
+            cached_meshes: self.mesh_cache.len(),
+            ..ResourceCounts::of(&self.inner, &self.scheduler)
+        }
+    }
+
+    /// Return the events emitted during the most recent call to `update`.
+    pub fn events(&self) -> &[GameEvent] {
+        &self.inner.events
+    }
+
+    /// Advance the world to `curr_time`. This does nothing if the world has
+    /// not been started.
+    pub fn update(
+        &mut self,
+        _ctx: &mut Context,
+        keyboard: &KeyboardState,
+        physics_delta_time: f64,
+        curr_time: Beats,
+    ) -> GameResult<()> {
+        // If the audio device just came back, pick the music up from where
+        // the song is now.
+        if self.audio.try_reinit() && self.started {
+            log::info!("Audio device reopened, restarting music");
+            let start = to_secs(curr_time, self.song_map.bpm);
+            self.instance_handle = self.audio.play(start, self.song_speed.rate());
+            self.music_start_pending = false;
+            self.rewind_sounds(curr_time);
+        }
+
+        if !self.started {
+            return Ok(());
+        }
+
+        if let Some(debug) = &mut self.debug {
+            debug.update(curr_time, self.inner.player.pos, None);
+
+            if debug.lifetime_state(curr_time) == EnemyLifetime::Dead {
+                self.debug = None;
+            }
+        }
+
+        self.inner.update(keyboard, physics_delta_time, curr_time);
+
+        self.update_scheduler(curr_time);
+        self.schedule_sounds(curr_time);
+
+        for event in self.inner.events.iter() {
+            self.stats.record(event);
+        }
+        self.stats.end = curr_time;
+        for objective in self.inner.objectives.iter_mut() {
+            objective.update(curr_time, &self.inner.events);
+        }
+        self.replay
+            .record(curr_time, self.inner.player.pos, keyboard.input_state());
+        self.replay.hits = self.stats.total_hits();
+        self.debug_stats = Some(DebugStats::collect(self, curr_time));
+
+        Ok(())
+    }
+
+    /// Turn the metronome's clicks on or off. The metronome starts from the
+    /// beat after `curr_time`.
+    pub fn set_metronome(&mut self, on: bool, curr_time: Beats) {
+        if on && !self.metronome {
+            self.next_click = Beats(self.next_click.0.max(curr_time.0.ceil()));
+        }
+        self.metronome = on;
+    }
+
+    /// Schedule every sound due before SOUND_LOOKAHEAD from now on the audio
+    /// device, including the metronome's clicks if it is on. Each sound is
+    /// given its exact delay from `curr_time`, so it is played by the audio
+    /// device's clock right on its beat, rather than on whichever frame comes
+    /// closest to it.
+    fn schedule_sounds(&mut self, curr_time: Beats) {
+        let bpm = self.song_map.bpm;
+        let rate = self.song_speed.rate();
+        let now = to_secs(curr_time, bpm);
+        let until = curr_time + to_beats(SOUND_LOOKAHEAD * rate, bpm);
+        let delay = |time: Beats| (to_secs(time, bpm) - now) / rate;
+
+        while let Some(cue) = self.sound_cues.get(self.next_cue) {
+            if cue.time > until {
+                break;
+            }
+            self.audio
+                .schedule_sound(&cue.path, delay(cue.time), cue.volume);
+            self.next_cue += 1;
+        }
+
+        if self.metronome {
+            while self.next_click <= until {
+                let accent = self.next_click.0.rem_euclid(BEATS_PER_MEASURE) == 0.0;
+                self.audio.schedule_click(delay(self.next_click), accent);
+                self.next_click = self.next_click + Beats(1.0);
+            }
+        }
+    }
+
+    /// Cancel every sound scheduled after `curr_time`, so that they are
+    /// scheduled again the next time the world updates. This is needed
+    /// whenever the sounds would no longer play on their beat (ex: the world
+    /// is paused).
+    fn rewind_sounds(&mut self, curr_time: Beats) {
+        self.audio.cancel_scheduled();
+        self.next_cue = self.sound_cues.partition_point(|cue| cue.time <= curr_time);
+        self.next_click = Beats(curr_time.0.floor() + 1.0);
+    }
+
+    /// Return the position of the music, if it is currently playing.
+    pub fn music_position(&self) -> Option<Seconds> {
+        match &self.instance_handle {
+            Some(handle) if handle.is_playing() => Some(handle.position()),
+            _ => None,
+        }
+    }
+
+    /// Keep `time` in sync with the music. The first time the music is seen
+    /// playing after the world starts, `time` is lined up with the music's
+    /// actual start. After that, `time` is only resynced if it drifts. This
+    /// should be called after `Time::update`.
+    pub fn sync_time(&mut self, time: &mut Time) {
+        if let Some(position) = self.music_position() {
+            if self.music_start_pending {
+                time.align_start(position);
+                self.music_start_pending = false;
+            } else {
+                time.resync(position);
+            }
+        }
+    }
+
+    /// Returns true if the current run started from the beginning of the
+    /// chart, rather than partway through (ex: to practice a section).
+    pub fn is_full_run(&self) -> bool {
+        self.stats.sections[0].start <= self.song_map.skip_amount
+    }
+
+    /// Returns true if the current run is played as charted: at normal speed,
+    /// without modifiers, and with the chart's own focus factor. Other runs
+    /// are easier or harder than the chart, so they don't earn medals.
+    pub fn is_as_charted(&self) -> bool {
+        self.stats.song_speed == SongSpeed::Normal
+            && self.stats.modifiers.is_empty()
+            && self.stats.focus_factor == self.song_map.player.focus_factor
+    }
+
+    /// Returns true if any of the chart's objectives have been failed.
+    pub fn objective_failed(&self) -> bool {
+        self.inner
+            .objectives
+            .iter()
+            .any(|objective| objective.status == ObjectiveStatus::Failed)
+    }
+
+    /// Returns true if an objective has been failed or a modifier has failed
+    /// the run (ex: sudden death).
+    pub fn failed(&self) -> bool {
+        self.objective_failed() || self.inner.modifiers.failed(&self.stats)
+    }
+
+    /// Returns true if the song has finished playing or the run has failed.
+    /// Worlds without music finish once every action has been performed and
+    /// every enemy is gone.
+    pub fn finished(&self) -> bool {
+        if !self.started {
+            return false;
+        }
+        if self.failed() {
+            return true;
+        }
+        match &self.instance_handle {
+            Some(handle) => handle.is_stopped(),
+            None => {
+                self.scheduler.is_empty()
+                    && self
+                        .inner
+                        .groups
+                        .iter()
+                        .all(|group| group.enemies.is_empty())
+            }
+        }
+    }
+
+    pub fn draw(&mut self, ctx: &mut Context, curr_time: Beats) -> GameResult<()> {
+        // The background is drawn before the screen transform, so that it
+        // always covers the whole screen.
+        let theme = self.inner.theme.current(curr_time);
+        theme.draw_background(ctx)?;
+        if let Some(flash) = &self.inner.background_flash {
+            let pulse = flash.pulse(curr_time) as f32;
+            if pulse > 0.0 {
+                let rect = ggez::graphics::Rect::new(0.0, 0.0, WINDOW_WIDTH, WINDOW_HEIGHT);
+                let color = Color::new(1.0, 1.0, 1.0, BACKGROUND_FLASH_OPACITY * pulse);
+                Mesh::new_rectangle(ctx, DrawMode::fill(), rect, color)?
+                    .draw(ctx, DrawParam::default())?;
+            }
+        }
+
+        let view_transform = self.inner.view_transform();
+        ggez::graphics::push_transform(ctx, Some(view_transform.as_screen_matrix()));
+        ggez::graphics::apply_transformations(ctx)?;
+        let result = self.draw_playfield(ctx, curr_time, theme);
+        ggez::graphics::pop_transform(ctx);
+        ggez::graphics::apply_transformations(ctx)?;
+        result?;
+
+        indicator::draw_offscreen_indicators(
+            ctx,
+            &self.inner.groups,
+            view_transform,
+            curr_time,
+            theme.enemy,
+        )
+    }
+
+    /// Build the meshes of every enemy the chart spawns whose meshes are
+    /// cached (see `meshcache`), so that the first time each shape shows up
+    /// doesn't cause a hitch. This should be called while the level loads,
+    /// before the song starts. Enemies which only spawn once the player has
+    /// been hit (see `SpawnCmd::IfHits`), or which other enemies spawn, are
+    /// cached the first time they are drawn instead.
+    pub fn load_meshes(&mut self, ctx: &mut Context) -> GameResult<()> {
+        let mut world = InnerWorldState::new(self.song_map.player);
+        for action in &self.song_map.actions {
+            action.perform(&mut world);
+            for group in world.groups.iter_mut() {
+                for GroupEnemy { enemy, .. } in group.enemies.drain(..) {
+                    enemy.cache_meshes(ctx, &mut self.mesh_cache)?;
+                }
+            }
+        }
+        log::info!("Cached {} enemy meshes", self.mesh_cache.len());
+        Ok(())
+    }
+
+    fn draw_playfield(
+        &mut self,
+        ctx: &mut Context,
+        curr_time: Beats,
+        theme: Theme,
+    ) -> GameResult<()> {
+        theme.draw_grid(ctx)?;
+
+        if let Some(preview) = &self.preview {
+            preview.draw(ctx)?;
+        }
+
+        if let Some(heatmap) = &self.heatmap {
+            heatmap.draw(ctx)?;
+        }
+
+        for force_field in self.inner.force_fields.iter() {
+            force_field.draw(ctx)?;
+        }
+
+        for obstacle in self.inner.obstacles.iter() {
+            obstacle.draw(ctx)?;
+        }
+
+        for teleporter in self.inner.teleporters.iter() {
+            teleporter.draw(ctx, curr_time)?;
+        }
+
+        for pickup in self.inner.pickups.iter() {
+            pickup.draw(ctx)?;
+        }
+
+        let circle_batch = self
+            .circle_batch
+            .get_or_insert_with(|| CircleBatch::new(ctx));
+        for group in self.inner.groups.iter() {
+            group.draw(
+                ctx,
+                curr_time,
+                theme.enemy,
+                &self.inner.modifiers,
+                circle_batch,
+                &mut self.mesh_cache,
+            )?;
+        }
+
+        self.inner.particles.draw(ctx, circle_batch)?;
+
+        self.inner
+            .modifiers
+            .draw_overlay(ctx, self.inner.player.pos, self.max_darkness)?;
+
+        if let Some(vision) = &self.inner.vision {
+            vision.draw(ctx, self.inner.player.pos, curr_time, self.max_darkness)?;
+        }
+
+        if let Some(ghost) = &self.ghost {
+            ghost.draw_ghost(ctx, curr_time, self.inner.player.size)?;
+        }
+
+        let player_mesh = self.inner.player.get_mesh(ctx)?;
+        player_mesh.draw(
+            ctx,
+            DrawParam::default().dest(self.inner.player.pos.as_screen_coords()),
+        )?;
+
+        Ok(())
+    }
+
+    fn update_scheduler(&mut self, time: Beats) {
+        self.scheduler.update(time, &mut self.inner);
+    }
+
+    pub fn stop_world(&mut self) {
+        // Stop the game, pausing the music, fetching a new Source instance, and
+        // rebuild the scheduler work queue.
+        self.started = false;
+        self.paused = false;
+        self.music_start_pending = false;
+        self.audio.cancel_scheduled();
+        if let Some(handle) = &mut self.instance_handle {
+            match handle.stop() {
+                Ok(()) => self.instance_handle = None,
+                Err(err) => log::error!("Error stopping music: {}", err),
+            }
+        }
+    }
+
+    /// Pause the music. Nothing else needs to be done to freeze the world, as
+    /// long as it isn't updated until `resume` is called.
+    pub fn pause(&mut self) {
+        self.paused = true;
+        self.rewind_sounds(self.stats.end);
+        if let Some(handle) = &mut self.instance_handle {
+            if let Err(err) = handle.pause() {
+                log::error!("Error pausing music: {}", err);
+            }
+        }
+    }
+
+    /// Resume the music after `pause`. The Time is lined up with the music
+    /// again once it is playing, like when the world first starts.
+    pub fn resume(&mut self) {
+        self.paused = false;
+        if let Some(handle) = &mut self.instance_handle {
+            match handle.resume() {
+                Ok(()) => self.music_start_pending = true,
+                Err(err) => log::error!("Error resuming music: {}", err),
+            }
+        }
+    }
+
+    /// Move the music over to the output device set by `audio::set_device`,
+    /// without restarting the level. If the world is playing, the music picks
+    /// up from `curr_time` on the new device, and stays paused if the world is
+    /// paused.
+    pub fn reopen_audio(&mut self, curr_time: Beats) {
+        if let Some(mut handle) = self.instance_handle.take() {
+            if let Err(err) = handle.stop() {
+                log::error!("Error stopping music: {}", err);
+            }
+        }
+        self.audio.reopen();
+        if !self.started {
+            return;
+        }
+        self.rewind_sounds(curr_time);
+        let start = to_secs(curr_time, self.song_map.bpm);
+        self.instance_handle = self.audio.play(start, self.song_speed.rate());
+        self.music_start_pending = self.instance_handle.is_some();
+        if self.paused {
+            self.pause();
+        }
+    }
+
+    /// Stop the world and start it again from the beginning, using the same
+    /// SongMap and music as the last time it was started. Unlike calling
+    /// `reset_to` with a freshly loaded SongMap, this does no disk IO and
+    /// so is fast enough to do in the middle of a song. The simulation has no
+    /// randomness of its own (charts are only random when compiled), so reusing
+    /// the SongMap means the retry plays exactly the same patterns, even if the
+    /// chart's cache has since been recompiled. This counts as a retry in the
+    /// run's stats.
+    pub fn restart(&mut self, time: &mut Time) {
+        let retries = self.stats.retries + 1;
+        let map = self.song_map.clone();
+        self.reset_to(&map, time, map.skip_amount);
+        self.stats.retries = retries;
+    }
+
+    /// Start (or restart) the world from `start`, which is usually the map's
+    /// `skip_amount`. Everything left over from the previous run is torn down
+    /// first, so this may be called at any point, even while the world is
+    /// playing: the music is stopped, every group, object and queued action is
+    /// thrown away, and the chart is resimulated up to `start`. The music is
+    /// then started from `start` and `time` is reset to match it, keeping the
+    /// player's input offset. The run's stats and replay start over.
+    pub fn reset_to(&mut self, map: &SongMap, time: &mut Time, start: Beats) {
+        self.stop_world();
+        self.song_map = map.clone();
+        self.sound_cues = SoundCue::all(map);
+        self.next_cue = self.sound_cues.partition_point(|cue| cue.time < start);
+        self.next_click = Beats(start.0.ceil());
+
+        // Reset the player and groups. The debug enemy is thrown away too,
+        // since it was spawned relative to the old time.
+        self.inner = InnerWorldState::new(map.player);
+        if let Some(focus_factor) = self.focus_factor {
+            self.inner.player.focus_factor = focus_factor;
+        }
+        self.inner.modifiers = self.modifiers.clone();
+        self.debug = None;
+        self.debug_stats = None;
+
+        // Simulate all events up to this point. We do this before the level
+        // starts in order to reduce the amount of BeatActions the scheduler needs
+        // to perform immediately, which could be a lot if there were many events.
+        self.scheduler = Scheduler::new(map);
+        self.update_scheduler(start);
+
+        // The run starts in whichever section the skipped events ended in.
+        let first_section = self
+            .inner
+            .events
+            .iter()
+            .filter_map(|event| match event {
+                GameEvent::SectionStarted { name, .. } => Some(name.clone()),
+                _ => None,
+            })
+            .last()
+            .unwrap_or_else(|| stats::FIRST_SECTION_NAME.to_string());
+        self.stats = RunStats::new(first_section, start);
+        self.stats.song_speed = self.song_speed;
+        self.replay = Replay::new(self.replay.chart_hash);
+        self.replay.song_speed = self.song_speed;
+        self.stats.modifiers = self.modifiers.clone();
+        self.replay.modifiers = self.modifiers.clone();
+        self.stats.focus_factor = self.inner.player.focus_factor;
+        self.replay.focus_factor = Some(self.inner.player.focus_factor);
+
+        let skip_amount = to_secs(start, map.bpm);
+
+        // Play the music
+        if self.audio.has_music() {
+            self.instance_handle = self.audio.play(skip_amount, self.song_speed.rate());
+            self.music_start_pending = self.instance_handle.is_some();
+        } else {
+            log::warn!("No music loaded!")
+        }
+
+        // Reset the timer, keeping the player's input offset
+        let input_offset = time.input_offset();
+        *time = Time::new(map.bpm, skip_amount);
+        time.set_input_offset(input_offset);
+        time.set_rate(self.song_speed.rate());
+
+        self.started = true;
+    }
+
+    pub fn draw_debug_hitbox(&self, ctx: &mut Context, time: &Time) -> Result<(), GameError> {
+        let curr_time = time.get_beats();
+        let rotated_about = if ggez::input::keyboard::is_key_pressed(ctx, KeyCode::V) {
+            Some((self.inner.player.pos, curr_time.0 * 400.0))
+        } else {
+            None
+        };
+
+        if let Some(enemy) = &self.debug {
+            if ggez::input::keyboard::is_key_pressed(ctx, KeyCode::C) {
+                let animation = EnemyAnimation::default();
+                let mut mesh_cache = MeshCache::new();
+                if let Some((mesh, param)) =
+                    enemy.draw(ctx, curr_time, rotated_about, &animation, &mut mesh_cache)?
+                {
+                    mesh.draw(ctx, param)?;
+                }
+            }
+            if ggez::input::keyboard::is_key_pressed(ctx, KeyCode::Z) {
+                for x in -20..20 {
+                    for y in -20..20 {
+                        let pos = WorldPos {
+                            x: x as f64,
+                            y: y as f64,
+                        };
+                        let sdf = enemy.sdf(pos, curr_time, rotated_about);
+                        let color = match sdf {
+                            None => crate::color::GUIDE_GREY,
+                            Some(sdf) => Color::lerp(
+                                crate::color::RED,
+                                crate::color::GREEN,
+                                (sdf.0.atan() / (std::f64::consts::PI / 2.0) + 1.0) / 2.0,
+                            ),
+                        };
+                        Mesh::new_circle(
+                            ctx,
+                            DrawMode::fill(),
+                            pos.as_screen_coords(),
+                            1.0,
+                            5.0,
+                            color,
+                        )?
+                        .draw(ctx, DrawParam::default())?;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
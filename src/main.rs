@@ -1,514 +1,2177 @@
-#![feature(drain_filter)]
-#![feature(trait_alias)]
-#![feature(float_interpolation)]
 #![feature(try_blocks)]
 
+use std::collections::HashMap;
 use std::env;
 use std::ffi::OsStr;
 use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
-use ggez::event::{KeyCode, KeyMods};
+use ggez::event::{Axis, Button, KeyCode, KeyMods, MouseButton};
 use ggez::graphics::mint::Point2;
 use ggez::graphics::{
     Color, DrawMode, DrawParam, Drawable, Font, Mesh, PxScale, Rect, Text, TextFragment,
 };
+use ggez::input::gamepad::GamepadId;
 use ggez::{conf, event, graphics, timer, Context, ContextBuilder, GameError, GameResult};
 
-use kira::instance::handle::InstanceHandle;
-use kira::instance::{InstanceSettings, StopInstanceSettings};
-use kira::manager::{AudioManager, AudioManagerSettings};
-use kira::sound::handle::SoundHandle;
-use kira::sound::{Sound, SoundSettings};
-
-use cgmath as cg;
-
-use chart::Scheduler;
-use color::{RED, WHITE};
-use ease::{BeatEasing, Lerp};
-use enemy::{Enemy, EnemyDurations, EnemyLifetime, Laser};
-use keyboard::KeyboardState;
-use parse::SongMap;
-use player::Player;
-use time::{to_secs, Beats, Time};
-use world::{WorldLen, WorldPos};
-
-use crate::time::Seconds;
-
-mod chart;
-mod color;
-mod ease;
-mod enemy;
-mod keyboard;
-mod parse;
-mod player;
-mod time;
-mod util;
-mod world;
-
-const TARGET_FPS: u32 = 60;
+use rand::seq::SliceRandom;
+
+use rthm::audio;
+use rthm::autoplay;
+use rthm::browser::{self, LevelIndex, LevelListing};
+use rthm::budget::{self, FrameBudget, GraphicsPreset};
+use rthm::cache;
+use rthm::color::{self, RED, WHITE};
+use rthm::debug::FrameTimes;
+use rthm::debug_draw;
+use rthm::ease::Lerp;
+use rthm::enemy::{EnemyDurations, Laser, LaserRender};
+use rthm::event::GameEvent;
+use rthm::event_loop::{self, DropHandler};
+use rthm::font::FontSet;
+use rthm::gamepad::{draw_stick_display, STICK_DISPLAY_SIZE};
+use rthm::heatmap::HitHeatmap;
+use rthm::input_display::{draw_input_display, INPUT_DISPLAY_SIZE};
+use rthm::keyboard::{FocusInput, KeyboardState};
+use rthm::kiosk::Kiosk;
+use rthm::lang::{self, Strings};
+#[cfg(feature = "leaderboard")]
+use rthm::leaderboard::Leaderboard;
+use rthm::medal;
+use rthm::memory::ResourceCeilings;
+use rthm::meshutil;
+use rthm::modifiers::ModifierKind;
+use rthm::narration::{self, Narration};
+use rthm::objective::ObjectiveStatus;
+use rthm::parse;
+use rthm::preview::PatternPreview;
+use rthm::replay::{self, Replay};
+use rthm::save::{self, HitRecord, SaveData};
+use rthm::stats::RunReport;
+use rthm::task::Task;
+use rthm::telegraph::TelegraphStyle;
+use rthm::time::{to_secs, Beats, Seconds, SongSpeed, Time};
+use rthm::ui::{self, Menu, MenuEvent, Widget};
+use rthm::util;
+use rthm::vfs;
+use rthm::world::{WorldPos, WORLD_SCALE_FACTOR};
+use rthm::{SongMap, WorldState, WINDOW_HEIGHT, WINDOW_WIDTH};
+
+/// The name of the game, which starts the window's title.
+const GAME_NAME: &str = "ʀᴛʜᴍ";
+/// The number of beats shown by the pattern preview.
+const PREVIEW_LENGTH: Beats = Beats(8.0);
+/// How long the level select may sit idle before a demo starts.
+const DEMO_IDLE_TIME: Duration = Duration::from_secs(30);
+/// How much the input offset changes by per key press, in seconds.
+const INPUT_OFFSET_STEP: f64 = 0.005;
+/// The largest input offset that may be set from the options menu, in seconds.
+const MAX_INPUT_OFFSET: f64 = 0.25;
+/// The tempo of the flashes on the calibration screen.
+const CALIBRATION_BPM: f64 = 100.0;
+/// How many of the most recent taps the calibration averages.
+const CALIBRATION_TAPS: usize = 16;
+/// How many taps the calibration needs before it trusts its measurement.
+const MIN_CALIBRATION_TAPS: usize = 8;
+/// The largest analog stick deadzone that may be set from the options menu.
+const MAX_DEADZONE: f64 = 0.9;
+const DEADZONE_STEP: f64 = 0.05;
+/// The range of analog stick response curves that may be set from the options
+/// menu. See `AxisSettings::curve`.
+const MIN_CURVE: f64 = 0.5;
+const MAX_CURVE: f64 = 3.0;
+const CURVE_STEP: f64 = 0.1;
+/// How much the max darkness option changes by per step. See
+/// `SaveData::max_darkness`.
+const MAX_DARKNESS_STEP: f64 = 0.1;
+/// The top left corner of every menu.
+const MENU_POSITION: Point2<f32> = Point2 { x: 20.0, y: 60.0 };
+/// Where the top scores are drawn on the level select and results screens.
+#[cfg(feature = "leaderboard")]
+const LEADERBOARD_POSITION: Point2<f32> = Point2 {
+    x: WINDOW_WIDTH - 320.0,
+    y: 60.0,
+};
 
 // Files read via ggez (usually music/font/images)
 // const ARIAL_PATH: &str = "/Arial.ttf";
 const FIRACODE_PATH: &str = "/FiraCode-Regular.ttf";
 
-pub const WINDOW_WIDTH: f32 = 1.5 * 640.0;
-pub const WINDOW_HEIGHT: f32 = 1.5 * 480.0;
-
 /// Stores assets like fonts, music, sprite images, etc
 struct Assets {
     debug_font: Font,
+    /// The fonts for menu text, which fall back to other fonts for characters
+    /// the debug font can't display.
+    fonts: FontSet,
 }
 
 impl Assets {
     fn new(ctx: &mut Context) -> Assets {
+        let debug_font = Font::new(ctx, FIRACODE_PATH).unwrap();
         Assets {
-            debug_font: Font::new(ctx, FIRACODE_PATH).unwrap(),
+            debug_font,
+            fonts: FontSet::new(ctx, debug_font),
         }
     }
 }
 
-pub struct EnemyGroup {
-    pub enemies: Vec<Box<dyn Enemy>>,
-    pub use_hitbox: bool,
-    pub do_render: bool,
-    pub render_warmup: bool,
-    pub fadeout: Option<BeatEasing<Color>>,
-    pub rotation: Option<(BeatEasing<f64>, WorldPos)>,
+/// An entry in the top level of the level select.
+pub enum LevelEntry {
+    Level(Level),
+    /// A folder of levels, such as a collection of levels released together.
+    Pack {
+        name: String,
+        folder: PathBuf,
+        levels: Vec<Level>,
+    },
 }
 
-impl EnemyGroup {
-    fn new() -> EnemyGroup {
-        EnemyGroup {
-            enemies: Vec::with_capacity(16),
-            use_hitbox: true,
-            do_render: true,
-            render_warmup: true,
-            fadeout: None,
-            rotation: None,
+impl LevelEntry {
+    fn name(&self) -> &str {
+        match self {
+            LevelEntry::Level(level) => &level.name,
+            LevelEntry::Pack { name, .. } => name,
+        }
+    }
+
+    fn folder(&self) -> &Path {
+        match self {
+            LevelEntry::Level(level) => &level.map_folder,
+            LevelEntry::Pack { folder, .. } => folder,
         }
     }
+}
 
-    fn update(&mut self, player: &mut Player, curr_time: Beats) {
-        let rotated_about = self.rotation_ease(curr_time);
-        for enemy in self.enemies.iter_mut() {
-            enemy.update(curr_time);
-            if let Some(sdf) = enemy.sdf(player.pos, curr_time, rotated_about) {
-                if sdf < player.size && self.use_hitbox {
-                    player.on_hit();
+/// What the player picked in the level select.
+pub enum LevelSelectAction {
+    StartLevel,
+    OpenOptions,
+}
+
+pub struct LevelSelect {
+    entries: Vec<LevelEntry>,
+    /// The index of the pack being browsed, if any.
+    open_pack: Option<usize>,
+    /// At the top level, the menu has one button per entry followed by the
+    /// options button. Inside of a pack, the menu has one button per level in
+    /// the pack followed by the back button.
+    menu: Menu,
+    /// The titles and medals of every level. These are read from the charts in
+    /// the background, since compiling every chart would hold up startup, so
+    /// levels are named after their folders until this finishes.
+    details: Option<Task<Vec<LevelDetails>>>,
+}
+
+impl LevelSelect {
+    fn new(
+        levels_folder: impl AsRef<Path>,
+        strings: &Strings,
+        save_data: &SaveData,
+    ) -> anyhow::Result<LevelSelect> {
+        let mut entries = vec![];
+        for entry in std::fs::read_dir(levels_folder.as_ref())? {
+            let result: anyhow::Result<Option<LevelEntry>> = try {
+                let path = entry?.path();
+                if Level::is_level(&path) {
+                    Some(LevelEntry::Level(Level::new(&path)?))
+                } else if path.is_dir() {
+                    // Folders without a chart are packs if they have any
+                    // levels in them, and are otherwise ignored.
+                    let levels = read_levels(&path)?;
+                    if levels.is_empty() {
+                        None
+                    } else {
+                        Some(LevelEntry::Pack {
+                            name: path
+                                .file_name()
+                                .unwrap_or_default()
+                                .to_string_lossy()
+                                .to_string(),
+                            folder: path,
+                            levels,
+                        })
+                    }
+                } else {
+                    None
+                }
+            };
+            match result {
+                Ok(Some(entry)) => {
+                    log::info!("Loaded level from path {:?}", entry.folder());
+                    entries.push(entry);
                 }
+                Ok(None) => (),
+                Err(err) => log::warn!("Couldn't load level: {}", err),
             }
         }
 
-        // remove dead enemies
-        self.enemies
-            .retain(|e| e.lifetime_state(curr_time) != EnemyLifetime::Dead);
+        sort_entries(&mut entries);
+
+        let mut level_select = LevelSelect::with_entries(entries, strings);
+        // Medals are recorded by name, so the chart is needed to tell how many
+        // of them have been earned.
+        let earned_medals: Vec<(PathBuf, Vec<String>)> = level_select
+            .levels()
+            .into_iter()
+            .map(|level| {
+                let earned = save_data
+                    .level(levels_folder.as_ref(), &level.map_folder)
+                    .map_or(vec![], |level| level.medals.clone());
+                (level.map_folder, earned)
+            })
+            .collect();
+        level_select.details = Some(Task::spawn(move || Ok(read_details(earned_medals))));
+        Ok(level_select)
+    }
+
+    fn with_entries(entries: Vec<LevelEntry>, strings: &Strings) -> LevelSelect {
+        let mut level_select = LevelSelect {
+            entries,
+            open_pack: None,
+            menu: Menu::new(vec![], MENU_POSITION),
+            details: None,
+        };
+        level_select.relabel(strings);
+        level_select
+    }
+
+    /// Rebuild the menu for the current pack, or the top level if no pack is
+    /// open. This is also used to update the text of the menu after the
+    /// language changes.
+    fn relabel(&mut self, strings: &Strings) {
+        let focused = self.menu.focused();
+        let items = match self.open_pack {
+            Some(pack) => {
+                let mut items: Vec<Widget> = self
+                    .pack_levels(pack)
+                    .iter()
+                    .map(|level| Widget::button(level.label()))
+                    .collect();
+                items.push(Widget::button(strings.get("menu.back")));
+                items
+            }
+            None => {
+                let mut items: Vec<Widget> = self
+                    .entries
+                    .iter()
+                    .map(|entry| match entry {
+                        LevelEntry::Level(level) => Widget::button(level.label()),
+                        LevelEntry::Pack { name, levels, .. } => Widget::button(
+                            strings.format("level_select.pack", &[name, &levels.len()]),
+                        ),
+                    })
+                    .collect();
+                items.push(Widget::button(strings.get("menu.options")));
+                items
+            }
+        };
+        self.menu = Menu::new(items, MENU_POSITION);
+        self.menu.set_focused(focused);
     }
 
-    fn draw(&self, ctx: &mut Context, curr_time: Beats) -> GameResult<()> {
-        if !self.do_render {
-            return Ok(());
+    /// Check on the level details, and rename and resort the levels once
+    /// they are read.
+    fn update(&mut self, strings: &Strings) {
+        let mut details = match self.details.take() {
+            Some(details) => details,
+            None => return,
+        };
+        match details.poll() {
+            Some(Ok(details)) => self.apply_details(details, strings),
+            Some(Err(err)) => log::warn!("Couldn't read level details: {}", err),
+            None => self.details = Some(details),
         }
+    }
 
-        for enemy in self.enemies.iter() {
-            if !self.render_warmup && enemy.lifetime_state(curr_time) == EnemyLifetime::Warmup {
-                continue;
+    /// Fill in the titles and medals of the levels. The focused level and the
+    /// open pack stay the same, even though their positions may change.
+    fn apply_details(&mut self, details: &[LevelDetails], strings: &Strings) {
+        let open_pack = self
+            .open_pack
+            .map(|pack| self.entries[pack].folder().to_path_buf());
+        let focused = match self.open_pack {
+            Some(pack) => self
+                .pack_levels(pack)
+                .get(self.menu.focused())
+                .map(|level| level.map_folder.clone()),
+            None => self
+                .entries
+                .get(self.menu.focused())
+                .map(|entry| entry.folder().to_path_buf()),
+        };
+
+        for entry in &mut self.entries {
+            let levels = match entry {
+                LevelEntry::Level(level) => std::slice::from_mut(level),
+                LevelEntry::Pack { levels, .. } => levels.as_mut_slice(),
+            };
+            for level in levels.iter_mut() {
+                if let Some(details) = details.iter().find(|d| d.folder == level.map_folder) {
+                    level.name = level_name(&level.map_folder, details.title.clone());
+                    level.medals = details.medals;
+                    level.earned_medals = details.earned_medals;
+                }
             }
+            sort_levels(levels);
+        }
+        sort_entries(&mut self.entries);
+
+        self.open_pack =
+            open_pack.and_then(|folder| self.entries.iter().position(|e| e.folder() == folder));
+        self.relabel(strings);
+        let focused = focused.and_then(|folder| match self.open_pack {
+            Some(pack) => self
+                .pack_levels(pack)
+                .iter()
+                .position(|level| level.map_folder == folder),
+            None => self.entries.iter().position(|e| e.folder() == folder),
+        });
+        if let Some(focused) = focused {
+            self.menu.set_focused(focused);
+        }
+    }
 
-            if let Some((mesh, param)) =
-                enemy.draw(ctx, curr_time, self.rotation_ease(curr_time))?
-            {
-                let param = if let Some(fadeout) = &self.fadeout {
-                    param.color(fadeout.ease(curr_time))
-                } else {
-                    param
-                };
+    fn draw(&self, ctx: &mut Context, fonts: &FontSet) -> GameResult<()> {
+        self.menu.draw(ctx, fonts)
+    }
 
-                mesh.draw(ctx, param)?;
+    /// Handle the item at index `i` being activated. Opening and closing packs
+    /// is handled by the level select itself.
+    fn activate(&mut self, i: usize, strings: &Strings) -> Option<LevelSelectAction> {
+        match self.open_pack {
+            Some(pack) if i == self.pack_levels(pack).len() => {
+                self.back(strings);
+                None
             }
+            Some(_) => Some(LevelSelectAction::StartLevel),
+            None if i == self.entries.len() => Some(LevelSelectAction::OpenOptions),
+            None => match self.entries[i] {
+                LevelEntry::Level(_) => Some(LevelSelectAction::StartLevel),
+                LevelEntry::Pack { .. } => {
+                    self.open_pack = Some(i);
+                    self.relabel(strings);
+                    self.menu.set_focused(0);
+                    None
+                }
+            },
         }
+    }
 
-        Ok(())
+    /// Leave the current pack, if one is open.
+    fn back(&mut self, strings: &Strings) {
+        if let Some(pack) = self.open_pack.take() {
+            self.relabel(strings);
+            self.menu.set_focused(pack);
+        }
     }
 
-    fn rotation_ease(&self, curr_time: Beats) -> Option<(WorldPos, f64)> {
-        self.rotation
-            .as_ref()
-            .map(|(easing, rot_point)| (*rot_point, easing.ease(curr_time)))
+    /// Return every level, including the levels inside of packs.
+    fn levels(&self) -> Vec<Level> {
+        self.entries
+            .iter()
+            .flat_map(|entry| match entry {
+                LevelEntry::Level(level) => std::slice::from_ref(level),
+                LevelEntry::Pack { levels, .. } => levels.as_slice(),
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// Returns the focused level, or None if a pack or a button is focused.
+    fn current_level(&self) -> Option<Level> {
+        match self.open_pack {
+            Some(pack) => self.pack_levels(pack).get(self.menu.focused()).cloned(),
+            None => match self.entries.get(self.menu.focused()) {
+                Some(LevelEntry::Level(level)) => Some(level.clone()),
+                _ => None,
+            },
+        }
+    }
+
+    /// Focus the level in `folder`, opening its pack if it is in one.
+    fn select(&mut self, folder: &Path, strings: &Strings) {
+        for (i, entry) in self.entries.iter().enumerate() {
+            match entry {
+                LevelEntry::Level(level) if level.map_folder == folder => {
+                    self.menu.set_focused(i);
+                    return;
+                }
+                LevelEntry::Pack { levels, .. } => {
+                    if let Some(j) = levels.iter().position(|level| level.map_folder == folder) {
+                        self.open_pack = Some(i);
+                        self.relabel(strings);
+                        self.menu.set_focused(j);
+                        return;
+                    }
+                }
+                LevelEntry::Level(_) => (),
+            }
+        }
     }
-}
 
-pub struct InnerWorldState {
-    pub player: Player,
-    pub groups: Vec<EnemyGroup>,
+    fn pack_levels(&self, pack: usize) -> &[Level] {
+        match &self.entries[pack] {
+            LevelEntry::Pack { levels, .. } => levels,
+            LevelEntry::Level(_) => &[],
+        }
+    }
 }
 
-pub struct WorldState {
-    inner: InnerWorldState,
-    music: Option<SoundHandle>,
-    audio_manager: AudioManager,
-    scheduler: Scheduler,
-    started: bool,
-    debug: Option<Box<dyn Enemy>>,
-    instance_handle: Option<InstanceHandle>,
+impl Default for LevelSelect {
+    fn default() -> Self {
+        LevelSelect::with_entries(vec![], &Strings::default())
+    }
 }
 
-impl WorldState {
-    pub fn new<P: AsRef<Path>>(base_folder: P, map: &SongMap) -> WorldState {
-        fn try_read(
-            audio_manager: &mut AudioManager,
-            path: impl AsRef<Path>,
-        ) -> anyhow::Result<SoundHandle> {
-            let music_file = std::fs::read(path)?;
-            let sound = Sound::from_mp3_reader(music_file.as_slice(), SoundSettings::default())?;
-            let song_handle = audio_manager.add_sound(sound)?;
-            Ok(song_handle)
+/// Read every level directly inside of `folder`, sorted by name.
+fn read_levels(folder: &Path) -> anyhow::Result<Vec<Level>> {
+    let mut levels = vec![];
+    for entry in std::fs::read_dir(folder)? {
+        let path = entry?.path();
+        if Level::is_level(&path) {
+            match Level::new(&path) {
+                Ok(level) => levels.push(level),
+                Err(err) => log::warn!("Couldn't load level: {}", err),
+            }
         }
+    }
+    sort_levels(&mut levels);
+    Ok(levels)
+}
 
-        let mut audio_manager = AudioManager::new(AudioManagerSettings::default()).unwrap();
-        let music = if let Some(path) = &map.music_path {
-            let path = base_folder.as_ref().join(path);
-            match try_read(&mut audio_manager, &path) {
-                Ok(handle) => Some(handle),
+// The order of read_dir depends on the OS, so levels are sorted to keep them in
+// the same order everywhere.
+fn sort_levels(levels: &mut [Level]) {
+    levels.sort_by(|a, b| {
+        util::natural_cmp(&a.name, &b.name).then_with(|| a.map_folder.cmp(&b.map_folder))
+    });
+}
+
+fn sort_entries(entries: &mut [LevelEntry]) {
+    entries.sort_by(|a, b| {
+        util::natural_cmp(a.name(), b.name()).then_with(|| a.folder().cmp(b.folder()))
+    });
+}
+
+/// The title of a level and how many medals it has, as read by `read_details`.
+struct LevelDetails {
+    folder: PathBuf,
+    title: Option<String>,
+    medals: usize,
+    earned_medals: usize,
+}
+
+/// Read the charts of every level in `levels`, which are paired with the names
+/// of the medals earned on them.
+fn read_details(levels: Vec<(PathBuf, Vec<String>)>) -> Vec<LevelDetails> {
+    levels
+        .into_iter()
+        .map(|(folder, earned)| {
+            let (title, medals) = match cache::load_song_map(&folder) {
+                Ok(map) => {
+                    // This runs in the background, so it is a good time to
+                    // measure the music, which loading the level can't wait
+                    // for.
+                    if let Err(err) = cache::measure_music_gain(&folder, &map) {
+                        log::warn!("Couldn't measure the music of {:?}: {}", folder, err);
+                    }
+                    (map.title, map.medals)
+                }
                 Err(err) => {
-                    log::warn!("Couldn't read music file from path {:?}: {}", path, err);
-                    None
+                    log::warn!("Couldn't read title of level {:?}: {}", folder, err);
+                    (None, vec![])
                 }
+            };
+            // Medals the chart no longer has don't count.
+            let earned_medals = medals
+                .iter()
+                .filter(|medal| earned.contains(&medal.name))
+                .count();
+            LevelDetails {
+                folder,
+                title,
+                medals: medals.len(),
+                earned_medals,
             }
+        })
+        .collect()
+}
+
+/// Return the name to show for the level in `base_folder`, given the chart's
+/// title. Charts without a title are named after their folder.
+fn level_name(base_folder: &Path, title: Option<String>) -> String {
+    // Archives are named after the archive, without the extension.
+    let file_name = if vfs::is_archive(base_folder) {
+        base_folder.file_stem()
+    } else {
+        base_folder.file_name()
+    };
+    title.unwrap_or_else(|| {
+        file_name
+            .unwrap_or_else(|| OsStr::new("No Name"))
+            .to_string_lossy()
+            .to_string()
+    })
+}
+
+#[derive(Clone)]
+pub struct Level {
+    name: String,
+    map_folder: PathBuf,
+    /// How many medals the chart has, and how many of them have been earned.
+    medals: usize,
+    earned_medals: usize,
+}
+
+impl Level {
+    /// Returns true if `path` is a folder containing a chart or a level archive.
+    fn is_level(path: &Path) -> bool {
+        vfs::is_level(path)
+    }
+
+    /// Create a Level named after its folder. The chart's title is preferred,
+    /// since folder names may not be able to hold every title (and aren't
+    /// always valid unicode), but reading it means compiling the chart, so it
+    /// is filled in later (see `LevelSelect::details`).
+    fn new(base_folder: impl AsRef<Path>) -> anyhow::Result<Level> {
+        let base_folder = base_folder.as_ref();
+        if Level::is_level(base_folder) {
+            Ok(Level {
+                name: level_name(base_folder, None),
+                map_folder: base_folder.to_path_buf(),
+                medals: 0,
+                earned_medals: 0,
+            })
         } else {
-            None
-        };
+            Err(anyhow::anyhow!(
+                "path {:?} is not a level folder! (it has no {} and is not a .{} archive)",
+                base_folder,
+                cache::CHART_FILE,
+                vfs::ARCHIVE_EXTENSION
+            ))
+        }
+    }
 
-        WorldState {
-            inner: InnerWorldState {
-                player: map.player,
-                groups: {
-                    let mut vec = Vec::with_capacity(8);
-                    vec.resize_with(8, EnemyGroup::new);
-                    vec
-                },
-            },
-            music,
-            audio_manager,
-            started: false,
-            scheduler: Scheduler::new(map),
-            debug: None,
-            instance_handle: None,
+    /// The level's name, followed by a filled circle for each medal earned
+    /// and an empty circle for each medal not yet earned.
+    fn label(&self) -> String {
+        if self.medals == 0 {
+            return self.name.clone();
         }
+        let icons: String = (0..self.medals)
+            .map(|i| if i < self.earned_medals { '●' } else { '○' })
+            .collect();
+        format!("{} {}", self.name, icons)
     }
 
-    fn update(
-        &mut self,
-        _ctx: &mut Context,
-        keyboard: &KeyboardState,
-        physics_delta_time: f64,
-        curr_time: Beats,
-    ) -> GameResult<()> {
-        if !self.started {
-            return Ok(());
+    fn load_level<P: AsRef<Path>>(&self, resource_path: P) -> anyhow::Result<SongMap> {
+        let base_folder = resource_path.as_ref().join(&self.map_folder);
+        cache::load_song_map(base_folder)
+    }
+}
+
+/// Where the header of the results screen is drawn.
+const HEADER_Y: f32 = MENU_POSITION.y - 50.0;
+const HEADER_LINE_HEIGHT: f32 = 22.0;
+
+/// The results of a finished run, broken down by chart section. Selecting a
+/// section restarts the level from the start of that section, so that the
+/// player can practice it. The run's stats may also be exported as JSON (see
+/// `stats::RunReport`).
+pub struct Results {
+    world: WorldState,
+    time: Time,
+    base_folder: PathBuf,
+    header: String,
+    /// The menu has one button per section, followed by the retry and export
+    /// buttons.
+    menu: Menu,
+}
+
+impl Results {
+    /// `medals` are the names of the medals earned on the run.
+    fn new(
+        world: WorldState,
+        time: Time,
+        base_folder: PathBuf,
+        medals: &[String],
+        strings: &Strings,
+    ) -> Results {
+        let mut items: Vec<Widget> = world
+            .stats
+            .sections
+            .iter()
+            .map(|section| {
+                Widget::button(strings.format(
+                    "results.section",
+                    &[&section.name, &section.start.0.round(), &section.hits],
+                ))
+            })
+            .collect();
+        items.push(Widget::button(strings.get("results.retry")));
+        items.push(Widget::button(strings.get("results.export")));
+        let mut header = strings.format(
+            "results.header",
+            &[&world.stats.total_hits(), &world.stats.retries],
+        );
+        if !medals.is_empty() {
+            header.push('\n');
+            header.push_str(&strings.format("results.medals", &[&medals.join(", ")]));
+        }
+        if world.stats.song_speed != SongSpeed::Normal {
+            header.push('\n');
+            header.push_str(&strings.format(
+                "results.song_speed",
+                &[&strings.get(world.stats.song_speed.key())],
+            ));
         }
+        if !world.stats.modifiers.is_empty() {
+            header.push('\n');
+            header.push_str(&strings.format(
+                "results.modifiers",
+                &[&world.stats.modifiers.label(strings)],
+            ));
+        }
+        if world.stats.grazes > 0 {
+            header.push('\n');
+            header.push_str(&strings.format("results.grazes", &[&world.stats.grazes]));
+        }
+        if world.stats.gems > 0 {
+            header.push('\n');
+            header.push_str(&strings.format("results.gems", &[&world.stats.gems]));
+        }
+        for objective in &world.inner.objectives {
+            let key = if objective.status == ObjectiveStatus::Completed {
+                "objective.completed"
+            } else {
+                "objective.failed"
+            };
+            header.push('\n');
+            header.push_str(&strings.format(key, &[&objective.label(strings)]));
+        }
+        // Push the menu down to make room for the header.
+        let header_height = HEADER_LINE_HEIGHT * header.lines().count() as f32;
+        let menu_position = Point2 {
+            x: MENU_POSITION.x,
+            y: f32::max(MENU_POSITION.y, HEADER_Y + header_height + 10.0),
+        };
+        Results {
+            world,
+            time,
+            base_folder,
+            header,
+            menu: Menu::new(items, menu_position),
+        }
+    }
 
-        if let Some(debug) = &mut self.debug {
-            debug.update(curr_time);
+    /// Returns the beat the focused section starts on, or None if the retry
+    /// or export button is focused.
+    fn selected_section_start(&self) -> Option<Beats> {
+        self.world
+            .stats
+            .sections
+            .get(self.menu.focused())
+            .map(|section| section.start)
+    }
 
-            if debug.lifetime_state(curr_time) == EnemyLifetime::Dead {
-                self.debug = None;
-            }
+    fn draw(&self, ctx: &mut Context, fonts: &FontSet) -> GameResult<()> {
+        fonts.text(&self.header, color::DEBUG_RED, 18.0).draw(
+            ctx,
+            DrawParam::default().dest(Point2 {
+                x: MENU_POSITION.x,
+                y: HEADER_Y,
+            }),
+        )?;
+        self.menu.draw(ctx, fonts)
+    }
+}
+
+/// The menu shown while the game is paused. The world is kept as it was, and
+/// is drawn frozen behind the menu.
+pub struct Pause {
+    world: WorldState,
+    time: Time,
+    base_folder: PathBuf,
+    menu: Menu,
+}
+
+// The index of each item in the pause menu.
+const PAUSE_RESUME: usize = 0;
+const PAUSE_RESTART: usize = 1;
+const PAUSE_AUDIO_DEVICE: usize = 2;
+const PAUSE_QUIT: usize = 3;
+
+impl Pause {
+    fn new(world: WorldState, time: Time, base_folder: PathBuf, strings: &Strings) -> Pause {
+        Pause {
+            world,
+            time,
+            base_folder,
+            menu: Pause::build_menu(strings),
         }
+    }
 
-        self.inner.player.update(physics_delta_time, keyboard);
+    fn build_menu(strings: &Strings) -> Menu {
+        let items = vec![
+            Widget::button(strings.get("pause.resume")),
+            Widget::button(strings.get("pause.restart")),
+            Widget::button(audio_device_label(strings)),
+            Widget::button(strings.get("pause.quit")),
+        ];
+        Menu::new(items, MENU_POSITION)
+    }
+
+    /// Switch the music to the output device set by `audio::set_device`,
+    /// carrying on from where the song is paused.
+    fn reopen_audio(&mut self, strings: &Strings) {
+        self.world.reopen_audio(self.time.get_beats());
+        let focused = self.menu.focused();
+        self.menu = Pause::build_menu(strings);
+        self.menu.set_focused(focused);
+    }
 
-        for group in self.inner.groups.iter_mut() {
-            group.update(&mut self.inner.player, curr_time);
+    fn draw(&mut self, ctx: &mut Context, fonts: &FontSet, strings: &Strings) -> GameResult<()> {
+        self.world.draw(ctx, self.time.get_beats())?;
+        let screen = Rect::new(0.0, 0.0, WINDOW_WIDTH, WINDOW_HEIGHT);
+        let shade = Color::new(0.0, 0.0, 0.0, 0.6);
+        Mesh::new_rectangle(ctx, DrawMode::fill(), screen, shade)?
+            .draw(ctx, DrawParam::default())?;
+        fonts.text(strings.get("pause.header"), WHITE, 18.0).draw(
+            ctx,
+            DrawParam::default().dest(Point2 {
+                x: MENU_POSITION.x,
+                y: HEADER_Y,
+            }),
+        )?;
+        self.menu.draw(ctx, fonts)
+    }
+}
+
+/// The options menu. Changes take effect immediately, and are written to the
+/// save file along with everything else.
+pub struct Options {
+    menu: Menu,
+    /// The level select to return to when leaving the options.
+    level_select: LevelSelect,
+    /// The name of the current profile.
+    profile: String,
+}
+
+// The index of each item in the options menu.
+const OPTION_PROFILE: usize = 0;
+const OPTION_NEW_PROFILE: usize = 1;
+const OPTION_INPUT_OFFSET: usize = 2;
+const OPTION_CALIBRATE: usize = 3;
+const OPTION_TICK_RATE: usize = 4;
+const OPTION_LANGUAGE: usize = 5;
+const OPTION_NARRATION: usize = 6;
+const OPTION_FOCUS_MODE: usize = 7;
+const OPTION_FOCUS_BINDING: usize = 8;
+const OPTION_FOCUS_FACTOR: usize = 9;
+const OPTION_SONG_SPEED: usize = 10;
+/// The first of the toggles for each modifier, in the order of
+/// `ModifierKind::ALL`.
+const OPTION_MODIFIERS: usize = 11;
+const OPTION_DEADZONE_X: usize = 15;
+const OPTION_DEADZONE_Y: usize = 16;
+const OPTION_CURVE_X: usize = 17;
+const OPTION_CURVE_Y: usize = 18;
+const OPTION_GRAPHICS: usize = 19;
+const OPTION_MAX_DARKNESS: usize = 20;
+const OPTION_AUDIO_DEVICE: usize = 21;
+const OPTION_GET_LEVELS: usize = 22;
+const OPTION_BACK: usize = 23;
+
+impl Options {
+    fn new(
+        save_data: &SaveData,
+        strings: &Strings,
+        profile: &str,
+        level_select: LevelSelect,
+    ) -> Options {
+        Options {
+            menu: Options::build_menu(save_data, strings, profile),
+            level_select,
+            profile: profile.to_string(),
         }
+    }
 
-        self.update_scheduler(curr_time);
+    fn build_menu(save_data: &SaveData, strings: &Strings, profile: &str) -> Menu {
+        let mut items = vec![
+            Widget::button(strings.format("options.profile", &[&profile])),
+            Widget::button(strings.get("options.new_profile")),
+            Widget::slider(
+                strings.get("options.input_offset"),
+                save_data.input_offset,
+                -MAX_INPUT_OFFSET,
+                MAX_INPUT_OFFSET,
+                INPUT_OFFSET_STEP,
+            ),
+            Widget::button(strings.get("options.calibrate")),
+            Widget::button(strings.format("options.tick_rate", &[&save_data.tick_rate])),
+            Widget::button(strings.format("options.language", &[&strings.language_name()])),
+            Widget::button(strings.format(
+                "options.narration",
+                &[&strings.get(if save_data.narration {
+                    "options.on"
+                } else {
+                    "options.off"
+                })],
+            )),
+            Widget::button(strings.format(
+                if save_data.focus_toggle {
+                    "options.focus_toggle"
+                } else {
+                    "options.focus_hold"
+                },
+                &[&strings.get(save_data.focus_binding.key())],
+            )),
+            Widget::button(strings.format(
+                "options.focus_binding",
+                &[&strings.get(save_data.focus_binding.key())],
+            )),
+            Widget::button(focus_factor_label(save_data, strings)),
+            Widget::button(strings.format(
+                "options.song_speed",
+                &[&strings.get(save_data.song_speed.key())],
+            )),
+        ];
+        items.extend(ModifierKind::ALL.iter().map(|&kind| {
+            let on_off = if save_data.modifiers.contains(kind) {
+                "options.on"
+            } else {
+                "options.off"
+            };
+            Widget::button(strings.format(
+                "options.modifier",
+                &[&strings.get(kind.key()), &strings.get(on_off)],
+            ))
+        }));
+        items.extend(vec![
+            Widget::slider(
+                strings.get("options.deadzone_x"),
+                save_data.stick.x.deadzone as f64,
+                0.0,
+                MAX_DEADZONE,
+                DEADZONE_STEP,
+            ),
+            Widget::slider(
+                strings.get("options.deadzone_y"),
+                save_data.stick.y.deadzone as f64,
+                0.0,
+                MAX_DEADZONE,
+                DEADZONE_STEP,
+            ),
+            Widget::slider(
+                strings.get("options.curve_x"),
+                save_data.stick.x.curve as f64,
+                MIN_CURVE,
+                MAX_CURVE,
+                CURVE_STEP,
+            ),
+            Widget::slider(
+                strings.get("options.curve_y"),
+                save_data.stick.y.curve as f64,
+                MIN_CURVE,
+                MAX_CURVE,
+                CURVE_STEP,
+            ),
+            Widget::button(strings.format(
+                "options.graphics",
+                &[&strings.get(save_data.graphics_preset.key())],
+            )),
+            Widget::slider(
+                strings.get("options.max_darkness"),
+                save_data.max_darkness as f64,
+                0.0,
+                1.0,
+                MAX_DARKNESS_STEP,
+            ),
+            Widget::button(audio_device_label(strings)),
+            Widget::button(strings.get("options.get_levels")),
+            Widget::button(strings.get("menu.back")),
+        ]);
+        Menu::new(items, MENU_POSITION)
+    }
 
-        Ok(())
+    /// Rebuild the menu, keeping the same item focused. This is used whenever
+    /// a change to the options changes the menu's text.
+    fn rebuild(&mut self, save_data: &SaveData, strings: &Strings) {
+        let focused = self.menu.focused();
+        self.menu = Options::build_menu(save_data, strings, &self.profile);
+        self.menu.set_focused(focused);
     }
+}
 
-    fn draw(&mut self, ctx: &mut Context, curr_time: Beats) -> GameResult<()> {
-        for group in self.inner.groups.iter() {
-            group.draw(ctx, curr_time)?;
+/// The label for the focus factor option, as a multiple of the player's speed.
+fn focus_factor_label(save_data: &SaveData, strings: &Strings) -> String {
+    let factor = match save_data.focus_factor {
+        Some(factor) => format!("{}x", factor),
+        None => strings.get("options.chart_focus_factor").to_string(),
+    };
+    strings.format("options.focus_factor", &[&factor])
+}
+
+/// The label for the audio device option, which names the device set by
+/// `audio::set_device`.
+fn audio_device_label(strings: &Strings) -> String {
+    let device = audio::device();
+    let name = device
+        .as_deref()
+        .unwrap_or_else(|| strings.get("options.default_device"));
+    strings.format("options.audio_device", &[&name])
+}
+
+/// Return the output device after `current`, going through the system's
+/// default first and then every device in the order they are listed.
+fn next_audio_device(current: Option<String>) -> Option<String> {
+    let devices = audio::output_devices();
+    let next = match current {
+        Some(current) => devices
+            .iter()
+            .position(|device| *device == current)
+            .map_or(0, |i| i + 1),
+        None => 0,
+    };
+    devices.get(next).cloned()
+}
+
+/// The "Get more levels" screen, which lists the levels in the level index and
+/// downloads whichever ones the player selects.
+pub struct Browser {
+    /// The request for the level index, or None if no index URL is set.
+    index: Option<Task<LevelIndex>>,
+    listings: Vec<LevelListing>,
+    /// The file name each listing is saved as. See `browser::file_names`.
+    file_names: Vec<String>,
+    /// The download of each listing the player selected, keyed by its index.
+    downloads: HashMap<usize, Task<PathBuf>>,
+    header: String,
+    /// The menu has one button per listing, followed by the back button.
+    menu: Menu,
+    /// The options to return to when leaving the browser.
+    options: Options,
+}
+
+impl Browser {
+    fn new(index_url: &str, strings: &Strings, options: Options) -> Browser {
+        let index = if index_url.is_empty() {
+            None
+        } else {
+            Some(browser::fetch_index(index_url.to_string()))
+        };
+        let mut browser = Browser {
+            index,
+            listings: vec![],
+            file_names: vec![],
+            downloads: HashMap::new(),
+            header: String::new(),
+            menu: Menu::new(vec![], MENU_POSITION),
+            options,
+        };
+        browser.relabel(strings);
+        browser
+    }
+
+    /// Check on the index and downloads, and update the menu to match.
+    fn update(&mut self, strings: &Strings) {
+        self.relabel(strings);
+    }
+
+    fn relabel(&mut self, strings: &Strings) {
+        self.header = match self.index.as_mut().map(Task::poll) {
+            None => strings.get("browser.no_url").to_string(),
+            Some(None) => strings.get("browser.loading").to_string(),
+            Some(Some(Err(err))) => strings.format("browser.error", &[err]),
+            Some(Some(Ok(index))) => {
+                if self.listings.is_empty() {
+                    self.listings = index.levels.clone();
+                    self.file_names = browser::file_names(&self.listings);
+                }
+                strings.get("browser.header").to_string()
+            }
+        };
+
+        let downloads = &mut self.downloads;
+        let mut items: Vec<Widget> = self
+            .listings
+            .iter()
+            .enumerate()
+            .map(|(i, listing)| {
+                let author = listing.author.as_deref().unwrap_or("?");
+                let mut label = strings.format("browser.level", &[&listing.name, &author]);
+                if let Some(bpm) = listing.bpm {
+                    label = strings.format("browser.bpm", &[&label, &bpm]);
+                }
+                let label = match downloads.get_mut(&i).map(Task::poll) {
+                    None => label,
+                    Some(None) => strings.format("browser.downloading", &[&label]),
+                    Some(Some(Ok(_))) => strings.format("browser.installed", &[&label]),
+                    Some(Some(Err(err))) => strings.format("browser.failed", &[&label, err]),
+                };
+                Widget::button(label)
+            })
+            .collect();
+        items.push(Widget::button(strings.get("menu.back")));
+
+        let focused = self.menu.focused();
+        self.menu = Menu::new(items, MENU_POSITION);
+        self.menu.set_focused(focused);
+    }
+
+    /// Start downloading the listing at index `i`, unless it is already being
+    /// downloaded or was downloaded already.
+    fn download(&mut self, i: usize, levels_folder: &Path) {
+        let failed = matches!(
+            self.downloads.get_mut(&i).map(Task::poll),
+            Some(Some(Err(_)))
+        );
+        if let (Some(listing), Some(file_name)) = (self.listings.get(i), self.file_names.get(i)) {
+            if failed || !self.downloads.contains_key(&i) {
+                let download =
+                    browser::download(listing, file_name.clone(), levels_folder.to_path_buf());
+                self.downloads.insert(i, download);
+            }
         }
+    }
 
-        let player_mesh = self.inner.player.get_mesh(ctx)?;
-        player_mesh.draw(
+    /// Return the paths of every level which has been downloaded.
+    fn downloaded(&mut self) -> Vec<PathBuf> {
+        self.downloads
+            .values_mut()
+            .filter_map(|download| match download.poll() {
+                Some(Ok(path)) => Some(path.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn draw(&self, ctx: &mut Context, fonts: &FontSet) -> GameResult<()> {
+        fonts.text(&self.header, color::DEBUG_RED, 18.0).draw(
             ctx,
-            DrawParam::default().dest(self.inner.player.pos.as_screen_coords()),
+            DrawParam::default().dest(Point2 {
+                x: MENU_POSITION.x,
+                y: MENU_POSITION.y - 40.0,
+            }),
         )?;
-
+        self.menu.draw(ctx, fonts)?;
+
+        let description = self
+            .listings
+            .get(self.menu.focused())
+            .and_then(|listing| listing.description.as_ref());
+        if let Some(description) = description {
+            fonts.text(description, color::GUIDE_GREY, 16.0).draw(
+                ctx,
+                DrawParam::default().dest(Point2 {
+                    x: MENU_POSITION.x,
+                    y: WINDOW_HEIGHT - 60.0,
+                }),
+            )?;
+        }
         Ok(())
     }
+}
+
+/// The input offset calibration screen. A circle flashes on every beat, and
+/// the player taps a direction key along with it. The input offset is how late
+/// the taps are on average.
+pub struct Calibration {
+    time: Time,
+    /// How many seconds after the nearest beat each recent tap was.
+    errors: Vec<f64>,
+    /// The options to return to when leaving the calibration.
+    options: Options,
+}
+
+impl Calibration {
+    fn new(options: Options) -> Calibration {
+        Calibration {
+            time: Time::new(CALIBRATION_BPM, Seconds(0.0)),
+            errors: vec![],
+            options,
+        }
+    }
 
-    fn update_scheduler(&mut self, time: Beats) {
-        self.scheduler.update(time, &mut self.inner);
+    /// Record a tap made at `instant`. The time's input offset is left at
+    /// zero, so this measures the player's whole latency.
+    fn tap(&mut self, instant: Instant) {
+        let beat = self.time.input_beats(instant).0;
+        let error = to_secs(Beats(beat - beat.round()), CALIBRATION_BPM);
+        self.errors.push(error.0);
+        if self.errors.len() > CALIBRATION_TAPS {
+            self.errors.remove(0);
+        }
     }
 
-    fn stop_world(&mut self) {
-        // Stop the game, pausing the music, fetching a new Source instance, and
-        // rebuild the scheduler work queue.
-        self.started = false;
-        if let Some(handle) = &mut self.instance_handle {
-            match handle.stop(StopInstanceSettings::new()) {
-                Ok(()) => self.instance_handle = None,
-                Err(err) => log::error!("Error stopping music: {}", err),
-            }
+    /// Return the average lateness of the recent taps, or None if there
+    /// haven't been enough taps yet.
+    fn measured_offset(&self) -> Option<f64> {
+        if self.errors.len() < MIN_CALIBRATION_TAPS {
+            return None;
         }
+        let mean = self.errors.iter().sum::<f64>() / self.errors.len() as f64;
+        Some(mean.clamp(-MAX_INPUT_OFFSET, MAX_INPUT_OFFSET))
     }
 
-    fn start_world(&mut self, map: &SongMap, time: &mut Time) {
-        // Reset the player and groups
-        self.inner.player = map.player;
-        self.inner.groups = {
-            let mut vec = Vec::with_capacity(8);
-            vec.resize_with(8, EnemyGroup::new);
-            vec
+    fn draw(&self, ctx: &mut Context, fonts: &FontSet, strings: &Strings) -> GameResult<()> {
+        fonts
+            .text(strings.get("calibration.header"), WHITE, 18.0)
+            .draw(
+                ctx,
+                DrawParam::default().dest(Point2 {
+                    x: MENU_POSITION.x,
+                    y: HEADER_Y,
+                }),
+            )?;
+        let status = match self.measured_offset() {
+            Some(offset) => strings.format("calibration.measured", &[&format!("{:.3}", offset)]),
+            None => strings.format(
+                "calibration.waiting",
+                &[&self.errors.len(), &MIN_CALIBRATION_TAPS],
+            ),
         };
+        fonts.text(&status, color::GUIDE_GREY, 18.0).draw(
+            ctx,
+            DrawParam::default().dest(Point2 {
+                x: MENU_POSITION.x,
+                y: HEADER_Y + HEADER_LINE_HEIGHT,
+            }),
+        )?;
 
-        // Simulate all events up to this point. We do this before the level
-        // starts in order to reduce the amount of BeatActions the scheduler needs
-        // to perform immediately, which could be a lot if there were many events.
-        self.scheduler = Scheduler::new(map);
-        self.update_scheduler(map.skip_amount);
+        let percent = self.time.get_beats().0.rem_euclid(1.0);
+        let color = Color::lerp(WHITE, color::GUIDE_GREY, percent);
+        let radius = 50.0;
+        Mesh::new_circle(
+            ctx,
+            DrawMode::fill(),
+            [WINDOW_WIDTH / 2.0, WINDOW_HEIGHT / 2.0],
+            radius,
+            meshutil::tolerance(radius, 1.0),
+            color,
+        )?
+        .draw(ctx, DrawParam::default())
+    }
+}
 
-        let skip_amount = to_secs(map.skip_amount, map.bpm);
+/// A level played on its own while the game is idle. This is the player's best
+/// run of the level if they have one: the replay's inputs are fed to the world
+/// in place of the keyboard, and the player is kept on the replay's path so
+/// that the demo can't drift from the recorded run. Otherwise, the autoplay
+/// bot plays the level. Any key ends the demo.
+pub struct Demo {
+    world: WorldState,
+    time: Time,
+    /// The run being played back, or None if the autoplay bot is playing.
+    replay: Option<Replay>,
+    /// The keys held in the replay.
+    keyboard: KeyboardState,
+    /// The level select to return to when the demo ends.
+    level_select: LevelSelect,
+}
 
-        // Play the music
-        if let Some(music) = &mut self.music {
-            match music.play(
-                InstanceSettings::new()
-                    .volume(0.5)
-                    .start_position(skip_amount.0),
-            ) {
-                Ok(handle) => self.instance_handle = Some(handle),
-                Err(err) => log::error!("Error starting music: {}", err),
+impl Demo {
+    fn update(&mut self, ctx: &mut Context, physics_delta_time: f64) -> GameResult<()> {
+        self.time.update();
+        self.world.sync_time(&mut self.time);
+        let curr_time = self.time.get_beats();
+        match &self.replay {
+            Some(replay) => {
+                if let Some(input) = replay.input_at(curr_time) {
+                    self.keyboard.set_input_state(input);
+                }
+                if let Some(pos) = replay.position_at(curr_time) {
+                    self.world.inner.player.pos = pos;
+                }
+            }
+            None => {
+                let input = autoplay::input(&self.world.inner, curr_time);
+                self.keyboard.set_input_state(input);
             }
-        } else {
-            log::warn!("No music loaded!")
         }
+        self.world
+            .update(ctx, &self.keyboard, physics_delta_time, curr_time)
+    }
 
-        // Reset the timer
-        *time = Time::new(map.bpm, skip_amount);
+    /// Returns true once the song or the replay is over.
+    fn finished(&self) -> bool {
+        let replay_over = match &self.replay {
+            Some(replay) => match replay.samples.last() {
+                Some(last) => self.time.get_beats() > last.beat,
+                None => true,
+            },
+            None => false,
+        };
+        replay_over || self.world.finished()
+    }
 
-        self.started = true;
+    fn draw(&mut self, ctx: &mut Context, fonts: &FontSet, strings: &Strings) -> GameResult<()> {
+        self.world.draw(ctx, self.time.get_beats())?;
+        let text = fonts.text(strings.get("demo.overlay"), WHITE, 48.0);
+        let x = (WINDOW_WIDTH - text.width(ctx) as f32) / 2.0;
+        text.draw(ctx, DrawParam::default().dest(Point2 { x, y: 20.0 }))?;
+        let text = fonts.text(strings.get("demo.exit"), color::GUIDE_GREY, 18.0);
+        let x = (WINDOW_WIDTH - text.width(ctx) as f32) / 2.0;
+        text.draw(ctx, DrawParam::default().dest(Point2 { x, y: 80.0 }))
     }
+}
 
-    #[allow(dead_code)]
-    fn draw_debug_hitbox(&self, ctx: &mut Context, time: &Time) -> Result<(), GameError> {
-        let curr_time = time.get_beats();
-        let rotated_about = if ggez::input::keyboard::is_key_pressed(ctx, KeyCode::V) {
-            Some((self.inner.player.pos, curr_time.0 * 400.0))
-        } else {
+pub enum Scene {
+    LevelSelect(LevelSelect),
+    MainGame(WorldState, Time, PathBuf),
+    Results(Results),
+    Options(Options),
+    Browser(Browser),
+    Demo(Demo),
+    Calibration(Calibration),
+    Pause(Pause),
+}
+
+struct MainState {
+    current_scene: Scene,
+    keyboard: KeyboardState,
+    assets: Assets,
+    resource_path: PathBuf,
+    save_data: SaveData,
+    strings: Strings,
+    /// How long recent frames took, which decides how far to degrade visuals.
+    frame_budget: FrameBudget,
+    /// When the current frame's update started.
+    frame_start: Instant,
+    /// How long recent frames took, including presenting, for the debug panel.
+    frame_times: FrameTimes,
+    /// If true, the debug stats panel is drawn during the main game. Toggled
+    /// with F3.
+    show_debug_stats: bool,
+    /// When the player last pressed a key or used the mouse, which decides
+    /// when to start a demo.
+    last_input: Instant,
+    /// False if no level could be loaded the last time a demo was started, in
+    /// which case no demo is started until the levels change.
+    demo_available: bool,
+    /// Reads the menus aloud, if the player turned narration on.
+    narration: Narration,
+    /// The raw position of the gamepad's left stick, with up being positive.
+    stick: Point2<f32>,
+    /// The name of the current profile, which `save_data` was loaded from.
+    profile: String,
+    /// The kiosk mode config and session, if the game is set up for a booth.
+    kiosk: Option<Kiosk>,
+    /// The window's current title. See `window_title`.
+    window_title: String,
+    /// The window scale factor the UI is currently scaled by. See
+    /// `ui::set_scale_factor`.
+    scale_factor: f32,
+    /// The leaderboard client, if the player turned the leaderboard on.
+    #[cfg(feature = "leaderboard")]
+    leaderboard: Option<Leaderboard>,
+}
+
+impl MainState {
+    /// Create the game, starting on the level select with the last level
+    /// played focused.
+    fn new(ctx: &mut Context) -> MainState {
+        let resource_path = resource_path();
+        let profile = save::current_profile(&resource_path);
+        let profile_folder = save::profile_folder(&resource_path, &profile);
+        let mut save_data = load_save(&profile_folder);
+        save_data.migrate_level_keys(&resource_path, &profile_folder);
+        parse::set_instruction_budget(save_data.instruction_budget);
+        audio::set_device(save_data.audio_device.clone());
+        let strings = load_strings(&resource_path, &save_data.language);
+        let mut kiosk = Kiosk::load(&resource_path).unwrap_or_else(|err| {
+            log::warn!("Couldn't load the kiosk config: {}", err);
             None
+        });
+        let mut level_select =
+            LevelSelect::new(&resource_path, &strings, &save_data).unwrap_or_default();
+        // A kiosk starts on its playlist rather than where the last visitor
+        // left off.
+        let kiosk_level = kiosk.as_mut().and_then(Kiosk::next_level);
+        let selected_level = kiosk_level.or_else(|| save_data.last_level.clone());
+        if let Some(level) = selected_level {
+            // The selected level may be absolute, in which case this join
+            // just returns the level's path.
+            level_select.select(&resource_path.join(level), &strings);
+        }
+        let mut keyboard = KeyboardState::default();
+        keyboard.focus_toggle = save_data.focus_toggle;
+        keyboard.set_focus_binding(save_data.focus_binding);
+        // Gizmos are shown along with the debug stats, which start hidden
+        // until F3 is pressed.
+        debug_draw::set_enabled(false);
+        MainState {
+            current_scene: Scene::LevelSelect(level_select),
+            keyboard,
+            assets: Assets::new(ctx),
+            resource_path,
+            #[cfg(feature = "leaderboard")]
+            leaderboard: Leaderboard::new(&save_data.leaderboard, &resource_path),
+            save_data,
+            strings,
+            frame_budget: FrameBudget::default(),
+            frame_start: Instant::now(),
+            frame_times: FrameTimes::default(),
+            show_debug_stats: false,
+            last_input: Instant::now(),
+            demo_available: true,
+            narration: Narration::new(narration::narrator(save_data.narration)),
+            stick: Point2 { x: 0.0, y: 0.0 },
+            profile,
+            kiosk,
+            window_title: GAME_NAME.to_string(),
+            // Start out unscaled, so that the first update scales the UI.
+            scale_factor: 1.0,
+        }
+    }
+
+    /// Returns true if kiosk mode is on and locked, in which case the game
+    /// can't be quit and the options can't be opened.
+    fn locked(&self) -> bool {
+        self.kiosk
+            .as_ref()
+            .map_or(false, |kiosk| kiosk.config.locked)
+    }
+
+    /// Returns true if the current kiosk session's time has run out.
+    fn session_over(&self) -> bool {
+        self.kiosk.as_ref().map_or(false, Kiosk::session_over)
+    }
+
+    /// End the kiosk session and go back to attract mode from the results,
+    /// with the next level of the playlist selected.
+    fn return_to_attract(&mut self) {
+        let mut level_select =
+            match LevelSelect::new(&self.resource_path, &self.strings, &self.save_data) {
+                Ok(level_select) => level_select,
+                Err(err) => {
+                    log::warn!("Couldn't reread levels: {}", err);
+                    LevelSelect::default()
+                }
+            };
+        if let Some(kiosk) = &mut self.kiosk {
+            kiosk.end_session();
+            if let Some(level) = kiosk.next_level() {
+                level_select.select(&self.resource_path.join(level), &self.strings);
+            }
+        }
+        log::info!("Kiosk session ended, returning to attract mode");
+        self.current_scene = Scene::LevelSelect(level_select);
+        self.start_demo();
+    }
+
+    /// Install the level at `path` into the levels folder, and reread the
+    /// levels so that it shows up in the level select, focused.
+    fn install_level(&mut self, path: &Path) {
+        let destination = match vfs::install_level(path, &self.resource_path) {
+            Ok(destination) => destination,
+            Err(err) => {
+                log::warn!("Couldn't install level {:?}: {}", path, err);
+                return;
+            }
+        };
+        log::info!("Installed level {:?} to {:?}", path, destination);
+        self.demo_available = true;
+        let level_select = match &mut self.current_scene {
+            Scene::LevelSelect(level_select) => level_select,
+            Scene::Options(options) => &mut options.level_select,
+            // Everything else rereads the levels when it returns to the level
+            // select.
+            _ => return,
         };
+        match LevelSelect::new(&self.resource_path, &self.strings, &self.save_data) {
+            Ok(mut new_level_select) => {
+                new_level_select.select(&destination, &self.strings);
+                *level_select = new_level_select;
+            }
+            Err(err) => log::warn!("Couldn't reread levels: {}", err),
+        }
+    }
 
-        if let Some(enemy) = &self.debug {
-            if ggez::input::keyboard::is_key_pressed(ctx, KeyCode::C) {
-                if let Some((mesh, param)) = enemy.draw(ctx, curr_time, rotated_about)? {
-                    mesh.draw(ctx, param)?;
+    /// Return the folder the current profile's save file and replays are in.
+    fn profile_folder(&self) -> PathBuf {
+        save::profile_folder(&self.resource_path, &self.profile)
+    }
+
+    /// Save the current profile and switch to `profile`, loading its settings.
+    /// This should only be done from the options, which are rebuilt to match.
+    fn switch_profile(&mut self, profile: String) {
+        write_save(&self.save_data, &self.profile_folder());
+        log::info!("Switching to profile {:?}", profile);
+        self.profile = profile;
+        if let Err(err) = save::set_current_profile(&self.resource_path, &self.profile) {
+            log::warn!("Couldn't record the current profile: {}", err);
+        }
+
+        let profile_folder = self.profile_folder();
+        self.save_data = load_save(&profile_folder);
+        self.save_data
+            .migrate_level_keys(&self.resource_path, &profile_folder);
+        parse::set_instruction_budget(self.save_data.instruction_budget);
+        audio::set_device(self.save_data.audio_device.clone());
+        self.strings = load_strings(&self.resource_path, &self.save_data.language);
+        self.keyboard.focus_toggle = self.save_data.focus_toggle;
+        self.keyboard
+            .set_focus_binding(self.save_data.focus_binding);
+        self.narration
+            .set_narrator(narration::narrator(self.save_data.narration));
+        #[cfg(feature = "leaderboard")]
+        {
+            self.leaderboard = Leaderboard::new(&self.save_data.leaderboard, &self.resource_path);
+        }
+
+        if let Scene::Options(options) = &mut self.current_scene {
+            options.profile = self.profile.clone();
+            options.rebuild(&self.save_data, &self.strings);
+            match LevelSelect::new(&self.resource_path, &self.strings, &self.save_data) {
+                Ok(mut level_select) => {
+                    if let Some(level) = &self.save_data.last_level {
+                        level_select.select(&self.resource_path.join(level), &self.strings);
+                    }
+                    options.level_select = level_select;
                 }
+                Err(err) => log::warn!("Couldn't reread levels: {}", err),
             }
-            if ggez::input::keyboard::is_key_pressed(ctx, KeyCode::Z) {
-                for x in -20..20 {
-                    for y in -20..20 {
-                        let pos = WorldPos {
-                            x: x as f64,
-                            y: y as f64,
-                        };
-                        let sdf = enemy.sdf(pos, curr_time, rotated_about);
-                        let color = match sdf {
-                            None => crate::color::GUIDE_GREY,
-                            Some(sdf) => Color::lerp(
-                                crate::color::RED,
-                                crate::color::GREEN,
-                                (sdf.0.atan() / (std::f64::consts::PI / 2.0) + 1.0) / 2.0,
-                            ),
-                        };
-                        Mesh::new_circle(
-                            ctx,
-                            DrawMode::fill(),
-                            pos.as_screen_coords(),
-                            1.0,
-                            5.0,
-                            color,
-                        )?
-                        .draw(ctx, DrawParam::default())?;
+        }
+    }
+
+    /// Switch from the MainGame scene to the results of the run.
+    fn show_results(&mut self) {
+        let scene = std::mem::replace(
+            &mut self.current_scene,
+            Scene::LevelSelect(LevelSelect::default()),
+        );
+        self.current_scene = match scene {
+            Scene::MainGame(mut world, time, base_folder) => {
+                world.stop_world();
+                // Practice runs and failed runs (ex: runs which ended by
+                // failing an objective) don't count towards medals, the best
+                // replay, or the leaderboard. Runs which aren't played as
+                // charted (ex: at Nightcore speed) still go to the
+                // leaderboard, which tags them, but don't earn medals.
+                let counts = world.is_full_run() && !world.failed();
+                let medals: Vec<String> = if counts && world.is_as_charted() {
+                    let (hits, grazes) = (world.stats.total_hits(), world.stats.grazes);
+                    medal::earned(&world.song_map().medals, hits, grazes)
+                        .map(|medal| medal.name.clone())
+                        .collect()
+                } else {
+                    vec![]
+                };
+                let level = self.save_data.level_mut(&self.resource_path, &base_folder);
+                for medal in &medals {
+                    level.award_medal(medal);
+                }
+                write_save(&self.save_data, &self.profile_folder());
+                let chart_focus_factor = world.song_map().player.focus_factor;
+                if counts
+                    && save_if_best(
+                        &self.profile_folder(),
+                        &self.resource_path,
+                        &base_folder,
+                        &world.replay,
+                        chart_focus_factor,
+                    )
+                    && world.ghost.is_some()
+                {
+                    world.ghost = Some(world.replay.clone());
+                }
+                #[cfg(feature = "leaderboard")]
+                if let Some(leaderboard) = &mut self.leaderboard {
+                    if counts {
+                        leaderboard.submit(&base_folder, world.replay.chart_hash, &world.stats);
                     }
                 }
+                // The results of a kiosk session are only shown for so long
+                // after the last input, so they're counted from here.
+                self.last_input = Instant::now();
+                Scene::Results(Results::new(
+                    world,
+                    time,
+                    base_folder,
+                    &medals,
+                    &self.strings,
+                ))
+            }
+            scene => scene,
+        };
+    }
+
+    /// Switch from the LevelSelect to a demo of a random level's best run. If
+    /// no level has a usable replay, the autoplay bot plays a random level
+    /// instead. If no level can be loaded at all, no demo is tried again until
+    /// the levels are reread (see `install_level`).
+    fn start_demo(&mut self) {
+        self.last_input = Instant::now();
+        let mut levels = match &self.current_scene {
+            Scene::LevelSelect(level_select) => level_select.levels(),
+            _ => return,
+        };
+        if let Some(kiosk) = &self.kiosk {
+            let resource_path = &self.resource_path;
+            levels.retain(|level| {
+                let folder = level.map_folder.strip_prefix(resource_path);
+                folder.map_or(false, |folder| kiosk.offers(folder))
+            });
+        }
+        levels.shuffle(&mut rand::thread_rng());
+        for level in &levels {
+            // The replay is checked first, since creating the world reads the
+            // music. Levels without a replay are skipped before hashing the
+            // chart, which also reads the music.
+            let (profile_folder, levels_folder) = (self.profile_folder(), &self.resource_path);
+            if !replay::replay_path(&profile_folder, levels_folder, &level.map_folder).exists() {
+                continue;
+            }
+            let replay = match cache::chart_hash(&level.map_folder) {
+                Ok(hash) => load_ghost(&profile_folder, levels_folder, &level.map_folder, hash),
+                Err(_) => None,
+            };
+            let replay = replay.filter(|replay| !replay.samples.is_empty());
+            if replay.is_some() && self.start_demo_of(level, replay) {
+                return;
+            }
+        }
+        log::info!("No level has a replay to play as a demo, autoplaying one instead");
+        for level in &levels {
+            if self.start_demo_of(level, None) {
+                return;
             }
         }
+        log::info!("No level could be loaded for a demo");
+        self.demo_available = false;
+    }
 
-        Ok(())
+    /// Switch from the LevelSelect to a demo of `level`, playing back `replay`,
+    /// or letting the autoplay bot play if it is None. Returns false if the
+    /// level couldn't be loaded.
+    fn start_demo_of(&mut self, level: &Level, replay: Option<Replay>) -> bool {
+        let map = match level.load_level(&self.resource_path) {
+            Ok(map) => map,
+            Err(err) => {
+                log::warn!("Couldn't load map for demo: {}", err);
+                return false;
+            }
+        };
+        log::info!("Starting demo of {:?}", level.map_folder);
+        let mut world = WorldState::new(&level.map_folder, &map);
+        world.max_darkness = self.save_data.max_darkness;
+        if let Some(replay) = &replay {
+            world.song_speed = replay.song_speed;
+            world.modifiers = replay.modifiers.clone();
+            world.focus_factor = replay.focus_factor;
+        }
+        let mut time = Time::new(map.bpm, Seconds(0.0));
+        world.reset_to(&map, &mut time, map.skip_amount);
+        let scene = std::mem::replace(
+            &mut self.current_scene,
+            Scene::LevelSelect(LevelSelect::default()),
+        );
+        self.current_scene = match scene {
+            Scene::LevelSelect(level_select) => Scene::Demo(Demo {
+                world,
+                time,
+                replay,
+                keyboard: KeyboardState::default(),
+                level_select,
+            }),
+            scene => scene,
+        };
+        true
     }
-}
 
-pub struct LevelSelect {
-    levels: Vec<Level>,
-    current_selection: usize,
-}
+    /// Switch from the demo back to the LevelSelect.
+    fn end_demo(&mut self) {
+        let scene = std::mem::replace(
+            &mut self.current_scene,
+            Scene::LevelSelect(LevelSelect::default()),
+        );
+        self.current_scene = match scene {
+            Scene::Demo(mut demo) => {
+                demo.world.stop_world();
+                Scene::LevelSelect(demo.level_select)
+            }
+            scene => scene,
+        };
+        self.last_input = Instant::now();
+    }
 
-impl LevelSelect {
-    fn new(levels_folder: impl AsRef<Path>) -> anyhow::Result<LevelSelect> {
-        let mut levels = vec![];
-        let levels_folder = std::fs::read_dir(levels_folder.as_ref())?;
-        for level in levels_folder {
-            let result: anyhow::Result<(Level, PathBuf)> = try {
-                let level = level?;
-                let path = level.path();
-                let level = Level::new(&path)?;
-                (level, path)
+    /// Export the stats of the run on the results screen to the profile's
+    /// RUNS_FOLDER. The export button says where the report was written.
+    fn export_results(&mut self) {
+        let profile_folder = self.profile_folder();
+        if let Scene::Results(results) = &mut self.current_scene {
+            let world = &results.world;
+            let report = RunReport::new(
+                &self.resource_path,
+                &results.base_folder,
+                world.replay.chart_hash,
+                world.song_map().bpm,
+                &world.stats,
+                &self.save_data,
+            );
+            let label = match report.export(&profile_folder) {
+                Ok(path) => {
+                    log::info!("Exported run stats to {:?}", path);
+                    self.strings.format("results.exported", &[&path.display()])
+                }
+                Err(err) => {
+                    log::warn!("Couldn't export run stats: {}", err);
+                    self.strings.get("results.export_failed").to_string()
+                }
             };
-            match result {
-                Ok((level, path)) => {
-                    levels.push(level);
-                    log::info!("Loaded level from path {:?}", path)
+            if let Some(button) = results.menu.items.last_mut() {
+                button.label = label;
+            }
+        }
+    }
+
+    /// Switch from the Results scene back to the MainGame, restarting the song
+    /// from the beginning with the same SongMap.
+    fn retry(&mut self) {
+        let scene = std::mem::replace(
+            &mut self.current_scene,
+            Scene::LevelSelect(LevelSelect::default()),
+        );
+        self.current_scene = match scene {
+            Scene::Results(results) => {
+                let Results {
+                    mut world,
+                    mut time,
+                    base_folder,
+                    ..
+                } = results;
+                world.restart(&mut time);
+                Scene::MainGame(world, time, base_folder)
+            }
+            scene => scene,
+        };
+    }
+
+    /// Load the focused level and switch to the MainGame.
+    fn start_selected_level(&mut self, ctx: &mut Context) {
+        if let Scene::LevelSelect(level_select) = &self.current_scene {
+            if let Some(level) = level_select.current_level() {
+                let folder = level
+                    .map_folder
+                    .strip_prefix(&self.resource_path)
+                    .ok()
+                    .map(Path::to_path_buf);
+                if let Some(kiosk) = &self.kiosk {
+                    let offered = folder
+                        .as_deref()
+                        .map_or(false, |folder| kiosk.offers(folder));
+                    if !offered {
+                        log::info!("{:?} isn't in the kiosk playlist", level.map_folder);
+                        return;
+                    }
+                }
+                self.save_data.last_level = folder;
+                match level.load_level(&self.resource_path) {
+                    Ok(map) => {
+                        if let Some(kiosk) = &mut self.kiosk {
+                            kiosk.start_session();
+                        }
+                        let mut world = WorldState::new(&level.map_folder, &map);
+                        world.focus_factor = self.save_data.focus_factor;
+                        world.max_darkness = self.save_data.max_darkness;
+                        world.song_speed = self.save_data.song_speed;
+                        world.modifiers = self.save_data.modifiers.clone();
+                        let show_ghost = self
+                            .save_data
+                            .level(&self.resource_path, &level.map_folder)
+                            .map_or(false, |level| level.show_ghost);
+                        if show_ghost {
+                            world.ghost = load_ghost(
+                                &self.profile_folder(),
+                                &self.resource_path,
+                                &level.map_folder,
+                                world.replay.chart_hash,
+                            );
+                        }
+                        if let Err(err) = world.load_meshes(ctx) {
+                            log::warn!("Couldn't cache enemy meshes: {}", err);
+                        }
+                        let mut time = Time::new(map.bpm, Seconds(0.0));
+                        time.set_input_offset(Seconds(self.save_data.input_offset));
+                        self.current_scene = Scene::MainGame(world, time, level.map_folder);
+                    }
+                    Err(err) => log::error!("Couldn't load map: {}", err),
                 }
-                Err(err) => log::warn!("Couldn't load level: {}", err),
             }
         }
-
-        Ok(LevelSelect {
-            levels,
-            current_selection: 0,
-        })
-    }
-
-    fn update(&mut self) {
-        // Nothing...?
     }
 
-    fn draw(&self, ctx: &mut Context, font: Font) -> GameResult<()> {
-        if let Some(level) = self.current_level() {
-            let fragment = TextFragment {
-                text: level.name,
-                color: Some(color::DEBUG_RED),
-                font: Some(font),
-                scale: Some(PxScale::from(18.0)),
-            };
-            let text = Text::new(fragment);
-            let text_height = text.height(ctx) as f32;
-            let screen = graphics::screen_coordinates(ctx);
-            text.draw(
-                ctx,
-                DrawParam::default().dest(Point2 {
-                    x: screen.x,
-                    y: screen.y + screen.h - text_height,
-                }),
-            )?;
+    /// Switch between the LevelSelect and the Options.
+    fn toggle_options(&mut self) {
+        let scene = std::mem::replace(
+            &mut self.current_scene,
+            Scene::LevelSelect(LevelSelect::default()),
+        );
+        self.current_scene = match scene {
+            Scene::LevelSelect(level_select) => Scene::Options(Options::new(
+                &self.save_data,
+                &self.strings,
+                &self.profile,
+                level_select,
+            )),
+            Scene::Options(mut options) => {
+                write_save(&self.save_data, &self.profile_folder());
+                options.level_select.relabel(&self.strings);
+                Scene::LevelSelect(options.level_select)
+            }
+            scene => scene,
+        };
+    }
+
+    /// Switch between the Options and the Browser. Leaving the browser rereads
+    /// the levels folder if any levels were downloaded, so that they show up
+    /// in the level select.
+    fn toggle_browser(&mut self) {
+        let scene = std::mem::replace(
+            &mut self.current_scene,
+            Scene::LevelSelect(LevelSelect::default()),
+        );
+        self.current_scene = match scene {
+            Scene::Options(options) => Scene::Browser(Browser::new(
+                &self.save_data.level_index_url,
+                &self.strings,
+                options,
+            )),
+            Scene::Browser(mut browser) => {
+                let downloaded = browser.downloaded();
+                let mut options = browser.options;
+                if let Some(level) = downloaded.last() {
+                    match LevelSelect::new(&self.resource_path, &self.strings, &self.save_data) {
+                        Ok(mut level_select) => {
+                            level_select.select(level, &self.strings);
+                            options.level_select = level_select;
+                        }
+                        Err(err) => log::warn!("Couldn't reread levels: {}", err),
+                    }
+                }
+                Scene::Options(options)
+            }
+            scene => scene,
+        };
+    }
+
+    /// Switch between the MainGame and the Pause menu. The music and the time
+    /// are frozen while paused.
+    fn toggle_pause(&mut self) {
+        let scene = std::mem::replace(
+            &mut self.current_scene,
+            Scene::LevelSelect(LevelSelect::default()),
+        );
+        self.current_scene = match scene {
+            Scene::MainGame(mut world, mut time, base_folder) => {
+                world.pause();
+                time.pause();
+                Scene::Pause(Pause::new(world, time, base_folder, &self.strings))
+            }
+            Scene::Pause(pause) => {
+                let Pause {
+                    mut world,
+                    mut time,
+                    base_folder,
+                    ..
+                } = pause;
+                time.resume();
+                world.resume();
+                Scene::MainGame(world, time, base_folder)
+            }
+            scene => scene,
+        };
+    }
+
+    /// Abandon the paused run and go back to the level select, with the
+    /// level that was being played selected.
+    fn quit_to_level_select(&mut self) {
+        let scene = std::mem::replace(
+            &mut self.current_scene,
+            Scene::LevelSelect(LevelSelect::default()),
+        );
+        self.current_scene = match scene {
+            Scene::Pause(mut pause) => {
+                log::info!("-- Quit Game --");
+                pause.world.stop_world();
+                write_save(&self.save_data, &self.profile_folder());
+                let mut level_select =
+                    match LevelSelect::new(&self.resource_path, &self.strings, &self.save_data) {
+                        Ok(level_select) => level_select,
+                        Err(err) => {
+                            log::warn!("Couldn't reread levels: {}", err);
+                            LevelSelect::default()
+                        }
+                    };
+                level_select.select(&pause.base_folder, &self.strings);
+                Scene::LevelSelect(level_select)
+            }
+            scene => scene,
+        };
+    }
+
+    /// Switch between the Options and the Calibration. The calibration's
+    /// measurement is only kept if the player confirms it, so leaving just
+    /// rebuilds the options to show the current input offset.
+    fn toggle_calibration(&mut self) {
+        let scene = std::mem::replace(
+            &mut self.current_scene,
+            Scene::LevelSelect(LevelSelect::default()),
+        );
+        self.current_scene = match scene {
+            Scene::Options(options) => Scene::Calibration(Calibration::new(options)),
+            Scene::Calibration(mut calibration) => {
+                calibration.options.rebuild(&self.save_data, &self.strings);
+                Scene::Options(calibration.options)
+            }
+            scene => scene,
+        };
+    }
+
+    fn on_menu_event(&mut self, ctx: &mut Context, event: MenuEvent) {
+        let session_over = self.session_over();
+        match &mut self.current_scene {
+            Scene::LevelSelect(level_select) => match event {
+                MenuEvent::Activated(i) => match level_select.activate(i, &self.strings) {
+                    Some(LevelSelectAction::StartLevel) => self.start_selected_level(ctx),
+                    Some(LevelSelectAction::OpenOptions) if self.locked() => (),
+                    Some(LevelSelectAction::OpenOptions) => self.toggle_options(),
+                    None => (),
+                },
+                MenuEvent::Back => level_select.back(&self.strings),
+                MenuEvent::Changed(_) => (),
+            },
+            Scene::Results(results) => match event {
+                // Once a kiosk session is over, the next visitor gets a turn.
+                MenuEvent::Activated(_) if session_over => (),
+                MenuEvent::Activated(i) if i == results.world.stats.sections.len() => self.retry(),
+                MenuEvent::Activated(i) if i == results.world.stats.sections.len() + 1 => {
+                    self.export_results()
+                }
+                MenuEvent::Activated(_) => self.practice_selected_section(),
+                MenuEvent::Changed(_) | MenuEvent::Back => (),
+            },
+            Scene::Options(options) => match event {
+                MenuEvent::Changed(OPTION_INPUT_OFFSET) => {
+                    if let Some(value) = options.menu.items[OPTION_INPUT_OFFSET].value() {
+                        self.save_data.input_offset = value;
+                    }
+                }
+                MenuEvent::Changed(OPTION_MAX_DARKNESS) => {
+                    if let Some(value) = options.menu.items[OPTION_MAX_DARKNESS].value() {
+                        self.save_data.max_darkness = value as f32;
+                    }
+                }
+                MenuEvent::Changed(i @ OPTION_DEADZONE_X..=OPTION_CURVE_Y) => {
+                    if let Some(value) = options.menu.items[i].value() {
+                        let stick = &mut self.save_data.stick;
+                        let setting = match i {
+                            OPTION_DEADZONE_X => &mut stick.x.deadzone,
+                            OPTION_DEADZONE_Y => &mut stick.y.deadzone,
+                            OPTION_CURVE_X => &mut stick.x.curve,
+                            _ => &mut stick.y.curve,
+                        };
+                        *setting = value as f32;
+                    }
+                }
+                MenuEvent::Activated(OPTION_PROFILE) => {
+                    let profiles = save::profiles(&self.resource_path);
+                    let next = profiles
+                        .iter()
+                        .position(|profile| *profile == self.profile)
+                        .map_or(0, |i| (i + 1) % profiles.len());
+                    self.switch_profile(profiles[next].clone());
+                }
+                MenuEvent::Activated(OPTION_NEW_PROFILE) => {
+                    match save::create_profile(&self.resource_path) {
+                        Ok(profile) => self.switch_profile(profile),
+                        Err(err) => log::warn!("Couldn't create profile: {}", err),
+                    }
+                }
+                MenuEvent::Activated(OPTION_CALIBRATE) => self.toggle_calibration(),
+                MenuEvent::Activated(OPTION_TICK_RATE) => {
+                    self.save_data.cycle_tick_rate();
+                    options.rebuild(&self.save_data, &self.strings);
+                }
+                MenuEvent::Activated(OPTION_LANGUAGE) => {
+                    let languages = lang::available_languages(&self.resource_path);
+                    let next = languages
+                        .iter()
+                        .position(|language| *language == self.save_data.language)
+                        .map_or(0, |i| (i + 1) % languages.len());
+                    if let Some(language) = languages.get(next) {
+                        self.save_data.language = language.clone();
+                        self.strings = load_strings(&self.resource_path, language);
+                        options.rebuild(&self.save_data, &self.strings);
+                    }
+                }
+                MenuEvent::Activated(OPTION_NARRATION) => {
+                    self.save_data.narration = !self.save_data.narration;
+                    self.narration
+                        .set_narrator(narration::narrator(self.save_data.narration));
+                    options.rebuild(&self.save_data, &self.strings);
+                }
+                MenuEvent::Activated(OPTION_FOCUS_MODE) => {
+                    self.save_data.focus_toggle = !self.save_data.focus_toggle;
+                    self.keyboard.focus_toggle = self.save_data.focus_toggle;
+                    options.rebuild(&self.save_data, &self.strings);
+                }
+                MenuEvent::Activated(OPTION_FOCUS_BINDING) => {
+                    self.save_data.focus_binding = self.save_data.focus_binding.next();
+                    self.keyboard
+                        .set_focus_binding(self.save_data.focus_binding);
+                    options.rebuild(&self.save_data, &self.strings);
+                }
+                MenuEvent::Activated(OPTION_FOCUS_FACTOR) => {
+                    self.save_data.cycle_focus_factor();
+                    options.rebuild(&self.save_data, &self.strings);
+                }
+                MenuEvent::Activated(OPTION_SONG_SPEED) => {
+                    self.save_data.song_speed = self.save_data.song_speed.next();
+                    options.rebuild(&self.save_data, &self.strings);
+                }
+                MenuEvent::Activated(i) if (OPTION_MODIFIERS..OPTION_DEADZONE_X).contains(&i) => {
+                    let kind = ModifierKind::ALL[i - OPTION_MODIFIERS];
+                    self.save_data.modifiers.toggle(kind);
+                    options.rebuild(&self.save_data, &self.strings);
+                }
+                MenuEvent::Activated(OPTION_GRAPHICS) => {
+                    self.save_data.graphics_preset = self.save_data.graphics_preset.next();
+                    options.rebuild(&self.save_data, &self.strings);
+                }
+                // Levels open the device when they load, so there is nothing
+                // to reopen here.
+                MenuEvent::Activated(OPTION_AUDIO_DEVICE) => {
+                    self.save_data.audio_device = next_audio_device(audio::device());
+                    audio::set_device(self.save_data.audio_device.clone());
+                    options.rebuild(&self.save_data, &self.strings);
+                }
+                MenuEvent::Activated(OPTION_GET_LEVELS) => self.toggle_browser(),
+                MenuEvent::Activated(OPTION_BACK) | MenuEvent::Back => self.toggle_options(),
+                _ => (),
+            },
+            Scene::Browser(browser) => match event {
+                MenuEvent::Activated(i) if i == browser.listings.len() => self.toggle_browser(),
+                MenuEvent::Activated(i) => browser.download(i, &self.resource_path),
+                MenuEvent::Back => self.toggle_browser(),
+                MenuEvent::Changed(_) => (),
+            },
+            Scene::Pause(pause) => match event {
+                MenuEvent::Activated(PAUSE_RESUME) | MenuEvent::Back => self.toggle_pause(),
+                MenuEvent::Activated(PAUSE_AUDIO_DEVICE) => {
+                    self.save_data.audio_device = next_audio_device(audio::device());
+                    audio::set_device(self.save_data.audio_device.clone());
+                    pause.reopen_audio(&self.strings);
+                }
+                MenuEvent::Activated(PAUSE_RESTART) => {
+                    self.toggle_pause();
+                    if let Scene::MainGame(world, time, _) = &mut self.current_scene {
+                        log::info!("<< Restarted Game >>");
+                        world.restart(time);
+                    }
+                }
+                // Kiosk visitors play until their session is over.
+                MenuEvent::Activated(PAUSE_QUIT) if self.locked() => (),
+                MenuEvent::Activated(PAUSE_QUIT) => self.quit_to_level_select(),
+                _ => (),
+            },
+            Scene::MainGame(..) | Scene::Demo(_) | Scene::Calibration(_) => (),
+        }
+    }
+
+    /// Return the title the window should have. While playing a level, this is
+    /// the game's name, followed by the level's name and the most recent
+    /// section marker, and then any suffix the chart has set.
+    fn window_title(&self) -> String {
+        let (world, base_folder) = match &self.current_scene {
+            Scene::MainGame(world, _, base_folder) => (world, base_folder),
+            Scene::Pause(pause) => (&pause.world, &pause.base_folder),
+            _ => return GAME_NAME.to_string(),
+        };
+        let mut title = format!(
+            "{} - {}",
+            GAME_NAME,
+            level_name(base_folder, world.song_map().title.clone())
+        );
+        if let Some(section) = &world.inner.section {
+            title = format!("{} ({})", title, section);
         }
-        Ok(())
-        // for (i, level) in self.levels.iter().enumerate() {
-        //     let pos = WorldPos {
-        //         x: -20.0,
-        //         y: i as f64 * 10.0 - 25.0,
-        //     };
-        // }
-    }
-
-    fn change_song(&mut self, delta: i32) {
-        let new_selection = delta + self.current_selection as i32;
-        self.current_selection = i32::rem_euclid(new_selection, self.levels.len() as i32) as usize;
+        if let Some(suffix) = &world.inner.title_suffix {
+            title = format!("{} {}", title, suffix);
+        }
+        title
     }
 
-    fn current_level(&self) -> Option<Level> {
-        if self.levels.is_empty() {
-            None
-        } else {
-            Some(self.levels[self.current_selection].clone())
+    /// Scale the UI to match the window's scale factor, if it changed. This is
+    /// polled, since ggez doesn't pass on scale factor change events.
+    fn update_scale_factor(&mut self, ctx: &mut Context) {
+        let scale_factor = ui::scale_factor(ctx);
+        if scale_factor == self.scale_factor {
+            return;
+        }
+        log::info!("Scaling the UI by {}", scale_factor);
+        match ui::set_scale_factor(ctx, scale_factor) {
+            Ok(()) => {
+                self.scale_factor = scale_factor;
+                meshutil::set_zoom(scale_factor);
+            }
+            Err(err) => log::warn!("Couldn't scale the UI by {}: {}", scale_factor, err),
         }
     }
-}
 
-impl Default for LevelSelect {
-    fn default() -> Self {
-        LevelSelect {
-            levels: vec![],
-            current_selection: 0,
+    /// Set the window's title, if it changed.
+    fn update_window_title(&mut self, ctx: &mut Context) {
+        let title = self.window_title();
+        if title != self.window_title {
+            graphics::set_window_title(ctx, &title);
+            self.window_title = title;
         }
     }
-}
 
-#[derive(Clone)]
-pub struct Level {
-    name: String,
-    map_folder: PathBuf,
-}
+    /// Announce the current screen's header and focused menu item, if they
+    /// changed.
+    fn narrate(&mut self) {
+        let header = match &self.current_scene {
+            Scene::Results(results) => Some(results.header.clone()),
+            Scene::Browser(browser) => Some(browser.header.clone()),
+            Scene::Calibration(_) => Some(self.strings.get("calibration.header").to_string()),
+            Scene::Pause(_) => Some(self.strings.get("pause.header").to_string()),
+            _ => None,
+        };
+        let focused = self
+            .current_menu()
+            .and_then(|menu| menu.focused_item())
+            .map(Widget::text);
+        self.narration.update(header.as_deref(), focused.as_deref());
+    }
 
-impl Level {
-    fn new(base_folder: impl AsRef<Path>) -> anyhow::Result<Level> {
-        let base_folder = base_folder.as_ref();
-        if base_folder.is_dir() {
-            Ok(Level {
-                name: base_folder
-                    .file_name()
-                    .unwrap_or_else(|| OsStr::new("No Name"))
-                    .to_string_lossy()
-                    .to_string(),
-                map_folder: base_folder.to_path_buf(),
-            })
-        } else {
-            Err(anyhow::anyhow!("path {:?} is not a folder!", base_folder))
+    fn current_menu(&mut self) -> Option<&mut Menu> {
+        match &mut self.current_scene {
+            Scene::LevelSelect(level_select) => Some(&mut level_select.menu),
+            Scene::Results(results) => Some(&mut results.menu),
+            Scene::Options(options) => Some(&mut options.menu),
+            Scene::Browser(browser) => Some(&mut browser.menu),
+            Scene::Pause(pause) => Some(&mut pause.menu),
+            Scene::MainGame(..) | Scene::Demo(_) | Scene::Calibration(_) => None,
         }
     }
 
-    fn load_level<P: AsRef<Path>>(&self, resource_path: P) -> anyhow::Result<SongMap> {
-        let base_folder = resource_path.as_ref().join(&self.map_folder);
-        let source = std::fs::read(base_folder.join("main.lua"))?;
-        let song_map = SongMap::run_lua(base_folder, &source)?;
-        Ok(song_map)
+    /// Switch from the Results scene back to the MainGame, starting from the
+    /// selected section.
+    fn practice_selected_section(&mut self) {
+        let scene = std::mem::replace(
+            &mut self.current_scene,
+            Scene::LevelSelect(LevelSelect::default()),
+        );
+        self.current_scene = match scene {
+            Scene::Results(results) => {
+                let start = results.selected_section_start();
+                let Results {
+                    mut world,
+                    mut time,
+                    base_folder,
+                    ..
+                } = results;
+                let start = start.unwrap_or(world.stats.sections[0].start);
+                match try_read_map(&base_folder) {
+                    Ok(map) => world.reset_to(&map, &mut time, start),
+                    Err(err) => {
+                        log::warn!("Couldn't load map from path {:?}! {:?}", base_folder, err)
+                    }
+                }
+                Scene::MainGame(world, time, base_folder)
+            }
+            scene => scene,
+        };
     }
 }
 
-pub enum Scene {
-    LevelSelect(LevelSelect),
-    MainGame(WorldState, Time, PathBuf),
-}
-
-struct MainState {
-    current_scene: Scene,
-    keyboard: KeyboardState,
-    assets: Assets,
-    resource_path: PathBuf,
-}
-
-impl MainState {
-    fn new(ctx: &mut Context) -> MainState {
-        // TODO: this is a stupid way to do this, use an actual virtual file system
-        let resource_path = match env::var("CARGO_MANIFEST_DIR") {
-            Ok(manifest_dir) => {
-                let mut path = PathBuf::from(manifest_dir);
-                path.push("resources");
-                path
-            }
-            Err(err) => panic!("{}", err),
-        };
-        MainState {
-            current_scene: Scene::LevelSelect(LevelSelect::new(&resource_path).unwrap_or_default()),
-            keyboard: KeyboardState::default(),
-            assets: Assets::new(ctx),
-            resource_path,
+// Dropping levels onto the window installs them, which lets players install
+// levels without needing to find the levels folder.
+impl DropHandler for MainState {
+    fn file_dropped_event(&mut self, _ctx: &mut Context, path: PathBuf) {
+        self.last_input = Instant::now();
+        if !self.locked() {
+            self.install_level(&path);
         }
     }
 }
 
 impl event::EventHandler<GameError> for MainState {
     fn update(&mut self, ctx: &mut Context) -> GameResult<()> {
-        // Lock the framerate at 60 FPS
-        while timer::check_update_time(ctx, TARGET_FPS) {
-            let physics_delta_time = 1.0 / f64::from(TARGET_FPS);
+        self.frame_start = Instant::now();
+        // Lock the simulation to the tick rate. Since the chart is driven by
+        // the song's time in beats and the player moves based on the time
+        // elapsed, the tick rate only affects how smooth the game is, not what
+        // happens in it.
+        let tick_rate = self.save_data.tick_rate;
+        while timer::check_update_time(ctx, tick_rate) {
+            let physics_delta_time = 1.0 / f64::from(tick_rate);
 
             match &mut self.current_scene {
-                Scene::LevelSelect(level_select) => level_select.update(),
-                Scene::MainGame(world, time, _) => {
+                Scene::LevelSelect(level_select) => level_select.update(&self.strings),
+                Scene::MainGame(world, time, base_folder) => {
                     time.update();
+                    world.sync_time(time);
                     let curr_time = time.get_beats();
-                    world.update(ctx, &self.keyboard, physics_delta_time, curr_time)?
+                    let metronome = ggez::input::keyboard::is_key_pressed(ctx, KeyCode::C);
+                    world.set_metronome(metronome, curr_time);
+                    world.update(ctx, &self.keyboard, physics_delta_time, curr_time)?;
+                    for event in world.events() {
+                        if let GameEvent::PlayerHit { group, pos, time } = *event {
+                            log::info!("Player hit by group {} at beat {:.2}", group, time.0);
+                            self.save_data
+                                .level_mut(&self.resource_path, &base_folder)
+                                .record_hit(HitRecord { pos, beat: time });
+                        }
+                    }
+                }
+                Scene::Browser(browser) => browser.update(&self.strings),
+                Scene::Demo(demo) => demo.update(ctx, physics_delta_time)?,
+                Scene::Calibration(calibration) => calibration.time.update(),
+                Scene::Results(_) | Scene::Options(_) | Scene::Pause(_) => (),
+            }
+
+            if matches!(&self.current_scene, Scene::MainGame(world, ..) if world.finished()) {
+                log::info!("-- Finished Game --");
+                self.show_results();
+            }
+            if matches!(&self.current_scene, Scene::Demo(demo) if demo.finished()) {
+                self.end_demo();
+            }
+            let playing = matches!(self.current_scene, Scene::MainGame(..) | Scene::Pause(_));
+            if playing && self.session_over() {
+                log::info!("-- Kiosk Session Over --");
+                // Unpause first, since the results are shown from the game.
+                if matches!(self.current_scene, Scene::Pause(_)) {
+                    self.toggle_pause();
                 }
+                self.show_results();
+            }
+        }
+
+        let results_over = match &self.kiosk {
+            Some(kiosk) => {
+                matches!(self.current_scene, Scene::Results(_))
+                    && self.last_input.elapsed() >= kiosk.results_length()
             }
+            None => false,
+        };
+        if results_over {
+            self.return_to_attract();
+        }
 
-            ggez::graphics::window(ctx).set_title(&format!("{}", ggez::timer::fps(ctx)));
+        let idle = matches!(self.current_scene, Scene::LevelSelect(_))
+            && self.demo_available
+            && self.last_input.elapsed() >= DEMO_IDLE_TIME;
+        if idle {
+            self.start_demo();
         }
 
+        self.narrate();
+        self.update_window_title(ctx);
+        self.update_scale_factor(ctx);
         Ok(())
     }
 
@@ -517,93 +2180,332 @@ impl event::EventHandler<GameError> for MainState {
         ctx: &mut Context,
         keycode: KeyCode,
         _keymod: KeyMods,
-        _repeat: bool,
+        repeat: bool,
     ) {
-        match &mut self.current_scene {
-            Scene::LevelSelect(level_select) => match keycode {
-                KeyCode::Up | KeyCode::W => {
-                    level_select.change_song(1);
-                }
-                KeyCode::Down | KeyCode::S => {
-                    level_select.change_song(-1);
-                }
-                KeyCode::Space => {
-                    let level = level_select.current_level();
-                    if let Some(level) = level {
-                        match level.load_level(&self.resource_path) {
-                            Ok(map) => {
-                                let world = WorldState::new(&level.map_folder, &map);
-                                let time = Time::new(map.bpm, Seconds(0.0));
-                                self.current_scene = Scene::MainGame(world, time, level.map_folder);
-                            }
-                            Err(err) => log::error!("Couldn't load map: {}", err),
-                        }
+        self.keyboard.update(keycode, true);
+        self.last_input = Instant::now();
+
+        if matches!(self.current_scene, Scene::Demo(_)) {
+            self.end_demo();
+            return;
+        }
+
+        if keycode == KeyCode::F3 {
+            self.show_debug_stats = !self.show_debug_stats;
+            debug_draw::set_enabled(self.show_debug_stats);
+            return;
+        }
+
+        // R is a shortcut for the retry button
+        if keycode == KeyCode::R && matches!(self.current_scene, Scene::Results(_)) {
+            if !self.session_over() {
+                self.retry();
+            }
+            return;
+        }
+
+        if let Scene::Calibration(calibration) = &mut self.current_scene {
+            let pressed = match keycode {
+                KeyCode::Left | KeyCode::A => Some(self.keyboard.left.last_pressed()),
+                KeyCode::Right | KeyCode::D => Some(self.keyboard.right.last_pressed()),
+                KeyCode::Up | KeyCode::W => Some(self.keyboard.up.last_pressed()),
+                KeyCode::Down | KeyCode::S => Some(self.keyboard.down.last_pressed()),
+                _ => None,
+            };
+            match (pressed, keycode) {
+                (Some(instant), _) if !repeat => calibration.tap(instant),
+                (_, KeyCode::Return) => {
+                    if let Some(offset) = calibration.measured_offset() {
+                        log::info!("Calibrated input offset: {:.3}s", offset);
+                        self.save_data.input_offset = offset;
                     }
+                    self.toggle_calibration();
                 }
+                (_, KeyCode::Escape) => self.toggle_calibration(),
                 _ => (),
-            },
-            Scene::MainGame(world, time, base_folder) => {
-                if keycode == KeyCode::P {
-                    if world.started {
-                        log::info!("-- Stopped Game --");
-                        world.stop_world();
-                    } else {
-                        log::info!("++ Started Game ++");
+            }
+            return;
+        }
+
+        if let Some(menu) = self.current_menu() {
+            if let Some(event) = menu.key_down(keycode) {
+                self.on_menu_event(ctx, event);
+            }
+            return;
+        }
+
+        if keycode == KeyCode::Escape
+            && matches!(&self.current_scene, Scene::MainGame(world, ..) if world.started())
+        {
+            self.toggle_pause();
+            return;
+        }
 
-                        match try_read_map(&base_folder) {
-                            Ok(map) => {
-                                if ggez::input::keyboard::is_key_pressed(ctx, KeyCode::R) {
-                                    log::info!("Reloaded music files!");
-                                    *world = WorldState::new(&base_folder, &map);
+        if let Scene::MainGame(world, time, base_folder) = &mut self.current_scene {
+            if keycode == KeyCode::P {
+                if world.started() {
+                    log::info!("-- Stopped Game --");
+                    world.stop_world();
+                    let profile_folder = save::profile_folder(&self.resource_path, &self.profile);
+                    write_save(&self.save_data, &profile_folder);
+                } else {
+                    log::info!("++ Started Game ++");
+
+                    match try_read_map(&base_folder) {
+                        Ok(map) => {
+                            if ggez::input::keyboard::is_key_pressed(ctx, KeyCode::R) {
+                                log::info!("Reloaded music files!");
+                                let ghost = world.ghost.take();
+                                *world = WorldState::new(&base_folder, &map);
+                                world.ghost = ghost;
+                                world.focus_factor = self.save_data.focus_factor;
+                                world.max_darkness = self.save_data.max_darkness;
+                                world.song_speed = self.save_data.song_speed;
+                                world.modifiers = self.save_data.modifiers.clone();
+                                if let Err(err) = world.load_meshes(ctx) {
+                                    log::warn!("Couldn't cache enemy meshes: {}", err);
                                 }
-                                world.start_world(&map, time);
-                            }
-                            Err(err) => {
-                                log::warn!(
-                                    "Couldn't load map from path {:?}! {:?}",
-                                    base_folder,
-                                    err
-                                )
                             }
+                            world.reset_to(&map, time, map.skip_amount);
+                        }
+                        Err(err) => {
+                            log::warn!("Couldn't load map from path {:?}! {:?}", base_folder, err)
+                        }
+                    }
+                }
+            }
+
+            // Restart the song. This only applies while the game is
+            // running, since R is also held while starting the game to
+            // reload the music files.
+            if keycode == KeyCode::R && world.started() {
+                log::info!("<< Restarted Game >>");
+                world.restart(time);
+            }
+
+            // Toggle the pattern preview for the next few measures
+            if keycode == KeyCode::O {
+                if world.preview.is_some() {
+                    world.preview = None;
+                } else {
+                    match try_read_map(&base_folder) {
+                        Ok(map) => {
+                            let start = Beats((time.get_beats().0 / 4.0).floor() * 4.0);
+                            world.preview =
+                                Some(PatternPreview::new(&map, start, start + PREVIEW_LENGTH));
                         }
+                        Err(err) => log::warn!("Couldn't preview map: {:?}", err),
                     }
                 }
+            }
 
-                // Debug spawn
-                if keycode == KeyCode::X {
-                    world.debug = Some(Box::new(crate::enemy::Laser::new_through_points(
-                        world.inner.player.pos,
-                        WorldPos::origin(),
-                        time.get_beats(),
-                        EnemyDurations::default_laser(Beats(16.0)),
-                        &Laser::default_outline_color(),
-                        &Laser::default_outline_keyframes(),
-                    )));
+            // Calibrate the input offset
+            if keycode == KeyCode::Minus || keycode == KeyCode::Equals {
+                let step = if keycode == KeyCode::Minus {
+                    -INPUT_OFFSET_STEP
+                } else {
+                    INPUT_OFFSET_STEP
+                };
+                self.save_data.input_offset += step;
+                time.set_input_offset(Seconds(self.save_data.input_offset));
+                log::info!("Input offset: {:.3}s", self.save_data.input_offset);
+            }
+
+            // Use a bomb
+            if keycode == KeyCode::B && !repeat && world.started() {
+                world.inner.bomb_pending = Some(time.input_beats(Instant::now()));
+            }
+
+            // Toggle the input display
+            if keycode == KeyCode::I {
+                self.save_data.show_input_display = !self.save_data.show_input_display;
+            }
+
+            // Toggle the ghost of the best run on this level
+            if keycode == KeyCode::G {
+                let level = self.save_data.level_mut(&self.resource_path, &base_folder);
+                level.show_ghost = !level.show_ghost;
+                world.ghost = if level.show_ghost {
+                    let profile_folder = save::profile_folder(&self.resource_path, &self.profile);
+                    load_ghost(
+                        &profile_folder,
+                        &self.resource_path,
+                        base_folder,
+                        world.replay.chart_hash,
+                    )
+                } else {
+                    None
+                };
+            }
+
+            // Toggle the heatmap of every past hit on this level
+            if keycode == KeyCode::H {
+                if world.heatmap.is_some() {
+                    world.heatmap = None;
+                } else {
+                    let hits = self
+                        .save_data
+                        .level(&self.resource_path, &base_folder)
+                        .map_or(&[][..], |level| &level.hits);
+                    world.heatmap = Some(HitHeatmap::new(hits));
                 }
             }
+
+            // Debug spawn
+            if keycode == KeyCode::X {
+                world.debug = Some(Box::new(Laser::new_through_points(
+                    world.inner.player.pos,
+                    WorldPos::origin(),
+                    time.get_beats(),
+                    EnemyDurations::default_laser(Beats(16.0)),
+                    &Laser::default_outline_color(),
+                    &Laser::default_outline_keyframes(),
+                    TelegraphStyle::Default,
+                    LaserRender::Flat,
+                )));
+            }
         }
+    }
 
-        self.keyboard.update(keycode, true);
+    fn quit_event(&mut self, _ctx: &mut Context) -> bool {
+        write_save(&self.save_data, &self.profile_folder());
+        // Returning true cancels quitting.
+        self.locked()
     }
 
     fn key_up_event(&mut self, _ctx: &mut Context, keycode: KeyCode, _keymod: KeyMods) {
         self.keyboard.update(keycode, false);
     }
 
+    fn gamepad_axis_event(&mut self, _ctx: &mut Context, axis: Axis, value: f32, _id: GamepadId) {
+        match axis {
+            Axis::LeftStickX => self.stick.x = value,
+            Axis::LeftStickY => self.stick.y = value,
+            _ => return,
+        }
+        let processed = self.save_data.stick.apply(self.stick);
+        self.keyboard.update_stick(processed.x, processed.y);
+        // Resting the stick isn't input, or else a drifting stick would keep
+        // the demo from ever starting.
+        if processed.x != 0.0 || processed.y != 0.0 {
+            self.last_input = Instant::now();
+        }
+    }
+
+    fn gamepad_button_down_event(&mut self, _ctx: &mut Context, button: Button, _id: GamepadId) {
+        self.last_input = Instant::now();
+        if matches!(button, Button::RightTrigger | Button::RightTrigger2) {
+            self.keyboard.update_focus(FocusInput::Trigger, true);
+        }
+        // The left trigger uses a bomb, like B on the keyboard.
+        if let Scene::MainGame(world, time, ..) = &mut self.current_scene {
+            if matches!(button, Button::LeftTrigger | Button::LeftTrigger2) && world.started() {
+                world.inner.bomb_pending = Some(time.input_beats(Instant::now()));
+            }
+        }
+    }
+
+    fn gamepad_button_up_event(&mut self, _ctx: &mut Context, button: Button, _id: GamepadId) {
+        if matches!(button, Button::RightTrigger | Button::RightTrigger2) {
+            self.keyboard.update_focus(FocusInput::Trigger, false);
+        }
+    }
+
+    fn mouse_motion_event(&mut self, ctx: &mut Context, x: f32, y: f32, _dx: f32, _dy: f32) {
+        self.last_input = Instant::now();
+        let pos = ui::to_screen_coords(ctx, x, y);
+        if let Some(menu) = self.current_menu() {
+            menu.mouse_motion(pos.x, pos.y);
+        }
+    }
+
+    fn mouse_button_down_event(&mut self, ctx: &mut Context, button: MouseButton, x: f32, y: f32) {
+        self.last_input = Instant::now();
+        let pos = ui::to_screen_coords(ctx, x, y);
+        if let Some(menu) = self.current_menu() {
+            if let Some(event) = menu.mouse_button_down(button, pos.x, pos.y) {
+                self.on_menu_event(ctx, event);
+            }
+        }
+    }
+
     fn draw(&mut self, ctx: &mut Context) -> GameResult<()> {
         graphics::clear(ctx, ggez::graphics::Color::BLACK);
 
         match &mut self.current_scene {
-            Scene::LevelSelect(level_select) => level_select.draw(ctx, self.assets.debug_font)?,
+            Scene::LevelSelect(level_select) => level_select.draw(ctx, &self.assets.fonts)?,
             Scene::MainGame(world, time, _) => {
                 let curr_time = time.get_beats();
+                queue_debug_world_lines();
                 world.draw(ctx, curr_time)?;
-                draw_debug_world_lines(ctx)?;
-                draw_debug_time(ctx, self.assets.debug_font, world, time)?;
+                let transform = world.inner.view_transform();
+                debug_draw::flush(ctx, self.assets.debug_font, transform)?;
+                if self.show_debug_stats {
+                    let frames = (&self.frame_budget, &self.frame_times);
+                    draw_debug_stats(ctx, self.assets.debug_font, world, time, frames)?;
+                }
                 draw_debug_metronome(ctx, time)?;
+                if self.save_data.show_input_display {
+                    draw_input_displays(ctx, &self.keyboard, world, curr_time)?;
+                }
+                draw_objectives(ctx, &self.assets.fonts, &self.strings, world, curr_time)?;
+                draw_items(ctx, &self.assets.fonts, &self.strings, world)?;
+                if !world.audio_available() {
+                    draw_audio_warning(ctx, &self.assets.fonts, &self.strings)?;
+                }
+            }
+            Scene::Results(results) => results.draw(ctx, &self.assets.fonts)?,
+            Scene::Options(options) => {
+                options.menu.draw(ctx, &self.assets.fonts)?;
+                let position = Point2 {
+                    x: WINDOW_WIDTH - STICK_DISPLAY_SIZE - MENU_POSITION.x,
+                    y: MENU_POSITION.y,
+                };
+                draw_stick_display(ctx, self.stick, &self.save_data.stick, position)?;
+            }
+            Scene::Browser(browser) => browser.draw(ctx, &self.assets.fonts)?,
+            Scene::Demo(demo) => demo.draw(ctx, &self.assets.fonts, &self.strings)?,
+            Scene::Calibration(calibration) => {
+                calibration.draw(ctx, &self.assets.fonts, &self.strings)?
+            }
+            Scene::Pause(pause) => pause.draw(ctx, &self.assets.fonts, &self.strings)?,
+        }
+        // Gizmos are only drawn in game, so anything queued elsewhere is
+        // thrown away rather than piling up.
+        debug_draw::clear();
+
+        #[cfg(feature = "leaderboard")]
+        if let Some(leaderboard) = &mut self.leaderboard {
+            let level_folder = match &self.current_scene {
+                Scene::LevelSelect(level_select) => {
+                    level_select.current_level().map(|level| level.map_folder)
+                }
+                Scene::Results(results) => Some(results.base_folder.clone()),
+                Scene::MainGame(..)
+                | Scene::Options(_)
+                | Scene::Browser(_)
+                | Scene::Demo(_)
+                | Scene::Calibration(_)
+                | Scene::Pause(_) => None,
+            };
+            if let Some(level_folder) = level_folder {
+                leaderboard.draw(
+                    ctx,
+                    &self.assets.fonts,
+                    &self.strings,
+                    &level_folder,
+                    LEADERBOARD_POSITION,
+                )?;
             }
         }
 
+        // The frame time is measured before presenting, since presenting
+        // waits for vsync.
+        self.frame_budget.record(self.frame_start.elapsed());
+        let min_degradation = self.save_data.graphics_preset.min_degradation();
+        budget::set_degradation(self.frame_budget.degradation().max(min_degradation));
+        self.frame_times.record(timer::delta(ctx));
+
         graphics::present(ctx)?;
 
         // if timer::ticks(ctx) % 1000 == 0 {
@@ -621,30 +2523,236 @@ impl event::EventHandler<GameError> for MainState {
     }
 }
 
+/// Load the strings for `language`, falling back to English if the language
+/// can't be loaded, and to the bare keys if English can't be loaded either.
+fn load_strings(resource_path: &Path, language: &str) -> Strings {
+    match Strings::load(resource_path, language) {
+        Ok(strings) => strings,
+        Err(err) if language != lang::DEFAULT_LANGUAGE => {
+            log::warn!(
+                "Couldn't load strings for language {:?}, using {:?}: {}",
+                language,
+                lang::DEFAULT_LANGUAGE,
+                err
+            );
+            load_strings(resource_path, lang::DEFAULT_LANGUAGE)
+        }
+        Err(err) => {
+            log::warn!("Couldn't load strings for language {:?}: {}", language, err);
+            Strings::default()
+        }
+    }
+}
+
+/// Load the save file in `profile_folder`, or start a new save if it can't be
+/// read.
+fn load_save(profile_folder: &Path) -> SaveData {
+    match SaveData::load(profile_folder.join(save::SAVE_FILE)) {
+        Ok(save_data) => save_data,
+        Err(err) => {
+            log::warn!("Couldn't read save file, starting a new one: {}", err);
+            SaveData::default()
+        }
+    }
+}
+
+fn write_save(save_data: &SaveData, profile_folder: &Path) {
+    let path = profile_folder.join(save::SAVE_FILE);
+    if let Err(err) = save_data.save(&path) {
+        log::warn!("Couldn't write save file {:?}: {}", path, err);
+    }
+}
+
+/// Load the current profile's best run of the level in `level_folder`, unless
+/// the chart has changed since it was recorded.
+fn load_ghost(
+    profile_folder: &Path,
+    levels_folder: &Path,
+    level_folder: &Path,
+    chart_hash: u64,
+) -> Option<Replay> {
+    let path = replay::replay_path(profile_folder, levels_folder, level_folder);
+    match Replay::load(&path) {
+        Ok(replay) if replay.chart_hash == chart_hash => Some(replay),
+        Ok(mut replay) => {
+            // Replays recorded under an older kind of chart hash are moved over
+            // to the current one if they are still for the same chart.
+            let legacy = cache::legacy_chart_hashes(level_folder).unwrap_or_default();
+            if !legacy.contains(&replay.chart_hash) {
+                log::info!("Replay {:?} is for an older version of the chart", path);
+                return None;
+            }
+            log::info!("Updating the chart hash of replay {:?}", path);
+            replay.chart_hash = chart_hash;
+            if let Err(err) = replay.save(&path) {
+                log::warn!("Couldn't save replay {:?}: {}", path, err);
+            }
+            Some(replay)
+        }
+        Err(err) => {
+            log::info!("No usable replay at {:?}: {}", path, err);
+            None
+        }
+    }
+}
+
+/// Save `replay` as the current profile's best run of the level in
+/// `level_folder` if it has fewer hits than the current best run. Returns true
+/// if it was saved.
+fn save_if_best(
+    profile_folder: &Path,
+    levels_folder: &Path,
+    level_folder: &Path,
+    replay: &Replay,
+    chart_focus_factor: f64,
+) -> bool {
+    // Runs which weren't played as charted can't be compared with those which
+    // were, so only runs played as charted are kept. They replace a best run
+    // which wasn't (ex: one saved before this was checked) no matter the hits.
+    if !replay.is_as_charted(chart_focus_factor) {
+        return false;
+    }
+    let is_best = load_ghost(
+        profile_folder,
+        levels_folder,
+        level_folder,
+        replay.chart_hash,
+    )
+    .map_or(true, |best| {
+        !best.is_as_charted(chart_focus_factor) || replay.hits < best.hits
+    });
+    if !is_best {
+        return false;
+    }
+    let path = replay::replay_path(profile_folder, levels_folder, level_folder);
+    match replay.save(&path) {
+        Ok(()) => true,
+        Err(err) => {
+            log::warn!("Couldn't save replay {:?}: {}", path, err);
+            false
+        }
+    }
+}
+
 fn try_read_map(base_folder: impl AsRef<Path>) -> anyhow::Result<SongMap> {
-    let source = std::fs::read(base_folder.as_ref().join("main.lua"))?;
-    let songmap = SongMap::run_lua(base_folder, &source)?;
-    Ok(songmap)
+    cache::load_song_map(base_folder)
+}
+
+/// Draw the chart's objectives in the top left corner of the screen.
+fn draw_objectives(
+    ctx: &mut Context,
+    fonts: &FontSet,
+    strings: &Strings,
+    world: &WorldState,
+    curr_time: Beats,
+) -> GameResult<()> {
+    for (i, objective) in world.inner.objectives.iter().enumerate() {
+        let color = match objective.status {
+            ObjectiveStatus::Active => WHITE,
+            ObjectiveStatus::Completed => color::GUIDE_GREY,
+            ObjectiveStatus::Failed => RED,
+        };
+        fonts
+            .text(&objective.hud_text(strings, curr_time), color, 18.0)
+            .draw(
+                ctx,
+                DrawParam::default().dest(Point2 {
+                    x: 20.0,
+                    y: 20.0 + i as f32 * HEADER_LINE_HEIGHT,
+                }),
+            )?;
+    }
+    Ok(())
+}
+
+/// Draw how many bombs and shields the player has in the bottom left corner of
+/// the screen, if they have any.
+fn draw_items(
+    ctx: &mut Context,
+    fonts: &FontSet,
+    strings: &Strings,
+    world: &WorldState,
+) -> GameResult<()> {
+    let player = &world.inner.player;
+    if player.bombs == 0 && player.shields == 0 {
+        return Ok(());
+    }
+    let text = strings.format("hud.items", &[&player.bombs, &player.shields]);
+    fonts.text(&text, WHITE, 18.0).draw(
+        ctx,
+        DrawParam::default().dest(Point2 {
+            x: 20.0,
+            y: WINDOW_HEIGHT - 20.0 - HEADER_LINE_HEIGHT,
+        }),
+    )
+}
+
+/// Draw a warning in the top right corner of the screen that the game is
+/// running without sound.
+fn draw_audio_warning(ctx: &mut Context, fonts: &FontSet, strings: &Strings) -> GameResult<()> {
+    let text = fonts.text(strings.get("audio.unavailable"), RED, 18.0);
+    let x = WINDOW_WIDTH - text.width(ctx) as f32 - 20.0;
+    text.draw(ctx, DrawParam::default().dest(Point2 { x, y: 20.0 }))
+}
+
+/// Draw the player's input display in the bottom right corner of the screen.
+/// If a ghost is shown, the ghost's inputs are drawn to the left of it.
+fn draw_input_displays(
+    ctx: &mut Context,
+    keyboard: &KeyboardState,
+    world: &WorldState,
+    curr_time: Beats,
+) -> GameResult<()> {
+    const MARGIN: f32 = 20.0;
+    let position = Point2 {
+        x: WINDOW_WIDTH - INPUT_DISPLAY_SIZE.x - MARGIN,
+        y: WINDOW_HEIGHT - INPUT_DISPLAY_SIZE.y - MARGIN,
+    };
+    draw_input_display(ctx, keyboard.input_state(), position, WHITE)?;
+
+    let ghost_input = world
+        .ghost
+        .as_ref()
+        .and_then(|ghost| ghost.input_at(curr_time));
+    if let Some(input) = ghost_input {
+        let position = Point2 {
+            x: position.x - INPUT_DISPLAY_SIZE.x - MARGIN,
+            ..position
+        };
+        draw_input_display(ctx, input, position, color::GUIDE_GREY)?;
+    }
+    Ok(())
 }
 
-/// Draw debug text at the bottom of the screen showing the time in the song, in beats.
-fn draw_debug_time(
+/// Draw the debug stats panel in the bottom left corner. The world's stats
+/// are the ones collected during the last update, so nothing but the frame
+/// times is shown until the world has been started.
+fn draw_debug_stats(
     ctx: &mut Context,
     font: Font,
-    world: &mut WorldState,
+    world: &WorldState,
     time: &Time,
+    (frame_budget, frame_times): (&FrameBudget, &FrameTimes),
 ) -> GameResult<()> {
-    let beat_time = time.get_beats();
-    let delta = ggez::timer::delta(ctx);
-    let text = format!(
-        "Measure: {}, Beat: {:.2?}\nPlayer position: {:.2?} ({:.2?}, {:.2?})\nDelta: {:.2?}",
-        (beat_time.0 / 4.0) as i32,
-        beat_time.0,
-        world.inner.player.pos,
-        world.inner.player.pos.as_screen_coords().x,
-        world.inner.player.pos.as_screen_coords().y,
-        delta
-    );
+    let mut lines = vec![
+        format!(
+            "FPS: {:.1}, frame time p50: {:.2?}, p99: {:.2?}",
+            frame_times.fps(),
+            frame_times.percentile(50.0),
+            frame_times.percentile(99.0)
+        ),
+        format!(
+            "CPU time per frame: {:.2?} (budget {:.2?}), degraded: {}",
+            frame_budget.average_frame_time(),
+            budget::FRAME_BUDGET,
+            frame_budget.degradation().description()
+        ),
+        format!("Input offset: {:.3}s", time.input_offset().0),
+    ];
+    if let Some(stats) = &world.debug_stats {
+        lines.extend(stats.lines());
+    }
+    let text = lines.join("\n");
 
     let fragment = TextFragment {
         text,
@@ -665,40 +2773,30 @@ fn draw_debug_time(
     Ok(())
 }
 
-fn draw_debug_world_lines(ctx: &mut Context) -> Result<(), GameError> {
-    let origin = WorldPos::origin().as_screen_coords_cg();
-    Mesh::new_line(
-        ctx,
-        &[
-            util::into_mint(origin + cg::Vector2::new(-5.0, 0.0)),
-            util::into_mint(origin + cg::Vector2::new(5.0, 0.0)),
-        ],
-        2.0,
-        crate::color::DEBUG_RED,
-    )?
-    .draw(ctx, DrawParam::default())?;
-    Mesh::new_line(
-        ctx,
-        &[
-            util::into_mint(origin + cg::Vector2::new(0.0, 5.0)),
-            util::into_mint(origin + cg::Vector2::new(0.0, -5.0)),
-        ],
-        2.0,
-        crate::color::DEBUG_RED,
-    )?
-    .draw(ctx, DrawParam::default())?;
-
-    let rect = WorldPos::as_screen_rect(WorldPos::origin(), WorldLen(100.0), WorldLen(100.0));
-    Mesh::new_rectangle(ctx, DrawMode::stroke(2.0), rect, crate::color::DEBUG_RED)?
-        .draw(ctx, DrawParam::default())?;
-
-    let rect = WorldPos::as_screen_rect(WorldPos::origin(), WorldLen(10.0), WorldLen(10.0));
-    Mesh::new_rectangle(ctx, DrawMode::stroke(2.0), rect, crate::color::DEBUG_RED)?
-        .draw(ctx, DrawParam::default())?;
-
-    Ok(())
+/// Queue a small cross at the origin of the world, with squares 10 and 100
+/// units wide around it.
+fn queue_debug_world_lines() {
+    let point = |x: f64, y: f64| WorldPos { x, y };
+    // The cross is 10 pixels wide.
+    let cross = 5.0 / WORLD_SCALE_FACTOR as f64;
+    debug_draw::line(point(-cross, 0.0), point(cross, 0.0), color::DEBUG_RED);
+    debug_draw::line(point(0.0, -cross), point(0.0, cross), color::DEBUG_RED);
+    for &half_size in &[50.0, 5.0] {
+        let corners = [
+            point(-half_size, -half_size),
+            point(half_size, -half_size),
+            point(half_size, half_size),
+            point(-half_size, half_size),
+        ];
+        for (i, &corner) in corners.iter().enumerate() {
+            let next = corners[(i + 1) % corners.len()];
+            debug_draw::line(corner, next, color::DEBUG_RED);
+        }
+    }
 }
 
+/// While C is held, flash a square on each beat of the measure. The world
+/// clicks along with it (see `WorldState::set_metronome`).
 fn draw_debug_metronome(ctx: &mut Context, time: &Time) -> Result<(), GameError> {
     if ggez::input::keyboard::is_key_pressed(ctx, KeyCode::C) {
         let curr_time = time.get_beats();
@@ -719,12 +2817,127 @@ fn draw_debug_metronome(ctx: &mut Context, time: &Time) -> Result<(), GameError>
     Ok(())
 }
 
-pub fn main() {
+fn resource_path() -> PathBuf {
+    // TODO: this is a stupid way to do this, use an actual virtual file system
+    match env::var("CARGO_MANIFEST_DIR") {
+        Ok(manifest_dir) => {
+            let mut path = PathBuf::from(manifest_dir);
+            path.push("resources");
+            path
+        }
+        Err(err) => panic!("{}", err),
+    }
+}
+
+/// Run a command line tool instead of the game, if one was requested.
+/// Returns true if a command was run.
+fn run_command(args: &[String]) -> bool {
+    match args.get(1).map(String::as_str) {
+        Some("test") => {
+            // Flags set the instruction budget and the resource ceilings,
+            // and the first other argument is the levels folder.
+            let (flags, rest): (Vec<&String>, Vec<&String>) =
+                args[2..].iter().partition(|arg| arg.starts_with("--"));
+            let mut ceilings = ResourceCeilings::default();
+            for flag in flags {
+                if let Some(budget) = flag.strip_prefix("--instruction-budget=") {
+                    match budget.parse() {
+                        Ok(budget) => parse::set_instruction_budget(budget),
+                        Err(err) => {
+                            eprintln!("Invalid instruction budget {:?}: {}", budget, err);
+                            std::process::exit(1);
+                        }
+                    }
+                } else if let Err(err) = ceilings.set_from_flag(flag) {
+                    eprintln!("{}", err);
+                    std::process::exit(1);
+                }
+            }
+            let levels_folder = rest
+                .first()
+                .map_or_else(resource_path, |path| PathBuf::from(path.as_str()));
+            match rthm::check::check_levels(&levels_folder, &ceilings) {
+                Ok(reports) => {
+                    for report in &reports {
+                        report.print();
+                    }
+                    if !reports.iter().all(|report| report.passed()) {
+                        std::process::exit(1);
+                    }
+                }
+                Err(err) => {
+                    eprintln!("Couldn't read levels from {:?}: {}", levels_folder, err);
+                    std::process::exit(1);
+                }
+            }
+            true
+        }
+        Some("install") => {
+            if args.len() < 3 {
+                eprintln!("Usage: rthm install <level folder or archive>...");
+            }
+            install_levels(&args[2..]);
+            true
+        }
+        Some("capture") => {
+            if args.len() < 5 {
+                eprintln!(
+                    "Usage: rthm capture <level folder> <start beat> <end beat> [output.gif]"
+                );
+                std::process::exit(1);
+            }
+            if let Err(err) = capture_pattern(&args[2..]) {
+                eprintln!("Couldn't capture pattern: {}", err);
+                std::process::exit(1);
+            }
+            true
+        }
+        Some("analyze") => {
+            match args.get(2) {
+                Some(path) => match rthm::analyze::estimate_tempo(path) {
+                    Ok(estimate) => estimate.print_suggestion(),
+                    Err(err) => eprintln!("Couldn't analyze {:?}: {}", path, err),
+                },
+                None => eprintln!("Usage: rthm analyze <audio file>"),
+            }
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Render the given beat range of a level to a GIF. `args` are the level
+/// folder, the start and end beats, and optionally the output path, which
+/// defaults to "capture.gif".
+fn capture_pattern(args: &[String]) -> anyhow::Result<()> {
+    let level_folder = PathBuf::from(&args[0]);
+    let start = Beats(args[1].parse()?);
+    let end = Beats(args[2].parse()?);
+    let out_path = args.get(3).map_or("capture.gif", String::as_str);
+    let map = cache::load_song_map(&level_folder)?;
+
+    // The capture is drawn offscreen, but ggez still needs a window to draw
+    // with, so it is kept hidden.
+    let (mut ctx, _events_loop) = context_builder(GraphicsPreset::High)
+        .window_mode(
+            conf::WindowMode::default()
+                .dimensions(WINDOW_WIDTH, WINDOW_HEIGHT)
+                .visible(false),
+        )
+        .build()?;
+    let frames = rthm::capture::capture_gif(&mut ctx, &level_folder, &map, start, end, out_path)?;
+    println!("Wrote {} frames to {}", frames, out_path);
+    Ok(())
+}
+
+/// Return the ContextBuilder for the game's window, with the resources path
+/// added if the game was built with cargo.
+fn context_builder(preset: GraphicsPreset) -> ContextBuilder {
     let mut cb = ContextBuilder::new("visual", "a2aaron")
         .window_setup(
             conf::WindowSetup::default()
-                .title("ʀᴛʜᴍ")
-                .samples(ggez::conf::NumSamples::Eight)
+                .title(GAME_NAME)
+                .samples(preset.samples())
                 .vsync(true),
         )
         .window_mode(conf::WindowMode::default().dimensions(WINDOW_WIDTH, WINDOW_HEIGHT));
@@ -740,7 +2953,14 @@ pub fn main() {
     } else {
         log::warn!("Not building from cargo");
     }
+    cb
+}
 
+pub fn main() {
+    let args: Vec<String> = env::args().collect();
+    if run_command(&args) {
+        return;
+    }
     // gfx_device_gl ends up spamming the log with Info messages.
     simple_logger::SimpleLogger::new()
         .with_level(log::LevelFilter::Info)
@@ -748,7 +2968,13 @@ pub fn main() {
         .init()
         .unwrap();
 
+    // The multisampling can only be set when the window is created, so the
+    // graphics preset is read from the save file before anything else.
+    let resource_path = resource_path();
+    let profile = save::current_profile(&resource_path);
+    let preset = load_save(&save::profile_folder(&resource_path, &profile)).graphics_preset;
+    let cb = context_builder(preset);
     let (mut ctx, events_loop) = cb.build().unwrap();
     let state = MainState::new(&mut ctx);
-    ggez::event::run(ctx, events_loop, state);
+    event_loop::run(ctx, events_loop, state);
 }
@@ -0,0 +1,53 @@
+/// This module implements medals, which charts hand out for finishing a run
+/// with few enough hits, or with enough grazes. Charts list their medals with a
+/// `medals` entry, like `{ medals = { { name = "No hit", max_hits = 0 }, {
+/// name = "Bronze", max_hits = 10 }, { name = "Full graze", min_grazes = 120 }
+/// } }`. A medal with both `max_hits` and `min_grazes` needs both to be met.
+/// Medals are only earned on full runs (not when practicing a section), and
+/// once earned are kept in the save file.
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Medal {
+    pub name: String,
+    /// The most hits a run may have and still earn the medal, if limited.
+    pub max_hits: Option<usize>,
+    /// The fewest grazes a run may have and still earn the medal, if limited.
+    pub min_grazes: Option<usize>,
+}
+
+impl Medal {
+    pub fn is_earned(&self, hits: usize, grazes: usize) -> bool {
+        self.max_hits.map_or(true, |max_hits| hits <= max_hits)
+            && self
+                .min_grazes
+                .map_or(true, |min_grazes| grazes >= min_grazes)
+    }
+}
+
+/// Return the medals earned by a run with `hits` hits and `grazes` grazes.
+pub fn earned(medals: &[Medal], hits: usize, grazes: usize) -> impl Iterator<Item = &Medal> {
+    medals
+        .iter()
+        .filter(move |medal| medal.is_earned(hits, grazes))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_medals_need_every_limit_met() {
+        let medal = |max_hits, min_grazes| Medal {
+            name: String::new(),
+            max_hits,
+            min_grazes,
+        };
+        assert!(medal(Some(0), None).is_earned(0, 0));
+        assert!(!medal(Some(0), None).is_earned(1, 50));
+        assert!(medal(None, Some(10)).is_earned(5, 10));
+        assert!(!medal(None, Some(10)).is_earned(0, 9));
+        assert!(!medal(Some(2), Some(10)).is_earned(3, 10));
+        assert!(medal(Some(2), Some(10)).is_earned(2, 10));
+    }
+}
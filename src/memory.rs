@@ -0,0 +1,147 @@
+/// This module implements counters for the things which pile up over a long
+/// session, such as live enemies, particles, queued actions and cached meshes.
+/// The counters are shown in the debug overlay, and the `test` subcommand
+/// checks them against ceilings while simulating each level, which catches
+/// groups that never clear (ex: a chart which spawns bullets that never die).
+/// The `test` subcommand never draws, so it always counts zero cached meshes.
+use crate::{meshcache, parse, InnerWorldState, Scheduler};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ResourceCounts {
+    /// The number of enemies in every group, including ones which haven't
+    /// spawned yet.
+    pub live_enemies: usize,
+    /// The number of obstacles, force fields, teleporters, and pickups.
+    pub world_objects: usize,
+    /// The number of particles. See `particle`.
+    pub particles: usize,
+    /// The number of actions which haven't been performed yet.
+    pub queued_actions: usize,
+    /// The number of parsed MIDI files kept in the MIDI cache.
+    pub cached_midi_files: usize,
+    /// The number of enemy meshes kept in the world's mesh cache. See
+    /// `meshcache`.
+    pub cached_meshes: usize,
+}
+
+impl ResourceCounts {
+    pub fn of(world: &InnerWorldState, scheduler: &Scheduler) -> ResourceCounts {
+        ResourceCounts {
+            live_enemies: world.groups.iter().map(|group| group.enemies.len()).sum(),
+            world_objects: world.obstacles.len()
+                + world.force_fields.len()
+                + world.teleporters.len()
+                + world.pickups.len(),
+            particles: world.particles.len(),
+            queued_actions: scheduler.len(),
+            cached_midi_files: parse::midi_cache_len(),
+            // The mesh cache belongs to the WorldState. See
+            // `WorldState::resource_counts`.
+            cached_meshes: 0,
+        }
+    }
+
+    /// Return the larger of each count.
+    pub fn max(self, other: ResourceCounts) -> ResourceCounts {
+        ResourceCounts {
+            live_enemies: self.live_enemies.max(other.live_enemies),
+            world_objects: self.world_objects.max(other.world_objects),
+            particles: self.particles.max(other.particles),
+            queued_actions: self.queued_actions.max(other.queued_actions),
+            cached_midi_files: self.cached_midi_files.max(other.cached_midi_files),
+            cached_meshes: self.cached_meshes.max(other.cached_meshes),
+        }
+    }
+
+    fn named(&self) -> [(&'static str, usize); 6] {
+        [
+            ("live enemies", self.live_enemies),
+            ("world objects", self.world_objects),
+            ("particles", self.particles),
+            ("queued actions", self.queued_actions),
+            ("cached MIDI files", self.cached_midi_files),
+            ("cached meshes", self.cached_meshes),
+        ]
+    }
+}
+
+/// The most of each resource a level may use at once before it is reported as
+/// a probable leak. The `test` subcommand lets each ceiling be overridden with
+/// flags like `--max-live-enemies=5000`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResourceCeilings(pub ResourceCounts);
+
+impl Default for ResourceCeilings {
+    fn default() -> Self {
+        ResourceCeilings(ResourceCounts {
+            live_enemies: 2000,
+            world_objects: 200,
+            particles: 2000,
+            queued_actions: 100_000,
+            cached_midi_files: 256,
+            cached_meshes: meshcache::MAX_MESHES,
+        })
+    }
+}
+
+impl ResourceCeilings {
+    /// Set the ceiling from a flag of the form `--max-<counter>=<value>`, where
+    /// the counter's name uses dashes instead of spaces. Returns an error if
+    /// the flag isn't a ceiling or the value isn't a number.
+    pub fn set_from_flag(&mut self, flag: &str) -> anyhow::Result<()> {
+        let (name, value) = flag
+            .strip_prefix("--max-")
+            .and_then(|flag| flag.split_once('='))
+            .ok_or_else(|| anyhow::anyhow!("Expected --max-<counter>=<value>, got {:?}", flag))?;
+        let value: usize = value.parse()?;
+        let counts = &mut self.0;
+        match name {
+            "live-enemies" => counts.live_enemies = value,
+            "world-objects" => counts.world_objects = value,
+            "particles" => counts.particles = value,
+            "queued-actions" => counts.queued_actions = value,
+            "cached-midi-files" => counts.cached_midi_files = value,
+            "cached-meshes" => counts.cached_meshes = value,
+            _ => anyhow::bail!("Unknown counter {:?}", name),
+        }
+        Ok(())
+    }
+
+    /// Return a message for each count which is above its ceiling.
+    pub fn exceeded(&self, counts: &ResourceCounts) -> Vec<String> {
+        counts
+            .named()
+            .iter()
+            .zip(self.0.named().iter())
+            .filter(|((_, count), (_, ceiling))| count > ceiling)
+            .map(|((name, count), (_, ceiling))| {
+                format!(
+                    "{} peaked at {}, above the ceiling of {}",
+                    name, count, ceiling
+                )
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_ceiling_flags() {
+        let mut ceilings = ResourceCeilings::default();
+        ceilings.set_from_flag("--max-live-enemies=10").unwrap();
+        assert_eq!(ceilings.0.live_enemies, 10);
+        ceilings.set_from_flag("--max-particles=20").unwrap();
+        assert_eq!(ceilings.0.particles, 20);
+        assert!(ceilings.set_from_flag("--max-meshes=10").is_err());
+        assert!(ceilings.set_from_flag("--max-live-enemies=lots").is_err());
+
+        let counts = ResourceCounts {
+            live_enemies: 11,
+            ..Default::default()
+        };
+        assert_eq!(ceilings.exceeded(&counts).len(), 1);
+    }
+}
@@ -0,0 +1,83 @@
+/// This module implements the mesh cache, which keeps enemy meshes around so
+/// that they don't have to be tessellated again every frame. Most enemies look
+/// the same at the same point in each phase of their lifetime, no matter where
+/// they are or when they spawned, since their meshes are built around the
+/// origin and moved into place when drawn. Those enemies have a shape (see
+/// `EnemyImpl::shape`), and their meshes are cached by shape, phase, and which
+/// of `FRAMES_PER_PHASE` frames of the phase they are in. The cache is filled
+/// while a level loads (see `WorldState::load_meshes`), so that the first time
+/// each shape shows up doesn't cause a hitch, and anything missed is cached the
+/// first time it is drawn.
+use std::collections::HashMap;
+
+use ggez::graphics::Mesh;
+use ggez::GameResult;
+
+use crate::budget;
+use crate::enemy::{EnemyLifetime, EnemyShape};
+use crate::meshutil;
+
+/// How many different meshes each phase of an enemy's lifetime is drawn with.
+/// Cached enemies animate at this many frames per phase, so this needs to be
+/// high enough for the longest phases to still look smooth.
+pub const FRAMES_PER_PHASE: u32 = 48;
+/// The most meshes which are cached at once. Meshes which don't fit are built
+/// every frame instead, the same way uncached enemies are.
+pub const MAX_MESHES: usize = 8192;
+
+/// Which cached mesh an enemy is drawn with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MeshKey {
+    pub shape: EnemyShape,
+    pub lifetime: EnemyLifetime,
+    /// Which of the `FRAMES_PER_PHASE` frames of the phase this is.
+    pub frame: u32,
+}
+
+#[derive(Debug, Default)]
+pub struct MeshCache {
+    meshes: HashMap<MeshKey, Mesh>,
+    /// The zoom and tolerance factor the meshes were tessellated with, as the
+    /// bits of their f32s. See `meshutil::tolerance`.
+    tessellation: (u32, u32),
+}
+
+impl MeshCache {
+    pub fn new() -> MeshCache {
+        MeshCache::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.meshes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.meshes.is_empty()
+    }
+
+    /// Return the mesh for `key`, building it with `build` if it isn't cached.
+    /// Meshes are tessellated for the current zoom and frame budget, so the
+    /// cache is cleared whenever either changes the tessellation.
+    pub fn get_or_build(
+        &mut self,
+        key: MeshKey,
+        build: impl FnOnce() -> GameResult<Mesh>,
+    ) -> GameResult<Mesh> {
+        let tessellation = (
+            meshutil::zoom().to_bits(),
+            budget::degradation().tolerance_factor().to_bits(),
+        );
+        if tessellation != self.tessellation {
+            self.meshes.clear();
+            self.tessellation = tessellation;
+        }
+        if let Some(mesh) = self.meshes.get(&key) {
+            return Ok(mesh.clone());
+        }
+        let mesh = build()?;
+        if self.meshes.len() < MAX_MESHES {
+            self.meshes.insert(key, mesh.clone());
+        }
+        Ok(mesh)
+    }
+}
@@ -0,0 +1,182 @@
+/// This module has helpers for building meshes of common shapes. The helpers
+/// take sizes the same way the sdfs do (ex: a line's thickness is measured
+/// from its center to its edge), so that what is drawn matches the hitbox.
+/// Curves are tessellated finely enough to look smooth at their size on
+/// screen. Since enemy meshes are built in world units and scaled up when
+/// drawn, helpers which tessellate take the `scale` the mesh is drawn at
+/// (ex: WORLD_SCALE_FACTOR for enemies, or 1.0 for meshes built in screen
+/// coordinates). Screen coordinates are zoomed by the window's scale factor
+/// (see `set_zoom`), which is applied on top of `scale`, so that curves stay
+/// smooth on large windows without being over-tessellated on small ones.
+/// Edges are smoothed by the window's multisampling.
+use std::f32::consts::{FRAC_PI_2, PI};
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use ggez::graphics::mint::Point2;
+use ggez::graphics::{Color, DrawMode, MeshBuilder, Rect};
+use ggez::GameResult;
+
+use crate::budget;
+
+/// The furthest a tessellated curve may stray from the true curve, in pixels.
+const SCREEN_TOLERANCE: f32 = 0.25;
+/// The furthest a tessellated curve may stray from the true curve, as a
+/// fraction of the curve's radius. This keeps tiny curves from looking like
+/// polygons.
+const RELATIVE_TOLERANCE: f32 = 0.01;
+/// The smallest tolerance ever used, which keeps degenerate curves from
+/// being split into an enormous number of segments.
+const MIN_TOLERANCE: f32 = 0.001;
+
+/// The bits of the f32 zoom. See `set_zoom`.
+static ZOOM: AtomicU32 = AtomicU32::new(0x3f80_0000); // 1.0
+
+/// Set how many pixels of the window each unit of screen coordinates covers.
+/// This is the window's scale factor, since the screen coordinates stay the
+/// same size when the window is scaled.
+pub fn set_zoom(zoom: f32) {
+    ZOOM.store(zoom.to_bits(), Ordering::Relaxed);
+}
+
+pub fn zoom() -> f32 {
+    f32::from_bits(ZOOM.load(Ordering::Relaxed))
+}
+
+/// Return the tessellation tolerance for a curve of the given radius, in the
+/// mesh's units, for a mesh drawn at `scale` screen units per mesh unit. The
+/// tolerance is based on the curve's radius in pixels, after the zoom. This is
+/// coarser while the frame budget is degrading meshes.
+pub fn tolerance(radius: f32, scale: f32) -> f32 {
+    let pixels_per_unit = scale.abs() * zoom();
+    let screen = SCREEN_TOLERANCE / pixels_per_unit.max(f32::EPSILON);
+    let tolerance = screen.min(radius * RELATIVE_TOLERANCE);
+    (tolerance * budget::degradation().tolerance_factor()).max(MIN_TOLERANCE)
+}
+
+/// Add a circle to the mesh, tessellated for its size on screen.
+pub fn circle(
+    mesh: &mut MeshBuilder,
+    mode: DrawMode,
+    center: Point2<f32>,
+    radius: f32,
+    scale: f32,
+    color: Color,
+) -> GameResult<()> {
+    mesh.circle(mode, center, radius, tolerance(radius, scale), color)?;
+    Ok(())
+}
+
+/// Add a line to the mesh which extends `thickness` out from either side of
+/// `points`, with square ends.
+pub fn thick_line(
+    mesh: &mut MeshBuilder,
+    points: &[Point2<f32>],
+    thickness: f32,
+    color: Color,
+) -> GameResult<()> {
+    // ggez measures line widths from edge to edge.
+    mesh.line(points, thickness * 2.0, color)?;
+    Ok(())
+}
+
+/// Add a capsule to the mesh, which is every point within `radius` of the
+/// line segment from `a` to `b`. This is the shape of a line with rounded
+/// ends.
+pub fn capsule(
+    mesh: &mut MeshBuilder,
+    a: Point2<f32>,
+    b: Point2<f32>,
+    radius: f32,
+    scale: f32,
+    color: Color,
+) -> GameResult<()> {
+    // A capsule with no radius is just a (degenerate) line. This still adds
+    // vertices, since building an empty mesh is an error.
+    if radius <= 0.0 {
+        return thick_line(mesh, &[a, b], 0.0, color);
+    }
+    let angle = (b.y - a.y).atan2(b.x - a.x);
+    let mut points = arc(b, radius, angle - FRAC_PI_2, angle + FRAC_PI_2, scale);
+    points.extend(arc(
+        a,
+        radius,
+        angle + FRAC_PI_2,
+        angle + 3.0 * FRAC_PI_2,
+        scale,
+    ));
+    mesh.polygon(DrawMode::fill(), &points, color)?;
+    Ok(())
+}
+
+/// Add a rectangle with rounded corners to the mesh. The corner radius is
+/// clamped so that it never exceeds half of the rectangle's width or height.
+pub fn rounded_rect(
+    mesh: &mut MeshBuilder,
+    mode: DrawMode,
+    rect: Rect,
+    radius: f32,
+    scale: f32,
+    color: Color,
+) -> GameResult<()> {
+    let radius = radius.min(rect.w / 2.0).min(rect.h / 2.0);
+    if radius <= 0.0 {
+        mesh.rectangle(mode, rect, color)?;
+        return Ok(());
+    }
+    let corner = |x: f32, y: f32| Point2 { x, y };
+    let (left, right) = (rect.x + radius, rect.x + rect.w - radius);
+    let (top, bottom) = (rect.y + radius, rect.y + rect.h - radius);
+    let mut points = arc(corner(right, bottom), radius, 0.0, FRAC_PI_2, scale);
+    points.extend(arc(corner(left, bottom), radius, FRAC_PI_2, PI, scale));
+    points.extend(arc(corner(left, top), radius, PI, 3.0 * FRAC_PI_2, scale));
+    points.extend(arc(
+        corner(right, top),
+        radius,
+        3.0 * FRAC_PI_2,
+        2.0 * PI,
+        scale,
+    ));
+    mesh.polygon(mode, &points, color)?;
+    Ok(())
+}
+
+// Return points along the arc of the given circle from `start` to `end`
+// radians, including both ends.
+fn arc(center: Point2<f32>, radius: f32, start: f32, end: f32, scale: f32) -> Vec<Point2<f32>> {
+    let segments = segments_for(radius, (end - start).abs(), scale);
+    (0..=segments)
+        .map(|i| {
+            let angle = start + (end - start) * (i as f32 / segments as f32);
+            Point2 {
+                x: center.x + radius * angle.cos(),
+                y: center.y + radius * angle.sin(),
+            }
+        })
+        .collect()
+}
+
+// Return how many segments an arc spanning `sweep` radians needs to stay
+// within tolerance.
+fn segments_for(radius: f32, sweep: f32, scale: f32) -> usize {
+    let tolerance = tolerance(radius, scale).min(radius);
+    // Each segment may span at most this angle. See lyon's `flattening_step`.
+    let step = 2.0 * (1.0 - tolerance / radius).acos();
+    if step.is_finite() && step > 0.0 {
+        ((sweep / step).ceil() as usize).max(1)
+    } else {
+        1
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_small_curves_get_enough_segments() {
+        // A bullet a few pixels across should still look round.
+        assert!(segments_for(0.5, 2.0 * PI, 6.0) >= 16);
+        // A huge circle needs more segments than a small one.
+        assert!(segments_for(50.0, 2.0 * PI, 6.0) > segments_for(0.5, 2.0 * PI, 6.0));
+    }
+}
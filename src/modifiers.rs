@@ -0,0 +1,255 @@
+/// This module implements modifiers, which the player can turn on before a
+/// song to change how it plays. Each modifier hooks into some of rendering,
+/// input and scoring through the Modifier trait:
+/// - Hidden fades enemies out once their hitboxes turn on, so that they have
+///   to be remembered from their warmups.
+/// - Flashlight darkens everything except for a small circle around the
+///   player.
+/// - Mirror flips the playfield horizontally, along with the player's input,
+///   so every pattern comes from the other side.
+/// - Sudden death fails the run on the first hit.
+/// The modifiers a run was played with are recorded in its stats and replay,
+/// since runs with different modifiers aren't comparable.
+use ggez::{Context, GameResult};
+use serde::{Deserialize, Serialize};
+
+use crate::enemy::Enemy;
+use crate::lang::Strings;
+use crate::stats::RunStats;
+use crate::time::Beats;
+use crate::vision;
+use crate::world::{ScreenTransform, WorldLen, WorldPos};
+
+/// How long Hidden takes to fade enemies out after they become Active. Enemies
+/// which are Active for less time than this fade out over their Active phase.
+const HIDDEN_FADE: Beats = Beats(0.5);
+/// The radius of the circle Flashlight leaves undarkened.
+const FLASHLIGHT_RADIUS: WorldLen = WorldLen(12.0);
+/// The opacity of the darkness drawn by Flashlight.
+const FLASHLIGHT_DARKNESS: f32 = 0.97;
+
+/// A change to how a song plays. Every hook defaults to doing nothing.
+pub trait Modifier {
+    /// Return how much to multiply the enemy's opacity by.
+    fn enemy_opacity(&self, _enemy: &dyn Enemy, _curr_time: Beats) -> f32 {
+        1.0
+    }
+
+    /// Draw on top of the enemies, underneath the player. This is drawn in the
+    /// playfield's coordinates. Darkness must be no more opaque than
+    /// `max_darkness` (see `WorldState::max_darkness`).
+    fn draw_overlay(
+        &self,
+        _ctx: &mut Context,
+        _player: WorldPos,
+        _max_darkness: f32,
+    ) -> GameResult<()> {
+        Ok(())
+    }
+
+    /// Return the transform to draw the playfield with, given the chart's own
+    /// transform. The player's input is transformed to match, so that the
+    /// player moves where they are told to move on screen.
+    fn screen_transform(&self, transform: ScreenTransform) -> ScreenTransform {
+        transform
+    }
+
+    /// Returns true if the run has failed.
+    fn failed(&self, _stats: &RunStats) -> bool {
+        false
+    }
+}
+
+pub struct Hidden;
+
+impl Modifier for Hidden {
+    fn enemy_opacity(&self, enemy: &dyn Enemy, curr_time: Beats) -> f32 {
+        let (active_start, active_end) = enemy.active_range();
+        let fade = f64::min(HIDDEN_FADE.0, (active_end - active_start).0);
+        if fade <= 0.0 {
+            // Enemies which are never Active never fade.
+            return 1.0;
+        }
+        (1.0 - (curr_time - active_start).0 / fade).clamp(0.0, 1.0) as f32
+    }
+}
+
+pub struct Flashlight;
+
+impl Modifier for Flashlight {
+    fn draw_overlay(
+        &self,
+        ctx: &mut Context,
+        player: WorldPos,
+        max_darkness: f32,
+    ) -> GameResult<()> {
+        let opacity = FLASHLIGHT_DARKNESS.min(max_darkness);
+        vision::draw_darkness(ctx, player, FLASHLIGHT_RADIUS, opacity)
+    }
+}
+
+pub struct Mirror;
+
+impl Modifier for Mirror {
+    fn screen_transform(&self, transform: ScreenTransform) -> ScreenTransform {
+        transform.mirrored()
+    }
+}
+
+pub struct SuddenDeath;
+
+impl Modifier for SuddenDeath {
+    fn failed(&self, stats: &RunStats) -> bool {
+        stats.total_hits() > 0
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum ModifierKind {
+    Hidden,
+    Flashlight,
+    Mirror,
+    SuddenDeath,
+}
+
+impl ModifierKind {
+    /// Every modifier, in the order they are listed in the options.
+    pub const ALL: [ModifierKind; 4] = [
+        ModifierKind::Hidden,
+        ModifierKind::Flashlight,
+        ModifierKind::Mirror,
+        ModifierKind::SuddenDeath,
+    ];
+
+    /// The key of the modifier's name in the lang files.
+    pub fn key(self) -> &'static str {
+        match self {
+            ModifierKind::Hidden => "modifier.hidden",
+            ModifierKind::Flashlight => "modifier.flashlight",
+            ModifierKind::Mirror => "modifier.mirror",
+            ModifierKind::SuddenDeath => "modifier.sudden_death",
+        }
+    }
+
+    fn modifier(self) -> &'static dyn Modifier {
+        match self {
+            ModifierKind::Hidden => &Hidden,
+            ModifierKind::Flashlight => &Flashlight,
+            ModifierKind::Mirror => &Mirror,
+            ModifierKind::SuddenDeath => &SuddenDeath,
+        }
+    }
+}
+
+/// A set of modifiers, which applies the hooks of every modifier in it.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Modifiers {
+    /// The modifiers in the set, sorted and without duplicates.
+    kinds: Vec<ModifierKind>,
+}
+
+impl Modifiers {
+    pub fn new(mut kinds: Vec<ModifierKind>) -> Modifiers {
+        kinds.sort();
+        kinds.dedup();
+        Modifiers { kinds }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.kinds.is_empty()
+    }
+
+    pub fn contains(&self, kind: ModifierKind) -> bool {
+        self.kinds.contains(&kind)
+    }
+
+    /// Turn `kind` on if it is off, and off if it is on.
+    pub fn toggle(&mut self, kind: ModifierKind) {
+        match self.kinds.binary_search(&kind) {
+            Ok(i) => {
+                self.kinds.remove(i);
+            }
+            Err(i) => self.kinds.insert(i, kind),
+        }
+    }
+
+    /// Return the modifiers' names, separated by commas.
+    pub fn label(&self, strings: &Strings) -> String {
+        let names: Vec<&str> = self
+            .kinds
+            .iter()
+            .map(|kind| strings.get(kind.key()))
+            .collect();
+        names.join(", ")
+    }
+
+    fn modifiers(&self) -> impl Iterator<Item = &'static dyn Modifier> + '_ {
+        self.kinds.iter().map(|kind| kind.modifier())
+    }
+}
+
+impl Modifier for Modifiers {
+    fn enemy_opacity(&self, enemy: &dyn Enemy, curr_time: Beats) -> f32 {
+        self.modifiers()
+            .map(|modifier| modifier.enemy_opacity(enemy, curr_time))
+            .product()
+    }
+
+    fn draw_overlay(
+        &self,
+        ctx: &mut Context,
+        player: WorldPos,
+        max_darkness: f32,
+    ) -> GameResult<()> {
+        for modifier in self.modifiers() {
+            modifier.draw_overlay(ctx, player, max_darkness)?;
+        }
+        Ok(())
+    }
+
+    fn screen_transform(&self, transform: ScreenTransform) -> ScreenTransform {
+        self.modifiers().fold(transform, |transform, modifier| {
+            modifier.screen_transform(transform)
+        })
+    }
+
+    fn failed(&self, stats: &RunStats) -> bool {
+        self.modifiers().any(|modifier| modifier.failed(stats))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_mirror_flips_after_transform() {
+        for &(flip_x, flip_y) in &[(false, false), (true, false), (false, true), (true, true)] {
+            for quarter_turns in 0..4 {
+                let transform = ScreenTransform {
+                    flip_x,
+                    flip_y,
+                    quarter_turns,
+                };
+                let (x, y) = transform.apply(1.0, 2.0);
+                let (mirrored_x, mirrored_y) = Mirror.screen_transform(transform).apply(1.0, 2.0);
+                assert!((mirrored_x + x).abs() < 1e-9 && (mirrored_y - y).abs() < 1e-9);
+            }
+        }
+    }
+
+    #[test]
+    pub fn test_toggle_keeps_modifiers_sorted() {
+        let mut modifiers = Modifiers::default();
+        modifiers.toggle(ModifierKind::SuddenDeath);
+        modifiers.toggle(ModifierKind::Hidden);
+        assert_eq!(
+            modifiers,
+            Modifiers::new(vec![ModifierKind::SuddenDeath, ModifierKind::Hidden])
+        );
+        modifiers.toggle(ModifierKind::SuddenDeath);
+        assert!(modifiers.contains(ModifierKind::Hidden));
+        assert!(!modifiers.contains(ModifierKind::SuddenDeath));
+    }
+}
@@ -0,0 +1,128 @@
+/// This module implements menu narration, which reads the menus aloud so that
+/// they can be navigated without looking at the screen. Whenever the focused
+/// menu item changes, it is announced, and whenever a screen with a header
+/// (ex: the results) is opened, the header is announced first.
+/// Speech goes through a Narrator, which does nothing unless the game was built
+/// with the `narration` feature, in which case the platform's text to speech
+/// is used.
+#[cfg(feature = "narration")]
+use tts::Tts;
+
+/// Something which can read text aloud.
+pub trait Narrator {
+    /// Read `text` aloud. If `interrupt` is true, anything still being read is
+    /// cut off, otherwise `text` is read after it. The default does nothing.
+    fn speak(&mut self, _text: &str, _interrupt: bool) {}
+}
+
+/// A Narrator which never says anything.
+pub struct Silent;
+
+impl Narrator for Silent {}
+
+/// A Narrator which uses the platform's text to speech.
+#[cfg(feature = "narration")]
+pub struct SystemNarrator(Tts);
+
+#[cfg(feature = "narration")]
+impl Narrator for SystemNarrator {
+    fn speak(&mut self, text: &str, interrupt: bool) {
+        if let Err(err) = self.0.speak(text, interrupt) {
+            log::warn!("Couldn't narrate {:?}: {}", text, err);
+        }
+    }
+}
+
+/// Return the platform's Narrator if `enabled` is true and the game was built
+/// with narration, and a Silent Narrator otherwise.
+pub fn narrator(enabled: bool) -> Box<dyn Narrator> {
+    #[cfg(feature = "narration")]
+    {
+        if enabled {
+            match Tts::default() {
+                Ok(tts) => return Box::new(SystemNarrator(tts)),
+                Err(err) => log::warn!("Couldn't start text to speech: {}", err),
+            }
+        }
+    }
+    #[cfg(not(feature = "narration"))]
+    let _ = enabled;
+    Box::new(Silent)
+}
+
+/// Tracks what was last announced, so that each thing is only announced once.
+pub struct Narration {
+    narrator: Box<dyn Narrator>,
+    header: Option<String>,
+    focused: Option<String>,
+}
+
+impl Narration {
+    pub fn new(narrator: Box<dyn Narrator>) -> Narration {
+        Narration {
+            narrator,
+            header: None,
+            focused: None,
+        }
+    }
+
+    /// Replace the Narrator. Everything on screen is announced again.
+    pub fn set_narrator(&mut self, narrator: Box<dyn Narrator>) {
+        *self = Narration::new(narrator);
+    }
+
+    /// Announce the current screen's header and focused menu item, if either
+    /// changed since the last call. Pass None for screens without either.
+    pub fn update(&mut self, header: Option<&str>, focused: Option<&str>) {
+        let header_changed = self.header.as_deref() != header;
+        if header_changed {
+            self.header = header.map(str::to_string);
+            if let Some(header) = header {
+                self.narrator.speak(header, true);
+            }
+        }
+        if header_changed || self.focused.as_deref() != focused {
+            self.focused = focused.map(str::to_string);
+            if let Some(focused) = focused {
+                // The focused item is read after a new header, but cuts off
+                // the previous item when the player is moving through a menu.
+                self.narrator
+                    .speak(focused, !header_changed || header.is_none());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+
+    struct Recorder(Rc<RefCell<Vec<(String, bool)>>>);
+
+    impl Narrator for Recorder {
+        fn speak(&mut self, text: &str, interrupt: bool) {
+            self.0.borrow_mut().push((text.to_string(), interrupt));
+        }
+    }
+
+    #[test]
+    pub fn test_announces_changes_once() {
+        let spoken = Rc::new(RefCell::new(vec![]));
+        let mut narration = Narration::new(Box::new(Recorder(spoken.clone())));
+        narration.update(Some("Results"), Some("Retry"));
+        narration.update(Some("Results"), Some("Retry"));
+        narration.update(Some("Results"), Some("Intro"));
+        narration.update(None, None);
+        assert_eq!(
+            *spoken.borrow(),
+            vec![
+                ("Results".to_string(), true),
+                ("Retry".to_string(), false),
+                ("Intro".to_string(), true),
+            ]
+        );
+    }
+}
@@ -0,0 +1,209 @@
+/// This module implements objectives, which let charts define goals beyond
+/// surviving to the end of the song. Objectives are started by `set_objective`
+/// spawn_cmds, like `{ spawn_cmd = "set_objective", kind = "survive",
+/// measures = 8, max_hits = 2, name = "Hold on!" }`, and are checked every
+/// frame. Failing an objective ends the run.
+/// `collect` objectives (`kind = "collect", count = 5, measures = 4`) are
+/// completed by collecting enough pickups in time. `graze` objectives (`kind =
+/// "graze", per_measure = 3, measures = 4`) are failed if, at the end of any
+/// measure, the player has grazed fewer than `per_measure` enemies for each
+/// measure so far.
+use serde::{Deserialize, Serialize};
+
+use crate::event::GameEvent;
+use crate::lang::Strings;
+use crate::time::Beats;
+
+/// Objectives are given in measures, which are assumed to be 4 beats long.
+pub const BEATS_PER_MEASURE: f64 = 4.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum ObjectiveKind {
+    /// Survive for `duration`, taking no more than `max_hits` hits.
+    Survive { duration: Beats, max_hits: usize },
+    /// Collect `count` pickups within `duration`.
+    Collect { count: usize, duration: Beats },
+    /// Graze at least `per_measure` enemies per measure, for `duration`.
+    Graze { per_measure: usize, duration: Beats },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ObjectiveStatus {
+    Active,
+    Completed,
+    Failed,
+}
+
+#[derive(Debug, Clone)]
+pub struct Objective {
+    /// The name shown for the objective. If None, a description of the
+    /// objective is shown instead.
+    pub name: Option<String>,
+    pub kind: ObjectiveKind,
+    pub start: Beats,
+    pub status: ObjectiveStatus,
+    /// How many times the player was hit since the objective started.
+    pub hits: usize,
+    /// How many pickups the player collected since the objective started.
+    pub collected: usize,
+    /// How many enemies the player grazed since the objective started.
+    pub grazes: usize,
+}
+
+impl Objective {
+    pub fn new(name: Option<String>, kind: ObjectiveKind, start: Beats) -> Objective {
+        Objective {
+            name,
+            kind,
+            start,
+            status: ObjectiveStatus::Active,
+            hits: 0,
+            collected: 0,
+            grazes: 0,
+        }
+    }
+
+    /// Return how many measures of the objective have finished by `curr_time`,
+    /// up to the end of the objective.
+    fn measures_done(&self, curr_time: Beats, duration: Beats) -> usize {
+        let elapsed = (curr_time - self.start).0.min(duration.0).max(0.0);
+        (elapsed / BEATS_PER_MEASURE).floor() as usize
+    }
+
+    /// Check the objective against the events of the most recent update.
+    /// Objectives which are already completed or failed are left as-is.
+    pub fn update(&mut self, curr_time: Beats, events: &[GameEvent]) {
+        if self.status != ObjectiveStatus::Active {
+            return;
+        }
+        for event in events {
+            match event {
+                GameEvent::PlayerHit { time, .. } if *time >= self.start => self.hits += 1,
+                GameEvent::PickupCollected { time, .. } if *time >= self.start => {
+                    self.collected += 1
+                }
+                GameEvent::Grazed { time, .. } if *time >= self.start => self.grazes += 1,
+                _ => (),
+            }
+        }
+        match self.kind {
+            ObjectiveKind::Survive { duration, max_hits } => {
+                if self.hits > max_hits {
+                    self.status = ObjectiveStatus::Failed;
+                } else if curr_time >= self.start + duration {
+                    self.status = ObjectiveStatus::Completed;
+                }
+            }
+            ObjectiveKind::Collect { count, duration } => {
+                if self.collected >= count {
+                    self.status = ObjectiveStatus::Completed;
+                } else if curr_time >= self.start + duration {
+                    self.status = ObjectiveStatus::Failed;
+                }
+            }
+            ObjectiveKind::Graze {
+                per_measure,
+                duration,
+            } => {
+                if self.grazes < per_measure * self.measures_done(curr_time, duration) {
+                    self.status = ObjectiveStatus::Failed;
+                } else if curr_time >= self.start + duration {
+                    self.status = ObjectiveStatus::Completed;
+                }
+            }
+        }
+    }
+
+    /// The objective's name, or a description of it if it has no name.
+    pub fn label(&self, strings: &Strings) -> String {
+        if let Some(name) = &self.name {
+            return name.clone();
+        }
+        match self.kind {
+            ObjectiveKind::Survive { duration, max_hits } => strings.format(
+                "objective.survive",
+                &[&(duration.0 / BEATS_PER_MEASURE), &max_hits],
+            ),
+            ObjectiveKind::Collect { count, duration } => strings.format(
+                "objective.collect",
+                &[&count, &(duration.0 / BEATS_PER_MEASURE)],
+            ),
+            ObjectiveKind::Graze {
+                per_measure,
+                duration,
+            } => strings.format(
+                "objective.graze",
+                &[&per_measure, &(duration.0 / BEATS_PER_MEASURE)],
+            ),
+        }
+    }
+
+    /// The line shown for the objective in the HUD.
+    pub fn hud_text(&self, strings: &Strings, curr_time: Beats) -> String {
+        let label = self.label(strings);
+        let beats_left = |duration: Beats| (self.start + duration - curr_time).0.max(0.0).ceil();
+        match (self.status, self.kind) {
+            (ObjectiveStatus::Active, ObjectiveKind::Survive { duration, max_hits }) => strings
+                .format(
+                    "objective.progress",
+                    &[&label, &self.hits, &max_hits, &beats_left(duration)],
+                ),
+            (ObjectiveStatus::Active, ObjectiveKind::Collect { count, duration }) => strings
+                .format(
+                    "objective.collect_progress",
+                    &[&label, &self.collected, &count, &beats_left(duration)],
+                ),
+            (
+                ObjectiveStatus::Active,
+                ObjectiveKind::Graze {
+                    per_measure,
+                    duration,
+                },
+            ) => {
+                // Show how many grazes are needed by the end of this measure.
+                let needed = per_measure * (self.measures_done(curr_time, duration) + 1);
+                strings.format(
+                    "objective.graze_progress",
+                    &[&label, &self.grazes, &needed, &beats_left(duration)],
+                )
+            }
+            (ObjectiveStatus::Completed, _) => strings.format("objective.completed", &[&label]),
+            (ObjectiveStatus::Failed, _) => strings.format("objective.failed", &[&label]),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::world::WorldPos;
+
+    fn graze(time: f64) -> GameEvent {
+        GameEvent::Grazed {
+            group: 0,
+            pos: WorldPos::origin(),
+            time: Beats(time),
+        }
+    }
+
+    #[test]
+    pub fn test_graze_objective_checks_each_measure() {
+        let kind = ObjectiveKind::Graze {
+            per_measure: 2,
+            duration: Beats(2.0 * BEATS_PER_MEASURE),
+        };
+        let mut objective = Objective::new(None, kind, Beats(0.0));
+        objective.update(Beats(1.0), &[graze(1.0), graze(1.0)]);
+        objective.update(Beats(4.0), &[]);
+        assert_eq!(objective.status, ObjectiveStatus::Active);
+        objective.update(Beats(8.0), &[graze(6.0)]);
+        assert_eq!(objective.status, ObjectiveStatus::Failed);
+
+        let mut objective = Objective::new(None, kind, Beats(0.0));
+        objective.update(Beats(3.0), &[graze(1.0), graze(2.0), graze(3.0)]);
+        objective.update(Beats(7.0), &[graze(7.0)]);
+        objective.update(Beats(8.0), &[]);
+        assert_eq!(objective.status, ObjectiveStatus::Completed);
+    }
+}
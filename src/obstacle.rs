@@ -0,0 +1,140 @@
+/// This module implements obstacles, which are pieces of static geometry that
+/// the player cannot pass through. Unlike enemies, obstacles never hurt the
+/// player. Instead, the player is pushed out of any obstacle they move into.
+use ggez::graphics::{Color, DrawMode, DrawParam, Drawable, Mesh};
+use ggez::{Context, GameResult};
+use serde::{Deserialize, Serialize};
+
+use crate::meshutil;
+use crate::time::Beats;
+use crate::world::{WorldLen, WorldPos};
+
+const OBSTACLE_GREY: Color = Color {
+    r: 0.5,
+    g: 0.5,
+    b: 0.5,
+    a: 1.0,
+};
+
+/// The step size used when estimating the gradient of an obstacle's SDF.
+const GRADIENT_EPSILON: f64 = 0.01;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum ObstacleShape {
+    Circle { radius: WorldLen },
+    Box { width: WorldLen, height: WorldLen },
+}
+
+impl ObstacleShape {
+    /// Return the sdf of the shape, where `pos` is relative to the center of
+    /// the shape.
+    fn sdf(&self, pos: WorldPos) -> f64 {
+        match self {
+            ObstacleShape::Circle { radius } => (pos.x * pos.x + pos.y * pos.y).sqrt() - radius.0,
+            ObstacleShape::Box { width, height } => {
+                let dx = pos.x.abs() - width.0 / 2.0;
+                let dy = pos.y.abs() - height.0 / 2.0;
+                let outside = (dx.max(0.0).powi(2) + dy.max(0.0).powi(2)).sqrt();
+                let inside = dx.max(dy).min(0.0);
+                outside + inside
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Obstacle {
+    pub shape: ObstacleShape,
+    pub position: WorldPos,
+    start_time: Beats,
+    duration: Beats,
+}
+
+impl Obstacle {
+    pub fn new(
+        shape: ObstacleShape,
+        position: WorldPos,
+        start_time: Beats,
+        duration: Beats,
+    ) -> Obstacle {
+        Obstacle {
+            shape,
+            position,
+            start_time,
+            duration,
+        }
+    }
+
+    /// Returns true if the obstacle should be removed from the world.
+    pub fn is_dead(&self, curr_time: Beats) -> bool {
+        curr_time > self.start_time + self.duration
+    }
+
+    /// Positive values give the distance to the obstacle and negative values
+    /// are inside the obstacle.
+    pub fn sdf(&self, pos: WorldPos) -> WorldLen {
+        WorldLen(self.shape.sdf(WorldPos {
+            x: pos.x - self.position.x,
+            y: pos.y - self.position.y,
+        }))
+    }
+
+    /// Return the closest position to `pos` such that a circle of the given
+    /// radius at that position does not overlap the obstacle. If there is no
+    /// overlap, `pos` is returned unchanged.
+    pub fn push_out(&self, pos: WorldPos, radius: WorldLen) -> WorldPos {
+        let sdf = self.sdf(pos).0;
+        if sdf >= radius.0 {
+            return pos;
+        }
+
+        // The gradient of the sdf points directly away from the obstacle's
+        // surface, so pushing along it is the shortest way out.
+        let sample = |dx: f64, dy: f64| {
+            self.sdf(WorldPos {
+                x: pos.x + dx,
+                y: pos.y + dy,
+            })
+            .0
+        };
+        let grad_x = sample(GRADIENT_EPSILON, 0.0) - sample(-GRADIENT_EPSILON, 0.0);
+        let grad_y = sample(0.0, GRADIENT_EPSILON) - sample(0.0, -GRADIENT_EPSILON);
+        let length = (grad_x * grad_x + grad_y * grad_y).sqrt();
+        // At the exact center of an obstacle the gradient may vanish, so just
+        // pick a direction.
+        let (grad_x, grad_y) = if length > 0.0 {
+            (grad_x / length, grad_y / length)
+        } else {
+            (0.0, 1.0)
+        };
+
+        let push = radius.0 - sdf;
+        WorldPos {
+            x: pos.x + grad_x * push,
+            y: pos.y + grad_y * push,
+        }
+    }
+
+    pub fn draw(&self, ctx: &mut Context) -> GameResult<()> {
+        let mesh = match self.shape {
+            ObstacleShape::Circle { radius } => {
+                let radius = radius.as_screen_length();
+                Mesh::new_circle(
+                    ctx,
+                    DrawMode::fill(),
+                    self.position.as_screen_coords(),
+                    radius,
+                    meshutil::tolerance(radius, 1.0),
+                    OBSTACLE_GREY,
+                )?
+            }
+            ObstacleShape::Box { width, height } => Mesh::new_rectangle(
+                ctx,
+                DrawMode::fill(),
+                WorldPos::as_screen_rect(self.position, width, height),
+                OBSTACLE_GREY,
+            )?,
+        };
+        mesh.draw(ctx, DrawParam::default())
+    }
+}
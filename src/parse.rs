@@ -1,40 +1,196 @@
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
 
 use ggez::graphics::Color;
 use midly::{Header, Smf, TrackEvent};
-use rlua::{FromLua, Lua, Table};
+use once_cell::sync::Lazy;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rlua::{FromLua, HookTriggers, Lua, StdLib, Table};
+use serde::{Deserialize, Serialize};
 
-use crate::chart::{BeatAction, LiveWorldPos, SpawnCmd};
-use crate::ease::{Easing, EasingKind};
-use crate::enemy::{EnemyDurations, Laser};
-use crate::player::Player;
+use crate::chart::{BeatAction, LiveAngle, LiveWorldPos, SpawnCmd};
+use crate::color::GroupBlendMode;
+use crate::ease::{Easing, EasingKind, FlashOnBeat, Lerp};
+use crate::enemy::{
+    BulletStyle, EmitterBullet, EmitterPattern, EnemyDurations, Laser, LaserRender, LaserSweep,
+    CHASER_WARMUP, DEFAULT_BULLET_WARMUP, EMITTER_WARMUP, PLATFORM_WARMUP, RING_WARMUP,
+};
+use crate::force::ForceFieldKind;
+use crate::medal::Medal;
+use crate::objective::{ObjectiveKind, BEATS_PER_MEASURE};
+use crate::obstacle::ObstacleShape;
+use crate::pickup::PickupKind;
+use crate::player::{self, Player};
+use crate::telegraph::TelegraphStyle;
+use crate::theme::Theme;
 use crate::time;
 use crate::time::Beats;
-use crate::world::WorldLen;
+use crate::vfs::Vfs;
+use crate::world::{ScreenTransform, WorldLen, WorldPos};
 
 /// This struct essentially acts as an interpreter for a song's file. All parsing
 /// occurs before the actual level is played, with the file format being line
 /// based.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SongMap {
     pub skip_amount: Beats,
     pub bpm: f64,
     pub actions: Vec<BeatAction>,
     pub player: Player,
     pub music_path: Option<PathBuf>,
+    /// The chart format the chart was written for. Charts which don't specify
+    /// a version are assumed to be version 1.
+    #[serde(default = "default_format_version")]
+    pub format_version: u32,
+    /// The name of the chart to show in menus. If this isn't set, the name of
+    /// the chart's folder is used instead.
+    #[serde(default)]
+    pub title: Option<String>,
+    /// The medals which may be earned on the chart. See `medal`.
+    #[serde(default)]
+    pub medals: Vec<Medal>,
+    /// The gain to play the music at, in dB. If this isn't set, the music's
+    /// loudness is measured and it is played at the same loudness as every
+    /// other level's music.
+    #[serde(default)]
+    pub music_gain_db: Option<f64>,
+}
+
+/// The newest chart format this version of the game understands. Charts
+/// declare which version they were written for with a `format_version` entry.
+pub const CURRENT_FORMAT_VERSION: u32 = 2;
+
+/// Changes to the chart format which may break older charts, along with the
+/// version which introduced the change. When a change can be shimmed, the
+/// shim lives in the relevant parsing code and the description says so.
+const FORMAT_CHANGES: &[(u32, &str)] = &[(
+    2,
+    "charts run in a sandbox without `io`, `os`, `dofile` or `loadfile`, and \
+     `require` only loads modules from the chart's folder or the shared lib \
+     folder. This can't be shimmed, so charts using these must be updated",
+)];
+
+fn default_format_version() -> u32 {
+    1
+}
+
+/// Return a warning for each format change since `version` which might break a
+/// chart written for that version.
+pub fn format_warnings(version: u32) -> Vec<String> {
+    if version > CURRENT_FORMAT_VERSION {
+        return vec![format!(
+            "Chart uses format version {}, but only versions up to {} are \
+             supported. Some parts of the chart may not load correctly",
+            version, CURRENT_FORMAT_VERSION
+        )];
+    }
+    FORMAT_CHANGES
+        .iter()
+        .filter(|(changed_in, _)| *changed_in > version)
+        .map(|(changed_in, change)| {
+            format!(
+                "Chart uses format version {}, but in version {} {}",
+                version, changed_in, change
+            )
+        })
+        .collect()
 }
 
+/// The default number of Lua instructions a chart may run before it is stopped.
+/// This is far more than any reasonable chart needs, and exists only to stop
+/// charts that loop forever from hanging the game.
+pub const DEFAULT_INSTRUCTION_BUDGET: u64 = 1_000_000_000;
+
+static INSTRUCTION_BUDGET: AtomicU64 = AtomicU64::new(DEFAULT_INSTRUCTION_BUDGET);
+
+/// Set the number of Lua instructions charts may run before they are stopped.
+/// This applies to every chart compiled from now on.
+pub fn set_instruction_budget(budget: u64) {
+    INSTRUCTION_BUDGET.store(budget, Ordering::Relaxed);
+}
+
+pub fn instruction_budget() -> u64 {
+    INSTRUCTION_BUDGET.load(Ordering::Relaxed)
+}
+
+/// How often the instruction budget is checked, in instructions.
+const BUDGET_CHECK_INTERVAL: u32 = 10_000;
+
 impl SongMap {
+    /// Run the Lua source. `base_folder` may be either a level folder or a
+    /// level archive.
     pub fn run_lua<P: AsRef<Path>>(base_folder: P, source: &[u8]) -> Result<SongMap, rlua::Error> {
-        let lua = Lua::new();
-        let base_folder = base_folder.as_ref().to_owned();
+        let vfs = Vfs::open(base_folder).map_err(rlua::Error::external)?;
+        let (songmap, _) = SongMap::run_lua_tracked(&vfs, source, instruction_budget())?;
+        Ok(songmap)
+    }
+
+    /// Run the Lua source, additionally returning the paths of every file the
+    /// source read while running (ex: MIDI files read via `read_midi`).
+    /// The source runs in a sandbox without access to the `io` or `os`
+    /// libraries, and is stopped with an error if it runs more than
+    /// `instruction_budget` instructions. Files are read through `vfs`.
+    pub fn run_lua_tracked(
+        vfs: &Vfs,
+        source: &[u8],
+        instruction_budget: u64,
+    ) -> Result<(SongMap, Vec<PathBuf>), rlua::Error> {
+        let lua = Lua::new_with(
+            StdLib::BASE
+                | StdLib::COROUTINE
+                | StdLib::TABLE
+                | StdLib::STRING
+                | StdLib::UTF8
+                | StdLib::MATH,
+        );
+
+        let mut instructions_run: u64 = 0;
+        lua.set_hook(
+            HookTriggers {
+                every_nth_instruction: Some(BUDGET_CHECK_INTERVAL),
+                ..Default::default()
+            },
+            move |_, _| {
+                instructions_run += BUDGET_CHECK_INTERVAL as u64;
+                if instructions_run > instruction_budget {
+                    Err(rlua::Error::external(format!(
+                        "Chart script exceeded its budget of {} instructions",
+                        instruction_budget
+                    )))
+                } else {
+                    Ok(())
+                }
+            },
+        );
+
+        let base_folder = vfs.root().to_owned();
         let base_folder2 = base_folder.clone();
-        lua.context(|ctx| {
+        let base_folder3 = base_folder.clone();
+        let base_folder4 = base_folder.clone();
+        let (vfs1, vfs2, vfs3) = (vfs.clone(), vfs.clone(), vfs.clone());
+        let (vfs4, vfs5) = (vfs.clone(), vfs.clone());
+        let read_files = Arc::new(Mutex::new(vec![]));
+        let read_files2 = read_files.clone();
+        let read_files3 = read_files.clone();
+        let read_files4 = read_files.clone();
+        let read_files5 = read_files.clone();
+        let read_files6 = read_files.clone();
+        let songmap = lua.context(|ctx| {
+            // The base library can also read files, so remove those functions
+            // too. Charts should use `require` instead.
+            ctx.globals().set("dofile", rlua::Value::Nil)?;
+            ctx.globals().set("loadfile", rlua::Value::Nil)?;
+
             let source = ctx.load(source);
 
             let read_midi = ctx.create_function(move |_, (path, bpm): (String, f64)| {
                 let path = base_folder.join(path);
-                match parse_midi(path, bpm, midi_to_beats_ungrouped) {
+                read_files2.lock().unwrap().push(path.clone());
+                match read_midi_cached(&vfs1, path, bpm, MidiGrouping::Ungrouped) {
                     Ok(beats) => Ok(beats),
                     Err(err) => Err(rlua::Error::external(err)),
                 }
@@ -43,15 +199,70 @@ impl SongMap {
 
             let read_midi = ctx.create_function(move |_, (path, bpm): (String, f64)| {
                 let path = base_folder2.join(path);
-                match parse_midi(path, bpm, midi_to_beats_grouped) {
+                read_files3.lock().unwrap().push(path.clone());
+                match read_midi_cached(&vfs2, path, bpm, MidiGrouping::Grouped) {
                     Ok(beats) => Ok(beats),
                     Err(err) => Err(rlua::Error::external(err)),
                 }
             })?;
             ctx.globals().set("read_midi_grouped", read_midi)?;
 
+            let read_midi = ctx.create_function(move |_, (path, bpm): (String, f64)| {
+                let path = base_folder3.join(path);
+                read_files4.lock().unwrap().push(path.clone());
+                match read_midi_cached(&vfs3, path, bpm, MidiGrouping::Chords) {
+                    Ok(beats) => Ok(beats),
+                    Err(err) => Err(rlua::Error::external(err)),
+                }
+            })?;
+            ctx.globals().set("read_midi_chords", read_midi)?;
+
+            let analyze_tempo = ctx.create_function(move |ctx, path: String| {
+                let path = base_folder4.join(path);
+                read_files6.lock().unwrap().push(path.clone());
+                let audio = vfs4.read(path).map_err(rlua::Error::external)?;
+                match crate::analyze::estimate_tempo_of(audio) {
+                    Ok(estimate) => {
+                        let table = ctx.create_table()?;
+                        table.set("bpm", estimate.bpm)?;
+                        table.set("offset", estimate.offset.0)?;
+                        Ok(table)
+                    }
+                    Err(err) => Err(rlua::Error::external(err)),
+                }
+            })?;
+            ctx.globals().set("analyze_tempo", analyze_tempo)?;
+
+            // `sequence` doesn't do anything on its own, since sequences are
+            // expanded when the SongMap is read. It exists so that charts can
+            // write `sequence { ... }` to make the intent clear.
+            let sequence = ctx.create_function(|_, table: Table| Ok(table))?;
+            ctx.globals().set("sequence", sequence)?;
+
+            register_require(ctx, vfs5, read_files5)?;
+            register_pitch_helpers(ctx)?;
+            register_beat_helpers(ctx)?;
+            register_constructors(ctx)?;
+            register_patterns(ctx)?;
+
             source.eval::<SongMap>()
-        })
+        })?;
+
+        let mut read_files = read_files.lock().unwrap().clone();
+        read_files.sort();
+        read_files.dedup();
+        Ok((songmap, read_files))
+    }
+
+    /// Serialize this SongMap into JSON. This is the same data that would be
+    /// produced by running the chart's Lua, so it may be loaded back with
+    /// `from_json` instead of running the Lua again.
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(self)
+    }
+
+    pub fn from_json(source: &[u8]) -> serde_json::Result<SongMap> {
+        serde_json::from_slice(source)
     }
 
     fn set_bpm(&mut self, bpm: f64) {
@@ -75,6 +286,10 @@ impl Default for SongMap {
             bpm: 150.0,
             actions: vec![],
             music_path: None,
+            format_version: default_format_version(),
+            title: None,
+            medals: vec![],
+            music_gain_db: None,
         }
     }
 }
@@ -82,6 +297,7 @@ impl Default for SongMap {
 impl<'lua> FromLua<'lua> for SongMap {
     fn from_lua(lua_value: rlua::Value<'lua>, lua: rlua::Context<'lua>) -> rlua::Result<Self> {
         let mut songmap = SongMap::default();
+        let mut defaults = SpawnDefaults::default();
         // dump_value(&lua_value);
         let table = Table::from_lua(lua_value, lua)?;
         for entry in table.sequence_values() {
@@ -95,8 +311,22 @@ impl<'lua> FromLua<'lua> for SongMap {
                 songmap.player = player;
             } else if let Ok(path) = get_key::<String>(&entry, "music") {
                 songmap.music_path = Some(path.into());
+            } else if let Ok(version) = get_key::<u32>(&entry, "format_version") {
+                songmap.format_version = version;
+            } else if let Ok(gain) = get_key::<f64>(&entry, "music_gain_db") {
+                songmap.music_gain_db = Some(gain);
+            } else if let Ok(title) = get_key::<String>(&entry, "title") {
+                songmap.title = Some(title);
+            } else if entry.contains_key("medals")? {
+                songmap.medals = get_key(&entry, "medals")?;
+            } else if entry.contains_key("defaults")? {
+                defaults.set(&entry)?;
+            } else if entry.contains_key("steps")? {
+                for action in expand_sequence(&entry, lua, &defaults)? {
+                    songmap.add_action(action);
+                }
             } else {
-                let action = BeatAction::from_table(&entry, lua);
+                let action = BeatAction::from_table(&entry, lua, &defaults);
                 match action {
                     Ok(action) => songmap.add_action(action),
                     Err(err) => {
@@ -111,59 +341,185 @@ impl<'lua> FromLua<'lua> for SongMap {
             }
         }
 
+        for warning in format_warnings(songmap.format_version) {
+            log::warn!("{}", warning);
+        }
+
         Ok(songmap)
     }
 }
 
+/// The default values set by `defaults` entries, like `{ defaults = { bullet =
+/// { size = 2 }, laser = { telegraph = "ghost" } }, enemygroup = 1 }`. Each
+/// spawn_cmd after a `defaults` entry has the defaults for its spawn_cmd filled
+/// in for any keys it doesn't set. Defaults without an `enemygroup` apply to
+/// every group, and group defaults take priority over them.
+/// A later `defaults` entry replaces the earlier defaults for the spawn_cmds
+/// it lists (so `bullet = {}` clears the bullet defaults). Values are not
+/// merged any deeper than the spawn_cmd table, so a default `style` table is
+/// replaced entirely by a spawn_cmd's own `style`.
+#[derive(Default)]
+struct SpawnDefaults<'lua> {
+    // Keyed by the group the defaults apply to (or None for every group) and
+    // the name of the spawn_cmd.
+    tables: HashMap<(Option<usize>, String), Table<'lua>>,
+}
+
+impl<'lua> SpawnDefaults<'lua> {
+    fn set(&mut self, entry: &Table<'lua>) -> rlua::Result<()> {
+        let group = get_key_or::<Option<usize>>(entry, "enemygroup", None)?;
+        let defaults = get_key::<Table>(entry, "defaults")?;
+        for pair in defaults.pairs::<String, Table>() {
+            let (spawn_cmd, table) = pair?;
+            self.tables.insert((group, spawn_cmd), table);
+        }
+        Ok(())
+    }
+
+    /// Return a copy of `spawn_cmd` with the defaults for it filled in.
+    fn apply(
+        &self,
+        lua: rlua::Context<'lua>,
+        spawn_cmd: &Table<'lua>,
+        group_number: usize,
+    ) -> rlua::Result<Table<'lua>> {
+        let name = match get_key::<String>(spawn_cmd, "spawn_cmd") {
+            Ok(name) if !self.tables.is_empty() => name,
+            // Missing spawn_cmds are reported by SpawnCmd::from_table.
+            _ => return Ok(spawn_cmd.clone()),
+        };
+        let merged = lua.create_table()?;
+        let layers = [
+            self.tables.get(&(None, name.clone())),
+            self.tables.get(&(Some(group_number), name)),
+            Some(spawn_cmd),
+        ];
+        for table in layers.iter().flatten() {
+            for pair in Table::clone(table).pairs::<rlua::Value, rlua::Value>() {
+                let (key, value) = pair?;
+                merged.set(key, value)?;
+            }
+        }
+        Ok(merged)
+    }
+}
+
+/// Expand a `sequence { base_beat = ..., steps = {...} }` entry into its
+/// BeatActions. Each step's `beat` is relative to the step before it (or to
+/// `base_beat`, for the first step) and defaults to zero. Steps without an
+/// `enemygroup` use the sequence's `enemygroup`.
+fn expand_sequence<'lua>(
+    sequence: &Table<'lua>,
+    lua: rlua::Context<'lua>,
+    defaults: &SpawnDefaults<'lua>,
+) -> rlua::Result<Vec<BeatAction>> {
+    let mut beat = get_key::<f64>(sequence, "base_beat")?;
+    let sequence_group = get_key_or::<Option<usize>>(sequence, "enemygroup", None)?;
+    let steps = get_key::<Table>(sequence, "steps")?;
+
+    let mut actions = vec![];
+    for step in steps.sequence_values::<Table>() {
+        let step = step?;
+        beat += get_key_or(&step, "beat", 0.0)?;
+        let group_number = get_key_or::<Option<usize>>(&step, "enemygroup", None)?
+            .or(sequence_group)
+            .ok_or_else(|| {
+                invalid_value(
+                    "lua table",
+                    "sequence step",
+                    "step has no enemygroup, and neither does the sequence",
+                )
+            })?;
+        let step = defaults.apply(lua, &step, group_number)?;
+        let action = SpawnCmd::from_table(&step, lua)?;
+        actions.push(BeatAction::new(Beats(beat), group_number, action));
+    }
+    Ok(actions)
+}
+
 impl BeatAction {
     fn from_table<'lua>(
         beat_action: &rlua::Table<'lua>,
         lua: rlua::Context<'lua>,
+        defaults: &SpawnDefaults<'lua>,
     ) -> rlua::Result<Self> {
         let start_time = get_key::<f64>(beat_action, "beat")?;
         let group_number = get_key::<usize>(beat_action, "enemygroup")?;
-        let action = SpawnCmd::from_table(beat_action, lua)?;
+        let action = SpawnCmd::from_table(&defaults.apply(lua, beat_action, group_number)?, lua)?;
 
         Ok(BeatAction::new(Beats(start_time), group_number, action))
     }
 }
 
 impl SpawnCmd {
+    /// Parse a spawn_cmd table. Any spawn_cmd which spawns enemies may set
+    /// `despawn = false` to make its enemies vanish as soon as they are dead,
+    /// rather than playing their group's despawn animation.
     fn from_table<'lua>(
         spawn_cmd: &rlua::Table<'lua>,
         lua: rlua::Context<'lua>,
+    ) -> rlua::Result<Self> {
+        let action = SpawnCmd::from_table_ignoring_despawn(spawn_cmd, lua)?;
+        if get_key_or(spawn_cmd, "despawn", true)? {
+            Ok(action)
+        } else {
+            Ok(SpawnCmd::WithoutDespawn(Box::new(action)))
+        }
+    }
+
+    fn from_table_ignoring_despawn<'lua>(
+        spawn_cmd: &rlua::Table<'lua>,
+        lua: rlua::Context<'lua>,
     ) -> rlua::Result<Self> {
         match get_key::<String>(spawn_cmd, "spawn_cmd")?.as_str() {
             "bullet" => {
                 let size = get_key_or(spawn_cmd, "size", 3.0)?;
                 let size = WorldLen(size);
+                let style = get_key_or(spawn_cmd, "style", BulletStyle::default())?;
+                let warmup = Beats(get_key_or(spawn_cmd, "warmup", DEFAULT_BULLET_WARMUP.0)?);
+                let telegraph = get_key_or(spawn_cmd, "telegraph", TelegraphStyle::Default)?;
 
                 if spawn_cmd.contains_key("angle")? {
-                    let angle = get_key::<f64>(spawn_cmd, "angle")?;
+                    let angle = get_key::<LiveAngle>(spawn_cmd, "angle")?;
                     let length = get_key::<f64>(spawn_cmd, "length")?;
 
                     if spawn_cmd.contains_key("start_pos")? {
                         let start = get_key::<LiveWorldPos>(spawn_cmd, "start_pos")?;
                         Ok(SpawnCmd::BulletAngleStart {
-                            angle: angle.to_radians(),
+                            angle,
                             length,
                             start,
                             size,
+                            style,
+                            warmup,
+                            telegraph,
                         })
                     } else {
                         let end = get_key::<LiveWorldPos>(spawn_cmd, "end_pos")?;
                         Ok(SpawnCmd::BulletAngleEnd {
-                            angle: angle.to_radians(),
+                            angle,
                             length,
                             end,
                             size,
+                            style,
+                            warmup,
+                            telegraph,
                         })
                     }
                 } else {
                     let start = get_key::<LiveWorldPos>(spawn_cmd, "start_pos")?;
                     let end = get_key::<LiveWorldPos>(spawn_cmd, "end_pos")?;
+                    let control = get_key_or(spawn_cmd, "control_pos", None)?;
 
-                    Ok(SpawnCmd::Bullet { start, end, size })
+                    Ok(SpawnCmd::Bullet {
+                        start,
+                        end,
+                        control,
+                        size,
+                        style,
+                        warmup,
+                        telegraph,
+                    })
                 }
             }
             "laser" => {
@@ -190,8 +546,24 @@ impl SpawnCmd {
                     "outline_keyframes",
                     Laser::default_outline_keyframes(),
                 )?;
+                let telegraph = get_key_or(spawn_cmd, "telegraph", TelegraphStyle::Default)?;
+                let render = get_key_or(spawn_cmd, "render", LaserRender::Flat)?;
 
-                if spawn_cmd.contains_key("a")? {
+                if spawn_cmd.contains_key("sweep")? {
+                    let position = get_key::<LiveWorldPos>(spawn_cmd, "position")?;
+                    let angle = get_key::<LiveAngle>(spawn_cmd, "angle")?;
+                    let sweep = get_key::<LaserSweep>(spawn_cmd, "sweep")?;
+                    Ok(SpawnCmd::LaserSweep {
+                        position,
+                        angle,
+                        durations,
+                        outline_colors,
+                        outline_keyframes,
+                        telegraph,
+                        render,
+                        sweep,
+                    })
+                } else if spawn_cmd.contains_key("a")? {
                     let a = get_key::<LiveWorldPos>(spawn_cmd, "a")?;
                     let b = get_key::<LiveWorldPos>(spawn_cmd, "b")?;
                     Ok(SpawnCmd::LaserThruPoints {
@@ -200,32 +572,228 @@ impl SpawnCmd {
                         durations,
                         outline_colors,
                         outline_keyframes,
+                        telegraph,
+                        render,
                     })
                 } else {
                     let position = get_key::<LiveWorldPos>(spawn_cmd, "position")?;
-                    let angle = get_key::<f64>(spawn_cmd, "angle")?;
+                    let angle = get_key::<LiveAngle>(spawn_cmd, "angle")?;
                     Ok(SpawnCmd::Laser {
                         position,
-                        angle: angle.to_radians(),
+                        angle,
                         durations,
                         outline_colors,
                         outline_keyframes,
+                        telegraph,
+                        render,
                     })
                 }
             }
             "bomb" => {
                 let pos = get_key::<LiveWorldPos>(spawn_cmd, "pos")?;
-                Ok(SpawnCmd::CircleBomb { pos })
+                let telegraph = get_key_or(spawn_cmd, "telegraph", TelegraphStyle::Default)?;
+                Ok(SpawnCmd::CircleBomb { pos, telegraph })
+            }
+            "rect_bomb" => {
+                let pos = get_key::<LiveWorldPos>(spawn_cmd, "pos")?;
+                let width = get_key::<f64>(spawn_cmd, "width")?;
+                let height = get_key::<f64>(spawn_cmd, "height")?;
+                let angle = get_key_or(spawn_cmd, "angle", LiveAngle::Constant(0.0))?;
+                let telegraph = get_key_or(spawn_cmd, "telegraph", TelegraphStyle::Default)?;
+                Ok(SpawnCmd::RectBomb {
+                    pos,
+                    width: WorldLen(width),
+                    height: WorldLen(height),
+                    angle,
+                    telegraph,
+                })
+            }
+            "ring_pulse" => {
+                let pos = get_key::<LiveWorldPos>(spawn_cmd, "pos")?;
+                let radius = get_key::<f64>(spawn_cmd, "radius")?;
+                let thickness = get_key_or(spawn_cmd, "thickness", 1.0)?;
+                let warmup = get_key_or(spawn_cmd, "warmup", RING_WARMUP.0)?;
+                let duration = get_key_or(spawn_cmd, "duration", 1.0)?;
+                let telegraph = get_key_or(spawn_cmd, "telegraph", TelegraphStyle::Default)?;
+                Ok(SpawnCmd::RingPulse {
+                    pos,
+                    radius: WorldLen(radius),
+                    thickness: WorldLen(thickness),
+                    durations: EnemyDurations {
+                        warmup: Beats(warmup),
+                        active: Beats(duration),
+                        cooldown: Beats(0.25),
+                    },
+                    telegraph,
+                })
+            }
+            "emitter" => {
+                let pos = get_key::<LiveWorldPos>(spawn_cmd, "pos")?;
+                let warmup = get_key_or(spawn_cmd, "warmup", EMITTER_WARMUP.0)?;
+                let duration = get_key::<f64>(spawn_cmd, "duration")?;
+                let interval = get_key::<f64>(spawn_cmd, "interval")?;
+                if interval <= 0.0 {
+                    return Err(invalid_value("lua number", "emitter interval", interval));
+                }
+                let angle_or_zero = |key: &'static str| {
+                    if spawn_cmd.contains_key(key)? {
+                        get_angle(spawn_cmd, key)
+                    } else {
+                        Ok(0.0)
+                    }
+                };
+                let pattern = match get_key_or(spawn_cmd, "pattern", "radial".to_string())?.as_str()
+                {
+                    "radial" => EmitterPattern::Radial {
+                        count: get_key_or(spawn_cmd, "count", 8)?,
+                        angle: angle_or_zero("angle")?,
+                        spin: angle_or_zero("spin")?,
+                    },
+                    "aimed" => EmitterPattern::Aimed {
+                        count: get_key_or(spawn_cmd, "count", 1)?,
+                        spread: angle_or_zero("spread")?,
+                    },
+                    x => return Err(invalid_value("lua string", "emitter pattern", x)),
+                };
+                // Streams of bullets are cluttered enough without guides.
+                let style = BulletStyle {
+                    show_guide: false,
+                    ..BulletStyle::default()
+                };
+                let bullet = EmitterBullet {
+                    size: WorldLen(get_key_or(spawn_cmd, "size", 2.0)?),
+                    length: get_key_or(spawn_cmd, "length", 100.0)?,
+                    duration: Beats(get_key_or(spawn_cmd, "bullet_duration", 4.0)?),
+                    style: get_key_or(spawn_cmd, "style", style)?,
+                };
+                Ok(SpawnCmd::Emitter {
+                    pos,
+                    durations: EnemyDurations {
+                        warmup: Beats(warmup),
+                        active: Beats(duration),
+                        cooldown: Beats(0.25),
+                    },
+                    interval: Beats(interval),
+                    pattern,
+                    bullet,
+                })
+            }
+            "chaser" => {
+                let pos = get_key::<LiveWorldPos>(spawn_cmd, "pos")?;
+                let warmup = get_key_or(spawn_cmd, "warmup", CHASER_WARMUP.0)?;
+                let duration = get_key::<f64>(spawn_cmd, "duration")?;
+                Ok(SpawnCmd::Chaser {
+                    pos,
+                    durations: EnemyDurations {
+                        warmup: Beats(warmup),
+                        active: Beats(duration),
+                        cooldown: Beats(0.25),
+                    },
+                    radius: WorldLen(get_key_or(spawn_cmd, "radius", 2.0)?),
+                    max_speed: get_key_or(spawn_cmd, "max_speed", 10.0)?,
+                    acceleration: get_key_or(spawn_cmd, "acceleration", 20.0)?,
+                })
+            }
+            "platform" => {
+                let path = get_key::<Vec<LiveWorldPos>>(spawn_cmd, "path")?;
+                if path.is_empty() {
+                    return Err(invalid_value("lua table", "platform path", "empty path"));
+                }
+                let radius = get_key_or(spawn_cmd, "radius", 8.0)?;
+                let duration = get_key::<f64>(spawn_cmd, "duration")?;
+                Ok(SpawnCmd::Platform {
+                    path,
+                    radius: WorldLen(radius),
+                    durations: EnemyDurations {
+                        warmup: PLATFORM_WARMUP,
+                        active: Beats(duration),
+                        cooldown: Beats(0.25),
+                    },
+                })
+            }
+            "obstacle" => {
+                let pos = get_key::<LiveWorldPos>(spawn_cmd, "pos")?;
+                let duration = get_key::<f64>(spawn_cmd, "duration")?;
+                let shape = if spawn_cmd.contains_key("radius")? {
+                    let radius = get_key::<f64>(spawn_cmd, "radius")?;
+                    ObstacleShape::Circle {
+                        radius: WorldLen(radius),
+                    }
+                } else {
+                    let width = get_key::<f64>(spawn_cmd, "width")?;
+                    let height = get_key::<f64>(spawn_cmd, "height")?;
+                    ObstacleShape::Box {
+                        width: WorldLen(width),
+                        height: WorldLen(height),
+                    }
+                };
+                Ok(SpawnCmd::Obstacle {
+                    pos,
+                    shape,
+                    duration: Beats(duration),
+                })
+            }
+            "force_field" => {
+                let duration = get_key::<f64>(spawn_cmd, "duration")?;
+                let kind = if spawn_cmd.contains_key("wind")? {
+                    let wind = get_key::<WorldPos>(spawn_cmd, "wind")?;
+                    ForceFieldKind::Wind {
+                        x: wind.x,
+                        y: wind.y,
+                    }
+                } else {
+                    let center = get_key::<WorldPos>(spawn_cmd, "center")?;
+                    let strength = get_key::<f64>(spawn_cmd, "strength")?;
+                    let radius = get_key::<f64>(spawn_cmd, "radius")?;
+                    ForceFieldKind::Radial {
+                        center,
+                        strength,
+                        radius: WorldLen(radius),
+                    }
+                };
+                Ok(SpawnCmd::ForceField {
+                    kind,
+                    duration: Beats(duration),
+                })
+            }
+            "teleporter" => {
+                let entrance = get_key::<LiveWorldPos>(spawn_cmd, "entrance")?;
+                let exit = get_key::<LiveWorldPos>(spawn_cmd, "exit")?;
+                let radius = get_key_or(spawn_cmd, "radius", 4.0)?;
+                let duration = get_key::<f64>(spawn_cmd, "duration")?;
+                Ok(SpawnCmd::Teleporter {
+                    entrance,
+                    exit,
+                    radius: WorldLen(radius),
+                    duration: Beats(duration),
+                })
+            }
+            "pickup" => {
+                let kind = match get_key_or(spawn_cmd, "kind", "gem".to_string())?.as_str() {
+                    "gem" => PickupKind::Gem,
+                    "bomb" => PickupKind::Bomb,
+                    "heal" => PickupKind::Heal,
+                    x => return Err(invalid_value("lua string", "PickupKind", x)),
+                };
+                let pos = get_key::<LiveWorldPos>(spawn_cmd, "pos")?;
+                let radius = get_key_or(spawn_cmd, "radius", 1.5)?;
+                let duration = get_key::<f64>(spawn_cmd, "duration")?;
+                Ok(SpawnCmd::Pickup {
+                    kind,
+                    pos,
+                    radius: WorldLen(radius),
+                    duration: Beats(duration),
+                })
             }
             "set_rotation_on" => {
-                let start_angle = get_key::<f64>(spawn_cmd, "start_angle")?;
-                let end_angle = get_key::<f64>(spawn_cmd, "end_angle")?;
+                let start_angle = get_angle(spawn_cmd, "start_angle")?;
+                let end_angle = get_angle(spawn_cmd, "end_angle")?;
                 let duration = get_key::<f64>(spawn_cmd, "duration")?;
                 let rot_point = get_key::<LiveWorldPos>(spawn_cmd, "rot_point")?;
 
                 Ok(SpawnCmd::SetGroupRotation(Some((
-                    start_angle.to_radians(),
-                    end_angle.to_radians(),
+                    start_angle,
+                    end_angle,
                     Beats(duration),
                     rot_point,
                 ))))
@@ -246,15 +814,161 @@ impl SpawnCmd {
                 let value = get_key::<bool>(spawn_cmd, "value")?;
                 Ok(SpawnCmd::SetRenderWarmup(value))
             }
+            "set_spawn_animation" => {
+                let duration = get_key::<f64>(spawn_cmd, "duration")?;
+                let spawn_in = if duration > 0.0 {
+                    Some(Beats(duration))
+                } else {
+                    None
+                };
+                Ok(SpawnCmd::SetSpawnAnimation(spawn_in))
+            }
+            "set_despawn_animation" => {
+                let duration = get_key::<f64>(spawn_cmd, "duration")?;
+                let despawn = if duration > 0.0 {
+                    Some(Beats(duration))
+                } else {
+                    None
+                };
+                Ok(SpawnCmd::SetDespawnAnimation(despawn))
+            }
+            "set_active_fade_on" => {
+                let alpha = get_key::<Easing<f64>>(spawn_cmd, "alpha")?;
+                Ok(SpawnCmd::SetActiveFade(Some(alpha)))
+            }
+            "set_active_fade_off" => Ok(SpawnCmd::SetActiveFade(None)),
+            "set_contact_damage_on" => {
+                let interval = get_key::<f64>(spawn_cmd, "interval")?;
+                Ok(SpawnCmd::SetContactDamage(Some(Beats(interval))))
+            }
+            "set_contact_damage_off" => Ok(SpawnCmd::SetContactDamage(None)),
             "set_render" => {
                 let value = get_key::<bool>(spawn_cmd, "value")?;
                 Ok(SpawnCmd::SetRender(value))
             }
+            "set_vision_on" => {
+                let radius = get_key::<f64>(spawn_cmd, "radius")?;
+                let duration = get_key::<f64>(spawn_cmd, "duration")?;
+                Ok(SpawnCmd::SetVision(Some((
+                    WorldLen(radius),
+                    Beats(duration),
+                ))))
+            }
+            "set_vision_off" => Ok(SpawnCmd::SetVision(None)),
+            "set_screen_transform" => {
+                let flip_x = get_key_or(spawn_cmd, "flip_x", false)?;
+                let flip_y = get_key_or(spawn_cmd, "flip_y", false)?;
+                let rotation = if spawn_cmd.contains_key("rotation")? {
+                    get_angle(spawn_cmd, "rotation")?
+                } else {
+                    0.0
+                };
+                let quarter_turns = rotation / std::f64::consts::FRAC_PI_2;
+                Ok(SpawnCmd::SetScreenTransform(ScreenTransform {
+                    flip_x,
+                    flip_y,
+                    quarter_turns: (quarter_turns.round() as i64).rem_euclid(4) as u8,
+                }))
+            }
+            "set_tint" => {
+                let color = get_key_color(spawn_cmd, "color")?;
+                Ok(SpawnCmd::SetGroupTint(color))
+            }
+            "set_theme" => {
+                let palette = get_key::<Theme>(spawn_cmd, "palette")?;
+                let duration = get_key_or(spawn_cmd, "duration", 0.0)?;
+                Ok(SpawnCmd::SetTheme {
+                    palette,
+                    duration: Beats(duration),
+                })
+            }
+            "set_blend_mode" => match get_key::<String>(spawn_cmd, "value")?.as_str() {
+                "alpha" => Ok(SpawnCmd::SetBlendMode(GroupBlendMode::Alpha)),
+                "additive" => Ok(SpawnCmd::SetBlendMode(GroupBlendMode::Additive)),
+                x => Err(invalid_value("lua string", "GroupBlendMode", x)),
+            },
+            "set_flash_on" => {
+                let flash = get_key::<FlashOnBeat>(spawn_cmd, "flash")?;
+                Ok(SpawnCmd::SetFlashOnBeat(Some(flash)))
+            }
+            "set_flash_off" => Ok(SpawnCmd::SetFlashOnBeat(None)),
+            "set_background_flash_on" => {
+                let flash = get_key::<FlashOnBeat>(spawn_cmd, "flash")?;
+                Ok(SpawnCmd::SetBackgroundFlash(Some(flash)))
+            }
+            "set_background_flash_off" => Ok(SpawnCmd::SetBackgroundFlash(None)),
             "set_hitbox" => {
                 let value = get_key::<bool>(spawn_cmd, "value")?;
                 Ok(SpawnCmd::SetHitbox(value))
             }
             "clear_enemies" => Ok(SpawnCmd::ClearEnemies),
+            "mark_section" => {
+                let name = get_key::<String>(spawn_cmd, "name")?;
+                Ok(SpawnCmd::MarkSection(name))
+            }
+            "set_title_suffix" => {
+                let suffix = get_key::<Option<String>>(spawn_cmd, "suffix")?;
+                Ok(SpawnCmd::SetTitleSuffix(suffix))
+            }
+            "play_sound" => Ok(SpawnCmd::PlaySound {
+                path: get_key::<String>(spawn_cmd, "path")?.into(),
+                volume: get_key_or(spawn_cmd, "volume", 1.0)?,
+            }),
+            "set_objective" => {
+                let name = get_key::<Option<String>>(spawn_cmd, "name")?;
+                let kind = match get_key::<String>(spawn_cmd, "kind")?.as_str() {
+                    "survive" => ObjectiveKind::Survive {
+                        duration: Beats(get_key::<f64>(spawn_cmd, "measures")? * BEATS_PER_MEASURE),
+                        max_hits: get_key_or(spawn_cmd, "max_hits", 0)?,
+                    },
+                    "collect" => ObjectiveKind::Collect {
+                        count: get_key(spawn_cmd, "count")?,
+                        duration: Beats(get_key::<f64>(spawn_cmd, "measures")? * BEATS_PER_MEASURE),
+                    },
+                    "graze" => ObjectiveKind::Graze {
+                        per_measure: get_key(spawn_cmd, "per_measure")?,
+                        duration: Beats(get_key::<f64>(spawn_cmd, "measures")? * BEATS_PER_MEASURE),
+                    },
+                    x => return Err(invalid_value("lua string", "ObjectiveKind", x)),
+                };
+                Ok(SpawnCmd::SetObjective { name, kind })
+            }
+            "if_hits" => {
+                let at_least = get_key::<usize>(spawn_cmd, "at_least")?;
+                let hit_by = get_key::<Option<usize>>(spawn_cmd, "hit_by")?;
+                let then = get_key::<rlua::Table>(spawn_cmd, "then")?;
+                let otherwise = get_key::<Option<rlua::Table>>(spawn_cmd, "otherwise")?;
+                let then = SpawnCmd::from_table(&then, lua)?;
+                let otherwise = match otherwise {
+                    Some(otherwise) => Some(SpawnCmd::from_table(&otherwise, lua)?),
+                    None => None,
+                };
+                // Sounds are scheduled before the condition can be checked.
+                let plays_sound = |cmd: &SpawnCmd| cmd.sound().is_some();
+                if plays_sound(&then) || otherwise.as_ref().map_or(false, plays_sound) {
+                    return Err(invalid_value(
+                        "lua table",
+                        "if_hits",
+                        "play_sound can't depend on the player's hits",
+                    ));
+                }
+                if let Some(otherwise) = &otherwise {
+                    let when = |cmd: &SpawnCmd| cmd.scheduled_time(Beats(0.0));
+                    if when(&then) != when(otherwise) {
+                        return Err(invalid_value(
+                            "lua table",
+                            "if_hits",
+                            "then and otherwise are spawned at different times",
+                        ));
+                    }
+                }
+                Ok(SpawnCmd::IfHits {
+                    at_least,
+                    hit_by,
+                    then: Box::new(then),
+                    otherwise: otherwise.map(Box::new),
+                })
+            }
             _ => Err(invalid_value(
                 "spawn_cmd (lua table)",
                 "SpawnCmd",
@@ -285,6 +999,52 @@ impl<'lua> FromLua<'lua> for LiveWorldPos {
     }
 }
 
+/// Angles which aim at the player are given as `"player"`, or as a table like
+/// `{ aim = "player", deg = 15 }` to aim 15 degrees counterclockwise of the
+/// player. Any other angle is constant (see `get_angle`).
+impl<'lua> FromLua<'lua> for LiveAngle {
+    fn from_lua(lua_value: rlua::Value<'lua>, _lua: rlua::Context<'lua>) -> rlua::Result<Self> {
+        match lua_value {
+            rlua::Value::String(string) => match string.to_str()? {
+                "player" => Ok(LiveAngle::AimAtPlayer { offset: 0.0 }),
+                x => Err(invalid_value("lua string", "LiveAngle", x)),
+            },
+            rlua::Value::Table(table) if table.contains_key("aim")? => {
+                match get_key::<String>(&table, "aim")?.as_str() {
+                    "player" => (),
+                    x => return Err(invalid_value("lua string", "aim", x)),
+                }
+                let has_offset = table.contains_key("deg")? || table.contains_key("rad")?;
+                let offset = if has_offset {
+                    angle_from_lua(rlua::Value::Table(table))?
+                } else {
+                    0.0
+                };
+                Ok(LiveAngle::AimAtPlayer { offset })
+            }
+            value => Ok(LiveAngle::Constant(angle_from_lua(value)?)),
+        }
+    }
+}
+
+impl<'lua> FromLua<'lua> for WorldPos {
+    fn from_lua(lua_value: rlua::Value<'lua>, lua: rlua::Context<'lua>) -> rlua::Result<Self> {
+        let table = Table::from_lua(lua_value, lua)?;
+        let x = get_key::<f64>(&table, "x")?;
+        let y = get_key::<f64>(&table, "y")?;
+        Ok(WorldPos { x, y })
+    }
+}
+
+impl<'lua> rlua::ToLua<'lua> for WorldPos {
+    fn to_lua(self, lua: rlua::Context<'lua>) -> rlua::Result<rlua::Value<'lua>> {
+        let table = lua.create_table()?;
+        table.set("x", self.x)?;
+        table.set("y", self.y)?;
+        Ok(rlua::Value::Table(table))
+    }
+}
+
 impl<'lua> FromLua<'lua> for EnemyDurations {
     fn from_lua(lua_value: rlua::Value<'lua>, lua: rlua::Context<'lua>) -> rlua::Result<Self> {
         let table = Table::from_lua(lua_value, lua)?;
@@ -349,14 +1109,151 @@ impl<'lua> FromLua<'lua> for EasingKind {
     }
 }
 
+impl<'lua> FromLua<'lua> for BulletStyle {
+    fn from_lua(lua_value: rlua::Value<'lua>, lua: rlua::Context<'lua>) -> rlua::Result<Self> {
+        let table = rlua::Table::from_lua(lua_value, lua)?;
+        let default = BulletStyle::default();
+
+        // `glow_size = false` disables the glow entirely.
+        let glow_size = match table.get::<_, rlua::Value>("glow_size")? {
+            rlua::Value::Nil => default.glow_size,
+            rlua::Value::Boolean(false) => None,
+            value => Some(WorldLen(f64::from_lua(value, lua)?)),
+        };
+        let show_guide = get_key_or(&table, "show_guide", default.show_guide)?;
+        let outline_thickness = get_key_or(&table, "outline_thickness", default.outline_thickness)?;
+        let get_color = |key, default| {
+            if table.contains_key(key)? {
+                get_key_color(&table, key)
+            } else {
+                Ok(default)
+            }
+        };
+
+        Ok(BulletStyle {
+            glow_size,
+            show_guide,
+            outline_thickness,
+            fill_color: get_color("fill_color", default.fill_color)?,
+            guide_color: get_color("guide_color", default.guide_color)?,
+            glow_color: get_color("glow_color", default.glow_color)?,
+        })
+    }
+}
+
+impl<'lua> FromLua<'lua> for LaserSweep {
+    fn from_lua(lua_value: rlua::Value<'lua>, lua: rlua::Context<'lua>) -> rlua::Result<Self> {
+        let table = rlua::Table::from_lua(lua_value, lua)?;
+        // The angles are given like every other angle. See `get_angle`.
+        let angle = if table.contains_key("angle")? {
+            let easing: Table = get_key(&table, "angle")?;
+            Some(Easing {
+                start: get_angle(&easing, "start_val")?,
+                end: get_angle(&easing, "end_val")?,
+                kind: get_key_or(&easing, "ease_kind", EasingKind::Linear)?,
+            })
+        } else {
+            None
+        };
+        Ok(LaserSweep {
+            x: get_key(&table, "x")?,
+            y: get_key(&table, "y")?,
+            angle,
+        })
+    }
+}
+
+impl<'lua> FromLua<'lua> for FlashOnBeat {
+    fn from_lua(lua_value: rlua::Value<'lua>, lua: rlua::Context<'lua>) -> rlua::Result<Self> {
+        let table = rlua::Table::from_lua(lua_value, lua)?;
+        // Subdivisions may be given by name, or as a number of pulses per beat.
+        let subdivision = match get_key::<rlua::Value>(&table, "subdivision")? {
+            rlua::Value::String(name) => match name.to_str()? {
+                "quarters" => 1.0,
+                "eighths" => 2.0,
+                "triplets" => 3.0,
+                "sixteenths" => 4.0,
+                x => return Err(invalid_value("lua string", "subdivision", x)),
+            },
+            value => f64::from_lua(value, lua)?,
+        };
+        let intensity = get_key_or(&table, "intensity", 0.5)?;
+        Ok(FlashOnBeat {
+            subdivision,
+            intensity: f64::clamp(intensity, 0.0, 1.0),
+        })
+    }
+}
+
+impl<'lua> FromLua<'lua> for TelegraphStyle {
+    fn from_lua(lua_value: rlua::Value<'lua>, lua: rlua::Context<'lua>) -> rlua::Result<Self> {
+        match String::from_lua(lua_value, lua)?.as_str() {
+            "default" => Ok(TelegraphStyle::Default),
+            "ghost" => Ok(TelegraphStyle::Ghost),
+            "countdown" => Ok(TelegraphStyle::CountdownRing),
+            "dashed" => Ok(TelegraphStyle::DashedOutline),
+            x => Err(invalid_value("lua string", "TelegraphStyle", x)),
+        }
+    }
+}
+
+impl<'lua> FromLua<'lua> for LaserRender {
+    fn from_lua(lua_value: rlua::Value<'lua>, lua: rlua::Context<'lua>) -> rlua::Result<Self> {
+        match String::from_lua(lua_value, lua)?.as_str() {
+            "flat" => Ok(LaserRender::Flat),
+            "glow" => Ok(LaserRender::Glow),
+            x => Err(invalid_value("lua string", "LaserRender", x)),
+        }
+    }
+}
+
 impl<'lua> FromLua<'lua> for Player {
     fn from_lua(lua_value: rlua::Value<'lua>, lua: rlua::Context<'lua>) -> rlua::Result<Self> {
         let table = rlua::Table::from_lua(lua_value, lua)?;
 
         let size = get_key_or(&table, "size", 2.0)?;
         let speed = get_key_or(&table, "speed", 100.0)?;
+        let focus_factor = get_key_or(&table, "focus_factor", player::DEFAULT_FOCUS_FACTOR)?;
+        let hit_cooldown: Option<f64> = get_key_or(&table, "hit_cooldown", None)?;
+        let knockback = get_key_or(&table, "knockback", 0.0)?;
+        let bombs = get_key_or(&table, "bombs", 0)?;
+
+        let mut player = Player::new(speed, WorldLen(size));
+        player.focus_factor = focus_factor;
+        player.hit_cooldown = hit_cooldown.map(Beats);
+        player.knockback = knockback;
+        player.bombs = bombs;
+        Ok(player)
+    }
+}
 
-        Ok(Player::new(speed, WorldLen(size)))
+impl<'lua> FromLua<'lua> for Theme {
+    fn from_lua(lua_value: rlua::Value<'lua>, lua: rlua::Context<'lua>) -> rlua::Result<Self> {
+        let table = rlua::Table::from_lua(lua_value, lua)?;
+        let default = Theme::default();
+        let get_color = |key: &'lua str| -> rlua::Result<Option<Color>> {
+            if table.contains_key(key)? {
+                get_key_color(&table, key).map(Some)
+            } else {
+                Ok(None)
+            }
+        };
+        Ok(Theme {
+            background: get_color("background")?.unwrap_or(default.background),
+            grid: get_color("grid")?.unwrap_or(default.grid),
+            enemy: get_color("enemy")?.unwrap_or(default.enemy),
+        })
+    }
+}
+
+impl<'lua> FromLua<'lua> for Medal {
+    fn from_lua(lua_value: rlua::Value<'lua>, lua: rlua::Context<'lua>) -> rlua::Result<Self> {
+        let table = rlua::Table::from_lua(lua_value, lua)?;
+        Ok(Medal {
+            name: get_key(&table, "name")?,
+            max_hits: get_key(&table, "max_hits")?,
+            min_grazes: get_key(&table, "min_grazes")?,
+        })
     }
 }
 
@@ -444,6 +1341,33 @@ fn get_key_or<'lua, T: FromLua<'lua>>(
     }
 }
 
+/// Return the angle at `key`, in radians. Angles are given as a number of
+/// degrees, or as a table with either a `deg` or `rad` key, like `{ rad =
+/// math.pi }`. See `world` for how angles are oriented.
+fn get_angle<'lua>(table: &Table<'lua>, key: &'lua str) -> rlua::Result<f64> {
+    let value: rlua::Value = get_key(table, key)?;
+    angle_from_lua(value)
+}
+
+fn angle_from_lua(lua_value: rlua::Value) -> rlua::Result<f64> {
+    match lua_value {
+        rlua::Value::Table(table) => {
+            if table.contains_key("rad")? {
+                get_key(&table, "rad")
+            } else {
+                get_key::<f64>(&table, "deg").map(f64::to_radians)
+            }
+        }
+        rlua::Value::Integer(degrees) => Ok((degrees as f64).to_radians()),
+        rlua::Value::Number(degrees) => Ok(degrees.to_radians()),
+        x => Err(rlua::Error::FromLuaConversionError {
+            from: "lua value",
+            to: "angle",
+            message: Some(format!("Expected a Number or Table. Got: {:?}", x)),
+        }),
+    }
+}
+
 fn get_key_color<'lua>(table: &Table<'lua>, key: &'lua str) -> rlua::Result<Color> {
     let value: rlua::Value = get_key(table, key)?;
     from_lua_color(value)
@@ -472,7 +1396,7 @@ fn expected_string_or_table(to_type: &'static str, value: rlua::Value) -> rlua::
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub struct MarkedBeat {
     pub beat: Beats,
     pub percent: f64,
@@ -480,8 +1404,23 @@ pub struct MarkedBeat {
     // a triple describing the midigroup_id, the ith note into the midigroup, and
     // total length of the midigroup
     pub midigroup: Option<(usize, usize, usize)>,
+    pub chord: Option<Chord>,
 }
 
+/// Describes the chord that a note belongs to. A chord is a set of notes whose
+/// onsets all fall within CHORD_WINDOW of the first note of the chord.
+#[derive(Debug, Clone)]
+pub struct Chord {
+    pub id: usize,
+    // The index of this note within the chord. Notes are ordered by onset time.
+    pub i: usize,
+    // The normalized pitches of every note in the chord, including this one.
+    pub pitches: Vec<f64>,
+}
+
+/// The maximum distance between the first and last onset of notes in a chord.
+pub const CHORD_WINDOW: Beats = Beats(1.0 / 32.0);
+
 impl<'lua> rlua::ToLua<'lua> for MarkedBeat {
     fn to_lua(self, lua: rlua::Context<'lua>) -> rlua::Result<rlua::Value<'lua>> {
         let table = lua.create_table()?;
@@ -497,10 +1436,436 @@ impl<'lua> rlua::ToLua<'lua> for MarkedBeat {
         table.set("midigroup_id", midigroup_id)?;
         table.set("midigroup_i", midigroup_i)?;
         table.set("midigroup_len", midigroup_len)?;
+        if let Some(chord) = self.chord {
+            table.set("chord_id", chord.id)?;
+            table.set("chord_i", chord.i)?;
+            table.set("chord_len", chord.pitches.len())?;
+            table.set("chord_pitches", chord.pitches)?;
+        }
         Ok(rlua::Value::Table(table))
     }
 }
 
+/// Register the pitch mapping functions into the Lua globals. Angles returned
+/// to Lua are in degrees, to match the rest of the chart format.
+fn register_pitch_helpers(ctx: rlua::Context) -> rlua::Result<()> {
+    let pitch_lane = ctx.create_function(
+        |_, (pitch, num_lanes, start, end): (f64, usize, WorldPos, WorldPos)| {
+            Ok(pitch_to_lane(pitch, num_lanes, start, end))
+        },
+    )?;
+    ctx.globals().set("pitch_lane", pitch_lane)?;
+
+    let pitch_class = ctx.create_function(|_, pitch: f64| Ok(pitch_class(pitch)))?;
+    ctx.globals().set("pitch_class", pitch_class)?;
+
+    let pitch_wheel_angle =
+        ctx.create_function(|_, pitch: f64| Ok(pitch_wheel_angle(pitch).to_degrees()))?;
+    ctx.globals().set("pitch_wheel_angle", pitch_wheel_angle)?;
+
+    let pitch_fifths_angle =
+        ctx.create_function(|_, pitch: f64| Ok(pitch_fifths_angle(pitch).to_degrees()))?;
+    ctx.globals()
+        .set("pitch_fifths_angle", pitch_fifths_angle)?;
+    Ok(())
+}
+
+/// Replace `require` with a version that only loads modules from the level's
+/// folder or from the shared library folder (`SHARED_LIB_FOLDER`, next to the
+/// level folders). Like Lua's `require`, dots in module names become folder
+/// separators and each module is only run once. Modules loaded this way are
+/// recorded in `read_files`, so that changing them invalidates cached charts.
+fn register_require(
+    ctx: rlua::Context,
+    vfs: Vfs,
+    read_files: Arc<Mutex<Vec<PathBuf>>>,
+) -> rlua::Result<()> {
+    const LOADED_MODULES: &str = "rthm_loaded_modules";
+    ctx.set_named_registry_value(LOADED_MODULES, ctx.create_table()?)?;
+
+    let require = ctx.create_function(move |ctx, name: String| {
+        let loaded: Table = ctx.named_registry_value(LOADED_MODULES)?;
+        match loaded.get::<_, rlua::Value>(name.as_str())? {
+            rlua::Value::Nil => (),
+            value => return Ok(value),
+        }
+
+        let path = resolve_module(&vfs, &name)?;
+        read_files.lock().unwrap().push(path.clone());
+        let source = vfs.read(&path).map_err(rlua::Error::external)?;
+        let value = ctx
+            .load(&source)
+            .set_name(path.to_string_lossy().as_bytes())?
+            .eval::<rlua::Value>()?;
+        // Just like Lua's require, modules which return nothing are recorded
+        // as `true`, so that they aren't run again.
+        let value = match value {
+            rlua::Value::Nil => rlua::Value::Boolean(true),
+            value => value,
+        };
+        loaded.set(name.as_str(), value.clone())?;
+        Ok(value)
+    })?;
+    ctx.globals().set("require", require)?;
+    Ok(())
+}
+
+/// The folder which holds Lua modules shared between levels. This lives in the
+/// same folder as the levels themselves.
+pub const SHARED_LIB_FOLDER: &str = "lib";
+
+/// Find the file for the module `name`, looking in the level's folder first and
+/// then in the shared library folder.
+fn resolve_module(vfs: &Vfs, name: &str) -> rlua::Result<PathBuf> {
+    // Only allow simple names, so that modules can't escape the allowed folders
+    // via things like absolute paths or "..".
+    let valid_name = name.split('.').all(|part| {
+        !part.is_empty()
+            && part
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    });
+    if !valid_name {
+        return Err(rlua::Error::external(format!(
+            "Invalid module name {:?}",
+            name
+        )));
+    }
+
+    let relative_path: PathBuf = name.split('.').collect();
+    let relative_path = relative_path.with_extension("lua");
+    let base_folder = vfs.root();
+    let mut search_folders = vec![base_folder.to_owned()];
+    if let Some(parent) = base_folder.parent() {
+        search_folders.push(parent.join(SHARED_LIB_FOLDER));
+    }
+
+    for folder in &search_folders {
+        let path = folder.join(&relative_path);
+        if vfs.is_file(&path) {
+            return Ok(path);
+        }
+    }
+    Err(rlua::Error::external(format!(
+        "Module {:?} not found (searched {:?})",
+        name, search_folders
+    )))
+}
+
+/// Register the `quantize`, `snap_list`, and `humanize` functions. `snap_list`
+/// and `humanize` accept lists of either beat numbers or tables with a `beat`
+/// key (such as those returned by `read_midi`), and return new lists of the
+/// same form.
+fn register_beat_helpers(ctx: rlua::Context) -> rlua::Result<()> {
+    let quantize_beat = ctx.create_function(|_, (beat, subdivision): (f64, f64)| {
+        check_subdivision(subdivision)?;
+        Ok(quantize(Beats(beat), subdivision).0)
+    })?;
+    ctx.globals().set("quantize", quantize_beat)?;
+
+    let snap_list = ctx.create_function(|ctx, (beats, subdivision): (Vec<rlua::Value>, f64)| {
+        check_subdivision(subdivision)?;
+        let mut snapped = Vec::with_capacity(beats.len());
+        let mut last_beat = None;
+        for beat in beats {
+            let (beat, new_beat) =
+                map_beat(ctx, beat, |beat| quantize(Beats(beat), subdivision).0)?;
+            // Drop beats which snapped onto the same beat as the previous one
+            if last_beat != Some(new_beat) {
+                snapped.push(beat);
+                last_beat = Some(new_beat);
+            }
+        }
+        Ok(snapped)
+    })?;
+    ctx.globals().set("snap_list", snap_list)?;
+
+    let humanize =
+        ctx.create_function(|ctx, (beats, amount, seed): (Vec<rlua::Value>, f64, u64)| {
+            let mut rng = StdRng::seed_from_u64(seed);
+            // gen_range can't take a NaN or infinite range, so those amounts
+            // leave the beats where they are.
+            let amount = if amount.is_finite() {
+                amount.abs()
+            } else {
+                0.0
+            };
+            beats
+                .into_iter()
+                .map(|beat| {
+                    let offset = rng.gen_range(-amount..=amount);
+                    map_beat(ctx, beat, |beat| beat + offset).map(|(beat, _)| beat)
+                })
+                .collect::<rlua::Result<Vec<_>>>()
+        })?;
+    ctx.globals().set("humanize", humanize)?;
+    Ok(())
+}
+
+/// Register constructors for the tables that colors and easings are read from,
+/// so that charts can write `ease_linear(color(1, 0, 0), "white")` instead of
+/// `{ start_val = { r = 1, g = 0, b = 0 }, end_val = "white" }`. The
+/// constructors only build tables, so anything they return can also be
+/// written out by hand.
+/// - `color(r, g, b, a)` builds a color. `a` defaults to 1.
+/// - `ease(start, end, kind)` builds an easing of the given EasingKind (linear
+///   if not given), and `ease_linear(start, end)` builds a linear easing.
+/// - `ease_out(kind)` builds an EasingKind which is the ease-out of `kind`.
+/// - `keyframes { a, b, c, ... }` builds an easing between each adjacent pair
+///   of values, for fields like `outline_keyframes` that take one easing per
+///   phase of an enemy's lifetime. An optional `kind` key sets the EasingKind
+///   of every easing.
+/// - `angle_to(from, to)` returns the angle, in degrees, pointing from the
+///   position `from` towards `to`. Use `angle = "player"` to aim at wherever
+///   the player is when the enemy spawns instead.
+fn register_constructors(ctx: rlua::Context) -> rlua::Result<()> {
+    let color = ctx.create_function(|ctx, (r, g, b, a): (f32, f32, f32, Option<f32>)| {
+        let table = ctx.create_table()?;
+        table.set("r", r)?;
+        table.set("g", g)?;
+        table.set("b", b)?;
+        table.set("a", a.unwrap_or(1.0))?;
+        Ok(table)
+    })?;
+    ctx.globals().set("color", color)?;
+
+    fn easing<'lua>(
+        ctx: rlua::Context<'lua>,
+        start: rlua::Value<'lua>,
+        end: rlua::Value<'lua>,
+        kind: rlua::Value<'lua>,
+    ) -> rlua::Result<Table<'lua>> {
+        let table = ctx.create_table()?;
+        table.set("start_val", start)?;
+        table.set("end_val", end)?;
+        table.set("ease_kind", kind)?;
+        Ok(table)
+    }
+
+    let ease = ctx.create_function(
+        |ctx, (start, end, kind): (rlua::Value, rlua::Value, rlua::Value)| {
+            // Check the kind now, so that mistakes are reported where they
+            // were made rather than when the spawn_cmd is read. A missing
+            // kind is linear.
+            if !matches!(kind, rlua::Value::Nil) {
+                EasingKind::from_lua(kind.clone(), ctx)?;
+            }
+            easing(ctx, start, end, kind)
+        },
+    )?;
+    ctx.globals().set("ease", ease)?;
+
+    let ease_linear = ctx.create_function(|ctx, (start, end): (rlua::Value, rlua::Value)| {
+        let linear = rlua::Value::String(ctx.create_string("linear")?);
+        easing(ctx, start, end, linear)
+    })?;
+    ctx.globals().set("ease_linear", ease_linear)?;
+
+    let ease_out = ctx.create_function(|ctx, kind: rlua::Value| {
+        EasingKind::from_lua(kind.clone(), ctx)?;
+        let table = ctx.create_table()?;
+        table.set("easing", kind)?;
+        Ok(table)
+    })?;
+    ctx.globals().set("ease_out", ease_out)?;
+
+    let keyframes = ctx.create_function(|ctx, values: Table| {
+        let kind: rlua::Value = values.get("kind")?;
+        let kind = match kind {
+            rlua::Value::Nil => rlua::Value::String(ctx.create_string("linear")?),
+            kind => {
+                EasingKind::from_lua(kind.clone(), ctx)?;
+                kind
+            }
+        };
+        let values = values
+            .sequence_values::<rlua::Value>()
+            .collect::<rlua::Result<Vec<_>>>()?;
+        if values.len() < 2 {
+            return Err(rlua::Error::external(format!(
+                "keyframes needs at least 2 values, got {}",
+                values.len()
+            )));
+        }
+        values
+            .windows(2)
+            .map(|pair| easing(ctx, pair[0].clone(), pair[1].clone(), kind.clone()))
+            .collect::<rlua::Result<Vec<_>>>()
+    })?;
+    ctx.globals().set("keyframes", keyframes)?;
+
+    // Charts give angles in degrees by default, so this does too.
+    let angle_to = ctx.create_function(|_, (from, to): (WorldPos, WorldPos)| {
+        Ok(WorldPos::angle_to(from, to).to_degrees())
+    })?;
+    ctx.globals().set("angle_to", angle_to)?;
+    Ok(())
+}
+
+/// The name of the registry table holding the patterns defined by
+/// `define_pattern`.
+const PATTERNS_REGISTRY_KEY: &str = "rthm_patterns";
+
+/// Register the `define_pattern` and `use_pattern` functions, which let a chart
+/// write a pattern once and use it at many beats.
+/// - `define_pattern(name, pattern)` defines a pattern, which is either a list
+///   of sequence steps or a function which takes a table of parameters and
+///   returns a list of sequence steps.
+/// - `use_pattern { name = ..., beat = ..., enemygroup = ..., params = {...} }`
+///   returns a sequence of the pattern's steps, starting at `beat`.
+///   Function patterns are called with `params`. For list patterns, `params`
+///   is instead merged into every step, overriding the step's own values.
+///
+/// Patterns are expanded while the chart runs, so using a pattern is the same
+/// as writing its steps out in full.
+fn register_patterns(ctx: rlua::Context) -> rlua::Result<()> {
+    ctx.set_named_registry_value(PATTERNS_REGISTRY_KEY, ctx.create_table()?)?;
+
+    let define_pattern = ctx.create_function(|ctx, (name, pattern): (String, rlua::Value)| {
+        match pattern {
+            rlua::Value::Table(_) | rlua::Value::Function(_) => (),
+            x => {
+                return Err(rlua::Error::external(format!(
+                    "Pattern {:?} must be a table or a function, got {:?}",
+                    name, x
+                )))
+            }
+        }
+        let patterns: Table = ctx.named_registry_value(PATTERNS_REGISTRY_KEY)?;
+        patterns.set(name, pattern)
+    })?;
+    ctx.globals().set("define_pattern", define_pattern)?;
+
+    let use_pattern = ctx.create_function(|ctx, usage: Table| {
+        let name = get_key::<String>(&usage, "name")?;
+        let params = get_key_or::<Option<Table>>(&usage, "params", None)?;
+        let patterns: Table = ctx.named_registry_value(PATTERNS_REGISTRY_KEY)?;
+        let steps = match patterns.get::<_, rlua::Value>(name.as_str())? {
+            rlua::Value::Function(pattern) => {
+                let params = match params {
+                    Some(params) => params,
+                    None => ctx.create_table()?,
+                };
+                pattern.call::<_, Table>(params)?
+            }
+            rlua::Value::Table(pattern) => {
+                let steps = ctx.create_table()?;
+                for (i, step) in pattern.sequence_values::<Table>().enumerate() {
+                    let new_step = ctx.create_table()?;
+                    for table in [Some(step?), params.clone()].iter().flatten() {
+                        for pair in table.clone().pairs::<rlua::Value, rlua::Value>() {
+                            let (key, value) = pair?;
+                            new_step.set(key, value)?;
+                        }
+                    }
+                    steps.set(i + 1, new_step)?;
+                }
+                steps
+            }
+            _ => {
+                return Err(rlua::Error::external(format!(
+                    "No pattern named {:?}. Patterns must be defined with define_pattern \
+                     before they are used",
+                    name
+                )))
+            }
+        };
+
+        let sequence = ctx.create_table()?;
+        sequence.set("base_beat", get_key::<f64>(&usage, "beat")?)?;
+        sequence.set(
+            "enemygroup",
+            get_key_or::<Option<usize>>(&usage, "enemygroup", None)?,
+        )?;
+        sequence.set("steps", steps)?;
+        Ok(sequence)
+    })?;
+    ctx.globals().set("use_pattern", use_pattern)?;
+    Ok(())
+}
+
+fn check_subdivision(subdivision: f64) -> rlua::Result<()> {
+    if subdivision > 0.0 {
+        Ok(())
+    } else {
+        Err(rlua::Error::external(format!(
+            "Subdivision must be positive, got {}",
+            subdivision
+        )))
+    }
+}
+
+/// Apply `f` to a Lua value which is either a beat number or a table with a
+/// `beat` key. Tables are copied rather than modified in place. Also returns the
+/// new beat.
+fn map_beat<'lua>(
+    ctx: rlua::Context<'lua>,
+    value: rlua::Value<'lua>,
+    f: impl FnOnce(f64) -> f64,
+) -> rlua::Result<(rlua::Value<'lua>, f64)> {
+    match value {
+        rlua::Value::Table(table) => {
+            let new_table = ctx.create_table()?;
+            for pair in table.clone().pairs::<rlua::Value, rlua::Value>() {
+                let (key, value) = pair?;
+                new_table.set(key, value)?;
+            }
+            let beat = f(get_key(&table, "beat")?);
+            new_table.set("beat", beat)?;
+            Ok((rlua::Value::Table(new_table), beat))
+        }
+        value => {
+            let beat = f(f64::from_lua(value, ctx)?);
+            Ok((rlua::Value::Number(beat), beat))
+        }
+    }
+}
+
+/// Round `beat` to the nearest multiple of `1 / subdivision` beats. For example,
+/// a subdivision of 4 snaps to the nearest sixteenth note (in 4/4 time).
+pub fn quantize(beat: Beats, subdivision: f64) -> Beats {
+    Beats((beat.0 * subdivision).round() / subdivision)
+}
+
+/// Convert a normalized pitch (as given by MarkedBeat::pitch) back into a MIDI
+/// key number.
+pub fn pitch_to_key(pitch: f64) -> u8 {
+    let max_key = midly::num::u7::max_value().as_int() as f64;
+    (pitch * max_key).round().clamp(0.0, max_key) as u8
+}
+
+/// Return the pitch class (C = 0, C# = 1, ..., B = 11) of a normalized pitch.
+pub fn pitch_class(pitch: f64) -> usize {
+    (pitch_to_key(pitch) % 12) as usize
+}
+
+/// Map a normalized pitch into one of `num_lanes` evenly spaced lanes between
+/// `start` and `end`. The lowest pitch maps to `start` and the highest pitch
+/// maps to `end`. If `num_lanes` is zero or one, `start` is returned.
+pub fn pitch_to_lane(pitch: f64, num_lanes: usize, start: WorldPos, end: WorldPos) -> WorldPos {
+    if num_lanes <= 1 {
+        return start;
+    }
+    let lane = (pitch.clamp(0.0, 1.0) * num_lanes as f64).floor() as usize;
+    let lane = lane.min(num_lanes - 1);
+    WorldPos::lerp(start, end, lane as f64 / (num_lanes - 1) as f64)
+}
+
+/// Map a normalized pitch onto a "pitch-class wheel", where each semitone is
+/// 30 degrees apart and C is at 0 degrees. Octaves map to the same angle. The
+/// returned angle is in radians.
+pub fn pitch_wheel_angle(pitch: f64) -> f64 {
+    (pitch_class(pitch) as f64 * 30.0).to_radians()
+}
+
+/// Map a normalized pitch onto the circle of fifths, so that notes a fifth
+/// apart are adjacent (C at 0 degrees, G at 30 degrees, D at 60 degrees, etc).
+/// Octaves map to the same angle. The returned angle is in radians.
+pub fn pitch_fifths_angle(pitch: f64) -> f64 {
+    let fifths_index = (pitch_class(pitch) * 7) % 12;
+    (fifths_index as f64 * 30.0).to_radians()
+}
+
 fn get_track_duration(track: &[TrackEvent], ticks_per_beat: f64) -> Beats {
     let ticks: u32 = track.iter().map(|event| event.delta.as_int()).sum();
     Beats(ticks as f64 / ticks_per_beat)
@@ -526,8 +1891,8 @@ pub fn midi_to_beats_grouped(track: &[TrackEvent], ticks_per_beat: f64) -> Vec<M
             }
         }
 
-        this_group.push(beat);
         last_note = Some(beat.pitch.unwrap());
+        this_group.push(beat);
     }
 
     for (midigroup_id, group) in grouped_beats.iter_mut().enumerate() {
@@ -540,6 +1905,36 @@ pub fn midi_to_beats_grouped(track: &[TrackEvent], ticks_per_beat: f64) -> Vec<M
     grouped_beats.into_iter().flatten().collect()
 }
 
+/// Group notes into chords. Each note gets a Chord describing every note whose
+/// onset is within CHORD_WINDOW of the first note in the chord.
+pub fn midi_to_beats_chords(track: &[TrackEvent], ticks_per_beat: f64) -> Vec<MarkedBeat> {
+    let mut beats = midi_to_beats_ungrouped(track, ticks_per_beat);
+
+    let mut chord_id = 0;
+    let mut chord_start = 0;
+    while chord_start < beats.len() {
+        let first_onset = beats[chord_start].beat;
+        let chord_end = beats[chord_start..]
+            .iter()
+            .position(|beat| beat.beat - first_onset > CHORD_WINDOW)
+            .map_or(beats.len(), |len| chord_start + len);
+
+        let chord = &mut beats[chord_start..chord_end];
+        let pitches: Vec<f64> = chord.iter().filter_map(|beat| beat.pitch).collect();
+        for (i, beat) in chord.iter_mut().enumerate() {
+            beat.chord = Some(Chord {
+                id: chord_id,
+                i,
+                pitches: pitches.clone(),
+            });
+        }
+
+        chord_id += 1;
+        chord_start = chord_end;
+    }
+    beats
+}
+
 pub fn midi_to_beats_ungrouped(track: &[TrackEvent], ticks_per_beat: f64) -> Vec<MarkedBeat> {
     let mut tick_number = 0;
     let mut beats = vec![];
@@ -560,6 +1955,7 @@ pub fn midi_to_beats_ungrouped(track: &[TrackEvent], ticks_per_beat: f64) -> Vec
                 percent: beat.0 / duration.0,
                 pitch: Some(normalized_absolute_pitch(key)),
                 midigroup: None,
+                chord: None,
             });
         }
     }
@@ -583,7 +1979,79 @@ pub fn parse_midi<P: AsRef<Path>, T>(
     func: impl Fn(&[TrackEvent], f64) -> T,
 ) -> anyhow::Result<T> {
     let midi = std::fs::read(path)?;
-    let smf = Smf::parse(&midi)?;
+    parse_midi_bytes(&midi, bpm, func)
+}
+
+/// Like `parse_midi`, but for a MIDI file which has already been read.
+pub fn parse_midi_bytes<T>(
+    midi: &[u8],
+    bpm: f64,
+    func: impl Fn(&[TrackEvent], f64) -> T,
+) -> anyhow::Result<T> {
+    let smf = Smf::parse(midi)?;
     let ticks_per_beat = get_ticks_per_beat(&smf.header, bpm);
     Ok(func(&smf.tracks[0], ticks_per_beat))
 }
+
+/// How the notes of a MIDI file should be turned into MarkedBeats.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MidiGrouping {
+    /// See `midi_to_beats_ungrouped`
+    Ungrouped,
+    /// See `midi_to_beats_grouped`
+    Grouped,
+    /// See `midi_to_beats_chords`
+    Chords,
+}
+
+impl MidiGrouping {
+    fn to_beats(self, track: &[TrackEvent], ticks_per_beat: f64) -> Vec<MarkedBeat> {
+        match self {
+            MidiGrouping::Ungrouped => midi_to_beats_ungrouped(track, ticks_per_beat),
+            MidiGrouping::Grouped => midi_to_beats_grouped(track, ticks_per_beat),
+            MidiGrouping::Chords => midi_to_beats_chords(track, ticks_per_beat),
+        }
+    }
+}
+
+// The BPM is stored as its bit pattern here so that the key can be hashed.
+type MidiCacheKey = (PathBuf, u64, MidiGrouping);
+
+/// Parsed MIDI files, along with the modification time of the file when it
+/// was parsed. This lives for the entire run of the program so that reloading
+/// a chart doesn't need to reparse MIDI files which haven't changed.
+static MIDI_CACHE: Lazy<Mutex<HashMap<MidiCacheKey, (SystemTime, Vec<MarkedBeat>)>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Return how many parsed MIDI files are in the cache.
+pub fn midi_cache_len() -> usize {
+    MIDI_CACHE.lock().unwrap().len()
+}
+
+/// Parse the MIDI file at `path`, reusing the previous result if this file was
+/// already parsed with the same bpm and grouping and hasn't been modified since.
+pub fn read_midi_cached(
+    vfs: &Vfs,
+    path: impl AsRef<Path>,
+    bpm: f64,
+    grouping: MidiGrouping,
+) -> anyhow::Result<Vec<MarkedBeat>> {
+    let path = path.as_ref();
+    let modified = vfs.modified(path)?;
+    let key = (path.to_path_buf(), bpm.to_bits(), grouping);
+
+    if let Some((cached_modified, beats)) = MIDI_CACHE.lock().unwrap().get(&key) {
+        if *cached_modified == modified {
+            return Ok(beats.clone());
+        }
+    }
+
+    let beats = parse_midi_bytes(&vfs.read(path)?, bpm, |track, ticks_per_beat| {
+        grouping.to_beats(track, ticks_per_beat)
+    })?;
+    MIDI_CACHE
+        .lock()
+        .unwrap()
+        .insert(key, (modified, beats.clone()));
+    Ok(beats)
+}
@@ -0,0 +1,273 @@
+/// This module implements particles, which are sparks thrown off when the
+/// player is hit, grazes an enemy, or uses a bomb. Particles are purely visual
+/// and never affect gameplay. They fly outwards, slow down, bounce off of
+/// hitboxes (see `Particles::deflect`), and fade away over their lifetime. How
+/// many particles may exist at once depends on how far visuals are degraded
+/// (see `budget::Degradation::max_particles`), and bursts which would go over
+/// the limit are cut short.
+use ggez::graphics::{BlendMode, Color, DrawMode, DrawParam, Drawable, MeshBuilder};
+use ggez::{Context, GameResult};
+use rand::Rng;
+
+use crate::budget;
+use crate::color;
+use crate::event::GameEvent;
+use crate::instanced::{Circle, CircleBatch};
+use crate::time::Beats;
+use crate::world::{WorldLen, WorldPos};
+use crate::{EnemyGroup, GroupEnemy};
+
+/// How much of a particle's speed is kept after each second.
+const DRAG: f64 = 0.05;
+
+/// What a burst of particles looks like. See `Particles::burst`.
+#[derive(Debug, Clone, Copy)]
+pub struct BurstStyle {
+    pub count: usize,
+    /// How fast the particles start out, in WorldLen units per second.
+    pub speed: f64,
+    /// How many seconds each particle lasts for.
+    pub lifetime: f64,
+    pub radius: WorldLen,
+    pub color: Color,
+}
+
+/// The burst thrown off when the player is hit.
+pub const HIT_BURST: BurstStyle = BurstStyle {
+    count: 24,
+    speed: 60.0,
+    lifetime: 0.5,
+    radius: WorldLen(0.5),
+    color: color::RED,
+};
+/// The burst thrown off when the player grazes an enemy.
+pub const GRAZE_BURST: BurstStyle = BurstStyle {
+    count: 4,
+    speed: 30.0,
+    lifetime: 0.25,
+    radius: WorldLen(0.3),
+    color: color::WHITE,
+};
+/// The burst thrown off when the player uses a bomb.
+pub const BOMB_BURST: BurstStyle = BurstStyle {
+    count: 96,
+    speed: 120.0,
+    lifetime: 0.75,
+    radius: WorldLen(0.75),
+    color: color::BOMB_PURPLE,
+};
+
+#[derive(Debug, Clone)]
+pub struct Particle {
+    pub pos: WorldPos,
+    /// In WorldLen units per second.
+    pub velocity: (f64, f64),
+    /// How many seconds the particle has existed for.
+    age: f64,
+    lifetime: f64,
+    radius: WorldLen,
+    color: Color,
+}
+
+impl Particle {
+    /// How far through its lifetime the particle is, from 0 to 1.
+    fn progress(&self) -> f64 {
+        (self.age / self.lifetime).clamp(0.0, 1.0)
+    }
+
+    /// The particle's color, which fades out over its lifetime.
+    fn faded_color(&self) -> Color {
+        let mut color = self.color;
+        color.a *= (1.0 - self.progress()) as f32;
+        color
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Particles {
+    particles: Vec<Particle>,
+}
+
+impl Particles {
+    pub fn new() -> Particles {
+        Particles::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.particles.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.particles.is_empty()
+    }
+
+    /// Throw off a burst of particles from `pos`, evenly spread out in every
+    /// direction with a little randomness.
+    pub fn burst(&mut self, pos: WorldPos, style: BurstStyle) {
+        let room = budget::degradation()
+            .max_particles()
+            .saturating_sub(self.particles.len());
+        let count = style.count.min(room);
+        let mut rng = rand::thread_rng();
+        for i in 0..count {
+            let angle = (i as f64 + rng.gen_range(0.0..1.0)) / count as f64 * std::f64::consts::TAU;
+            let speed = style.speed * rng.gen_range(0.5..1.0);
+            self.particles.push(Particle {
+                pos,
+                velocity: (angle.cos() * speed, angle.sin() * speed),
+                age: 0.0,
+                lifetime: style.lifetime * rng.gen_range(0.75..1.0),
+                radius: style.radius,
+                color: style.color,
+            });
+        }
+    }
+
+    /// Throw off the bursts for the events of the most recent update.
+    pub fn burst_for_events(&mut self, events: &[GameEvent]) {
+        for event in events {
+            match *event {
+                GameEvent::PlayerHit { pos, .. } => self.burst(pos, HIT_BURST),
+                GameEvent::Grazed { pos, .. } => self.burst(pos, GRAZE_BURST),
+                GameEvent::BombUsed { pos, .. } => self.burst(pos, BOMB_BURST),
+                _ => (),
+            }
+        }
+    }
+
+    /// Move every particle and remove the ones which have faded away.
+    pub fn update(&mut self, dt: f64) {
+        let drag = DRAG.powf(dt);
+        for particle in self.particles.iter_mut() {
+            particle.pos.x += particle.velocity.0 * dt;
+            particle.pos.y += particle.velocity.1 * dt;
+            particle.velocity = (particle.velocity.0 * drag, particle.velocity.1 * drag);
+            particle.age += dt;
+        }
+        self.particles
+            .retain(|particle| particle.age < particle.lifetime);
+    }
+
+    /// Bounce every particle which has flown into the hitbox of an enemy back
+    /// off of it, reflecting the particle's velocity about the enemy's sdf
+    /// gradient. Particles already moving away from the hitbox are left alone,
+    /// so that bursts thrown off inside one (ex: when the player is hit) fly
+    /// out of it.
+    pub fn deflect(&mut self, groups: &[EnemyGroup], curr_time: Beats) {
+        if self.particles.is_empty() {
+            return;
+        }
+        for group in groups.iter().filter(|group| group.use_hitbox) {
+            let rotated_about = group.rotation_ease(curr_time);
+            for GroupEnemy { enemy, .. } in &group.enemies {
+                for particle in self.particles.iter_mut() {
+                    let (pos, radius) = (particle.pos, particle.radius);
+                    if !enemy.could_touch(pos, radius, curr_time, rotated_about) {
+                        continue;
+                    }
+                    match enemy.sdf(pos, curr_time, rotated_about) {
+                        Some(sdf) if sdf < radius => (),
+                        _ => continue,
+                    }
+                    let (x, y) = match enemy.sdf_gradient(pos, curr_time, rotated_about) {
+                        Some(gradient) => gradient,
+                        None => continue,
+                    };
+                    let length = x.hypot(y);
+                    if length <= 0.0 {
+                        continue;
+                    }
+                    let (x, y) = (x / length, y / length);
+                    let dot = particle.velocity.0 * x + particle.velocity.1 * y;
+                    if dot < 0.0 {
+                        particle.velocity.0 -= 2.0 * dot * x;
+                        particle.velocity.1 -= 2.0 * dot * y;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Draw every particle, in the `circle_batch` if it is available and as a
+    /// mesh otherwise.
+    pub fn draw(&self, ctx: &mut Context, circle_batch: &mut CircleBatch) -> GameResult<()> {
+        if self.particles.is_empty() {
+            return Ok(());
+        }
+        if circle_batch.available() {
+            for particle in &self.particles {
+                circle_batch.add(Circle {
+                    center: particle.pos.as_screen_coords(),
+                    radius: particle.radius.as_screen_length(),
+                    color: particle.faded_color(),
+                });
+            }
+            return circle_batch.draw(ctx, BlendMode::Add);
+        }
+        let mut mesh = MeshBuilder::new();
+        for particle in &self.particles {
+            mesh.circle(
+                DrawMode::fill(),
+                particle.pos.as_screen_coords(),
+                particle.radius.as_screen_length(),
+                0.5,
+                particle.faded_color(),
+            )?;
+        }
+        let mut mesh = mesh.build(ctx)?;
+        mesh.set_blend_mode(Some(BlendMode::Add));
+        mesh.draw(ctx, DrawParam::default())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::enemy::{CircleBomb, BOMB_WARMUP};
+    use crate::player::Player;
+    use crate::telegraph::TelegraphStyle;
+    use crate::InnerWorldState;
+
+    #[test]
+    pub fn test_particles_fade_away() {
+        let mut particles = Particles::new();
+        particles.burst(WorldPos::origin(), HIT_BURST);
+        assert_eq!(particles.len(), HIT_BURST.count);
+        particles.update(HIT_BURST.lifetime / 2.0);
+        assert!(particles
+            .particles
+            .iter()
+            .all(|particle| WorldPos::distance(particle.pos, WorldPos::origin()).0 > 0.0));
+        particles.update(HIT_BURST.lifetime);
+        assert!(particles.is_empty());
+    }
+
+    #[test]
+    pub fn test_bursts_are_capped() {
+        let mut particles = Particles::new();
+        let max_particles = budget::degradation().max_particles();
+        for _ in 0..max_particles / BOMB_BURST.count + 2 {
+            particles.burst(WorldPos::origin(), BOMB_BURST);
+        }
+        assert_eq!(particles.len(), max_particles);
+    }
+
+    #[test]
+    pub fn test_particles_bounce_off_hitboxes() {
+        let mut world = InnerWorldState::new(Player::default());
+        let bomb = CircleBomb::new(Beats(0.0), WorldPos::origin(), TelegraphStyle::Default);
+        world.groups[0].spawn(Box::new(bomb));
+
+        let mut particles = Particles::new();
+        particles.burst(WorldPos { x: 5.0, y: 0.0 }, HIT_BURST);
+        for particle in particles.particles.iter_mut() {
+            particle.velocity = (-10.0, 0.0);
+        }
+        particles.deflect(&world.groups, BOMB_WARMUP + Beats(0.5));
+        assert!(particles
+            .particles
+            .iter()
+            .all(|particle| (particle.velocity.0 - 10.0).abs() < 0.0001));
+    }
+}
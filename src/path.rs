@@ -0,0 +1,190 @@
+/// This module implements the paths which moving enemies travel along, and the
+/// previews drawn of them. A preview shows the rest of a path as a line ending
+/// in a circle where the enemy will arrive, and may also have a tick which
+/// moves along the path, so that the player can see which way an enemy is
+/// going to move before it starts moving. Previews are added to the enemy's own
+/// mesh, so they are built relative to wherever the enemy is drawn.
+use ggez::graphics::{Color, DrawMode, MeshBuilder};
+use ggez::GameResult;
+use serde::{Deserialize, Serialize};
+
+use crate::ease::Lerp;
+use crate::meshutil;
+use crate::world::{WorldLen, WorldPos, WORLD_SCALE_FACTOR};
+
+/// How far the tick of a preview extends to either side of the path, in
+/// WorldLen units.
+pub const TICK_HALF_LENGTH: f64 = 1.5;
+/// How many segments curved paths are split into when previewed.
+const CURVE_SEGMENTS: usize = 32;
+/// How far apart the points used to find the direction of a path are, as a
+/// fraction of the path.
+const TANGENT_STEP: f64 = 0.01;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum Path {
+    /// A straight line from `start` to `end`.
+    Line { start: WorldPos, end: WorldPos },
+    /// A quadratic Bezier curve from `start` to `end`, which bends towards
+    /// `control`.
+    Bezier {
+        start: WorldPos,
+        control: WorldPos,
+        end: WorldPos,
+    },
+}
+
+impl Path {
+    /// Return the point `t` of the way along the path, where 0 is the start
+    /// and 1 is the end. `t` is clamped to that range.
+    pub fn pos(&self, t: f64) -> WorldPos {
+        match *self {
+            Path::Line { start, end } => WorldPos::lerp(start, end, t),
+            Path::Bezier {
+                start,
+                control,
+                end,
+            } => {
+                let towards_control = WorldPos::lerp(start, control, t);
+                let from_control = WorldPos::lerp(control, end, t);
+                WorldPos::lerp(towards_control, from_control, t)
+            }
+        }
+    }
+
+    pub fn end(&self) -> WorldPos {
+        match *self {
+            Path::Line { end, .. } | Path::Bezier { end, .. } => end,
+        }
+    }
+
+    /// Return a circle, as a center and a radius, which contains the part of
+    /// the path from `t` to the end.
+    pub fn bounds(&self, t: f64) -> (WorldPos, WorldLen) {
+        // The rest of a Bezier curve is contained by its current point and
+        // the remaining control points.
+        let points = match *self {
+            Path::Line { end, .. } => vec![self.pos(t), end],
+            Path::Bezier { control, end, .. } => vec![self.pos(t), control, end],
+        };
+        let (mut min, mut max) = (points[0], points[0]);
+        for point in &points {
+            min = WorldPos {
+                x: min.x.min(point.x),
+                y: min.y.min(point.y),
+            };
+            max = WorldPos {
+                x: max.x.max(point.x),
+                y: max.y.max(point.y),
+            };
+        }
+        let center = WorldPos::lerp(min, max, 0.5);
+        (center, WorldPos::distance(center, max))
+    }
+
+    fn segments(&self) -> usize {
+        match self {
+            Path::Line { .. } => 1,
+            Path::Bezier { .. } => CURVE_SEGMENTS,
+        }
+    }
+}
+
+/// How to draw the preview of a path.
+#[derive(Debug, Clone, Copy)]
+pub struct PathPreview {
+    /// How far along the path the enemy is. Only the path from here on is
+    /// drawn.
+    pub from: f64,
+    /// How far along the path to draw the tick, if there is one.
+    pub tick: Option<f64>,
+    /// The radius of the circle at the end of the path.
+    pub end_radius: f32,
+    pub thickness: f32,
+    pub color: Color,
+}
+
+impl PathPreview {
+    /// Add the preview of `path` to the mesh, for a mesh drawn at `origin`.
+    pub fn draw(&self, mesh: &mut MeshBuilder, path: &Path, origin: WorldPos) -> GameResult<()> {
+        let local = |pos: WorldPos| {
+            WorldPos {
+                x: pos.x - origin.x,
+                y: pos.y - origin.y,
+            }
+            .as_mint()
+        };
+        let end = path.end();
+        meshutil::circle(
+            mesh,
+            DrawMode::stroke(self.thickness),
+            local(end),
+            self.end_radius,
+            WORLD_SCALE_FACTOR,
+            self.color,
+        )?;
+
+        // The line stops at the edge of the circle.
+        let end_radius = self.end_radius as f64;
+        let segments = path.segments();
+        let mut points: Vec<WorldPos> = (0..=segments)
+            .map(|i| path.pos(f64::lerp(self.from, 1.0, i as f64 / segments as f64)))
+            .take_while(|&pos| WorldPos::distance(pos, end).0 > end_radius)
+            .collect();
+        if let Some(&last) = points.last() {
+            let distance = WorldPos::distance(last, end).0;
+            points.push(WorldPos::lerp(end, last, end_radius / distance));
+        }
+        if points.len() >= 2 {
+            let points: Vec<_> = points.into_iter().map(local).collect();
+            meshutil::thick_line(mesh, &points, self.thickness / 2.0, self.color)?;
+        }
+
+        if let Some(t) = self.tick {
+            let ahead = path.pos(t + TANGENT_STEP);
+            let behind = path.pos(t - TANGENT_STEP);
+            let (dx, dy) = (ahead.x - behind.x, ahead.y - behind.y);
+            let length = dx.hypot(dy);
+            if length > 0.0 {
+                let pos = path.pos(t);
+                let (x, y) = (-dy / length, dx / length);
+                let a = WorldPos {
+                    x: pos.x - x * TICK_HALF_LENGTH,
+                    y: pos.y - y * TICK_HALF_LENGTH,
+                };
+                let b = WorldPos {
+                    x: pos.x + x * TICK_HALF_LENGTH,
+                    y: pos.y + y * TICK_HALF_LENGTH,
+                };
+                meshutil::thick_line(
+                    mesh,
+                    &[local(a), local(b)],
+                    self.thickness / 2.0,
+                    self.color,
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_bounds_contain_rest_of_path() {
+        let path = Path::Bezier {
+            start: WorldPos { x: -10.0, y: 0.0 },
+            control: WorldPos { x: 0.0, y: 20.0 },
+            end: WorldPos { x: 10.0, y: 0.0 },
+        };
+        for &from in &[0.0, 0.5] {
+            let (center, radius) = path.bounds(from);
+            for i in 0..=10 {
+                let t = f64::lerp(from, 1.0, i as f64 / 10.0);
+                assert!(WorldPos::distance(center, path.pos(t)).0 <= radius.0 + 1e-9);
+            }
+        }
+    }
+}
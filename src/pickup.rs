@@ -0,0 +1,121 @@
+/// This module implements pickups, which are harmless items the player can
+/// collect by touching them. While focusing (holding the slow movement key),
+/// nearby pickups are pulled towards the player, so that collecting them
+/// doesn't require leaving a safe spot at full speed. Pickups left alone for
+/// too long disappear.
+/// Gems are counted in the run's stats. Bomb pickups give the player another
+/// bomb, and heal pickups give the player a shield, which absorbs their next
+/// hit (see `Player`). Every kind of pickup counts towards `collect`
+/// objectives.
+use ggez::graphics::{DrawMode, DrawParam, Drawable, Mesh};
+use ggez::{Context, GameResult};
+use serde::{Deserialize, Serialize};
+
+use crate::color;
+use crate::player::Player;
+use crate::time::Beats;
+use crate::world::{WorldLen, WorldPos};
+
+/// How close a pickup must be to the focusing player to be pulled in.
+pub const MAGNET_RADIUS: WorldLen = WorldLen(20.0);
+/// How fast pulled in pickups move, in WorldLen units per second.
+const MAGNET_SPEED: f64 = 60.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PickupKind {
+    Gem,
+    Bomb,
+    Heal,
+}
+
+#[derive(Debug, Clone)]
+pub struct Pickup {
+    pub kind: PickupKind,
+    pub pos: WorldPos,
+    radius: WorldLen,
+    start_time: Beats,
+    duration: Beats,
+}
+
+impl Pickup {
+    pub fn new(
+        kind: PickupKind,
+        pos: WorldPos,
+        radius: WorldLen,
+        start_time: Beats,
+        duration: Beats,
+    ) -> Pickup {
+        Pickup {
+            kind,
+            pos,
+            radius,
+            start_time,
+            duration,
+        }
+    }
+
+    /// Returns true if the pickup should be removed from the world.
+    pub fn is_dead(&self, curr_time: Beats) -> bool {
+        curr_time > self.start_time + self.duration
+    }
+
+    /// Give the player whatever the pickup holds.
+    pub fn collect(&self, player: &mut Player) {
+        match self.kind {
+            PickupKind::Gem => (),
+            PickupKind::Bomb => player.bombs += 1,
+            PickupKind::Heal => player.shields += 1,
+        }
+    }
+
+    /// Pull the pickup towards the player if they are focusing and nearby.
+    /// Returns true if the player touched the pickup, in which case it should
+    /// be removed from the world.
+    pub fn update(&mut self, player: &Player, focused: bool, dt: f64) -> bool {
+        let distance = WorldPos::distance(player.pos, self.pos);
+        if focused && distance < MAGNET_RADIUS && distance.0 > 0.0 {
+            let step = (MAGNET_SPEED * dt).min(distance.0);
+            self.pos.x += (player.pos.x - self.pos.x) / distance.0 * step;
+            self.pos.y += (player.pos.y - self.pos.y) / distance.0 * step;
+        }
+        // The sdf of the player is a circle, so the pickup is touched once the
+        // circles overlap.
+        WorldPos::distance(player.pos, self.pos) <= player.size + self.radius
+    }
+
+    pub fn draw(&self, ctx: &mut Context) -> GameResult<()> {
+        let color = match self.kind {
+            PickupKind::Gem => color::GEM_YELLOW,
+            PickupKind::Bomb => color::BOMB_PURPLE,
+            PickupKind::Heal => color::GREEN,
+        };
+        let center = self.pos.as_screen_coords();
+        let radius = self.radius.as_screen_length();
+        // Pickups are drawn as diamonds, so they can't be mistaken for bullets.
+        let points = [
+            [center.x, center.y - radius],
+            [center.x + radius, center.y],
+            [center.x, center.y + radius],
+            [center.x - radius, center.y],
+        ];
+        Mesh::new_polygon(ctx, DrawMode::fill(), &points, color)?.draw(ctx, DrawParam::default())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_magnet_only_while_focused() {
+        let player = Player::new(1.0, WorldLen(1.0));
+        let far = WorldPos { x: 10.0, y: 0.0 };
+        let mut pickup = Pickup::new(PickupKind::Gem, far, WorldLen(1.0), Beats(0.0), Beats(4.0));
+
+        assert!(!pickup.update(&player, false, 0.1));
+        assert_eq!(pickup.pos.x, far.x);
+
+        // 10 units away at 60 units per second takes under a fifth of a second.
+        assert!(pickup.update(&player, true, 0.2));
+    }
+}
@@ -1,19 +1,65 @@
 use ggez::graphics::{Color, DrawMode, Mesh};
 use ggez::{Context, GameResult};
+use serde::{Deserialize, Serialize};
 
 use crate::color::{self, RED, WHITE};
 use crate::ease::Lerp;
+use crate::force::ForceField;
 use crate::keyboard::KeyboardState;
-use crate::world::{WorldLen, WorldPos};
+use crate::meshutil;
+use crate::obstacle::Obstacle;
+use crate::time::Beats;
+use crate::world::{ScreenTransform, WorldLen, WorldPos, ARENA_HALF_SIZE};
 
-const HIT_TIME_LENGTH: f64 = 1.5; // How many seconds the hit timer should be
+/// How many seconds the player flashes red for after being hit. Unless the
+/// chart sets a `hit_cooldown`, the player is also invulnerable for this long,
+/// which keeps the cooldown the same length at every BPM.
+const HIT_TIME_LENGTH: f64 = 1.5;
+const OBSTACLE_ITERATIONS: usize = 4; // How many times to resolve obstacle collisions
+const TELEPORT_INVULN_LENGTH: f64 = 0.5; // How many seconds of invulnerability a teleport gives
+/// How much the player's speed is multiplied by while focusing, unless the
+/// chart or the player sets otherwise.
+pub const DEFAULT_FOCUS_FACTOR: f64 = 0.2;
+/// How long the player is invulnerable for after using a bomb.
+const BOMB_INVULN_LENGTH: Beats = Beats(2.0);
+/// How many seconds it takes for most of a knockback to happen. The player
+/// slows down exponentially over the course of a knockback.
+const KNOCKBACK_TIME: f64 = 0.1;
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub struct Player {
     pub pos: WorldPos, // The current position of the Player
     speed: f64,        // In WorldLen units per second
     pub size: WorldLen,
+    /// How much the player's speed is multiplied by while focusing.
+    #[serde(default = "default_focus_factor")]
+    pub focus_factor: f64,
+    /// How long the player is invulnerable for after being hit. If None, the
+    /// player is invulnerable for HIT_TIME_LENGTH seconds instead.
+    #[serde(default)]
+    pub hit_cooldown: Option<Beats>,
+    /// How far the player is pushed away from whatever hits them, in WorldLen
+    /// units. If 0, the player isn't pushed at all.
+    #[serde(default)]
+    pub knockback: f64,
+    /// How many bombs the player has. Charts set how many the player starts
+    /// with, and bomb pickups give the player more. See `use_bomb`.
+    #[serde(default)]
+    pub bombs: u32,
+    /// How many hits the player can take without them counting, from heal
+    /// pickups.
+    #[serde(skip)]
+    pub shields: u32,
+    #[serde(skip)]
     hit_timer: f64,
+    #[serde(skip)]
+    invuln_timer: f64,
+    /// The beat the player stops being invulnerable from their last hit.
+    #[serde(skip)]
+    invuln_until: Option<Beats>,
+    /// The velocity of the current knockback, in WorldLen units per second.
+    #[serde(skip)]
+    knockback_velocity: (f64, f64),
 }
 
 impl Player {
@@ -22,12 +68,29 @@ impl Player {
             pos: WorldPos { x: 0.0, y: 0.0 },
             speed,
             size,
+            focus_factor: DEFAULT_FOCUS_FACTOR,
+            hit_cooldown: None,
+            knockback: 0.0,
+            bombs: 0,
+            shields: 0,
             hit_timer: 0.0,
+            invuln_timer: 0.0,
+            invuln_until: None,
+            knockback_velocity: (0.0, 0.0),
         }
     }
 
+    /// How fast the player moves along each axis, in WorldLen units per
+    /// second. Moving diagonally moves at this speed along both axes at once.
+    pub fn speed(&self) -> f64 {
+        self.speed
+    }
+
     pub fn color(&self) -> Color {
-        if self.hit_timer <= 0.0 {
+        if self.invuln_timer > 0.0 {
+            let percent = self.invuln_timer / TELEPORT_INVULN_LENGTH;
+            Color::lerp(WHITE, color::TELEPORT_BLUE, percent)
+        } else if self.hit_timer <= 0.0 {
             color::WHITE
         } else {
             let percent = (self.hit_timer as f64) / HIT_TIME_LENGTH as f64;
@@ -40,14 +103,96 @@ impl Player {
         }
     }
 
-    pub fn on_hit(&mut self) {
+    /// Returns true if the player was hit within the last `hit_cooldown`
+    /// beats (or HIT_TIME_LENGTH seconds), used a bomb recently, or has just
+    /// teleported.
+    pub fn invulnerable(&self, curr_time: Beats) -> bool {
+        self.invuln_timer > 0.0
+            || (self.hit_cooldown.is_none() && self.hit_timer > 0.0)
+            || self.invuln_until.map_or(false, |until| curr_time < until)
+    }
+
+    /// Mark the player as hit, unless they are invulnerable. Returns true if
+    /// the hit counts. Hits taken while the player has a shield use up the
+    /// shield instead of counting, but still make the player invulnerable.
+    pub fn on_hit(&mut self, curr_time: Beats) -> bool {
+        if self.invulnerable(curr_time) {
+            return false;
+        }
+        self.hurt(curr_time)
+    }
+
+    /// Mark the player as hit by contact damage, which ignores the cooldown of
+    /// earlier hits, but not the invulnerability from teleporting. Returns true
+    /// if the hit counts.
+    pub fn on_contact_hit(&mut self, curr_time: Beats) -> bool {
+        if self.invuln_timer > 0.0 {
+            return false;
+        }
+        self.hurt(curr_time)
+    }
+
+    /// Returns true if the hit counts, which it doesn't if it used up a shield.
+    fn hurt(&mut self, curr_time: Beats) -> bool {
         self.hit_timer = HIT_TIME_LENGTH;
+        if let Some(hit_cooldown) = self.hit_cooldown {
+            self.invuln_until = Some(curr_time + hit_cooldown);
+        }
+        if self.shields > 0 {
+            self.shields -= 1;
+            return false;
+        }
+        true
+    }
+
+    /// Push the player in `direction` by `knockback` units over the next few
+    /// frames. Zero directions are ignored.
+    pub fn knock_back(&mut self, (x, y): (f64, f64)) {
+        let length = x.hypot(y);
+        if length <= 0.0 {
+            return;
+        }
+        let speed = self.knockback / KNOCKBACK_TIME / length;
+        self.knockback_velocity = (x * speed, y * speed);
+    }
+
+    /// Use up one of the player's bombs, making them invulnerable for a short
+    /// time. Returns false if the player has no bombs left. The world clears
+    /// the enemies around the player (see `InnerWorldState::use_bomb`).
+    pub fn use_bomb(&mut self, curr_time: Beats) -> bool {
+        if self.bombs == 0 {
+            return false;
+        }
+        self.bombs -= 1;
+        let until = curr_time + BOMB_INVULN_LENGTH;
+        self.invuln_until = Some(match self.invuln_until {
+            Some(invuln_until) if invuln_until > until => invuln_until,
+            _ => until,
+        });
+        true
+    }
+
+    /// Move the player to `pos`, making them briefly invulnerable so that they
+    /// aren't hit by something they had no chance to see.
+    pub fn teleport(&mut self, pos: WorldPos) {
+        self.pos = pos;
+        self.invuln_timer = TELEPORT_INVULN_LENGTH;
     }
 
     /// Move the Player closer to the next keyframe, and drop that keyframe if
     /// sufficiently close. The last keyframe never drops as that is the latest
-    /// intended position.
-    pub fn update(&mut self, dt: f64, keyboard: &KeyboardState) {
+    /// intended position. The player is then pushed around by any knockback and
+    /// force fields, and pushed out of any obstacles. Input is given relative to
+    /// the screen, so it is untransformed by `screen_transform` before moving
+    /// the player.
+    pub fn update(
+        &mut self,
+        dt: f64,
+        keyboard: &KeyboardState,
+        screen_transform: ScreenTransform,
+        obstacles: &[Obstacle],
+        force_fields: &[ForceField],
+    ) {
         if let Ok(direction) = keyboard.direction() {
             use crate::util::Direction8::*;
             let delta_x = match direction {
@@ -61,25 +206,55 @@ impl Player {
                 Left | Right => 0.0,
             };
 
-            let slow = if keyboard.space.is_down { 0.2 } else { 1.0 };
+            let (delta_x, delta_y) = screen_transform.apply_inverse(delta_x, delta_y);
+
+            let slow = if keyboard.space.is_down {
+                self.focus_factor
+            } else {
+                1.0
+            };
 
             self.pos.x += delta_x * dt * self.speed * slow;
             self.pos.y += delta_y * dt * self.speed * slow;
+        }
+
+        // Integrate the knockback's decay exactly, so that the player is
+        // pushed the same distance at any tick rate.
+        let decay = (-dt / KNOCKBACK_TIME).exp();
+        let (velocity_x, velocity_y) = self.knockback_velocity;
+        self.pos.x += velocity_x * KNOCKBACK_TIME * (1.0 - decay);
+        self.pos.y += velocity_y * KNOCKBACK_TIME * (1.0 - decay);
+        self.knockback_velocity = (velocity_x * decay, velocity_y * decay);
 
-            self.pos.y = self.pos.y.clamp(-50.0, 50.0);
-            self.pos.x = self.pos.x.clamp(-50.0, 50.0);
+        for force_field in force_fields {
+            let (velocity_x, velocity_y) = force_field.velocity(self.pos);
+            self.pos.x += velocity_x * dt;
+            self.pos.y += velocity_y * dt;
         }
 
+        // Pushing out of one obstacle may push the player into another, so
+        // repeat a few times to settle into a spot clear of all of them.
+        for _ in 0..OBSTACLE_ITERATIONS {
+            for obstacle in obstacles {
+                self.pos = obstacle.push_out(self.pos, self.size);
+            }
+        }
+
+        self.pos.y = self.pos.y.clamp(-ARENA_HALF_SIZE, ARENA_HALF_SIZE);
+        self.pos.x = self.pos.x.clamp(-ARENA_HALF_SIZE, ARENA_HALF_SIZE);
+
         self.hit_timer -= dt;
+        self.invuln_timer -= dt;
     }
 
     pub fn get_mesh(&self, ctx: &mut Context) -> GameResult<Mesh> {
+        let radius = self.size.as_screen_length();
         Mesh::new_circle(
             ctx,
             DrawMode::fill(),
             [0.0, 0.0],
-            self.size.as_screen_length(),
-            0.1,
+            radius,
+            meshutil::tolerance(radius, 1.0),
             self.color(),
         )
     }
@@ -91,7 +266,120 @@ impl Default for Player {
             pos: WorldPos { x: 0.0, y: 0.0 },
             speed: 100.0,
             size: WorldLen(2.0),
+            focus_factor: DEFAULT_FOCUS_FACTOR,
+            hit_cooldown: None,
+            knockback: 0.0,
+            bombs: 0,
+            shields: 0,
             hit_timer: 0.0,
+            invuln_timer: 0.0,
+            invuln_until: None,
+            knockback_velocity: (0.0, 0.0),
         }
     }
 }
+
+fn default_focus_factor() -> f64 {
+    DEFAULT_FOCUS_FACTOR
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const COOLDOWN: Beats = Beats(2.0);
+
+    // Move right for a quarter of a second at the given tick rate, returning
+    // how far the player moved. This is short enough that the player doesn't
+    // reach the edge of the arena.
+    fn distance_moved(tick_rate: u32) -> f64 {
+        let mut keyboard = KeyboardState::default();
+        keyboard.right.is_down = true;
+        let mut player = Player::default();
+        let dt = 1.0 / f64::from(tick_rate);
+        for _ in 0..tick_rate / 4 {
+            player.update(dt, &keyboard, ScreenTransform::default(), &[], &[]);
+        }
+        player.pos.x
+    }
+
+    #[test]
+    pub fn test_speed_independent_of_tick_rate() {
+        let slow = distance_moved(60);
+        for &tick_rate in &[120, 240] {
+            let fast = distance_moved(tick_rate);
+            if (slow - fast).abs() > 0.0001 {
+                panic!("{} does not approx. equal {}", slow, fast);
+            }
+        }
+    }
+
+    #[test]
+    pub fn test_knockback_distance() {
+        for &tick_rate in &[60, 240] {
+            let mut player = Player {
+                knockback: 5.0,
+                ..Player::default()
+            };
+            player.knock_back((0.0, -1.0));
+            let dt = 1.0 / f64::from(tick_rate);
+            for _ in 0..tick_rate {
+                player.update(
+                    dt,
+                    &KeyboardState::default(),
+                    ScreenTransform::default(),
+                    &[],
+                    &[],
+                );
+            }
+            assert!((player.pos.y + 5.0).abs() < 0.001);
+        }
+    }
+
+    #[test]
+    pub fn test_hit_cooldown() {
+        let mut player = Player {
+            hit_cooldown: Some(COOLDOWN),
+            ..Player::default()
+        };
+        assert!(player.on_hit(Beats(1.0)));
+        assert!(!player.on_hit(Beats(1.0) + COOLDOWN - Beats(0.01)));
+        // Contact damage isn't held back by the cooldown.
+        assert!(player.on_contact_hit(Beats(1.5)));
+        assert!(!player.on_hit(Beats(1.5) + COOLDOWN - Beats(0.01)));
+        assert!(player.on_hit(Beats(1.5) + COOLDOWN));
+    }
+
+    #[test]
+    pub fn test_default_hit_cooldown_is_in_seconds() {
+        let keyboard = KeyboardState::default();
+        let mut player = Player::default();
+        assert!(player.on_hit(Beats(1.0)));
+        // The beat doesn't matter, only how many seconds have passed.
+        let almost = HIT_TIME_LENGTH - 0.01;
+        player.update(almost, &keyboard, ScreenTransform::default(), &[], &[]);
+        assert!(!player.on_hit(Beats(100.0)));
+        player.update(0.02, &keyboard, ScreenTransform::default(), &[], &[]);
+        assert!(player.on_hit(Beats(100.0)));
+    }
+
+    #[test]
+    pub fn test_shields_and_bombs() {
+        let mut player = Player {
+            hit_cooldown: Some(COOLDOWN),
+            ..Player::default()
+        };
+        player.shields = 1;
+        assert!(!player.on_hit(Beats(1.0)));
+        assert_eq!(player.shields, 0);
+        // The shield still gives the usual cooldown.
+        assert!(player.invulnerable(Beats(1.0) + COOLDOWN - Beats(0.01)));
+
+        assert!(!player.use_bomb(Beats(4.0)));
+        player.bombs = 1;
+        assert!(player.use_bomb(Beats(4.0)));
+        assert_eq!(player.bombs, 0);
+        assert!(!player.on_hit(Beats(4.0) + BOMB_INVULN_LENGTH - Beats(0.01)));
+        assert!(player.on_hit(Beats(4.0) + BOMB_INVULN_LENGTH));
+    }
+}
@@ -0,0 +1,122 @@
+/// This module implements the pattern preview, a debug view which shows every
+/// area covered by an active hitbox over some range of beats, all at once.
+/// This lets chart authors see what parts of the arena a pattern covers (and
+/// where the safe spots are) without needing to play through it.
+use ggez::graphics::{Color, DrawMode, DrawParam, Drawable, MeshBuilder};
+use ggez::{Context, GameResult};
+
+use crate::ease::Lerp;
+use crate::keyboard::KeyboardState;
+use crate::parse::SongMap;
+use crate::time::{self, Beats};
+use crate::world::{WorldLen, WorldPos, ARENA_HALF_SIZE};
+use crate::{InnerWorldState, Scheduler};
+
+/// The width of each cell of the preview grid, in WorldLen units.
+const CELL_SIZE: f64 = 2.0;
+/// How often to sample the hitboxes of the pattern.
+const SAMPLE_RATE: Beats = Beats(1.0 / 8.0);
+
+const EARLY_COLOR: Color = Color {
+    r: 0.2,
+    g: 0.4,
+    b: 1.0,
+    a: 0.5,
+};
+
+const LATE_COLOR: Color = Color {
+    r: 1.0,
+    g: 0.2,
+    b: 0.2,
+    a: 0.5,
+};
+
+pub struct PatternPreview {
+    start: Beats,
+    end: Beats,
+    cells_per_side: usize,
+    // The earliest time each cell is covered by a hitbox, if ever. Stored in
+    // row-major order, starting from the bottom left of the arena.
+    covered_at: Vec<Option<Beats>>,
+}
+
+impl PatternPreview {
+    /// Compute the preview of the SongMap between `start` and `end`. This
+    /// simulates the SongMap from the beginning, with the player standing still.
+    /// A cell counts as covered if the player would be hit while standing in
+    /// the center of it.
+    pub fn new(song_map: &SongMap, start: Beats, end: Beats) -> PatternPreview {
+        let cells_per_side = (2.0 * ARENA_HALF_SIZE / CELL_SIZE).ceil() as usize;
+        let mut covered_at = vec![None; cells_per_side * cells_per_side];
+
+        let keyboard = KeyboardState::default();
+        let mut world = InnerWorldState::new(song_map.player);
+        world.hitboxes_only = true;
+        let mut scheduler = Scheduler::new(song_map);
+        scheduler.update(start, &mut world);
+
+        let player_size = song_map.player.size;
+        let physics_delta_time = time::to_secs(SAMPLE_RATE, song_map.bpm);
+        let mut curr_time = start;
+        while curr_time < end {
+            world.update(&keyboard, physics_delta_time.0, curr_time);
+            scheduler.update(curr_time, &mut world);
+
+            for (i, covered) in covered_at.iter_mut().enumerate() {
+                if covered.is_some() {
+                    continue;
+                }
+                let pos = cell_center(i, cells_per_side);
+                if world.covers(pos, player_size, curr_time) {
+                    *covered = Some(curr_time);
+                }
+            }
+
+            curr_time = curr_time + SAMPLE_RATE;
+        }
+
+        PatternPreview {
+            start,
+            end,
+            cells_per_side,
+            covered_at,
+        }
+    }
+
+    /// Draw the preview. Cells are colored from blue to red depending on how
+    /// early or late in the range they first become covered.
+    pub fn draw(&self, ctx: &mut Context) -> GameResult<()> {
+        let mut mesh = MeshBuilder::new();
+        let mut any_covered = false;
+        for (i, covered) in self.covered_at.iter().enumerate() {
+            if let Some(covered) = covered {
+                let t = (covered.0 - self.start.0) / (self.end.0 - self.start.0);
+                let rect = WorldPos::as_screen_rect(
+                    cell_center(i, self.cells_per_side),
+                    WorldLen(CELL_SIZE),
+                    WorldLen(CELL_SIZE),
+                );
+                mesh.rectangle(
+                    DrawMode::fill(),
+                    rect,
+                    Color::lerp(EARLY_COLOR, LATE_COLOR, t),
+                )?;
+                any_covered = true;
+            }
+        }
+
+        // MeshBuilder::build errors on an empty mesh.
+        if any_covered {
+            mesh.build(ctx)?.draw(ctx, DrawParam::default())?;
+        }
+        Ok(())
+    }
+}
+
+fn cell_center(i: usize, cells_per_side: usize) -> WorldPos {
+    let (col, row) = (i % cells_per_side, i / cells_per_side);
+    WorldPos {
+        x: -ARENA_HALF_SIZE + (col as f64 + 0.5) * CELL_SIZE,
+        y: -ARENA_HALF_SIZE + (row as f64 + 0.5) * CELL_SIZE,
+    }
+}
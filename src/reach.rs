@@ -0,0 +1,135 @@
+/// This module implements the reachability check, which finds sections of a
+/// chart that can't be dodged. The chart is simulated with the player standing
+/// still, and at every sample the arena is split into a coarse grid of cells,
+/// each of which is either safe or covered by a hitbox. Starting from where the
+/// player spawns, the set of cells the player could be standing in is grown by
+/// as far as the player can move between samples, then trimmed down to just
+/// the safe cells. If nothing is left, there is no way to dodge that part of
+/// the chart.
+/// A cell only counts as covered if every point in it is covered, so that the
+/// coarseness of the grid never makes a dodgeable pattern look impossible.
+/// Obstacles, force fields and teleporters aren't accounted for, and enemies
+/// aimed at the player are aimed at where the player spawned. Enemies which
+/// end early on hitting the player (ex: bullets) end where they would have hit
+/// the standing player.
+use std::f64::consts::FRAC_1_SQRT_2;
+
+use crate::keyboard::KeyboardState;
+use crate::parse::SongMap;
+use crate::time::{self, Beats};
+use crate::world::{WorldLen, WorldPos, ARENA_HALF_SIZE};
+use crate::{InnerWorldState, Scheduler};
+
+/// The width of each cell of the grid, in WorldLen units.
+const CELL_SIZE: f64 = 2.0;
+/// How often to sample the hitboxes of the chart.
+const SAMPLE_RATE: Beats = Beats(1.0 / 8.0);
+
+/// Return the ranges of beats between `start` and `end` in which the player
+/// can't avoid being hit, in order. A range which is still going at `end`
+/// ends at `end`.
+pub fn unavoidable_ranges(song_map: &SongMap, start: Beats, end: Beats) -> Vec<(Beats, Beats)> {
+    let cells_per_side = (2.0 * ARENA_HALF_SIZE / CELL_SIZE).ceil() as usize;
+    let keyboard = KeyboardState::default();
+    let mut world = InnerWorldState::new(song_map.player);
+    world.hitboxes_only = true;
+    let mut scheduler = Scheduler::new(song_map);
+    scheduler.update(start, &mut world);
+
+    let player = song_map.player;
+    // Hitboxes are only measured at the center of each cell, so a cell is
+    // only covered if the player would still be hit at the cell's corners.
+    let cover_size = WorldLen(player.size.0 - CELL_SIZE * FRAC_1_SQRT_2);
+    let physics_delta_time = time::to_secs(SAMPLE_RATE, song_map.bpm).0;
+    let max_step = (player.speed() * physics_delta_time / CELL_SIZE).ceil() as usize;
+
+    let mut reachable = vec![false; cells_per_side * cells_per_side];
+    reachable[cell_index(player.pos, cells_per_side)] = true;
+    let mut ranges = vec![];
+    let mut unavoidable_since = None;
+    let mut curr_time = start;
+    while curr_time < end {
+        world.update(&keyboard, physics_delta_time, curr_time);
+        scheduler.update(curr_time, &mut world);
+
+        reachable = grow(&reachable, cells_per_side, max_step);
+        for (i, cell) in reachable.iter_mut().enumerate() {
+            if *cell && world.covers(cell_center(i, cells_per_side), cover_size, curr_time) {
+                *cell = false;
+            }
+        }
+
+        if reachable.contains(&true) {
+            if let Some(since) = unavoidable_since.take() {
+                ranges.push((since, curr_time));
+            }
+        } else {
+            unavoidable_since.get_or_insert(curr_time);
+            // The player takes a hit here and carries on, possibly from
+            // anywhere, so that the rest of the chart is still checked.
+            reachable.iter_mut().for_each(|cell| *cell = true);
+        }
+        curr_time = curr_time + SAMPLE_RATE;
+    }
+    if let Some(since) = unavoidable_since {
+        ranges.push((since, end));
+    }
+    ranges
+}
+
+/// Return every cell within `steps` cells of a reachable cell, along either
+/// axis. This is the shape the player covers, since moving diagonally moves
+/// at full speed along both axes.
+fn grow(reachable: &[bool], cells_per_side: usize, steps: usize) -> Vec<bool> {
+    let mut grown = vec![false; reachable.len()];
+    for i in (0..reachable.len()).filter(|&i| reachable[i]) {
+        let (col, row) = (i % cells_per_side, i / cells_per_side);
+        for row in row.saturating_sub(steps)..(row + steps + 1).min(cells_per_side) {
+            for col in col.saturating_sub(steps)..(col + steps + 1).min(cells_per_side) {
+                grown[row * cells_per_side + col] = true;
+            }
+        }
+    }
+    grown
+}
+
+fn cell_index(pos: WorldPos, cells_per_side: usize) -> usize {
+    let cell = |x: f64| {
+        let cell = ((x + ARENA_HALF_SIZE) / CELL_SIZE).floor() as usize;
+        cell.min(cells_per_side - 1)
+    };
+    cell(pos.y) * cells_per_side + cell(pos.x)
+}
+
+fn cell_center(i: usize, cells_per_side: usize) -> WorldPos {
+    let (col, row) = (i % cells_per_side, i / cells_per_side);
+    WorldPos {
+        x: -ARENA_HALF_SIZE + (col as f64 + 0.5) * CELL_SIZE,
+        y: -ARENA_HALF_SIZE + (row as f64 + 0.5) * CELL_SIZE,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_grow_stops_at_edges() {
+        let mut reachable = vec![false; 5 * 5];
+        reachable[0] = true;
+        let grown = grow(&reachable, 5, 1);
+        let grown: Vec<usize> = (0..grown.len()).filter(|&i| grown[i]).collect();
+        assert_eq!(grown, vec![0, 1, 5, 6]);
+    }
+
+    #[test]
+    pub fn test_cell_index_matches_center() {
+        let cells_per_side = (2.0 * ARENA_HALF_SIZE / CELL_SIZE).ceil() as usize;
+        for &i in &[0, 17, cells_per_side * cells_per_side - 1] {
+            assert_eq!(
+                cell_index(cell_center(i, cells_per_side), cells_per_side),
+                i
+            );
+        }
+    }
+}
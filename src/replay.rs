@@ -0,0 +1,262 @@
+/// This module implements replays, which record where the player was over the
+/// course of a run and which keys they were holding. Replays are used to draw a
+/// "ghost" of a previous run alongside the player, so that players can learn
+/// routes through dense patterns from their own best run (or from a replay
+/// someone else shared).
+///
+/// The player's position is sampled every SAMPLE_INTERVAL beats, and the ghost
+/// moves smoothly between samples. Replays are tied to the chart they were
+/// recorded on, since a ghost is useless if the chart has changed since.
+use std::path::{Path, PathBuf};
+
+use ggez::graphics::{Color, DrawMode, DrawParam, Drawable, Mesh};
+use ggez::{Context, GameResult};
+use serde::{Deserialize, Serialize};
+
+use crate::ease::Lerp;
+use crate::keyboard::InputState;
+use crate::meshutil;
+use crate::modifiers::Modifiers;
+use crate::save;
+use crate::time::{Beats, SongSpeed};
+use crate::world::{WorldLen, WorldPos};
+
+/// The folder replays are saved in, which lives next to the level folders.
+/// Each level's best run is saved as `<level key>.replay` (see
+/// `save::level_key`), so a downloaded replay can be used by saving it there
+/// under the same name.
+pub const REPLAY_FOLDER: &str = "replays";
+const REPLAY_EXTENSION: &str = "replay";
+
+/// How often the player's position is recorded.
+const SAMPLE_INTERVAL: Beats = Beats(1.0 / 16.0);
+
+/// The opacity of the ghost.
+const GHOST_OPACITY: f32 = 0.35;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct ReplaySample {
+    pub beat: Beats,
+    pub pos: WorldPos,
+    pub input: InputState,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Replay {
+    /// The hash of the chart when the replay was recorded. See
+    /// `cache::song_map_hash`. Replays saved under an older kind of hash are
+    /// updated when they are next loaded, if the chart hasn't changed since.
+    pub chart_hash: u64,
+    /// How many times the player was hit during the run.
+    pub hits: usize,
+    /// The player's position over the run, in order of increasing beat.
+    pub samples: Vec<ReplaySample>,
+    /// How fast the song was played. Replays must be played back at the same
+    /// speed, since the player moves the same distance per second no matter
+    /// the speed.
+    pub song_speed: SongSpeed,
+    /// The modifiers the run was played with, which also need to be the same
+    /// when the replay is played back (ex: Mirror flips the input).
+    pub modifiers: Modifiers,
+    /// How much the player's speed was multiplied by while focusing, which
+    /// also changes how far the player moves. This is None for replays saved
+    /// before it was recorded, which are played back with the chart's own.
+    pub focus_factor: Option<f64>,
+}
+
+/// A replay saved before replays recorded the focus factor.
+#[derive(Deserialize)]
+struct UnfocusedReplay {
+    chart_hash: u64,
+    hits: usize,
+    samples: Vec<ReplaySample>,
+    song_speed: SongSpeed,
+    modifiers: Modifiers,
+}
+
+/// A replay saved before replays recorded the song's speed and modifiers, back
+/// when every run was played at normal speed without modifiers.
+#[derive(Deserialize)]
+struct UntaggedReplay {
+    chart_hash: u64,
+    hits: usize,
+    samples: Vec<ReplaySample>,
+}
+
+impl Replay {
+    pub fn new(chart_hash: u64) -> Replay {
+        Replay {
+            chart_hash,
+            hits: 0,
+            samples: vec![],
+            song_speed: SongSpeed::Normal,
+            modifiers: Modifiers::default(),
+            focus_factor: None,
+        }
+    }
+
+    /// Returns true if the run was played as charted, at normal speed without
+    /// modifiers and with the chart's own focus factor (`chart_focus_factor`).
+    /// Replays which didn't record their focus factor are assumed to have
+    /// used the chart's. See `WorldState::is_as_charted`.
+    pub fn is_as_charted(&self, chart_focus_factor: f64) -> bool {
+        self.song_speed == SongSpeed::Normal
+            && self.modifiers.is_empty()
+            && self
+                .focus_factor
+                .map_or(true, |factor| factor == chart_focus_factor)
+    }
+
+    /// Record the player's position and input, if enough time has passed
+    /// since the last sample or the input changed.
+    pub fn record(&mut self, beat: Beats, pos: WorldPos, input: InputState) {
+        match self.samples.last() {
+            Some(last) if beat < last.beat + SAMPLE_INTERVAL && input == last.input => (),
+            _ => self.samples.push(ReplaySample { beat, pos, input }),
+        }
+    }
+
+    /// Return which keys the player held at `beat`, or None if the replay
+    /// doesn't cover that beat.
+    pub fn input_at(&self, beat: Beats) -> Option<InputState> {
+        match self.samples.last() {
+            Some(last) if beat <= last.beat => (),
+            _ => return None,
+        }
+        let i = self.samples.partition_point(|sample| sample.beat <= beat);
+        i.checked_sub(1).map(|i| self.samples[i].input)
+    }
+
+    /// Return where the player was at `beat`, or None if the replay doesn't
+    /// cover that beat.
+    pub fn position_at(&self, beat: Beats) -> Option<WorldPos> {
+        let first = self.samples.first()?;
+        let last = self.samples.last()?;
+        if beat < first.beat || beat > last.beat {
+            return None;
+        }
+        let i = self.samples.partition_point(|sample| sample.beat <= beat);
+        let before = self.samples[i - 1];
+        let after = match self.samples.get(i) {
+            Some(after) => after,
+            None => return Some(before.pos),
+        };
+        let t = (beat - before.beat).0 / (after.beat - before.beat).0;
+        Some(WorldPos {
+            x: f64::lerp(before.pos.x, after.pos.x, t),
+            y: f64::lerp(before.pos.y, after.pos.y, t),
+        })
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Replay> {
+        let bytes = std::fs::read(path)?;
+        let err = match bincode::deserialize(&bytes) {
+            Ok(replay) => return Ok(replay),
+            Err(err) => err,
+        };
+        // Older replays end where the focus factor (or the speed) would be.
+        if let Ok(replay) = bincode::deserialize::<UnfocusedReplay>(&bytes) {
+            return Ok(Replay {
+                chart_hash: replay.chart_hash,
+                hits: replay.hits,
+                samples: replay.samples,
+                song_speed: replay.song_speed,
+                modifiers: replay.modifiers,
+                focus_factor: None,
+            });
+        }
+        match bincode::deserialize::<UntaggedReplay>(&bytes) {
+            Ok(replay) => Ok(Replay {
+                chart_hash: replay.chart_hash,
+                hits: replay.hits,
+                samples: replay.samples,
+                song_speed: SongSpeed::Normal,
+                modifiers: Modifiers::default(),
+                focus_factor: None,
+            }),
+            Err(_) => Err(err.into()),
+        }
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        if let Some(folder) = path.parent() {
+            std::fs::create_dir_all(folder)?;
+        }
+        std::fs::write(path, bincode::serialize(self)?)?;
+        Ok(())
+    }
+
+    /// Draw the ghost of the player at `beat`, if the replay covers it.
+    pub fn draw_ghost(&self, ctx: &mut Context, beat: Beats, size: WorldLen) -> GameResult<()> {
+        let pos = match self.position_at(beat) {
+            Some(pos) => pos,
+            None => return Ok(()),
+        };
+        let radius = size.as_screen_length();
+        let mesh = Mesh::new_circle(
+            ctx,
+            DrawMode::fill(),
+            pos.as_screen_coords(),
+            radius,
+            meshutil::tolerance(radius, 1.0),
+            Color::new(1.0, 1.0, 1.0, GHOST_OPACITY),
+        )?;
+        mesh.draw(ctx, DrawParam::default())
+    }
+}
+
+/// Return where the best run of the level in `level_folder` is saved by the
+/// profile saving in `profile_folder`. `levels_folder` is the folder holding
+/// every level.
+pub fn replay_path(profile_folder: &Path, levels_folder: &Path, level_folder: &Path) -> PathBuf {
+    let key = save::level_key(levels_folder, level_folder);
+    key_replay_path(profile_folder, &key)
+}
+
+/// Return where the best run of the level saved under `key` (see
+/// `save::level_key`) is saved. Levels in packs are saved in a folder named
+/// after the pack.
+pub fn key_replay_path(profile_folder: &Path, key: &str) -> PathBuf {
+    let file_name = format!("{}.{}", key, REPLAY_EXTENSION);
+    profile_folder.join(REPLAY_FOLDER).join(file_name)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::modifiers::ModifierKind;
+
+    #[test]
+    pub fn test_position_at_interpolates() {
+        let mut replay = Replay::new(0);
+        let input = InputState::default();
+        replay.record(Beats(0.0), WorldPos { x: 0.0, y: 0.0 }, input);
+        // Too soon after the last sample, so this is skipped.
+        replay.record(Beats(0.01), WorldPos { x: 50.0, y: 50.0 }, input);
+        replay.record(Beats(1.0), WorldPos { x: 10.0, y: -10.0 }, input);
+
+        assert_eq!(replay.samples.len(), 2);
+        let pos = replay.position_at(Beats(0.25)).unwrap();
+        assert!((pos.x - 2.5).abs() < 1e-9 && (pos.y + 2.5).abs() < 1e-9);
+        let pos = replay.position_at(Beats(1.0)).unwrap();
+        assert!((pos.x - 10.0).abs() < 1e-9);
+        assert!(replay.position_at(Beats(-1.0)).is_none());
+        assert!(replay.position_at(Beats(2.0)).is_none());
+    }
+
+    #[test]
+    pub fn test_is_as_charted() {
+        let mut replay = Replay::new(0);
+        assert!(replay.is_as_charted(0.2));
+        replay.focus_factor = Some(0.5);
+        assert!(!replay.is_as_charted(0.2));
+        replay.focus_factor = Some(0.2);
+        replay.song_speed = SongSpeed::Fast;
+        assert!(!replay.is_as_charted(0.2));
+        replay.song_speed = SongSpeed::Normal;
+        replay.modifiers.toggle(ModifierKind::Mirror);
+        assert!(!replay.is_as_charted(0.2));
+    }
+}
@@ -0,0 +1,455 @@
+/// This module handles the save file, which stores data about the player's
+/// progress on each level across sessions. The save file is plain JSON so that
+/// it stays readable (and editable) by hand.
+///
+/// Each player profile has its own save file and replays, so that several
+/// people can share a machine (ex: at events) without mixing up their scores
+/// and settings. The default profile is saved directly in the levels folder,
+/// where saves were kept before there were profiles, and every other profile
+/// is saved in its own folder inside PROFILES_FOLDER.
+///
+/// Levels are saved under their path relative to the levels folder (see
+/// `level_key`). Saves from before levels could be in packs used the name of
+/// the level's folder instead, and are moved over when they are loaded (see
+/// `SaveData::migrate_level_keys`).
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::budget::GraphicsPreset;
+use crate::gamepad::StickSettings;
+use crate::keyboard::FocusBinding;
+use crate::lang;
+use crate::modifiers::Modifiers;
+use crate::parse;
+use crate::replay;
+use crate::time::{Beats, SongSpeed};
+use crate::vfs;
+use crate::world::WorldPos;
+
+pub const SAVE_FILE: &str = "save.json";
+/// The folder other profiles are kept in, which lives next to the level
+/// folders.
+pub const PROFILES_FOLDER: &str = "profiles";
+/// The file in the levels folder which records the profile used most recently.
+pub const CURRENT_PROFILE_FILE: &str = "profile.txt";
+/// The name of the profile saved directly in the levels folder.
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// The most hits recorded per level. Once a level has this many, the oldest
+/// hits are forgotten so that the save file doesn't grow forever.
+const MAX_HITS_PER_LEVEL: usize = 10_000;
+
+/// The simulation tick rates the player may choose between, in ticks per second.
+pub const TICK_RATES: [u32; 3] = [60, 120, 240];
+
+/// The focus factors the player may choose between. None uses each chart's
+/// own. See `Player::focus_factor`.
+pub const FOCUS_FACTORS: [Option<f64>; 5] = [None, Some(0.1), Some(0.2), Some(0.3), Some(0.5)];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SaveData {
+    /// The save data for each level, keyed by `level_key`.
+    #[serde(default)]
+    pub levels: HashMap<String, LevelSaveData>,
+    /// The player's input offset, in seconds. See `Time::input_offset`.
+    #[serde(default)]
+    pub input_offset: f64,
+    /// How many times per second the simulation is updated. This should be one
+    /// of TICK_RATES.
+    #[serde(default = "default_tick_rate")]
+    pub tick_rate: u32,
+    /// The language code of the UI language (ex: "en").
+    #[serde(default = "default_language")]
+    pub language: String,
+    /// The folder of the level most recently played, relative to the levels
+    /// folder. The level select starts with this level selected.
+    #[serde(default)]
+    pub last_level: Option<PathBuf>,
+    #[serde(default)]
+    pub leaderboard: LeaderboardSettings,
+    /// The URL of the level index used by the "Get more levels" screen. See
+    /// `browser`.
+    #[serde(default)]
+    pub level_index_url: String,
+    /// Whether to draw the input display while playing.
+    #[serde(default)]
+    pub show_input_display: bool,
+    /// If true, focus is toggled on and off instead of being held.
+    #[serde(default)]
+    pub focus_toggle: bool,
+    /// Which inputs the player focuses with.
+    #[serde(default)]
+    pub focus_binding: FocusBinding,
+    /// How much the player's speed is multiplied by while focusing. If this
+    /// isn't set, each chart's own focus factor is used. This should be one
+    /// of FOCUS_FACTORS.
+    #[serde(default)]
+    pub focus_factor: Option<f64>,
+    /// How the gamepad's analog stick responds to being pushed.
+    #[serde(default)]
+    pub stick: StickSettings,
+    /// Whether to read menus aloud. This only has an effect if the game was
+    /// built with the `narration` feature.
+    #[serde(default)]
+    pub narration: bool,
+    /// How fast songs are played. See `SongSpeed`.
+    #[serde(default)]
+    pub song_speed: SongSpeed,
+    /// The modifiers songs are played with.
+    #[serde(default)]
+    pub modifiers: Modifiers,
+    /// How good the game looks. See `GraphicsPreset`.
+    #[serde(default)]
+    pub graphics_preset: GraphicsPreset,
+    /// The most opaque that darkness may be drawn, from 0 to 1. This is an
+    /// accessibility setting for players who are sensitive to the screen
+    /// going dark. See `WorldState::max_darkness`.
+    #[serde(default = "default_max_darkness")]
+    pub max_darkness: f32,
+    /// How many Lua instructions a chart may run before it is stopped. See
+    /// `parse::set_instruction_budget`.
+    #[serde(default = "default_instruction_budget")]
+    pub instruction_budget: u64,
+    /// The name of the output device to play audio on, or None for the
+    /// system's default. See `audio::set_device`.
+    #[serde(default)]
+    pub audio_device: Option<String>,
+}
+
+/// The settings for the online leaderboard. These only have an effect if the
+/// game was built with the `leaderboard` feature.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LeaderboardSettings {
+    /// Whether to submit scores and fetch the top scores. This is off unless
+    /// the player turns it on.
+    #[serde(default)]
+    pub enabled: bool,
+    /// The URL of the leaderboard server.
+    #[serde(default)]
+    pub endpoint: String,
+    /// The name shown next to the player's scores.
+    #[serde(default = "default_player_name")]
+    pub player_name: String,
+}
+
+impl Default for LeaderboardSettings {
+    fn default() -> Self {
+        LeaderboardSettings {
+            enabled: false,
+            endpoint: String::new(),
+            player_name: default_player_name(),
+        }
+    }
+}
+
+impl Default for SaveData {
+    fn default() -> Self {
+        SaveData {
+            levels: HashMap::new(),
+            input_offset: 0.0,
+            tick_rate: default_tick_rate(),
+            language: default_language(),
+            last_level: None,
+            leaderboard: LeaderboardSettings::default(),
+            level_index_url: String::new(),
+            show_input_display: false,
+            focus_toggle: false,
+            focus_binding: FocusBinding::Any,
+            focus_factor: None,
+            stick: StickSettings::default(),
+            narration: false,
+            song_speed: SongSpeed::Normal,
+            modifiers: Modifiers::default(),
+            graphics_preset: GraphicsPreset::default(),
+            max_darkness: default_max_darkness(),
+            instruction_budget: default_instruction_budget(),
+            audio_device: None,
+        }
+    }
+}
+
+fn default_tick_rate() -> u32 {
+    TICK_RATES[0]
+}
+
+fn default_instruction_budget() -> u64 {
+    parse::DEFAULT_INSTRUCTION_BUDGET
+}
+
+fn default_max_darkness() -> f32 {
+    crate::DEFAULT_MAX_DARKNESS
+}
+
+fn default_language() -> String {
+    lang::DEFAULT_LANGUAGE.to_string()
+}
+
+fn default_player_name() -> String {
+    "Player".to_string()
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LevelSaveData {
+    /// Every time the player was hit on this level, oldest first.
+    #[serde(default)]
+    pub hits: Vec<HitRecord>,
+    /// Whether to draw the ghost of the best run while playing this level.
+    #[serde(default)]
+    pub show_ghost: bool,
+    /// The names of every medal earned on this level.
+    #[serde(default)]
+    pub medals: Vec<String>,
+}
+
+/// Where and when the player was hit.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct HitRecord {
+    pub pos: WorldPos,
+    pub beat: Beats,
+}
+
+impl SaveData {
+    /// Load the save file at `path`. If the file doesn't exist, an empty save
+    /// is returned instead.
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<SaveData> {
+        let path = path.as_ref();
+        if !path.exists() {
+            return Ok(SaveData::default());
+        }
+        let bytes = std::fs::read(path)?;
+        Ok(serde_json::from_slice(&bytes)?)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        if let Some(folder) = path.parent() {
+            std::fs::create_dir_all(folder)?;
+        }
+        let bytes = serde_json::to_vec_pretty(self)?;
+        std::fs::write(path, bytes)?;
+        Ok(())
+    }
+
+    /// Return the save data for the level in `level_folder`, creating it if
+    /// there isn't any yet. `levels_folder` is the folder holding every level.
+    pub fn level_mut(
+        &mut self,
+        levels_folder: impl AsRef<Path>,
+        level_folder: impl AsRef<Path>,
+    ) -> &mut LevelSaveData {
+        self.levels
+            .entry(level_key(levels_folder, level_folder))
+            .or_default()
+    }
+
+    pub fn level(
+        &self,
+        levels_folder: impl AsRef<Path>,
+        level_folder: impl AsRef<Path>,
+    ) -> Option<&LevelSaveData> {
+        self.levels.get(&level_key(levels_folder, level_folder))
+    }
+
+    /// Move the save data and replays (in `profile_folder`) of every level in
+    /// a pack from the level's legacy key (see `legacy_level_key`) to its
+    /// current one. Legacy keys shared by several levels are left alone,
+    /// since there's no telling which of them the save data was for.
+    pub fn migrate_level_keys(&mut self, levels_folder: &Path, profile_folder: &Path) {
+        let level_folders = level_folders(levels_folder);
+        for level_folder in &level_folders {
+            let legacy_key = legacy_level_key(level_folder);
+            let key = level_key(levels_folder, level_folder);
+            let shared = level_folders
+                .iter()
+                .filter(|other| legacy_level_key(other) == legacy_key)
+                .count()
+                > 1;
+            if key == legacy_key || shared || self.levels.contains_key(&key) {
+                continue;
+            }
+            if let Some(level) = self.levels.remove(&legacy_key) {
+                log::info!("Moving the save data of {:?} to {:?}", legacy_key, key);
+                self.levels.insert(key.clone(), level);
+            }
+            let legacy_replay = replay::key_replay_path(profile_folder, &legacy_key);
+            let replay = replay::key_replay_path(profile_folder, &key);
+            if legacy_replay.is_file() && !replay.exists() {
+                let moved: std::io::Result<()> = try {
+                    if let Some(folder) = replay.parent() {
+                        std::fs::create_dir_all(folder)?;
+                    }
+                    std::fs::rename(&legacy_replay, &replay)?
+                };
+                if let Err(err) = moved {
+                    log::warn!("Couldn't move replay {:?}: {}", legacy_replay, err);
+                }
+            }
+        }
+    }
+
+    /// Switch to the next tick rate in TICK_RATES, wrapping back to the first.
+    pub fn cycle_tick_rate(&mut self) {
+        let next = TICK_RATES
+            .iter()
+            .position(|&rate| rate == self.tick_rate)
+            .map_or(0, |i| (i + 1) % TICK_RATES.len());
+        self.tick_rate = TICK_RATES[next];
+    }
+
+    /// Switch to the next focus factor in FOCUS_FACTORS, wrapping back to the
+    /// first.
+    pub fn cycle_focus_factor(&mut self) {
+        let next = FOCUS_FACTORS
+            .iter()
+            .position(|&factor| factor == self.focus_factor)
+            .map_or(0, |i| (i + 1) % FOCUS_FACTORS.len());
+        self.focus_factor = FOCUS_FACTORS[next];
+    }
+}
+
+impl LevelSaveData {
+    pub fn record_hit(&mut self, hit: HitRecord) {
+        if self.hits.len() >= MAX_HITS_PER_LEVEL {
+            let excess = self.hits.len() + 1 - MAX_HITS_PER_LEVEL;
+            self.hits.drain(..excess);
+        }
+        self.hits.push(hit);
+    }
+
+    /// Record that the medal named `name` was earned, if it wasn't already.
+    pub fn award_medal(&mut self, name: &str) {
+        if !self.medals.iter().any(|medal| medal == name) {
+            self.medals.push(name.to_string());
+        }
+    }
+}
+
+/// Return the folder the profile named `profile` saves its save file and
+/// replays in.
+pub fn profile_folder(levels_folder: impl AsRef<Path>, profile: &str) -> PathBuf {
+    if profile == DEFAULT_PROFILE {
+        levels_folder.as_ref().to_path_buf()
+    } else {
+        levels_folder.as_ref().join(PROFILES_FOLDER).join(profile)
+    }
+}
+
+/// Return the name of every profile, starting with the default profile and
+/// followed by the rest in alphabetical order.
+pub fn profiles(levels_folder: impl AsRef<Path>) -> Vec<String> {
+    let mut profiles: Vec<String> = std::fs::read_dir(levels_folder.as_ref().join(PROFILES_FOLDER))
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .filter(|entry| entry.path().is_dir())
+                .map(|entry| entry.file_name().to_string_lossy().to_string())
+                .filter(|name| name != DEFAULT_PROFILE)
+                .collect()
+        })
+        .unwrap_or_default();
+    profiles.sort();
+    profiles.insert(0, DEFAULT_PROFILE.to_string());
+    profiles
+}
+
+/// Return the profile used most recently, or the default profile if that
+/// profile no longer exists.
+pub fn current_profile(levels_folder: impl AsRef<Path>) -> String {
+    let levels_folder = levels_folder.as_ref();
+    match std::fs::read_to_string(levels_folder.join(CURRENT_PROFILE_FILE)) {
+        Ok(profile) if profiles(levels_folder).contains(&profile.trim().to_string()) => {
+            profile.trim().to_string()
+        }
+        _ => DEFAULT_PROFILE.to_string(),
+    }
+}
+
+/// Record `profile` as the profile used most recently.
+pub fn set_current_profile(levels_folder: impl AsRef<Path>, profile: &str) -> anyhow::Result<()> {
+    std::fs::write(levels_folder.as_ref().join(CURRENT_PROFILE_FILE), profile)?;
+    Ok(())
+}
+
+/// Create a new, empty profile, returning its name. Profiles are named
+/// "player 2", "player 3" and so on, and may be renamed by renaming their
+/// folder.
+pub fn create_profile(levels_folder: impl AsRef<Path>) -> anyhow::Result<String> {
+    let levels_folder = levels_folder.as_ref();
+    let existing = profiles(levels_folder);
+    let name = (2..)
+        .map(|i| format!("player {}", i))
+        .find(|name| !existing.contains(name))
+        .expect("there are infinitely many profile names");
+    std::fs::create_dir_all(profile_folder(levels_folder, &name))?;
+    Ok(name)
+}
+
+/// Return the key the level in `level_folder` is saved under, which is its path
+/// relative to `levels_folder` (ex: "pack/level"), so that levels with the same
+/// folder name in different packs are kept apart. Levels outside of
+/// `levels_folder` are keyed by the name of their folder.
+pub fn level_key(levels_folder: impl AsRef<Path>, level_folder: impl AsRef<Path>) -> String {
+    let level_folder = level_folder.as_ref();
+    match level_folder.strip_prefix(levels_folder.as_ref()) {
+        Ok(relative) if relative.components().next().is_some() => relative
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/"),
+        _ => legacy_level_key(level_folder),
+    }
+}
+
+/// Return the key the level in `level_folder` was saved under before levels
+/// could be in packs, which is the name of its folder.
+fn legacy_level_key(level_folder: &Path) -> String {
+    level_folder
+        .file_name()
+        .unwrap_or_else(|| level_folder.as_os_str())
+        .to_string_lossy()
+        .to_string()
+}
+
+/// Return the folder of every level in `levels_folder`, including the levels
+/// in packs.
+fn level_folders(levels_folder: &Path) -> Vec<PathBuf> {
+    let read_dir = |folder: &Path| -> Vec<PathBuf> {
+        std::fs::read_dir(folder)
+            .map(|entries| {
+                entries
+                    .filter_map(|entry| Some(entry.ok()?.path()))
+                    .collect()
+            })
+            .unwrap_or_default()
+    };
+    let mut level_folders = vec![];
+    for path in read_dir(levels_folder) {
+        if vfs::is_level(&path) {
+            level_folders.push(path);
+        } else if path.is_dir() {
+            level_folders.extend(read_dir(&path).into_iter().filter(vfs::is_level));
+        }
+    }
+    level_folders
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_level_key_keeps_packs_apart() {
+        let levels_folder = Path::new("levels");
+        assert_eq!(level_key(levels_folder, levels_folder.join("song")), "song");
+        assert_eq!(
+            level_key(levels_folder, levels_folder.join("pack").join("song")),
+            "pack/song"
+        );
+        assert_eq!(
+            level_key(levels_folder, Path::new("elsewhere/song")),
+            "song"
+        );
+    }
+}
@@ -0,0 +1,264 @@
+/// This module tracks statistics about a single run of a level, such as how
+/// many times the player was hit in each section of the chart. Charts divide
+/// themselves into sections with `mark_section` spawn_cmds.
+/// Runs may be exported as a JSON `RunReport` for other tools to analyze.
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+
+use crate::event::GameEvent;
+use crate::modifiers::Modifiers;
+use crate::pickup::PickupKind;
+use crate::player::DEFAULT_FOCUS_FACTOR;
+use crate::save::{self, SaveData};
+use crate::time::{self, Beats, SongSpeed};
+
+/// The name of the section covering everything before the first section
+/// marker (or the whole chart, if the chart has no section markers).
+pub const FIRST_SECTION_NAME: &str = "Start";
+
+/// The folder exported run reports are saved in, which lives in the profile's
+/// folder next to the replays.
+pub const RUNS_FOLDER: &str = "runs";
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SectionStats {
+    pub name: String,
+    /// The beat the section starts on.
+    pub start: Beats,
+    pub hits: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct RunStats {
+    /// Every section reached during the run, in the order they were reached.
+    /// This always has at least one section.
+    pub sections: Vec<SectionStats>,
+    /// When the player was hit, in order.
+    pub hit_times: Vec<Beats>,
+    /// The last beat the run was played up to.
+    pub end: Beats,
+    /// How many times the level was restarted in a row to get to this run.
+    pub retries: u32,
+    /// How many gems the player collected.
+    pub gems: usize,
+    /// How many enemies the player grazed. See `GameEvent::Grazed`.
+    pub grazes: usize,
+    /// When the player grazed an enemy, in order.
+    pub graze_times: Vec<Beats>,
+    /// How fast the song was played.
+    pub song_speed: SongSpeed,
+    /// The modifiers the run was played with.
+    pub modifiers: Modifiers,
+    /// How much the player's speed was multiplied by while focusing. See
+    /// `Player::focus_factor`.
+    pub focus_factor: f64,
+}
+
+impl RunStats {
+    /// Create the stats for a run starting at `start`, partway through the
+    /// section named `first_section`.
+    pub fn new(first_section: String, start: Beats) -> RunStats {
+        RunStats {
+            sections: vec![SectionStats {
+                name: first_section,
+                start,
+                hits: 0,
+            }],
+            hit_times: vec![],
+            end: start,
+            retries: 0,
+            gems: 0,
+            grazes: 0,
+            graze_times: vec![],
+            song_speed: SongSpeed::Normal,
+            modifiers: Modifiers::default(),
+            focus_factor: DEFAULT_FOCUS_FACTOR,
+        }
+    }
+
+    pub fn record(&mut self, event: &GameEvent) {
+        match event {
+            GameEvent::PlayerHit { time, .. } => {
+                // There is always at least one section.
+                self.sections.last_mut().unwrap().hits += 1;
+                self.hit_times.push(*time);
+            }
+            GameEvent::PickupCollected {
+                kind: PickupKind::Gem,
+                ..
+            } => self.gems += 1,
+            GameEvent::Grazed { time, .. } => {
+                self.grazes += 1;
+                self.graze_times.push(*time);
+            }
+            GameEvent::SectionStarted { name, time } => {
+                // A section starting right where the run starts replaces the
+                // run's first section, rather than leaving an empty one before it.
+                if let [first] = self.sections.as_slice() {
+                    if first.hits == 0 && first.start >= *time {
+                        self.sections.clear();
+                    }
+                }
+                self.sections.push(SectionStats {
+                    name: name.clone(),
+                    start: *time,
+                    hits: 0,
+                });
+            }
+            _ => (),
+        }
+    }
+
+    pub fn total_hits(&self) -> usize {
+        self.sections.iter().map(|section| section.hits).sum()
+    }
+
+    /// Return how many times the player was hit during each second of the run,
+    /// starting from the beginning of the run. Seconds are in song time, so
+    /// runs at different song speeds line up with each other.
+    pub fn hit_timeline(&self, bpm: f64) -> Vec<usize> {
+        self.timeline(&self.hit_times, bpm)
+    }
+
+    /// Return how many enemies the player grazed during each second of the
+    /// run, the same way as `hit_timeline`.
+    pub fn graze_timeline(&self, bpm: f64) -> Vec<usize> {
+        self.timeline(&self.graze_times, bpm)
+    }
+
+    /// Return how many of `times` fall in each second of the run.
+    fn timeline(&self, times: &[Beats], bpm: f64) -> Vec<usize> {
+        let start = self.sections[0].start;
+        let second = |beat: Beats| time::to_secs(beat - start, bpm).0.max(0.0) as usize;
+        let mut timeline = vec![0; second(self.end) + 1];
+        for &beat in times {
+            let i = second(beat).min(timeline.len() - 1);
+            timeline[i] += 1;
+        }
+        timeline
+    }
+}
+
+/// A finished run, as exported for spreadsheets and other external tools.
+#[derive(Debug, Clone, Serialize)]
+pub struct RunReport {
+    pub level: String,
+    /// The hash of the chart, so that runs on different versions of a chart
+    /// can be told apart. See `cache::song_map_hash`.
+    pub chart_hash: u64,
+    /// When the run finished, in seconds since the Unix epoch.
+    pub finished_at: u64,
+    pub bpm: f64,
+    pub hits: usize,
+    pub retries: u32,
+    pub gems: usize,
+    pub grazes: usize,
+    pub sections: Vec<SectionStats>,
+    /// How many times the player was hit during each second of the run. See
+    /// `RunStats::hit_timeline`.
+    pub hit_timeline: Vec<usize>,
+    /// How many enemies the player grazed during each second of the run. See
+    /// `RunStats::graze_timeline`.
+    pub graze_timeline: Vec<usize>,
+    pub song_speed: SongSpeed,
+    pub modifiers: Modifiers,
+    pub settings: ReportSettings,
+}
+
+/// The settings a run was played with which could affect how well it went.
+#[derive(Debug, Clone, Serialize)]
+pub struct ReportSettings {
+    pub input_offset: f64,
+    pub tick_rate: u32,
+    pub focus_toggle: bool,
+    pub focus_factor: f64,
+}
+
+impl RunReport {
+    pub fn new(
+        levels_folder: &Path,
+        level_folder: &Path,
+        chart_hash: u64,
+        bpm: f64,
+        stats: &RunStats,
+        save_data: &SaveData,
+    ) -> RunReport {
+        let finished_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_secs());
+        RunReport {
+            level: save::level_key(levels_folder, level_folder),
+            chart_hash,
+            finished_at,
+            bpm,
+            hits: stats.total_hits(),
+            retries: stats.retries,
+            gems: stats.gems,
+            grazes: stats.grazes,
+            sections: stats.sections.clone(),
+            hit_timeline: stats.hit_timeline(bpm),
+            graze_timeline: stats.graze_timeline(bpm),
+            song_speed: stats.song_speed,
+            modifiers: stats.modifiers.clone(),
+            settings: ReportSettings {
+                input_offset: save_data.input_offset,
+                tick_rate: save_data.tick_rate,
+                focus_toggle: save_data.focus_toggle,
+                focus_factor: stats.focus_factor,
+            },
+        }
+    }
+
+    /// Write the report to RUNS_FOLDER in `profile_folder`, returning where it
+    /// was written. Each report gets its own file, named after the level and
+    /// when the run finished.
+    pub fn export(&self, profile_folder: &Path) -> anyhow::Result<PathBuf> {
+        let folder = profile_folder.join(RUNS_FOLDER);
+        std::fs::create_dir_all(&folder)?;
+        let path = folder.join(format!("{}-{}.json", self.level, self.finished_at));
+        std::fs::write(&path, serde_json::to_vec_pretty(self)?)?;
+        Ok(path)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_hit_timeline_counts_hits_per_second() {
+        use crate::world::WorldPos;
+
+        // At 120 bpm, each second is two beats.
+        let mut stats = RunStats::new(FIRST_SECTION_NAME.to_string(), Beats(4.0));
+        for &beat in &[4.5, 5.0, 8.0, 9.9] {
+            stats.record(&GameEvent::PlayerHit {
+                group: 0,
+                pos: WorldPos::origin(),
+                time: Beats(beat),
+            });
+        }
+        stats.end = Beats(11.0);
+        assert_eq!(stats.hit_timeline(120.0), vec![2, 0, 2, 0]);
+    }
+
+    #[test]
+    pub fn test_graze_timeline_counts_grazes_per_second() {
+        use crate::world::WorldPos;
+
+        let mut stats = RunStats::new(FIRST_SECTION_NAME.to_string(), Beats(0.0));
+        for &beat in &[1.0, 2.5, 3.0] {
+            stats.record(&GameEvent::Grazed {
+                group: 0,
+                pos: WorldPos::origin(),
+                time: Beats(beat),
+            });
+        }
+        stats.end = Beats(4.0);
+        assert_eq!(stats.grazes, 3);
+        assert_eq!(stats.graze_timeline(120.0), vec![1, 2, 0]);
+        assert_eq!(stats.hit_timeline(120.0), vec![0, 0, 0]);
+    }
+}
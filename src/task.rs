@@ -0,0 +1,35 @@
+/// This module runs slow work (such as network requests) on a background
+/// thread, so that the game keeps running while it waits. A task's result is
+/// picked up by polling the task, usually once per frame.
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+
+pub struct Task<T> {
+    receiver: Receiver<anyhow::Result<T>>,
+    result: Option<anyhow::Result<T>>,
+}
+
+impl<T: Send + 'static> Task<T> {
+    pub fn spawn(work: impl FnOnce() -> anyhow::Result<T> + Send + 'static) -> Task<T> {
+        let (sender, receiver) = mpsc::channel();
+        std::thread::spawn(move || {
+            // The receiver may have been dropped by now, which is fine.
+            let _ = sender.send(work());
+        });
+        Task {
+            receiver,
+            result: None,
+        }
+    }
+
+    /// Return the result of the task, or None if it hasn't finished yet.
+    pub fn poll(&mut self) -> Option<&anyhow::Result<T>> {
+        if self.result.is_none() {
+            self.result = match self.receiver.try_recv() {
+                Ok(result) => Some(result),
+                Err(TryRecvError::Empty) => None,
+                Err(TryRecvError::Disconnected) => Some(Err(anyhow::anyhow!("Task panicked"))),
+            };
+        }
+        self.result.as_ref()
+    }
+}
@@ -0,0 +1,212 @@
+/// This module handles the warmup telegraphs of enemies, which warn the player
+/// of where an enemy is about to become active. Each enemy has its own default
+/// warning, but charts may pick one of the shared styles here instead so that
+/// warnings look consistent across different enemy types.
+use ggez::graphics::{Color, DrawMode, MeshBuilder, Rect};
+use ggez::GameResult;
+use serde::{Deserialize, Serialize};
+
+use crate::color::{TRANSPARENT, WARNING_RED};
+use crate::ease::Lerp;
+use crate::enemy::OUTLINE_THICKNESS;
+use crate::meshutil;
+use crate::util;
+use crate::world::WORLD_SCALE_FACTOR;
+
+/// How many times the dashed outline blinks over the warmup.
+const DASH_BLINKS: f64 = 8.0;
+/// How many dashes make up a dashed circle.
+const DASH_COUNT: usize = 24;
+/// The length of each dash (and the gap after it) of a dashed line.
+const DASH_LENGTH: f32 = 2.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum TelegraphStyle {
+    /// The enemy's own warmup animation.
+    Default,
+    /// A faint preview of the enemy's final shape.
+    Ghost,
+    /// A ring which shrinks onto the enemy's final shape as the warmup ends.
+    CountdownRing,
+    /// A blinking dashed outline of the enemy's final shape.
+    DashedOutline,
+}
+
+impl Default for TelegraphStyle {
+    fn default() -> Self {
+        TelegraphStyle::Default
+    }
+}
+
+/// The final shape of an enemy, used to draw its telegraph. All units are in
+/// WorldLen units, and shapes are centered on the origin of the mesh.
+#[derive(Debug, Clone, Copy)]
+pub enum TelegraphShape {
+    Circle {
+        radius: f32,
+    },
+    /// A horizontal line, extending `length` in both directions.
+    Line {
+        length: f32,
+        thickness: f32,
+    },
+    /// An axis-aligned rectangle, extending `half_width` and `half_height` in
+    /// each direction.
+    Rect {
+        half_width: f32,
+        half_height: f32,
+    },
+}
+
+fn centered_rect(half_width: f32, half_height: f32) -> Rect {
+    Rect::new(
+        -half_width,
+        -half_height,
+        2.0 * half_width,
+        2.0 * half_height,
+    )
+}
+
+// Add dashes along the line from `start` to `end`, starting with a dash.
+fn dashes(
+    mesh: &mut MeshBuilder,
+    start: (f32, f32),
+    end: (f32, f32),
+    color: Color,
+) -> GameResult<()> {
+    let length = (end.0 - start.0).hypot(end.1 - start.1);
+    let at = |d: f32| {
+        let t = d / length;
+        util::mint(f32::lerp(start.0, end.0, t), f32::lerp(start.1, end.1, t))
+    };
+    let mut d = 0.0;
+    while d < length {
+        let points = [at(d), at((d + DASH_LENGTH).min(length))];
+        mesh.line(&points, OUTLINE_THICKNESS, color)?;
+        d += DASH_LENGTH * 2.0;
+    }
+    Ok(())
+}
+
+/// Add the telegraph for the given style and shape to the mesh. `t` is the
+/// percent of the way through the warmup. This does nothing for
+/// TelegraphStyle::Default, which enemies should handle themselves.
+pub fn draw_telegraph(
+    mesh: &mut MeshBuilder,
+    style: TelegraphStyle,
+    shape: TelegraphShape,
+    t: f64,
+) -> GameResult<()> {
+    let origin = util::mint(0.0, 0.0);
+    match style {
+        TelegraphStyle::Default => (),
+        TelegraphStyle::Ghost => {
+            let color = Color {
+                a: 0.3 * t as f32,
+                ..WARNING_RED
+            };
+            match shape {
+                TelegraphShape::Circle { radius } => {
+                    meshutil::circle(
+                        mesh,
+                        DrawMode::fill(),
+                        origin,
+                        radius,
+                        WORLD_SCALE_FACTOR,
+                        color,
+                    )?;
+                }
+                TelegraphShape::Line { length, thickness } => {
+                    let points = [util::mint(-length, 0.0), util::mint(length, 0.0)];
+                    meshutil::thick_line(mesh, &points, thickness, color)?;
+                }
+                TelegraphShape::Rect {
+                    half_width,
+                    half_height,
+                } => {
+                    let rect = centered_rect(half_width, half_height);
+                    mesh.rectangle(DrawMode::fill(), rect, color)?;
+                }
+            }
+        }
+        TelegraphStyle::CountdownRing => {
+            match shape {
+                TelegraphShape::Circle { radius } => {
+                    let ring_radius = f32::lerp(radius * 3.0, radius, t);
+                    meshutil::circle(
+                        mesh,
+                        DrawMode::stroke(OUTLINE_THICKNESS),
+                        origin,
+                        ring_radius,
+                        WORLD_SCALE_FACTOR,
+                        WARNING_RED,
+                    )?;
+                }
+                TelegraphShape::Line { length, thickness } => {
+                    // Lines have no natural center to shrink onto, so the ring
+                    // shrinks onto the line's anchor point instead and the
+                    // line itself is shown faintly.
+                    let ring_radius = f32::lerp(10.0, thickness, t);
+                    meshutil::circle(
+                        mesh,
+                        DrawMode::stroke(OUTLINE_THICKNESS),
+                        origin,
+                        ring_radius,
+                        WORLD_SCALE_FACTOR,
+                        WARNING_RED,
+                    )?;
+                    let points = [util::mint(-length, 0.0), util::mint(length, 0.0)];
+                    mesh.line(&points, OUTLINE_THICKNESS, WARNING_RED)?;
+                }
+                TelegraphShape::Rect {
+                    half_width,
+                    half_height,
+                } => {
+                    let scale = f32::lerp(3.0, 1.0, t);
+                    let rect = centered_rect(half_width * scale, half_height * scale);
+                    mesh.rectangle(DrawMode::stroke(OUTLINE_THICKNESS), rect, WARNING_RED)?;
+                }
+            }
+        }
+        TelegraphStyle::DashedOutline => {
+            // Blink on and off, spending half of each blink visible. The dashes
+            // are still added to the mesh while invisible, since building an
+            // empty mesh is an error.
+            let color = if (t * DASH_BLINKS).fract() > 0.5 {
+                TRANSPARENT
+            } else {
+                WARNING_RED
+            };
+            match shape {
+                TelegraphShape::Circle { radius } => {
+                    let step = std::f32::consts::TAU / DASH_COUNT as f32;
+                    for i in 0..DASH_COUNT {
+                        // Each dash covers half of its step, leaving a gap.
+                        let (start, end) = (i as f32 * step, (i as f32 + 0.5) * step);
+                        let points = [
+                            util::mint(radius * start.cos(), radius * start.sin()),
+                            util::mint(radius * end.cos(), radius * end.sin()),
+                        ];
+                        mesh.line(&points, OUTLINE_THICKNESS, color)?;
+                    }
+                }
+                TelegraphShape::Line { length, thickness } => {
+                    for &y in &[-thickness, thickness] {
+                        dashes(mesh, (-length, y), (length, y), color)?;
+                    }
+                }
+                TelegraphShape::Rect {
+                    half_width,
+                    half_height,
+                } => {
+                    let (w, h) = (half_width, half_height);
+                    let corners = [(-w, -h), (w, -h), (w, h), (-w, h), (-w, -h)];
+                    for edge in corners.windows(2) {
+                        dashes(mesh, edge[0], edge[1], color)?;
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
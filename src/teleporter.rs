@@ -0,0 +1,154 @@
+/// This module implements teleporters, which are pairs of pads that move the
+/// player from one point in the arena to another. Standing on the entrance pad
+/// for long enough warps the player to the exit pad. Teleporters are one way,
+/// so a chart that wants the player to be able to come back should spawn a
+/// second teleporter going the other way. After warping the player, the
+/// entrance pad doesn't charge again until the player has stepped off of it,
+/// so an exit pad placed on top of the entrance can't warp the player over and
+/// over.
+use ggez::graphics::{DrawMode, DrawParam, Drawable, MeshBuilder};
+use ggez::{Context, GameResult};
+
+use crate::color;
+use crate::meshutil;
+use crate::player::Player;
+use crate::time::Beats;
+use crate::world::{WorldLen, WorldPos};
+
+/// How long the player must stand on the entrance pad before being warped.
+pub const TELEPORT_CHARGE: Beats = Beats(1.0);
+
+#[derive(Debug, Clone)]
+pub struct Teleporter {
+    entrance: WorldPos,
+    exit: WorldPos,
+    radius: WorldLen,
+    start_time: Beats,
+    duration: Beats,
+    // When the player started standing on the entrance pad, if they currently
+    // are on it.
+    standing_since: Option<Beats>,
+    // True if the player hasn't stepped off the entrance pad since they were
+    // last warped.
+    warped: bool,
+}
+
+impl Teleporter {
+    pub fn new(
+        entrance: WorldPos,
+        exit: WorldPos,
+        radius: WorldLen,
+        start_time: Beats,
+        duration: Beats,
+    ) -> Teleporter {
+        Teleporter {
+            entrance,
+            exit,
+            radius,
+            start_time,
+            duration,
+            standing_since: None,
+            warped: false,
+        }
+    }
+
+    /// Returns true if the teleporter should be removed from the world.
+    pub fn is_dead(&self, curr_time: Beats) -> bool {
+        curr_time > self.start_time + self.duration
+    }
+
+    /// Warp the player if they have been standing on the entrance pad for long
+    /// enough. Returns true if the player was warped.
+    pub fn update(&mut self, player: &mut Player, curr_time: Beats) -> bool {
+        if WorldPos::distance(player.pos, self.entrance) > self.radius {
+            self.standing_since = None;
+            self.warped = false;
+            return false;
+        }
+        if self.warped {
+            return false;
+        }
+
+        let standing_since = *self.standing_since.get_or_insert(curr_time);
+        if curr_time - standing_since >= TELEPORT_CHARGE {
+            player.teleport(self.exit);
+            self.standing_since = None;
+            self.warped = true;
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn draw(&self, ctx: &mut Context, curr_time: Beats) -> GameResult<()> {
+        let charge = match self.standing_since {
+            Some(standing_since) => {
+                ((curr_time - standing_since).0 / TELEPORT_CHARGE.0).clamp(0.0, 1.0)
+            }
+            None => 0.0,
+        };
+        let radius = self.radius.as_screen_length();
+
+        let mut mesh = MeshBuilder::new();
+        mesh.line(
+            &[
+                self.entrance.as_screen_coords(),
+                self.exit.as_screen_coords(),
+            ],
+            1.0,
+            color::GUIDE_GREY,
+        )?;
+        meshutil::circle(
+            &mut mesh,
+            DrawMode::stroke(2.0),
+            self.entrance.as_screen_coords(),
+            radius,
+            1.0,
+            color::TELEPORT_BLUE,
+        )?;
+        meshutil::circle(
+            &mut mesh,
+            DrawMode::stroke(1.0),
+            self.exit.as_screen_coords(),
+            radius,
+            1.0,
+            color::TELEPORT_BLUE,
+        )?;
+        // Fill in the entrance pad as the player charges it up.
+        if charge > 0.0 {
+            meshutil::circle(
+                &mut mesh,
+                DrawMode::fill(),
+                self.entrance.as_screen_coords(),
+                radius * charge as f32,
+                1.0,
+                color::TELEPORT_BLUE,
+            )?;
+        }
+        mesh.build(ctx)?.draw(ctx, DrawParam::default())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_exit_on_entrance_warps_once() {
+        let pos = WorldPos { x: 10.0, y: 0.0 };
+        let mut teleporter = Teleporter::new(pos, pos, WorldLen(4.0), Beats(0.0), Beats(100.0));
+        let mut player = Player::default();
+        player.pos = pos;
+
+        let warps = (0..1000)
+            .filter(|&i| teleporter.update(&mut player, Beats(i as f64 * 0.01)))
+            .count();
+        assert_eq!(warps, 1);
+
+        player.pos = WorldPos::origin();
+        teleporter.update(&mut player, Beats(10.0));
+        player.pos = pos;
+        teleporter.update(&mut player, Beats(10.5));
+        assert!(teleporter.update(&mut player, Beats(11.5)));
+    }
+}
@@ -0,0 +1,132 @@
+/// This module implements arena themes, which set the colors of the background,
+/// the arena grid, and the enemies. Charts switch themes with `set_theme`
+/// spawn_cmds, like `{ spawn_cmd = "set_theme", palette = { background = ...,
+/// grid = ..., enemy = ... }, duration = 4 }`, which crossfade from the current
+/// theme over `duration` beats. Since palettes are just Lua tables, a chart can
+/// define its themes once as variables and reuse them at each section.
+use ggez::graphics::{Color, DrawMode, DrawParam, Drawable, MeshBuilder, Rect};
+use ggez::{Context, GameResult};
+use serde::{Deserialize, Serialize};
+
+use crate::color::{self, serde_color};
+use crate::ease::Lerp;
+use crate::meshutil;
+use crate::time::Beats;
+use crate::world::{WorldLen, WorldPos, ARENA_HALF_SIZE};
+use crate::{WINDOW_HEIGHT, WINDOW_WIDTH};
+
+/// The spacing between the lines of the arena grid.
+const GRID_SPACING: f64 = 10.0;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Theme {
+    #[serde(with = "serde_color")]
+    pub background: Color,
+    /// The color of the arena grid. The default theme has no grid.
+    #[serde(with = "serde_color")]
+    pub grid: Color,
+    /// The color every enemy is multiplied by, on top of each group's tint.
+    #[serde(with = "serde_color")]
+    pub enemy: Color,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Theme {
+            background: Color::BLACK,
+            grid: color::TRANSPARENT,
+            enemy: color::WHITE,
+        }
+    }
+}
+
+impl Lerp for Theme {
+    fn lerp_unclamped(a: Self, b: Self, t: f64) -> Self {
+        Theme {
+            background: Color::lerp_unclamped(a.background, b.background, t),
+            grid: Color::lerp_unclamped(a.grid, b.grid, t),
+            enemy: Color::lerp_unclamped(a.enemy, b.enemy, t),
+        }
+    }
+}
+
+impl Theme {
+    /// Draw the background over the whole screen. This should be called before
+    /// the screen transform is applied.
+    pub fn draw_background(&self, ctx: &mut Context) -> GameResult<()> {
+        let rect = Rect::new(0.0, 0.0, WINDOW_WIDTH, WINDOW_HEIGHT);
+        let mut mesh = MeshBuilder::new();
+        mesh.rectangle(DrawMode::fill(), rect, self.background)?;
+        mesh.build(ctx)?.draw(ctx, DrawParam::default())
+    }
+
+    /// Draw the grid over the arena, if the theme has one.
+    pub fn draw_grid(&self, ctx: &mut Context) -> GameResult<()> {
+        if self.grid.a <= 0.0 {
+            return Ok(());
+        }
+        let mut mesh = MeshBuilder::new();
+        let lines = (ARENA_HALF_SIZE / GRID_SPACING) as i32;
+        for i in -lines..=lines {
+            let offset = i as f64 * GRID_SPACING;
+            let vertical = [
+                WorldPos::from((offset, -ARENA_HALF_SIZE)).as_screen_coords(),
+                WorldPos::from((offset, ARENA_HALF_SIZE)).as_screen_coords(),
+            ];
+            let horizontal = [
+                WorldPos::from((-ARENA_HALF_SIZE, offset)).as_screen_coords(),
+                WorldPos::from((ARENA_HALF_SIZE, offset)).as_screen_coords(),
+            ];
+            meshutil::thick_line(&mut mesh, &vertical, 0.5, self.grid)?;
+            meshutil::thick_line(&mut mesh, &horizontal, 0.5, self.grid)?;
+        }
+        let size = WorldLen(2.0 * ARENA_HALF_SIZE);
+        let arena = WorldPos::as_screen_rect(WorldPos::origin(), size, size);
+        mesh.rectangle(DrawMode::stroke(2.0), arena, self.grid)?;
+        mesh.build(ctx)?.draw(ctx, DrawParam::default())
+    }
+}
+
+/// A crossfade from one theme to another.
+#[derive(Debug, Clone, Copy)]
+pub struct ThemeFade {
+    from: Theme,
+    to: Theme,
+    start_time: Beats,
+    duration: Beats,
+}
+
+impl Default for ThemeFade {
+    fn default() -> Self {
+        ThemeFade {
+            from: Theme::default(),
+            to: Theme::default(),
+            start_time: Beats(0.0),
+            duration: Beats(0.0),
+        }
+    }
+}
+
+impl ThemeFade {
+    /// Start fading from the theme at `start_time` to `to`.
+    pub fn fade_to(&self, to: Theme, start_time: Beats, duration: Beats) -> ThemeFade {
+        ThemeFade {
+            from: self.current(start_time),
+            to,
+            start_time,
+            duration,
+        }
+    }
+
+    pub fn current(&self, curr_time: Beats) -> Theme {
+        if self.duration.0 <= 0.0 {
+            return if curr_time >= self.start_time {
+                self.to
+            } else {
+                self.from
+            };
+        }
+        let t = (curr_time - self.start_time).0 / self.duration.0;
+        Theme::lerp(self.from, self.to, t)
+    }
+}
@@ -1,14 +1,19 @@
 use std::fmt::Debug;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 use derive_more::{Add, Div, From, Mul, Rem, Sub};
+use serde::{Deserialize, Serialize};
 
 /// Unit of time representing seconds
-#[derive(Copy, Clone, Debug, Add, Div, From, Mul, Rem, Sub, PartialEq, PartialOrd)]
+#[derive(
+    Copy, Clone, Debug, Add, Div, From, Mul, Rem, Sub, PartialEq, PartialOrd, Serialize, Deserialize,
+)]
 pub struct Seconds(pub f64);
 
 /// Unit of time representing beats
-#[derive(Copy, Clone, Add, Div, From, Mul, Rem, Sub, PartialEq, PartialOrd)]
+#[derive(
+    Copy, Clone, Add, Div, From, Mul, Rem, Sub, PartialEq, PartialOrd, Serialize, Deserialize,
+)]
 pub struct Beats(pub f64);
 
 /// Convert Beats to the number of Seconds, given some BPM. For example, if the
@@ -46,6 +51,93 @@ impl Debug for Beats {
     }
 }
 
+/// A ranked change to the speed of the song. The music and the chart are sped
+/// up (or slowed down) together, so that the chart stays in time with the
+/// music, but the player moves as fast as ever, which makes faster songs
+/// harder. Runs are tagged with the speed they were played at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum SongSpeed {
+    /// 0.9x speed, also known as daycore.
+    Slow,
+    Normal,
+    /// 1.1x speed, also known as nightcore.
+    Fast,
+    /// 1.2x speed.
+    Faster,
+}
+
+impl SongSpeed {
+    /// Every speed, from slowest to fastest.
+    pub const ALL: [SongSpeed; 4] = [
+        SongSpeed::Slow,
+        SongSpeed::Normal,
+        SongSpeed::Fast,
+        SongSpeed::Faster,
+    ];
+
+    /// How many seconds of the song pass per second.
+    pub fn rate(self) -> f64 {
+        match self {
+            SongSpeed::Slow => 0.9,
+            SongSpeed::Normal => 1.0,
+            SongSpeed::Fast => 1.1,
+            SongSpeed::Faster => 1.2,
+        }
+    }
+
+    /// The key of the speed's name in the lang files.
+    pub fn key(self) -> &'static str {
+        match self {
+            SongSpeed::Slow => "song_speed.slow",
+            SongSpeed::Normal => "song_speed.normal",
+            SongSpeed::Fast => "song_speed.fast",
+            SongSpeed::Faster => "song_speed.faster",
+        }
+    }
+
+    /// Return the next fastest speed, wrapping around to the slowest.
+    pub fn next(self) -> SongSpeed {
+        let i = SongSpeed::ALL
+            .iter()
+            .position(|&speed| speed == self)
+            .unwrap();
+        SongSpeed::ALL[(i + 1) % SongSpeed::ALL.len()]
+    }
+}
+
+impl Default for SongSpeed {
+    fn default() -> Self {
+        SongSpeed::Normal
+    }
+}
+
+/// How far the Time may drift from the music's actual position before it is
+/// resynced.
+const RESYNC_THRESHOLD: Seconds = Seconds(0.1);
+/// How long a resync takes. The clock is slewed over this long rather than
+/// jumping straight to the correct time, so that enemies don't visibly teleport.
+const RESYNC_DURATION: Seconds = Seconds(0.5);
+
+/// A resync in progress, which gradually shifts `Time::correction`.
+#[derive(Debug, Clone, Copy)]
+struct Slew {
+    start: Instant,
+    from: Seconds,
+    to: Seconds,
+}
+
+impl Slew {
+    fn correction_at(&self, instant: Instant) -> Seconds {
+        let t = instant.saturating_duration_since(self.start).as_secs_f64() / RESYNC_DURATION.0;
+        let t = t.clamp(0.0, 1.0);
+        Seconds(self.from.0 + (self.to.0 - self.from.0) * t)
+    }
+
+    fn is_done(&self, instant: Instant) -> bool {
+        instant.saturating_duration_since(self.start).as_secs_f64() >= RESYNC_DURATION.0
+    }
+}
+
 /// Time keeping struct for when music is playing
 #[derive(Debug)]
 pub struct Time {
@@ -60,6 +152,21 @@ pub struct Time {
     // This is useful if an audio file contains a small delay at the start of
     // the song. For example, if `offset` is 0.65 then 0.65 seconds are added to `get_time()`.
     offset: Seconds,
+    // How long it takes for the player's input to reach the game, relative to
+    // what is shown on screen. Key presses are treated as having happened this
+    // much earlier than they actually did. This is separate from `offset`,
+    // since display latency and audio latency often differ.
+    input_offset: Seconds,
+    // The amount of time added to `get_time()` to keep it in sync with the
+    // music. This is only nonzero if the audio device has stalled at some point.
+    correction: Seconds,
+    // The resync currently in progress, if any.
+    slew: Option<Slew>,
+    // How many seconds of the song pass per real second. See `SongSpeed`.
+    // Everything except the real time since the start is in song seconds.
+    rate: f64,
+    // When the time was paused, if it is currently paused.
+    paused_at: Option<Instant>,
 }
 
 impl Time {
@@ -71,11 +178,98 @@ impl Time {
             exact_start: Instant::now(),
             last_update: None,
             offset,
+            input_offset: Seconds(0.0),
+            correction: Seconds(0.0),
+            slew: None,
+            rate: 1.0,
+            paused_at: None,
+        }
+    }
+
+    pub fn rate(&self) -> f64 {
+        self.rate
+    }
+
+    /// Set how fast the song plays. This should be set before the song starts
+    /// (ex: right after `new()`), since the song's current position isn't kept.
+    pub fn set_rate(&mut self, rate: f64) {
+        self.rate = rate;
+    }
+
+    pub fn input_offset(&self) -> Seconds {
+        self.input_offset
+    }
+
+    pub fn set_input_offset(&mut self, input_offset: Seconds) {
+        self.input_offset = input_offset;
+    }
+
+    /// Stop the time from advancing until `resume()` is called. The time
+    /// shouldn't be updated while paused.
+    pub fn pause(&mut self) {
+        if self.paused_at.is_none() {
+            self.paused_at = Some(Instant::now());
+        }
+    }
+
+    /// Start the time again from where it was paused.
+    pub fn resume(&mut self) {
+        if let Some(paused_at) = self.paused_at.take() {
+            self.exact_start += paused_at.elapsed();
         }
     }
 
     pub fn update(&mut self) {
-        self.last_update = Some(Instant::now());
+        let now = Instant::now();
+        self.last_update = Some(now);
+        if let Some(slew) = self.slew {
+            if slew.is_done(now) {
+                self.correction = slew.to;
+                self.slew = None;
+            }
+        }
+    }
+
+    /// Compare the time against the music's actual position, and begin slewing
+    /// towards it if the two have drifted too far apart (ex: because the audio
+    /// device stalled). This should be called after `update()`.
+    pub fn resync(&mut self, music_position: Seconds) {
+        if self.slew.is_some() {
+            return;
+        }
+        let drift = music_position.0 - self.get_time().0;
+        if drift.abs() > RESYNC_THRESHOLD.0 {
+            log::warn!("Music drifted by {:.3}s, resyncing", drift);
+            self.slew = Some(Slew {
+                start: self.last_update.unwrap_or_else(Instant::now),
+                from: self.correction,
+                to: Seconds(self.correction.0 + drift),
+            });
+        }
+    }
+
+    /// Line the Time up with the music, given the music's position as of the
+    /// most recent `update()` call. kira only starts the music once the audio
+    /// thread gets to it, so this should be called on the first update where
+    /// the music is playing, which lines beat 0 up with the song itself rather
+    /// than with whenever `play` happened to be called. Unlike `resync`, this
+    /// jumps straight to the music's time, which is fine since the jump is at
+    /// most the audio device's latency.
+    pub fn align_start(&mut self, music_position: Seconds) {
+        let now = self.last_update.unwrap_or_else(Instant::now);
+        let elapsed = (music_position.0 - self.offset.0).max(0.0) / self.rate;
+        let elapsed = Duration::from_secs_f64(elapsed);
+        self.exact_start = now.checked_sub(elapsed).unwrap_or(now);
+        self.correction = Seconds(0.0);
+        self.slew = None;
+    }
+
+    // Return the correction as of the most recent `update()` call.
+    fn current_correction(&self) -> Seconds {
+        match (self.slew, self.last_update) {
+            (Some(slew), Some(last_update)) => slew.correction_at(last_update),
+            _ => self.correction,
+        }
     }
 
     /// Return the time sinceDuration::from_std( the SongTime started ticking. This is affected by).unwrap()
@@ -92,10 +286,46 @@ impl Time {
             0.0
         };
 
-        Seconds(exact) + self.offset
+        Seconds(exact * self.rate) + self.offset + self.current_correction()
     }
 
     pub fn get_beats(&self) -> Beats {
         to_beats(self.get_time(), self.bpm)
     }
+
+    /// Return the beat that an input received at `instant` should count as
+    /// happening on, compensating for the input offset.
+    pub fn input_beats(&self, instant: Instant) -> Beats {
+        let exact = instant
+            .saturating_duration_since(self.exact_start)
+            .as_secs_f64();
+        let correction = match self.slew {
+            Some(slew) => slew.correction_at(instant),
+            None => self.correction,
+        };
+        // The input offset is real time, since it's the player's latency.
+        to_beats(
+            Seconds((exact - self.input_offset.0) * self.rate) + self.offset + correction,
+            self.bpm,
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_input_offset_moves_presses_earlier() {
+        let mut time = Time::new(120.0, Seconds(0.0));
+        let pressed = time.exact_start + Duration::from_secs(1);
+        assert!((time.input_beats(pressed).0 - 2.0).abs() < 0.0001);
+
+        time.set_input_offset(Seconds(0.05));
+        assert!((time.input_beats(pressed).0 - 1.9).abs() < 0.0001);
+
+        // The input offset is in real seconds, no matter the song's speed.
+        time.set_rate(2.0);
+        assert!((time.input_beats(pressed).0 - 3.8).abs() < 0.0001);
+    }
 }
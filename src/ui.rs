@@ -0,0 +1,319 @@
+/// This module implements the widgets shared by every menu, so that all menus
+/// behave the same way. A menu is a vertical list of items which can be used
+/// with the keyboard (up and down to move, left and right to adjust sliders,
+/// space or enter to activate, escape to go back) or with the mouse (hover to
+/// focus, click to activate or to set a slider). Every menu and the HUD are
+/// laid out in a WINDOW_WIDTH by WINDOW_HEIGHT screen, which is scaled up to
+/// match the window's scale factor (see `set_scale_factor`) so that text stays
+/// readable on high DPI displays.
+use ggez::event::{KeyCode, MouseButton};
+use ggez::graphics::mint::Point2;
+use ggez::graphics::{self, DrawMode, DrawParam, Drawable, Mesh, Rect};
+use ggez::{Context, GameResult};
+
+use crate::color;
+use crate::font::FontSet;
+use crate::{WINDOW_HEIGHT, WINDOW_WIDTH};
+
+const LINE_HEIGHT: f32 = 24.0;
+const TEXT_SIZE: f32 = 18.0;
+const MENU_WIDTH: f32 = 500.0;
+/// The width of the bar drawn for sliders, which sits at the right side of the
+/// slider's line.
+const SLIDER_WIDTH: f32 = 150.0;
+
+/// Return the window's scale factor, which is how many physical pixels the
+/// display uses for each logical pixel.
+pub fn scale_factor(ctx: &Context) -> f32 {
+    graphics::window(ctx).scale_factor() as f32
+}
+
+/// Resize the window to `scale` times the size of the screen, so that all text
+/// and HUD sizes are multiplied by `scale`. The screen coordinates stay the
+/// same, so nothing needs to be laid out differently.
+pub fn set_scale_factor(ctx: &mut Context, scale: f32) -> GameResult<()> {
+    graphics::set_drawable_size(ctx, WINDOW_WIDTH * scale, WINDOW_HEIGHT * scale)?;
+    graphics::set_screen_coordinates(ctx, Rect::new(0.0, 0.0, WINDOW_WIDTH, WINDOW_HEIGHT))
+}
+
+/// Convert a position in the window, such as the mouse's position, to screen
+/// coordinates.
+pub fn to_screen_coords(ctx: &Context, x: f32, y: f32) -> Point2<f32> {
+    let screen = graphics::screen_coordinates(ctx);
+    let (width, height) = graphics::drawable_size(ctx);
+    Point2 {
+        x: screen.x + x * screen.w / width,
+        y: screen.y + y * screen.h / height,
+    }
+}
+
+pub enum WidgetKind {
+    Button,
+    Slider {
+        value: f64,
+        min: f64,
+        max: f64,
+        step: f64,
+    },
+}
+
+pub struct Widget {
+    pub label: String,
+    pub kind: WidgetKind,
+}
+
+impl Widget {
+    pub fn button(label: impl Into<String>) -> Widget {
+        Widget {
+            label: label.into(),
+            kind: WidgetKind::Button,
+        }
+    }
+
+    pub fn slider(label: impl Into<String>, value: f64, min: f64, max: f64, step: f64) -> Widget {
+        Widget {
+            label: label.into(),
+            kind: WidgetKind::Slider {
+                value: value.clamp(min, max),
+                min,
+                max,
+                step,
+            },
+        }
+    }
+
+    /// Return the text shown for the widget, which is also what is narrated.
+    pub fn text(&self) -> String {
+        match self.kind {
+            WidgetKind::Button => self.label.clone(),
+            WidgetKind::Slider { value, .. } => format!("{}: {:.3}", self.label, value),
+        }
+    }
+
+    /// Return the value of the widget, if it is a slider.
+    pub fn value(&self) -> Option<f64> {
+        match self.kind {
+            WidgetKind::Slider { value, .. } => Some(value),
+            WidgetKind::Button => None,
+        }
+    }
+
+    // Move the slider by `steps` steps. Returns true if the value changed.
+    fn nudge(&mut self, steps: f64) -> bool {
+        match &mut self.kind {
+            WidgetKind::Slider {
+                value,
+                min,
+                max,
+                step,
+            } => {
+                let new_value = (*value + steps * *step).clamp(*min, *max);
+                let changed = new_value != *value;
+                *value = new_value;
+                changed
+            }
+            WidgetKind::Button => false,
+        }
+    }
+
+    // Set the slider to the point `t` of the way between its min and max,
+    // rounded to the nearest step. Returns true if the value changed.
+    fn set_fraction(&mut self, t: f64) -> bool {
+        match &mut self.kind {
+            WidgetKind::Slider {
+                value,
+                min,
+                max,
+                step,
+            } => {
+                let raw = *min + (*max - *min) * t.clamp(0.0, 1.0);
+                let new_value = (*min + ((raw - *min) / *step).round() * *step).clamp(*min, *max);
+                let changed = new_value != *value;
+                *value = new_value;
+                changed
+            }
+            WidgetKind::Button => false,
+        }
+    }
+}
+
+/// Something the player did to a menu.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MenuEvent {
+    /// The item at this index was activated.
+    Activated(usize),
+    /// The slider at this index changed value.
+    Changed(usize),
+    /// The player asked to leave the menu.
+    Back,
+}
+
+pub struct Menu {
+    pub items: Vec<Widget>,
+    focused: usize,
+    /// The top left corner of the menu, in screen coordinates.
+    position: Point2<f32>,
+}
+
+impl Menu {
+    pub fn new(items: Vec<Widget>, position: Point2<f32>) -> Menu {
+        Menu {
+            items,
+            focused: 0,
+            position,
+        }
+    }
+
+    /// Return the index of the focused item. If the menu is empty, this is 0.
+    pub fn focused(&self) -> usize {
+        self.focused
+    }
+
+    /// Return the focused item, if the menu isn't empty.
+    pub fn focused_item(&self) -> Option<&Widget> {
+        self.items.get(self.focused)
+    }
+
+    pub fn set_focused(&mut self, focused: usize) {
+        if !self.items.is_empty() {
+            self.focused = focused.min(self.items.len() - 1);
+        }
+    }
+
+    pub fn key_down(&mut self, keycode: KeyCode) -> Option<MenuEvent> {
+        if keycode == KeyCode::Escape {
+            return Some(MenuEvent::Back);
+        }
+        if self.items.is_empty() {
+            return None;
+        }
+
+        match keycode {
+            KeyCode::Up | KeyCode::W => {
+                self.move_focus(-1);
+                None
+            }
+            KeyCode::Down | KeyCode::S => {
+                self.move_focus(1);
+                None
+            }
+            KeyCode::Left | KeyCode::A | KeyCode::Right | KeyCode::D => {
+                let steps = if matches!(keycode, KeyCode::Left | KeyCode::A) {
+                    -1.0
+                } else {
+                    1.0
+                };
+                if self.items[self.focused].nudge(steps) {
+                    Some(MenuEvent::Changed(self.focused))
+                } else {
+                    None
+                }
+            }
+            KeyCode::Space | KeyCode::Return => match self.items[self.focused].kind {
+                WidgetKind::Button => Some(MenuEvent::Activated(self.focused)),
+                WidgetKind::Slider { .. } => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Focus whichever item the mouse is over, if any.
+    pub fn mouse_motion(&mut self, x: f32, y: f32) {
+        if let Some(i) = self.item_at(x, y) {
+            self.focused = i;
+        }
+    }
+
+    pub fn mouse_button_down(&mut self, button: MouseButton, x: f32, y: f32) -> Option<MenuEvent> {
+        if button != MouseButton::Left {
+            return None;
+        }
+        let i = self.item_at(x, y)?;
+        self.focused = i;
+        match self.items[i].kind {
+            WidgetKind::Button => Some(MenuEvent::Activated(i)),
+            WidgetKind::Slider { .. } => {
+                let bar = self.slider_rect(i);
+                if x >= bar.x && self.items[i].set_fraction(((x - bar.x) / bar.w) as f64) {
+                    Some(MenuEvent::Changed(i))
+                } else {
+                    None
+                }
+            }
+        }
+    }
+
+    pub fn draw(&self, ctx: &mut Context, fonts: &FontSet) -> GameResult<()> {
+        for (i, item) in self.items.iter().enumerate() {
+            let rect = self.item_rect(i);
+            let focused = i == self.focused;
+            let text_color = if focused {
+                color::WHITE
+            } else {
+                color::GUIDE_GREY
+            };
+
+            let text = item.text();
+            let cursor = if focused { "> " } else { "  " };
+            fonts
+                .text(&format!("{}{}", cursor, text), text_color, TEXT_SIZE)
+                .draw(
+                    ctx,
+                    DrawParam::default().dest(Point2 {
+                        x: rect.x,
+                        y: rect.y,
+                    }),
+                )?;
+
+            if let WidgetKind::Slider {
+                value, min, max, ..
+            } = item.kind
+            {
+                let bar = self.slider_rect(i);
+                Mesh::new_rectangle(ctx, DrawMode::stroke(1.0), bar, text_color)?
+                    .draw(ctx, DrawParam::default())?;
+                let t = if max > min {
+                    ((value - min) / (max - min)) as f32
+                } else {
+                    0.0
+                };
+                if t > 0.0 {
+                    let filled = Rect::new(bar.x, bar.y, bar.w * t, bar.h);
+                    Mesh::new_rectangle(ctx, DrawMode::fill(), filled, text_color)?
+                        .draw(ctx, DrawParam::default())?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn move_focus(&mut self, delta: i32) {
+        let new_focus = delta + self.focused as i32;
+        self.focused = i32::rem_euclid(new_focus, self.items.len() as i32) as usize;
+    }
+
+    // The area of the screen covered by the item at index i.
+    fn item_rect(&self, i: usize) -> Rect {
+        Rect::new(
+            self.position.x,
+            self.position.y + i as f32 * LINE_HEIGHT,
+            MENU_WIDTH,
+            LINE_HEIGHT,
+        )
+    }
+
+    // The area of the screen covered by the bar of the slider at index i.
+    fn slider_rect(&self, i: usize) -> Rect {
+        let rect = self.item_rect(i);
+        Rect::new(
+            rect.x + rect.w - SLIDER_WIDTH,
+            rect.y + LINE_HEIGHT / 4.0,
+            SLIDER_WIDTH,
+            LINE_HEIGHT / 2.0,
+        )
+    }
+
+    fn item_at(&self, x: f32, y: f32) -> Option<usize> {
+        (0..self.items.len()).find(|&i| self.item_rect(i).contains(Point2 { x, y }))
+    }
+}
@@ -1,3 +1,5 @@
+use std::cmp::Ordering;
+
 use ggez::mint;
 
 #[derive(PartialEq, Debug, Clone, Copy)]
@@ -34,3 +36,76 @@ pub fn quartic(n: f64) -> f64 {
 pub fn rev_quartic(n: f64) -> f64 {
     1.0 - quartic(1.0 - n)
 }
+
+/// Hash some bytes using 64-bit FNV-1a. Unlike std's DefaultHasher, this hash
+/// is stable across runs and Rust versions, so it may be used for things saved
+/// to disk.
+pub fn hash_bytes(bytes: &[u8]) -> u64 {
+    const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+    const FNV_PRIME: u64 = 0x100000001b3;
+    let mut hash = FNV_OFFSET_BASIS;
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(FNV_PRIME);
+    }
+    hash
+}
+
+/// Compare two strings in "natural" order, where runs of digits are compared by
+/// their numeric value and letters are compared case insensitively. For
+/// example, "Level 2" sorts before "level 10". Strings which only differ in
+/// case are then compared normally, so that the ordering is total.
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let mut a_chars = a.chars().peekable();
+    let mut b_chars = b.chars().peekable();
+    loop {
+        match (a_chars.peek().copied(), b_chars.peek().copied()) {
+            (None, None) => return a.cmp(b),
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let x = take_digits(&mut a_chars);
+                let y = take_digits(&mut b_chars);
+                // Compare by length first so that arbitrarily long numbers
+                // don't overflow, ignoring leading zeros.
+                let (x, y) = (x.trim_start_matches('0'), y.trim_start_matches('0'));
+                match x.len().cmp(&y.len()).then_with(|| x.cmp(y)) {
+                    Ordering::Equal => (),
+                    ordering => return ordering,
+                }
+            }
+            (Some(x), Some(y)) => {
+                a_chars.next();
+                b_chars.next();
+                match x.to_lowercase().cmp(y.to_lowercase()) {
+                    Ordering::Equal => (),
+                    ordering => return ordering,
+                }
+            }
+        }
+    }
+}
+
+fn take_digits(chars: &mut std::iter::Peekable<std::str::Chars>) -> String {
+    let mut digits = String::new();
+    while let Some(c) = chars.peek().copied().filter(char::is_ascii_digit) {
+        digits.push(c);
+        chars.next();
+    }
+    digits
+}
+
+#[cfg(test)]
+mod test {
+    use super::natural_cmp;
+
+    #[test]
+    pub fn test_natural_sort() {
+        let mut names = vec!["level 10", "Level 2", "level 1", "b", "A", "Level 02"];
+        names.sort_by(|a, b| natural_cmp(a, b));
+        assert_eq!(
+            names,
+            vec!["A", "b", "level 1", "Level 02", "Level 2", "level 10"]
+        );
+    }
+}
@@ -0,0 +1,300 @@
+/// This module lets levels be read either from a folder or from a single .zip
+/// archive, so that a level (or a pack of levels) can be shared as one file.
+/// Archives are read into memory when opened, but each file in the archive is
+/// only decompressed when it is read. Since the sizes an archive claims for its
+/// files can't be trusted, no file may decompress to more than
+/// MAX_ARCHIVE_SIZE, and archives are only installed if all of their files
+/// together fit in it. Paths are always given as
+/// if the archive were a folder, so `levels/song.zip/song.mid` refers to
+/// `song.mid` inside of `levels/song.zip`. Paths which fall outside of the
+/// archive (ex: the shared library folder) are read from the filesystem.
+/// This module also handles installing levels into the levels folder.
+use std::collections::HashMap;
+use std::io::{Cursor, Read, Seek};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use crate::cache::CHART_FILE;
+
+/// The file extension of level archives.
+pub const ARCHIVE_EXTENSION: &str = "zip";
+/// The most bytes the files in an archive may decompress to, which keeps an
+/// archive from using up all of the memory (ex: a "zip bomb").
+pub const MAX_ARCHIVE_SIZE: u64 = 256 * 1024 * 1024;
+
+#[derive(Debug, Clone)]
+pub struct Vfs {
+    root: PathBuf,
+    archive: Option<Archive>,
+}
+
+#[derive(Debug, Clone)]
+struct Archive {
+    // The archive itself, still compressed.
+    bytes: Arc<Vec<u8>>,
+    // The index of each file in the archive, keyed by their path relative to
+    // the root.
+    files: Arc<HashMap<PathBuf, usize>>,
+    // When the archive itself was last modified. Files inside of the archive
+    // count as modified whenever the archive is.
+    modified: SystemTime,
+}
+
+impl Vfs {
+    /// Open the level at `root`, which is either a folder or an archive.
+    pub fn open(root: impl AsRef<Path>) -> anyhow::Result<Vfs> {
+        let root = root.as_ref().to_path_buf();
+        let archive = if is_archive(&root) {
+            let bytes = std::fs::read(&root)?;
+            let files = index_archive(&bytes, &root)?;
+            Some(Archive {
+                bytes: Arc::new(bytes),
+                files: Arc::new(files),
+                modified: std::fs::metadata(&root)?.modified()?,
+            })
+        } else {
+            None
+        };
+        Ok(Vfs { root, archive })
+    }
+
+    /// The folder or archive this Vfs was opened on.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn is_archive(&self) -> bool {
+        self.archive.is_some()
+    }
+
+    pub fn read(&self, path: impl AsRef<Path>) -> std::io::Result<Vec<u8>> {
+        let path = path.as_ref();
+        match self.archive_path(path) {
+            Some((archive, relative)) => {
+                let index = *archive.files.get(relative).ok_or_else(|| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::NotFound,
+                        format!("{:?} is not in the archive {:?}", relative, self.root),
+                    )
+                })?;
+                let mut zip = zip::ZipArchive::new(Cursor::new(archive.bytes.as_slice()))?;
+                read_entry(&mut zip, index, MAX_ARCHIVE_SIZE)
+            }
+            None => std::fs::read(path),
+        }
+    }
+
+    pub fn is_file(&self, path: impl AsRef<Path>) -> bool {
+        let path = path.as_ref();
+        match self.archive_path(path) {
+            Some((archive, relative)) => archive.files.contains_key(relative),
+            None => path.is_file(),
+        }
+    }
+
+    pub fn modified(&self, path: impl AsRef<Path>) -> std::io::Result<SystemTime> {
+        let path = path.as_ref();
+        match self.archive_path(path) {
+            Some((archive, _)) => Ok(archive.modified),
+            None => std::fs::metadata(path)?.modified(),
+        }
+    }
+
+    // If `path` is inside of the archive, return the archive and the path
+    // relative to the archive's root.
+    fn archive_path<'a, 'b>(&'a self, path: &'b Path) -> Option<(&'a Archive, &'b Path)> {
+        let archive = self.archive.as_ref()?;
+        let relative = path.strip_prefix(&self.root).ok()?;
+        Some((archive, relative))
+    }
+}
+
+/// Returns true if `path` is a level archive.
+pub fn is_archive(path: impl AsRef<Path>) -> bool {
+    let path = path.as_ref();
+    path.extension()
+        .map_or(false, |ext| ext == ARCHIVE_EXTENSION)
+        && path.is_file()
+}
+
+/// Returns true if `path` is a level, either as a folder containing a chart or
+/// as a level archive.
+pub fn is_level(path: impl AsRef<Path>) -> bool {
+    let path = path.as_ref();
+    is_archive(path) || path.join(CHART_FILE).is_file()
+}
+
+/// Returns an error if `bytes` are not a valid level archive, or if its files
+/// decompress to more than MAX_ARCHIVE_SIZE in total.
+pub fn check_archive(bytes: &[u8]) -> anyhow::Result<()> {
+    let files = index_archive(bytes, Path::new("<download>"))?;
+    let mut zip = zip::ZipArchive::new(Cursor::new(bytes))?;
+    let mut remaining = MAX_ARCHIVE_SIZE;
+    for &index in files.values() {
+        let contents = read_entry(&mut zip, index, remaining)?;
+        remaining -= contents.len() as u64;
+    }
+    Ok(())
+}
+
+/// Return the index of every file in the archive `bytes`, keyed by their path.
+/// Archives made by zipping up a level folder usually have the folder itself as
+/// their only top level entry, so in that case the folder is treated as the
+/// root. `path` is only used for error messages.
+fn index_archive(bytes: &[u8], path: &Path) -> anyhow::Result<HashMap<PathBuf, usize>> {
+    let mut zip = zip::ZipArchive::new(Cursor::new(bytes))?;
+
+    let mut files = HashMap::new();
+    for i in 0..zip.len() {
+        let file = zip.by_index(i)?;
+        if file.is_dir() {
+            continue;
+        }
+        // Skip any entries which would escape the archive, like absolute paths
+        // or paths containing "..".
+        let name = match file.enclosed_name() {
+            Some(name) => name.to_path_buf(),
+            None => {
+                log::warn!("Skipping invalid path {:?} in {:?}", file.name(), path);
+                continue;
+            }
+        };
+        files.insert(name, i);
+    }
+
+    if !files.contains_key(Path::new(CHART_FILE)) {
+        if let Some(folder) = single_top_folder(&files) {
+            files = files
+                .into_iter()
+                .filter_map(|(name, index)| {
+                    let name = name.strip_prefix(&folder).ok()?.to_path_buf();
+                    Some((name, index))
+                })
+                .collect();
+        }
+    }
+
+    if !files.contains_key(Path::new(CHART_FILE)) {
+        anyhow::bail!("archive {:?} has no {}", path, CHART_FILE);
+    }
+    Ok(files)
+}
+
+/// Decompress the file at `index`, failing if it decompresses to more than
+/// `limit` bytes. The size the archive records for the file isn't trusted, so
+/// the file is only read up to the limit and memory is only allocated as it
+/// is read.
+fn read_entry<R: Read + Seek>(
+    zip: &mut zip::ZipArchive<R>,
+    index: usize,
+    limit: u64,
+) -> std::io::Result<Vec<u8>> {
+    let file = zip.by_index(index)?;
+    let name = file.name().to_string();
+    let mut contents = vec![];
+    file.take(limit.saturating_add(1))
+        .read_to_end(&mut contents)?;
+    if contents.len() as u64 > limit {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("{:?} decompresses to more than {} bytes", name, limit),
+        ));
+    }
+    Ok(contents)
+}
+
+// Return the top level folder of the files, if every file is inside of it.
+fn single_top_folder(files: &HashMap<PathBuf, usize>) -> Option<PathBuf> {
+    let mut top_folders = files.keys().map(|name| {
+        let mut components = name.components();
+        let first = components.next()?;
+        // Files at the top level have no folder.
+        components.next()?;
+        Some(PathBuf::from(first.as_os_str()))
+    });
+    let first = top_folders.next()??;
+    if top_folders.all(|folder| folder.as_ref() == Some(&first)) {
+        Some(first)
+    } else {
+        None
+    }
+}
+
+/// Install the level at `source` (a level folder or a level archive) into
+/// `levels_folder`, returning the path of the installed level. Archives are
+/// copied as-is, since they can be played without being extracted. Installing
+/// a level which already exists overwrites its files, so that newer versions
+/// of a level can be installed over older ones.
+pub fn install_level(source: &Path, levels_folder: &Path) -> anyhow::Result<PathBuf> {
+    let file_name = source
+        .file_name()
+        .ok_or_else(|| anyhow::anyhow!("{:?} has no file name", source))?;
+    let destination = levels_folder.join(file_name);
+    if source.canonicalize()? == destination.canonicalize().unwrap_or_default() {
+        anyhow::bail!("{:?} is already installed", source);
+    }
+
+    if is_archive(source) {
+        // Make sure the archive is actually a level before installing it.
+        check_archive(&std::fs::read(source)?)?;
+        std::fs::copy(source, &destination)?;
+    } else if is_level(source) {
+        copy_folder(source, &destination)?;
+    } else {
+        anyhow::bail!(
+            "{:?} is not a level (it has no {} and is not a .{} archive)",
+            source,
+            CHART_FILE,
+            ARCHIVE_EXTENSION
+        );
+    }
+    Ok(destination)
+}
+
+fn copy_folder(source: &Path, destination: &Path) -> std::io::Result<()> {
+    std::fs::create_dir_all(destination)?;
+    for entry in std::fs::read_dir(source)? {
+        let entry = entry?;
+        let path = entry.path();
+        let target = destination.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_folder(&path, &target)?;
+        } else {
+            std::fs::copy(&path, &target)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Write;
+
+    use super::*;
+
+    // Return an archive holding a chart and a file of `size` zeros.
+    fn archive_with_file(size: usize) -> Vec<u8> {
+        let mut bytes = vec![];
+        {
+            let mut zip = zip::ZipWriter::new(Cursor::new(&mut bytes));
+            let options = zip::write::FileOptions::default();
+            zip.start_file(CHART_FILE, options).unwrap();
+            zip.write_all(b"-- chart").unwrap();
+            zip.start_file("song.mid", options).unwrap();
+            zip.write_all(&vec![0; size]).unwrap();
+            zip.finish().unwrap();
+        }
+        bytes
+    }
+
+    #[test]
+    pub fn test_entries_are_read_up_to_limit() {
+        let bytes = archive_with_file(1000);
+        let files = index_archive(&bytes, Path::new("test.zip")).unwrap();
+        let index = files[Path::new("song.mid")];
+        let mut zip = zip::ZipArchive::new(Cursor::new(bytes.as_slice())).unwrap();
+        assert_eq!(read_entry(&mut zip, index, 1000).unwrap().len(), 1000);
+        assert!(read_entry(&mut zip, index, 999).is_err());
+    }
+}
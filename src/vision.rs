@@ -0,0 +1,83 @@
+/// This module implements the vision limit, which darkens everything outside
+/// of a circle around the player. The darkness fades in and out gradually
+/// rather than appearing all at once, which avoids sudden changes in brightness.
+use ggez::graphics::{Color, DrawMode, DrawParam, Drawable, Mesh};
+use ggez::{Context, GameResult};
+
+use crate::meshutil;
+use crate::time::Beats;
+use crate::world::{WorldLen, WorldPos};
+
+/// How long the darkness takes to fade in and out.
+const VISION_FADE: Beats = Beats(1.0);
+/// The opacity of the darkness when it is fully faded in.
+const VISION_DARKNESS: f32 = 0.9;
+
+/// Wide enough to cover the whole screen, no matter where the player is.
+const DARKNESS_WIDTH: f32 = 3000.0;
+
+#[derive(Debug, Clone)]
+pub struct Vision {
+    radius: WorldLen,
+    start_time: Beats,
+    duration: Beats,
+}
+
+impl Vision {
+    pub fn new(radius: WorldLen, start_time: Beats, duration: Beats) -> Vision {
+        Vision {
+            radius,
+            start_time,
+            duration,
+        }
+    }
+
+    /// Returns true if the vision limit has ended.
+    pub fn is_dead(&self, curr_time: Beats) -> bool {
+        curr_time > self.start_time + self.duration
+    }
+
+    /// Return how dark the area outside of the vision circle should be, from 0
+    /// (not darkened) to 1 (fully darkened).
+    fn darkness(&self, curr_time: Beats) -> f64 {
+        let since_start = (curr_time - self.start_time).0;
+        let until_end = (self.start_time + self.duration - curr_time).0;
+        (since_start.min(until_end) / VISION_FADE.0).clamp(0.0, 1.0)
+    }
+
+    /// Draw the darkness around `center`. The darkness is never more opaque
+    /// than `max_darkness`. See `WorldState::max_darkness`.
+    pub fn draw(
+        &self,
+        ctx: &mut Context,
+        center: WorldPos,
+        curr_time: Beats,
+        max_darkness: f32,
+    ) -> GameResult<()> {
+        let opacity = VISION_DARKNESS.min(max_darkness) * self.darkness(curr_time) as f32;
+        draw_darkness(ctx, center, self.radius, opacity)
+    }
+}
+
+/// Darken everything further than `radius` from `center`, to `opacity`.
+pub fn draw_darkness(
+    ctx: &mut Context,
+    center: WorldPos,
+    radius: WorldLen,
+    opacity: f32,
+) -> GameResult<()> {
+    if opacity <= 0.0 {
+        return Ok(());
+    }
+
+    let radius = radius.as_screen_length() + DARKNESS_WIDTH / 2.0;
+    let mesh = Mesh::new_circle(
+        ctx,
+        DrawMode::stroke(DARKNESS_WIDTH),
+        center.as_screen_coords(),
+        radius,
+        meshutil::tolerance(radius, 1.0),
+        Color::new(0.0, 0.0, 0.0, opacity),
+    )?;
+    mesh.draw(ctx, DrawParam::default())
+}
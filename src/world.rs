@@ -4,9 +4,16 @@
 /// screen. Additionally, World space has the y-axis increasing in the upwards
 /// direction (opposite to screen space, where it increases in the downwards
 /// direction)
+/// Angles in world space are measured counterclockwise from the positive
+/// x-axis, as in a standard Cartesian plane. Since screen space is flipped
+/// vertically, a world angle appears counterclockwise on screen too, but has to
+/// be negated when passed to ggez (whose rotations are clockwise in screen
+/// space). Internally angles are always in radians. Charts give angles in
+/// degrees unless they say otherwise (see `parse::get_angle`).
 use derive_more::{Add, From, Sub};
 use ggez::graphics::Rect;
 use ggez::mint;
+use serde::{Deserialize, Serialize};
 
 use crate::ease::Lerp;
 use crate::{WINDOW_HEIGHT, WINDOW_WIDTH};
@@ -16,10 +23,14 @@ use crate::{WINDOW_HEIGHT, WINDOW_WIDTH};
 /// apart in screen space.
 pub const WORLD_SCALE_FACTOR: f32 = 6.0;
 
+/// The player may only move within the square spanning from -ARENA_HALF_SIZE
+/// to ARENA_HALF_SIZE on both axes.
+pub const ARENA_HALF_SIZE: f64 = 50.0;
+
 /// A position in "world space". This is defined as a square whose origin is at
 /// the center of the world, and may range from positive to negative along both
 /// axes. The axes are oriented like a standard Cartesian plane.
-#[derive(Debug, Clone, Copy, From, Add, Sub)]
+#[derive(Debug, Clone, Copy, From, Add, Sub, Serialize, Deserialize)]
 pub struct WorldPos {
     pub x: f64,
     pub y: f64,
@@ -70,11 +81,22 @@ impl WorldPos {
         }
     }
 
+    /// Returns true if this position lies within the arena.
+    pub fn in_arena(&self) -> bool {
+        self.x.abs() <= ARENA_HALF_SIZE && self.y.abs() <= ARENA_HALF_SIZE
+    }
+
     pub fn distance(a: WorldPos, b: WorldPos) -> WorldLen {
         let dx = a.x - b.x;
         let dy = a.y - b.y;
         WorldLen((dx * dx + dy * dy).sqrt())
     }
+
+    /// Return the angle, in radians, of the direction pointing from `from`
+    /// towards `to`.
+    pub fn angle_to(from: WorldPos, to: WorldPos) -> f64 {
+        (to.y - from.y).atan2(to.x - from.x)
+    }
 }
 
 impl Lerp for WorldPos {
@@ -86,8 +108,17 @@ impl Lerp for WorldPos {
     }
 }
 
+/// Return the radius of a circle around the world origin which covers the
+/// whole screen. ScreenTransforms only flip and rotate about the origin, so
+/// anything entirely outside of this circle is off screen no matter the
+/// transform.
+pub fn view_radius() -> WorldLen {
+    let half_diagonal = (WINDOW_WIDTH / 2.0).hypot(WINDOW_HEIGHT / 2.0);
+    WorldLen((half_diagonal / WORLD_SCALE_FACTOR) as f64)
+}
+
 /// A length in World-space
-#[derive(Debug, Clone, Copy, From, Add, Sub, PartialOrd, PartialEq)]
+#[derive(Debug, Clone, Copy, From, Add, Sub, PartialOrd, PartialEq, Serialize, Deserialize)]
 pub struct WorldLen(pub f64);
 
 impl WorldLen {
@@ -101,3 +132,97 @@ impl Lerp for WorldLen {
         WorldLen(f64::lerp_unclamped(a.0, b.0, t))
     }
 }
+
+/// A transformation of the whole playfield, made of flips followed by a number
+/// of counterclockwise quarter turns. This is only applied when rendering and
+/// when reading input, so the world itself (and every sdf) is unaffected.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct ScreenTransform {
+    pub flip_x: bool,
+    pub flip_y: bool,
+    pub quarter_turns: u8,
+}
+
+impl ScreenTransform {
+    /// Return the world-space matrix of this transform, in row-major order.
+    fn matrix(&self) -> [[f64; 2]; 2] {
+        let sx = if self.flip_x { -1.0 } else { 1.0 };
+        let sy = if self.flip_y { -1.0 } else { 1.0 };
+        let (cos, sin) = match self.quarter_turns % 4 {
+            0 => (1.0, 0.0),
+            1 => (0.0, 1.0),
+            2 => (-1.0, 0.0),
+            _ => (0.0, -1.0),
+        };
+        // The rotation matrix multiplied by the flip matrix.
+        [[cos * sx, -sin * sy], [sin * sx, cos * sy]]
+    }
+
+    /// Transform a world-space vector by this transform.
+    pub fn apply(&self, x: f64, y: f64) -> (f64, f64) {
+        let m = self.matrix();
+        (m[0][0] * x + m[0][1] * y, m[1][0] * x + m[1][1] * y)
+    }
+
+    /// Undo the transform on a world-space vector. This works because flips and
+    /// rotations are orthogonal, so the inverse is just the transpose.
+    pub fn apply_inverse(&self, x: f64, y: f64) -> (f64, f64) {
+        let m = self.matrix();
+        (m[0][0] * x + m[1][0] * y, m[0][1] * x + m[1][1] * y)
+    }
+
+    /// Return this transform followed by flipping the screen horizontally.
+    pub fn mirrored(&self) -> ScreenTransform {
+        // Flipping after rotating is the same as rotating the other way after
+        // flipping.
+        ScreenTransform {
+            flip_x: !self.flip_x,
+            flip_y: self.flip_y,
+            quarter_turns: (4 - self.quarter_turns % 4) % 4,
+        }
+    }
+
+    /// Return the transform as a screen-space matrix, suitable for passing to
+    /// `ggez::graphics::push_transform`. The transform is centered on the
+    /// world origin.
+    pub fn as_screen_matrix(&self) -> mint::ColumnMatrix4<f32> {
+        let m = self.matrix();
+        // Screen space has the y-axis flipped, which negates the off-diagonal
+        // entries of the matrix.
+        let (a, b, c, d) = (
+            m[0][0] as f32,
+            -m[0][1] as f32,
+            -m[1][0] as f32,
+            m[1][1] as f32,
+        );
+        let center = WorldPos::origin().as_screen_coords();
+        let tx = center.x - (a * center.x + b * center.y);
+        let ty = center.y - (c * center.x + d * center.y);
+        mint::ColumnMatrix4 {
+            x: mint::Vector4 {
+                x: a,
+                y: c,
+                z: 0.0,
+                w: 0.0,
+            },
+            y: mint::Vector4 {
+                x: b,
+                y: d,
+                z: 0.0,
+                w: 0.0,
+            },
+            z: mint::Vector4 {
+                x: 0.0,
+                y: 0.0,
+                z: 1.0,
+                w: 0.0,
+            },
+            w: mint::Vector4 {
+                x: tx,
+                y: ty,
+                z: 0.0,
+                w: 1.0,
+            },
+        }
+    }
+}